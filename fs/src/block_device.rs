@@ -77,6 +77,15 @@ impl BlockCache {
         }
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.modified
+    }
+
+    // 放弃修改, drop 时不再写回
+    pub fn discard(&mut self) {
+        self.modified = false;
+    }
+
     pub fn read<T, V>(&self, offset: usize, f: impl FnOnce(&T) -> V) -> V {
         f(unsafe { self.get_ref(offset) })
     }
@@ -92,63 +101,154 @@ impl Drop for BlockCache {
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 32;
+/// 默认最多缓存的块数
+pub const BLOCK_CACHE_SIZE: usize = 32;
 
-#[derive(Default)]
+/// 按 LRU 淘汰的块缓存. 容量满时淘汰最久没有访问, 且没有被外部持有的块,
+/// 被淘汰的脏块先写回设备. 所有块都被持有时允许暂时超出容量
 pub struct BlockCacheManager {
-    map: BTreeMap<usize, Arc<Mutex<BlockCache>>>,
+    // block_id -> (缓存, 最近一次访问的时间戳)
+    map: BTreeMap<usize, (Arc<Mutex<BlockCache>>, u64)>,
+    // 时间戳 -> block_id, 从小到大就是从最久到最近
+    lru: BTreeMap<u64, usize>,
+    tick: u64,
+    capacity: usize,
     block_device: Option<Arc<dyn BlockDevice>>,
 }
 
+impl Default for BlockCacheManager {
+    fn default() -> Self {
+        Self::with_capacity(BLOCK_CACHE_SIZE)
+    }
+}
+
 impl BlockCacheManager {
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert_ne!(capacity, 0, "block cache capacity must be positive");
+        Self {
+            map: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            capacity,
+            block_device: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 修改容量, 缩小时立即淘汰多出来的块
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert_ne!(capacity, 0, "block cache capacity must be positive");
+        self.capacity = capacity;
+        self.evict_to(capacity);
+    }
+
+    /// 当前缓存的块数
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    // 把 block_id 标记为最近访问
+    fn touch(&mut self, block_id: usize) {
+        self.tick += 1;
+        let (_, stamp) = self.map.get_mut(&block_id).unwrap();
+        self.lru.remove(stamp);
+        *stamp = self.tick;
+        self.lru.insert(self.tick, block_id);
+    }
+
+    // 从最久没访问的开始淘汰, 直到不超过 target 个或者剩下的都被持有
+    fn evict_to(&mut self, target: usize) {
+        let excess = self.map.len().saturating_sub(target);
+        if excess == 0 {
+            return;
+        }
+        let victims: Vec<(u64, usize)> = self
+            .lru
+            .iter()
+            .filter(|(_, block_id)| Arc::strong_count(&self.map[block_id].0) == 1)
+            .take(excess)
+            .map(|(&stamp, &block_id)| (stamp, block_id))
+            .collect();
+        for (stamp, block_id) in victims {
+            self.lru.remove(&stamp);
+            let (block_cache, _) = self.map.remove(&block_id).unwrap();
+            block_cache.lock().sync();
+        }
+    }
+
     pub fn get_block_cache(&mut self, block_id: usize) -> Arc<Mutex<BlockCache>> {
         // 如果已经在缓存中
-        if let Some(block_cache) = self.map.get(&block_id) {
-            block_cache.clone()
-        } else {
-            // 保留还有引用的
-            if self.map.len() == BLOCK_CACHE_SIZE {
-                if let Some((&key, _)) = self
-                    .map
-                    .iter()
-                    .find(|(_, cache)| Arc::strong_count(cache) == 1)
-                {
-                    self.map.remove(&key);
-                }
-            }
-
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(
-                    self.block_device
-                        .as_ref()
-                        .expect("block_device haven't been registered yet"),
-                ),
-            )));
-            self.map.insert(block_id, block_cache.clone());
-            block_cache
+        if self.map.contains_key(&block_id) {
+            self.touch(block_id);
+            return self.map[&block_id].0.clone();
         }
+
+        // 为新块腾出位置
+        self.evict_to(self.capacity - 1);
+        let block_cache = Arc::new(Mutex::new(BlockCache::new(
+            block_id,
+            Arc::clone(
+                self.block_device
+                    .as_ref()
+                    .expect("block_device haven't been registered yet"),
+            ),
+        )));
+        self.map.insert(block_id, (block_cache.clone(), 0));
+        self.touch(block_id);
+        block_cache
     }
 
     pub fn flush(&mut self) {
-        for (_, block_cache) in self.map.iter_mut() {
+        for (block_cache, _) in self.map.values() {
             block_cache.lock().sync();
         }
     }
+
+    /// 有未写回修改的块, 从小到大
+    pub fn dirty_blocks(&self) -> Vec<usize> {
+        self.map
+            .iter()
+            .filter(|(_, (block_cache, _))| block_cache.lock().is_dirty())
+            .map(|(&block_id, _)| block_id)
+            .collect()
+    }
+
+    pub fn discard(&mut self) {
+        self.lru.clear();
+        for (_, (block_cache, _)) in core::mem::take(&mut self.map) {
+            block_cache.lock().discard();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.lru.clear();
+        self.map.clear();
+    }
 }
 
+// 重复注册会替换掉旧设备, 旧设备的缓存在丢弃时各自写回
 pub fn register_block_device(block_device: impl BlockDevice) {
-    let old: Option<Arc<dyn BlockDevice>> = crate::BLOCK_CACHE_MANAGER
-        .lock()
-        .block_device
-        .replace(Arc::new(block_device));
-    assert!(old.is_none(), "block device double register");
+    let mut manager = crate::BLOCK_CACHE_MANAGER.lock();
+    manager.clear();
+    manager.block_device.replace(Arc::new(block_device));
 }
 
 fn block_nth(block_id: usize) -> Arc<Mutex<BlockCache>> {
     crate::BLOCK_CACHE_MANAGER.lock().get_block_cache(block_id)
 }
 
+/// 取得 block_id 的缓存并持有, 持有期间它不会被换出
+pub fn pin(block_id: usize) -> Arc<Mutex<BlockCache>> {
+    block_nth(block_id)
+}
+
 pub fn read<T, V>(block_id: usize, offset: usize, operation: impl FnOnce(&T) -> V) -> V {
     block_nth(block_id).lock().read(offset, operation)
 }
@@ -161,6 +261,31 @@ pub fn sync(block_id: usize) {
     block_nth(block_id).lock().sync()
 }
 
-pub fn flush() {
+/// 把缓存中所有的脏块写回设备
+pub fn sync_all() {
     crate::BLOCK_CACHE_MANAGER.lock().flush()
 }
+
+/// 同 sync_all
+pub fn flush() {
+    sync_all()
+}
+
+/// 设置全局块缓存的容量, 见 BlockCacheManager::set_capacity
+pub fn set_cache_capacity(capacity: usize) {
+    crate::BLOCK_CACHE_MANAGER.lock().set_capacity(capacity)
+}
+
+/// 全局块缓存中有未写回修改的块
+pub fn dirty_blocks() -> Vec<usize> {
+    crate::BLOCK_CACHE_MANAGER.lock().dirty_blocks()
+}
+
+/// 全局块缓存中当前缓存的块数
+pub fn cached_blocks() -> usize {
+    crate::BLOCK_CACHE_MANAGER.lock().len()
+}
+
+pub fn discard() {
+    crate::BLOCK_CACHE_MANAGER.lock().discard()
+}
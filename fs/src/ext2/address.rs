@@ -19,21 +19,29 @@ impl Address {
         Self { block_id, offset }
     }
 
+    /// offset 必须落在 [0, block::SIZE) 内, 跨块的位置用 advance 计算
     pub fn new(block_id: usize, offset: isize) -> Self {
-        let block_size = block::SIZE as isize;
-
-        let new_block_id = block_id as isize + (offset >> 12);
         assert!(
-            new_block_id >= 0,
-            "block_id={}, offset={}",
+            (0..block::SIZE as isize).contains(&offset),
+            "offset out of block bounds: block_id={}, offset={}",
             block_id,
             offset
         );
+        unsafe { Self::new_unchecked(block_id, offset as usize) }
+    }
 
-        let new_offset = (offset % block_size + block_size) % block_size;
-        assert!(new_offset >= 0 && new_offset < block_size);
-
-        unsafe { Self::new_unchecked(new_block_id as usize, new_offset as usize) }
+    /// 向后 (bytes 为负时向前) 移动 bytes 字节, 越过块边界时进位到相邻的块
+    pub fn advance(self, bytes: isize) -> Self {
+        let block_size = block::SIZE as isize;
+        let total = self.offset as isize + bytes;
+        let new_block_id = self.block_id as isize + total.div_euclid(block_size);
+        assert!(
+            new_block_id >= 0,
+            "advance before block 0: {}, bytes={}",
+            self,
+            bytes
+        );
+        Self::new(new_block_id as usize, total.rem_euclid(block_size))
     }
 
     pub fn block_id(&self) -> usize {
@@ -74,19 +82,15 @@ impl From<Address> for usize {
 impl Add for Address {
     type Output = Address;
     fn add(self, rhs: Address) -> Address {
-        Address::new(
-            self.block_id + rhs.block_id,
-            (self.offset + rhs.offset) as isize,
-        )
+        Address::new(self.block_id + rhs.block_id, self.offset as isize)
+            .advance(rhs.offset as isize)
     }
 }
 
 impl Sub for Address {
     type Output = Address;
     fn sub(self, rhs: Address) -> Address {
-        Address::new(
-            self.block_id - rhs.block_id,
-            self.offset as isize - rhs.offset as isize,
-        )
+        Address::new(self.block_id - rhs.block_id, self.offset as isize)
+            .advance(-(rhs.offset as isize))
     }
 }
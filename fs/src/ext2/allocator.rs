@@ -1,47 +1,122 @@
-use alloc::{sync::Arc, vec::Vec};
+use core::fmt::{self, Debug};
+
+use alloc::{format, sync::Arc, vec::Vec};
 use spin::Mutex;
 
-use crate::vfs::error::{IOError, IOErrorKind, VfsResult};
+use crate::vfs::error::{IOError, IOErrorKind, VfsError, VfsResult};
 
 use super::{blockgroup::Ext2BlockGroupDesc, layout::Ext2Layout, superblock::Superblock};
 
+// 所有 inode 的分配情况, 第 i 位对应 inode i + 1
+struct InodeBitset {
+    bits: Vec<u64>,
+}
+
+impl InodeBitset {
+    fn contains(&self, inode_id: u32) -> bool {
+        let idx = inode_id as usize - 1;
+        self.bits
+            .get(idx / 64)
+            .is_some_and(|unit| unit & (1 << (idx % 64)) != 0)
+    }
+
+    fn set(&mut self, inode_id: u32, allocated: bool) {
+        let idx = inode_id as usize - 1;
+        if allocated {
+            self.bits[idx / 64] |= 1 << (idx % 64);
+        } else {
+            self.bits[idx / 64] &= !(1 << (idx % 64));
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.bits.iter().enumerate().flat_map(|(pos, &unit)| {
+            (0..64)
+                .filter(move |bit| unit & (1 << bit) != 0)
+                .map(move |bit| (pos * 64 + bit + 1) as u32)
+        })
+    }
+}
+
+// 位图可能很大, 只打印已分配的个数
+impl Debug for InodeBitset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let used: u32 = self.bits.iter().map(|unit| unit.count_ones()).sum();
+        write!(f, "InodeBitset {{ used: {} }}", used)
+    }
+}
+
 #[derive(Debug)]
 pub struct Ext2Allocator {
     blocks_per_group: u32,
     inodes_per_group: u32,
+    // 第一个非保留的 inode
+    first_ino: u32,
+    // 上一次成功分配数据块的块组, 没有 goal 的分配从这里开始找.
+    // 带 goal 的分配 (alloc_data_near) 从 goal 开始, 但成功后同样更新它
+    last_group: usize,
 
     superblock: Arc<Mutex<Superblock>>,
     blockgroups: Arc<Vec<Mutex<Ext2BlockGroupDesc>>>,
+    // 开启后缓存所有 inode 的分配情况, 查询时不用再读 bitmap
+    inode_cache: Option<InodeBitset>,
 }
 impl Ext2Allocator {
     pub(crate) fn new(layout: Arc<Ext2Layout>) -> Ext2Allocator {
         Self {
             blocks_per_group: layout.blocks_per_group(),
             inodes_per_group: layout.inodes_per_group(),
+            first_ino: layout.superblock().lock().first_ino(),
+            last_group: 0,
             superblock: layout.superblock(),
             blockgroups: layout.blockgroups(),
+            inode_cache: None,
         }
     }
 
-    fn free_blocks(&self) -> u32 {
-        let sb = self.superblock.lock();
-        sb.free_blocks_count - sb.r_blocks_count
-    }
-
-    fn inc_free_blocks(&mut self, n: usize) {
-        self.superblock.lock().free_blocks_count += n as u32;
+    /// 扫描一遍所有块组的 inode bitmap 建立缓存, 之后随分配和释放增量更新
+    pub(crate) fn enable_inode_cache(&mut self) {
+        let inodes_count = self.superblock.lock().inodes_count as usize;
+        let mut cache = InodeBitset {
+            bits: alloc::vec![0; inodes_count.div_ceil(64)],
+        };
+        for (bg_idx, bg) in self.blockgroups.iter().enumerate() {
+            let group_start = bg_idx as u32 * self.inodes_per_group;
+            for inner_idx in bg.lock().used_inodes(self.inodes_per_group as usize) {
+                cache.set(group_start + inner_idx as u32 + 1, true);
+            }
+        }
+        self.inode_cache = Some(cache);
     }
 
-    fn dec_free_blocks(&mut self, n: usize) {
-        self.superblock.lock().free_blocks_count -= n as u32;
+    pub(crate) fn is_inode_allocated(&self, inode_id: u32) -> bool {
+        if inode_id == 0 || inode_id > self.superblock.lock().inodes_count {
+            return false;
+        }
+        if let Some(cache) = &self.inode_cache {
+            return cache.contains(inode_id);
+        }
+        let (bg_idx, inner_idx) = self.decomposition_inode_id(inode_id);
+        self.blockgroups[bg_idx].lock().inode_used(inner_idx)
     }
 
-    fn inc_free_inode(&mut self) {
-        self.superblock.lock().free_inodes_count += 1;
+    /// 所有已分配的 inode 号, 从小到大
+    pub(crate) fn allocated_inodes(&self) -> Vec<u32> {
+        if let Some(cache) = &self.inode_cache {
+            return cache.iter().collect();
+        }
+        let mut inodes = Vec::new();
+        for (bg_idx, bg) in self.blockgroups.iter().enumerate() {
+            let group_start = bg_idx as u32 * self.inodes_per_group;
+            let used = bg.lock().used_inodes(self.inodes_per_group as usize);
+            inodes.extend(used.into_iter().map(|idx| group_start + idx as u32 + 1));
+        }
+        inodes
     }
 
-    fn dec_free_inode(&mut self) {
-        self.superblock.lock().free_inodes_count -= 1;
+    fn free_blocks(&self) -> u32 {
+        let sb = self.superblock.lock();
+        sb.free_blocks_count - sb.r_blocks_count
     }
 
     fn free_inodes(&self) -> u32 {
@@ -71,30 +146,112 @@ impl Ext2Allocator {
         }
 
         // 到此则有可用 inode
-        self.dec_free_inode();
-        for bg in self.blockgroups.iter() {
+        for (bg_idx, bg) in self.blockgroups.iter().enumerate() {
             let mut bg = bg.lock();
-            if bg.free_blocks_count == 0 {
+            if bg.free_inodes_count == 0 {
                 continue;
             }
-            return Ok(bg.alloc_inode(is_dir));
+            // inode 1 ~ first_ino-1 是保留的, 只可能落在靠前的块组中
+            let group_start = bg_idx as u32 * self.inodes_per_group;
+            let reserved = (self.first_ino - 1).saturating_sub(group_start) as usize;
+            if let Some(inner_idx) =
+                bg.alloc_inode(is_dir, reserved, self.inodes_per_group as usize)
+            {
+                self.superblock.lock().free_inodes_count -= 1;
+                // 特别注意 inode 从 1 开始计数
+                let inode_id = group_start + inner_idx + 1;
+                if let Some(cache) = &mut self.inode_cache {
+                    cache.set(inode_id, true);
+                }
+                return Ok(inode_id);
+            }
+        }
+
+        Err(IOError::new(IOErrorKind::NoFreeInodes).into())
+    }
+
+    /// 一次分配 num 个 inode, 要么全部成功, 要么一个也不分配.
+    /// 优先放在空闲 inode 多的块组中, 让同一批 inode 尽量聚在一起
+    pub(crate) fn alloc_inodes(&mut self, num: usize, is_dir: bool) -> VfsResult<Vec<u32>> {
+        if num > self.free_inodes() as usize {
+            return Err(IOError::new(IOErrorKind::NoFreeInodes).into());
+        }
+
+        let mut order: Vec<usize> = (0..self.blockgroups.len()).collect();
+        order.sort_by_key(|&bg_idx| {
+            core::cmp::Reverse(self.blockgroups[bg_idx].lock().free_inodes_count)
+        });
+
+        let mut ret = Vec::with_capacity(num);
+        for bg_idx in order {
+            if ret.len() == num {
+                break;
+            }
+            let mut bg = self.blockgroups[bg_idx].lock();
+            let group_start = bg_idx as u32 * self.inodes_per_group;
+            let reserved = (self.first_ino - 1).saturating_sub(group_start) as usize;
+            let allocated = bg.alloc_inodes(
+                num - ret.len(),
+                is_dir,
+                reserved,
+                self.inodes_per_group as usize,
+            );
+            // 持有 bg 锁的同时更新 superblock, 两处的 free_inodes 不会分叉
+            self.superblock.lock().free_inodes_count -= allocated.len() as u32;
+            ret.extend(
+                allocated
+                    .into_iter()
+                    .map(|inner_idx| group_start + inner_idx + 1),
+            );
+        }
+        if let Some(cache) = &mut self.inode_cache {
+            for &inode_id in &ret {
+                cache.set(inode_id, true);
+            }
         }
 
-        unreachable!()
+        // 计数显示有空闲但 bitmap 中找不到, 归还已分配的
+        if ret.len() != num {
+            for inode_id in ret {
+                self.dealloc_inode(inode_id, is_dir)?;
+            }
+            return Err(IOError::new(IOErrorKind::NoFreeInodes).into());
+        }
+        Ok(ret)
     }
 
     pub(crate) fn dealloc_inode(&mut self, inode_id: u32, is_dir: bool) -> VfsResult<()> {
+        // 保留的 inode 不会被分配, 也就不应该被释放
+        if inode_id < self.first_ino {
+            let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+            return Err(err.with_additional(format!("free reserved inode {}", inode_id)));
+        }
         // 找出属于哪个块组, 块组内偏移多少
         let (bg_idx, inner_idx) = self.decomposition_inode_id(inode_id);
+        let Some(bg) = self.blockgroups.get(bg_idx) else {
+            let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+            return Err(err.with_additional(format!("free inode {} out of range", inode_id)));
+        };
 
-        let bg = self.blockgroups.get(bg_idx).unwrap();
-        bg.lock().dealloc_inode(inner_idx as u32, is_dir);
-        self.inc_free_inode();
+        // 持有 bg 锁的同时更新 superblock, 两处的 free_inodes 不会分叉
+        let mut bg = bg.lock();
+        bg.dealloc_inode(inner_idx as u32, is_dir);
+        self.superblock.lock().free_inodes_count += 1;
+        if let Some(cache) = &mut self.inode_cache {
+            cache.set(inode_id, false);
+        }
 
         Ok(())
     }
 
+    /// 分配 needed 个不要求连续的块, 用于目录扩容和间接块等没有 goal 的分配.
+    /// 从 last_group 开始找, 前面已经分配满的块组不会再被扫描
     pub(crate) fn alloc_data(&mut self, needed: usize) -> VfsResult<Vec<u32>> {
+        self.alloc_scattered(needed, self.last_group)
+    }
+
+    // 从 first_group 开始, 绕一圈回来, 每个块组尽力分配, 块不一定连续
+    fn alloc_scattered(&mut self, needed: usize, first_group: usize) -> VfsResult<Vec<u32>> {
         if needed > self.free_blocks() as usize {
             return Err(IOError::new(IOErrorKind::NoFreeBlocks).into());
         }
@@ -104,25 +261,77 @@ impl Ext2Allocator {
         }
 
         let mut unmet = needed;
-        // 需要分别更新 superblock 的 free_blocks 和 blockgroups 的 free_blocks_count
-        for bg in self.blockgroups.iter() {
-            let mut bg = bg.lock();
-            // 每一个 bg 都尽力分配 unmet 个块, 返回分配的块数
-            let allocated = bg.alloc_blocks(unmet);
+        for bg_idx in Self::ring_from(first_group, self.blockgroups.len()) {
+            let mut bg = self.blockgroups[bg_idx].lock();
+            if bg.free_blocks_count == 0 {
+                continue;
+            }
+            // 每一个 bg 都尽力分配 unmet 个块, 返回的是 bg 内的相对块号
+            let allocated = bg.alloc_blocks(unmet, self.blocks_in_group(bg_idx));
+            // 持有 bg 锁的同时更新 superblock, 两处的 free_blocks 不会分叉
+            self.superblock.lock().free_blocks_count -= allocated.len() as u32;
             unmet -= allocated.len();
-            ret.extend(allocated);
+            let group_start = bg_idx as u32 * self.blocks_per_group;
+            ret.extend(allocated.into_iter().map(|bg_bid| group_start + bg_bid));
             if unmet == 0 {
+                self.last_group = bg_idx;
                 break;
             }
         }
 
-        // 扣除 free_blocks
-        self.dec_free_blocks(needed);
-        // 前面判断有空间, 因此跳出循环时必然 unmet == 0
-        assert_eq!(unmet, 0);
+        // 计数显示有空间但 bitmap 中找不到, 归还已分配的块
+        if unmet != 0 {
+            self.dealloc_data(ret)?;
+            return Err(IOError::new(IOErrorKind::NoFreeBlocks).into());
+        }
         Ok(ret)
     }
 
+    /// 尽量分配连续的 needed 个块, 减少大文件的碎片. 从上一次分配的块组开始,
+    /// 在每个块组的 bitmap 中找足够长的连续空闲位, 都找不到时退回零散分配
+    pub(crate) fn alloc_data_contiguous(&mut self, needed: usize) -> VfsResult<Vec<u32>> {
+        self.alloc_data_near(needed, self.last_group)
+    }
+
+    /// 同 alloc_data_contiguous, 但从 goal_bg 开始查找, 不看 last_group.
+    /// goal_bg 一般是 inode 所在的块组, 让文件的数据块和 inode 靠在一起, 减少寻道.
+    /// 成功后 last_group 指向分配所在的块组
+    pub(crate) fn alloc_data_near(&mut self, needed: usize, goal_bg: usize) -> VfsResult<Vec<u32>> {
+        let goal_bg = goal_bg.min(self.blockgroups.len() - 1);
+        if needed <= 1 || needed > self.blocks_per_group as usize {
+            return self.alloc_scattered(needed, goal_bg);
+        }
+        if needed > self.free_blocks() as usize {
+            return Err(IOError::new(IOErrorKind::NoFreeBlocks).into());
+        }
+
+        for bg_idx in Self::ring_from(goal_bg, self.blockgroups.len()) {
+            let group_start = bg_idx * self.blocks_per_group as usize;
+            let blocks_in_group = self.blocks_in_group(bg_idx);
+            let mut bg = self.blockgroups[bg_idx].lock();
+            if let Some(run_start) = bg.alloc_run(needed, blocks_in_group) {
+                // 持有 bg 锁的同时更新 superblock, 两处的 free_blocks 不会分叉
+                self.superblock.lock().free_blocks_count -= needed as u32;
+                self.last_group = bg_idx;
+                let first = group_start as u32 + run_start;
+                return Ok((first..first + needed as u32).collect());
+            }
+        }
+        self.alloc_scattered(needed, goal_bg)
+    }
+
+    // 块组实际拥有的块数, 最后一个块组可能不满
+    fn blocks_in_group(&self, bg_idx: usize) -> usize {
+        let blocks_count = self.superblock.lock().blocks_count as usize;
+        let group_start = bg_idx * self.blocks_per_group as usize;
+        (blocks_count - group_start).min(self.blocks_per_group as usize)
+    }
+
+    // 从 first 开始绕一圈的块组下标, 避免每次都重新扫描前面已满的块组
+    fn ring_from(first: usize, count: usize) -> impl Iterator<Item = usize> {
+        (first..count).chain(0..first)
+    }
+
     pub(crate) fn dealloc_data(&mut self, mut freed: Vec<u32>) -> VfsResult<()> {
         let mut slots = alloc::vec![0; self.blockgroups.len()];
 
@@ -140,14 +349,14 @@ impl Ext2Allocator {
             let mut bg = bg.lock();
             let bg_blocks = &freed[offset..offset + slots[idx]]
                 .iter()
-                .map(|&block_id| (block_id % self.blocks_per_group) as u32)
+                .map(|&block_id| block_id % self.blocks_per_group)
                 .collect::<Vec<_>>();
 
             bg.dealloc_blocks(bg_blocks);
+            self.superblock.lock().free_blocks_count += bg_blocks.len() as u32;
             offset += slots[idx];
         }
 
-        self.inc_free_blocks(freed.len());
         assert_eq!(offset, freed.len());
 
         Ok(())
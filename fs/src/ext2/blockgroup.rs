@@ -5,7 +5,7 @@ use spin::Mutex;
 
 use crate::{
     block::{self, DataBlock},
-    block_device, cast,
+    block_device, cast, ceil_index, try_cast,
     vfs::meta::VfsFileType,
 };
 
@@ -33,22 +33,38 @@ pub struct Ext2BlockGroupDesc {
     _reserved: [u8; 14],
 }
 
-const UNIT_WIDTH: usize = 64;
-type BitmapBlock = [u64; block::BITS / UNIT_WIDTH];
+pub(crate) const UNIT_WIDTH: usize = 64;
+pub(crate) type BitmapBlock = [u64; block::BITS / UNIT_WIDTH];
 
 impl Ext2BlockGroupDesc {
+    // 4096 字节的块中, 描述符表紧跟在 superblock 所在的 0 号块之后
+    pub(crate) const TABLE_START: usize = 1;
+    pub(crate) const PER_BLOCK: usize = block::SIZE / core::mem::size_of::<Self>();
+
+    // 描述符表占用的块数
+    pub(crate) fn table_blocks(count: u32) -> usize {
+        ceil_index!(count as usize, Self::PER_BLOCK)
+    }
+
+    // 全 0 的描述符, 供 mkfs 逐项填写
+    pub(crate) fn zeroed() -> Self {
+        unsafe { core::mem::zeroed() }
+    }
+
     pub(crate) fn find(count: u32) -> Vec<Self> {
-        block_device::read(1, 0, |data: &DataBlock| {
-            let mut vec = Vec::new();
-            let mut offset = 0;
-            for _ in 0..count {
-                let current = &data[offset..];
-                let desc = cast!(current.as_ptr(), Ext2BlockGroupDesc);
-                vec.push(desc.clone());
-                offset += core::mem::size_of::<Ext2BlockGroupDesc>();
-            }
-            vec
-        })
+        let desc_size = core::mem::size_of::<Self>();
+        let mut vec = Vec::with_capacity(count as usize);
+        for table_idx in 0..Self::table_blocks(count) {
+            let in_block = (count as usize - table_idx * Self::PER_BLOCK).min(Self::PER_BLOCK);
+            block_device::read(Self::TABLE_START + table_idx, 0, |data: &DataBlock| {
+                for idx in 0..in_block {
+                    let desc = try_cast::<Ext2BlockGroupDesc>(&data[idx * desc_size..])
+                        .expect("block group descriptor out of the descriptor block");
+                    vec.push(desc.clone());
+                }
+            });
+        }
+        vec
     }
 
     fn block_bitmap_bid(&self) -> usize {
@@ -63,7 +79,12 @@ impl Ext2BlockGroupDesc {
         self.inode_table_block as usize
     }
 
-    /// inode_inner_idx 指的是 inode 在 block group 中的内部偏移
+    /// inode_inner_idx 指的是 inode 在 block group 中的内部偏移,
+    /// inode_size 是 inode 表的步长, 取自 superblock
+    pub fn inode_address(&self, inode_inner_idx: usize, inode_size: usize) -> Address {
+        Address::new(self.inode_table_bid(), 0).advance((inode_inner_idx * inode_size) as isize)
+    }
+
     pub fn get_inode(
         &self,
         inode_id: usize,
@@ -71,10 +92,7 @@ impl Ext2BlockGroupDesc {
         layout: Arc<Ext2Layout>,
         allocator: Arc<Mutex<Ext2Allocator>>,
     ) -> Inode {
-        let address = Address::new(
-            self.inode_table_bid(),
-            (inode_inner_idx * core::mem::size_of::<Ext2Inode>()) as isize,
-        );
+        let address = self.inode_address(inode_inner_idx, layout.inode_size());
         Inode::read(inode_id, address, layout, allocator)
     }
 
@@ -86,10 +104,7 @@ impl Ext2BlockGroupDesc {
         layout: Arc<Ext2Layout>,
         allocator: Arc<Mutex<Ext2Allocator>>,
     ) -> Inode {
-        let address = Address::new(
-            self.inode_table_bid(),
-            (inode_inner_idx * core::mem::size_of::<Ext2Inode>()) as isize,
-        );
+        let address = self.inode_address(inode_inner_idx, layout.inode_size());
         Inode::new(inode_id, address, filetype, layout, allocator)
     }
 
@@ -98,31 +113,82 @@ impl Ext2BlockGroupDesc {
         (bit_idx as usize / UNIT_WIDTH, bit_idx as usize % UNIT_WIDTH)
     }
 
-    // 调用该函数必然成功, 所有的检查应该在外部完成
-    pub fn alloc_inode(&mut self, is_dir: bool) -> u32 {
-        assert_ne!(self.free_inodes_count, 0);
+    // 前 reserved 个 inode 永远不会被分配出去, bitmap 中 inodes_in_group 之后的位也不会.
+    // 找不到空闲 inode 时返回 None. 返回的是块组内的索引, 从 0 开始
+    pub fn alloc_inode(
+        &mut self,
+        is_dir: bool,
+        reserved: usize,
+        inodes_in_group: usize,
+    ) -> Option<u32> {
+        if self.free_inodes_count == 0 {
+            return None;
+        }
+
+        let inner_idx =
+            block_device::modify(self.inode_bitmap_bid(), 0, |bitmap: &mut BitmapBlock| {
+                use core::ops::Not;
+                for (pos, bits) in bitmap.iter_mut().enumerate() {
+                    let mut neg_bits = bits.not();
+                    // 屏蔽保留区间内的位
+                    let start = pos * UNIT_WIDTH;
+                    if start < reserved {
+                        neg_bits &= u64::MAX.checked_shl((reserved - start) as u32).unwrap_or(0);
+                    }
+                    if neg_bits != 0 {
+                        let inner_pos = neg_bits.trailing_zeros() as usize;
+                        if start + inner_pos >= inodes_in_group {
+                            return None;
+                        }
+                        *bits |= 1 << inner_pos;
+                        return Some((start + inner_pos) as u32);
+                    }
+                }
+                None
+            })?;
+
         // 不要忘记更新 free_inodes_count
         self.free_inodes_count -= 1;
+        if is_dir {
+            self.dirs_count += 1;
+        }
+        Some(inner_idx)
+    }
 
-        block_device::modify(self.inode_bitmap_bid(), 0, |bitmap: &mut BitmapBlock| {
-            use core::ops::Not;
-            for (pos, bits) in bitmap.iter_mut().enumerate() {
-                let neg_bits = bits.not();
-                while neg_bits != 0 {
-                    let inner_pos = neg_bits.trailing_zeros() as usize;
-                    *bits |= 1 << inner_pos;
+    // 与 alloc_inode 相同, 但一次扫描 bitmap 尽力分配 num 个, 返回块组内的索引
+    pub fn alloc_inodes(
+        &mut self,
+        num: usize,
+        is_dir: bool,
+        reserved: usize,
+        inodes_in_group: usize,
+    ) -> Vec<u32> {
+        let num = num.min(self.free_inodes_count as usize);
+        if num == 0 {
+            return Vec::new();
+        }
 
-                    if is_dir {
-                        self.dirs_count += 1;
+        let allocated =
+            block_device::modify(self.inode_bitmap_bid(), 0, |bitmap: &mut BitmapBlock| {
+                let mut allocated = Vec::with_capacity(num);
+                for idx in reserved..inodes_in_group {
+                    if allocated.len() == num {
+                        break;
+                    }
+                    let (pos, inner_pos) = self.decomposition(idx as u32);
+                    if bitmap[pos] & (1u64 << inner_pos) == 0 {
+                        bitmap[pos] |= 1u64 << inner_pos;
+                        allocated.push(idx as u32);
                     }
-
-                    // 特别注意 inode 从 1 开始计数
-                    return (pos * UNIT_WIDTH + inner_pos + 1) as u32;
                 }
-            }
+                allocated
+            });
 
-            unreachable!()
-        })
+        self.free_inodes_count -= allocated.len() as u16;
+        if is_dir {
+            self.dirs_count += allocated.len() as u16;
+        }
+        allocated
     }
 
     pub fn dealloc_inode(&mut self, bit_idx: u32, is_dir: bool) {
@@ -139,9 +205,67 @@ impl Ext2BlockGroupDesc {
         }
     }
 
+    // 统计 bitmap 前 bits 位中被置位的个数
+    fn count_used(bitmap_bid: usize, bits: usize) -> usize {
+        block_device::read(bitmap_bid, 0, |bitmap: &BitmapBlock| {
+            let full = bits / UNIT_WIDTH;
+            let rest = bits % UNIT_WIDTH;
+            let mut used = bitmap[..full]
+                .iter()
+                .map(|unit| unit.count_ones() as usize)
+                .sum::<usize>();
+            if rest != 0 {
+                used += (bitmap[full] & ((1u64 << rest) - 1)).count_ones() as usize;
+            }
+            used
+        })
+    }
+
+    /// inode bitmap 前 inodes_in_group 位中被置位的块组内索引
+    pub fn used_inodes(&self, inodes_in_group: usize) -> Vec<usize> {
+        block_device::read(self.inode_bitmap_bid(), 0, |bitmap: &BitmapBlock| {
+            (0..inodes_in_group)
+                .filter(|&idx| bitmap[idx / UNIT_WIDTH] & (1u64 << (idx % UNIT_WIDTH)) != 0)
+                .collect()
+        })
+    }
+
+    /// inode bitmap 中块组内索引为 inner_idx 的位是否被置位
+    pub fn inode_used(&self, inner_idx: usize) -> bool {
+        block_device::read(self.inode_bitmap_bid(), 0, |bitmap: &BitmapBlock| {
+            let (pos, inner_pos) = self.decomposition(inner_idx as u32);
+            bitmap[pos] & (1u64 << inner_pos) != 0
+        })
+    }
+
+    /// block bitmap 中块组内相对块号为 bg_bid 的位是否被置位
+    pub fn block_used(&self, bg_bid: usize) -> bool {
+        block_device::read(self.block_bitmap_bid(), 0, |bitmap: &BitmapBlock| {
+            let (pos, inner_pos) = self.decomposition(bg_bid as u32);
+            bitmap[pos] & (1u64 << inner_pos) != 0
+        })
+    }
+
+    /// 按 bitmap 重新统计出的 (free_blocks, free_inodes)
+    pub fn recount_free(&self, blocks_in_group: usize, inodes_in_group: usize) -> (usize, usize) {
+        (
+            blocks_in_group - Self::count_used(self.block_bitmap_bid(), blocks_in_group),
+            inodes_in_group - Self::count_used(self.inode_bitmap_bid(), inodes_in_group),
+        )
+    }
+
+    /// block bitmap 中超出 blocks_in_group 的填充位里没有置位的个数, 正常应为 0
+    pub fn clear_padding_bits(&self, blocks_in_group: usize) -> usize {
+        let padding = block::BITS - blocks_in_group;
+        padding
+            - (Self::count_used(self.block_bitmap_bid(), block::BITS)
+                - Self::count_used(self.block_bitmap_bid(), blocks_in_group))
+    }
+
     // 调用该函数必然成功, 所有的检查应该在外部完成
-    // 在本 blockgroup 中尽力分配 num 个 block, 但是不一定能完成
-    pub fn alloc_blocks(&mut self, num: usize) -> Vec<u32> {
+    // 在本 blockgroup 的前 blocks_in_group 个块中尽力分配 num 个 block, 但是不一定能完成.
+    // 不依赖填充位, 即使填充位没有置位也不会分配出不存在的块
+    pub fn alloc_blocks(&mut self, num: usize, blocks_in_group: usize) -> Vec<u32> {
         assert_ne!(num, 0);
 
         let mut vec = Vec::new();
@@ -149,7 +273,14 @@ impl Ext2BlockGroupDesc {
         block_device::modify(self.block_bitmap_bid(), 0, |bitmap: &mut BitmapBlock| {
             use core::ops::Not;
             for (pos, bits) in bitmap.iter_mut().enumerate() {
+                let valid = blocks_in_group.saturating_sub(pos * UNIT_WIDTH);
+                if valid == 0 {
+                    break;
+                }
                 let mut neg_bits = bits.not();
+                if valid < UNIT_WIDTH {
+                    neg_bits &= (1u64 << valid) - 1;
+                }
                 while neg_bits != 0 {
                     let inner_pos = neg_bits.trailing_zeros() as usize;
                     *bits |= 1 << inner_pos;
@@ -170,6 +301,43 @@ impl Ext2BlockGroupDesc {
         })
     }
 
+    // 在本 blockgroup 的前 blocks_in_group 个块中找连续 num 个空闲块并全部分配,
+    // 返回第一个块的相对块号. 找不到时不做任何修改
+    pub fn alloc_run(&mut self, num: usize, blocks_in_group: usize) -> Option<u32> {
+        assert_ne!(num, 0);
+        if (self.free_blocks_count as usize) < num {
+            return None;
+        }
+
+        block_device::modify(self.block_bitmap_bid(), 0, |bitmap: &mut BitmapBlock| {
+            let mut run_start = 0;
+            let mut bit = 0;
+            while bit < blocks_in_group {
+                let (pos, inner_pos) = (bit / UNIT_WIDTH, bit % UNIT_WIDTH);
+                // 整个单元都被占用时直接跳过
+                if inner_pos == 0 && bitmap[pos] == u64::MAX {
+                    bit += UNIT_WIDTH;
+                    run_start = bit;
+                    continue;
+                }
+                if bitmap[pos] & (1u64 << inner_pos) != 0 {
+                    bit += 1;
+                    run_start = bit;
+                    continue;
+                }
+                bit += 1;
+                if bit - run_start == num {
+                    for used in run_start..bit {
+                        bitmap[used / UNIT_WIDTH] |= 1u64 << (used % UNIT_WIDTH);
+                    }
+                    self.free_blocks_count -= num as u16;
+                    return Some(run_start as u32);
+                }
+            }
+            None
+        })
+    }
+
     // 参数 bg_blocks 只是自己所管辖的 blockgroup 内的相对 block 而不是全局 block_id
     pub fn dealloc_blocks(&mut self, bg_blocks: &[u32]) {
         if bg_blocks.is_empty() {
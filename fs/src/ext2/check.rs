@@ -0,0 +1,288 @@
+use core::fmt::{self, Display};
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+
+use crate::block_device;
+
+use super::{allocator::Ext2Allocator, dir::Dir, disk_inode::Ext2Inode, layout::Ext2Layout};
+
+/// Ext2FileSystem::check 发现的一处不一致
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// inode 占用的块在 block bitmap 中没有标记为已用
+    BlockNotMarked { inode_id: usize, block_id: usize },
+    /// inode 引用了超出文件系统的块
+    BlockOutOfRange { inode_id: usize, block_id: usize },
+    /// 同一个块被引用了两次, first 是先扫描到的 inode, 可能与 second 相同
+    DuplicateBlock {
+        block_id: usize,
+        first: usize,
+        second: usize,
+    },
+    /// 目录的 "." 缺失或者不指向自身
+    BadDot { dir: usize, found: Option<usize> },
+    /// 目录的 ".." 缺失或者不指向引用它的父目录
+    BadDotDot {
+        dir: usize,
+        found: Option<usize>,
+        expected: usize,
+    },
+    /// 目录项指向没有分配的 inode
+    DanglingEntry {
+        dir: usize,
+        name: String,
+        inode_id: usize,
+    },
+    /// hard_links 与引用该 inode 的目录项个数不一致, 目录的 "." 和 ".." 也计算在内
+    LinkCount {
+        inode_id: usize,
+        recorded: u16,
+        counted: usize,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BlockNotMarked { inode_id, block_id } => write!(
+                f,
+                "block {} of inode {} is not marked in the block bitmap",
+                block_id, inode_id
+            ),
+            Problem::BlockOutOfRange { inode_id, block_id } => write!(
+                f,
+                "block {} of inode {} is out of range",
+                block_id, inode_id
+            ),
+            Problem::DuplicateBlock {
+                block_id,
+                first,
+                second,
+            } => write!(
+                f,
+                "block {} is referenced by inode {} and inode {}",
+                block_id, first, second
+            ),
+            Problem::BadDot { dir, found } => {
+                write!(f, "'.' of directory {} points to {:?}", dir, found)
+            }
+            Problem::BadDotDot {
+                dir,
+                found,
+                expected,
+            } => write!(
+                f,
+                "'..' of directory {} points to {:?}, expected {}",
+                dir, found, expected
+            ),
+            Problem::DanglingEntry {
+                dir,
+                name,
+                inode_id,
+            } => write!(
+                f,
+                "entry {:?} in directory {} points to unallocated inode {}",
+                name, dir, inode_id
+            ),
+            Problem::LinkCount {
+                inode_id,
+                recorded,
+                counted,
+            } => write!(
+                f,
+                "inode {} has {} hard links but {} directory entries",
+                inode_id, recorded, counted
+            ),
+        }
+    }
+}
+
+/// 一致性检查的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// 检查过的 inode 个数
+    pub inodes: usize,
+    /// 检查过的块数, 包括间接块
+    pub blocks: usize,
+    /// 按发现的顺序排列
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    /// 没有发现任何问题
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// 目录中除 "." 和 ".." 之外的目录项, 以及这两项各自指向的 inode
+struct DirScan {
+    entries: Vec<(String, usize)>,
+    dot: Option<usize>,
+    dot_dot: Option<usize>,
+}
+
+// 只读地扫描所有 inode bitmap 中标记为已用的 inode, 不修改磁盘.
+// 链接数为 0 的 inode 视为空闲; 根目录以外的保留 inode (resize inode 等) 有各自的块布局约定, 不参与检查
+pub(crate) fn check(
+    layout: &Arc<Ext2Layout>,
+    allocator: &Arc<Mutex<Ext2Allocator>>,
+) -> CheckReport {
+    let (blocks_count, first_ino) = {
+        let sb = layout.superblock();
+        let sb = sb.lock();
+        (sb.blocks_count as usize, sb.first_ino() as usize)
+    };
+    let blocks_per_group = layout.blocks_per_group() as usize;
+    let inodes_per_group = layout.inodes_per_group() as usize;
+    let blockgroups = layout.blockgroups();
+
+    let mut report = CheckReport::default();
+    // inode 号 -> 磁盘上记录的 hard_links
+    let mut recorded = BTreeMap::new();
+    let mut dirs = BTreeMap::new();
+    // 块号 -> 第一个引用它的 inode
+    let mut owners: BTreeMap<usize, usize> = BTreeMap::new();
+
+    for (bg_idx, bg) in blockgroups.iter().enumerate() {
+        let used = bg.lock().used_inodes(inodes_per_group);
+        for inner_idx in used {
+            let inode_id = bg_idx * inodes_per_group + inner_idx + 1;
+            if inode_id < first_ino && inode_id != 2 {
+                continue;
+            }
+            let address = bg.lock().inode_address(inner_idx, layout.inode_size());
+            let (hard_links, blocks, dir) = block_device::read(
+                address.block_id(),
+                address.offset(),
+                |ext2_inode: &Ext2Inode| {
+                    if ext2_inode.hard_links == 0 {
+                        return (0, Vec::new(), None);
+                    }
+                    let dir = ext2_inode.filetype().is_dir().then(|| {
+                        Dir::from_inode(inode_id, ext2_inode, layout.clone(), allocator.clone())
+                    });
+                    (ext2_inode.hard_links, ext2_inode.all_owned_blocks(), dir)
+                },
+            );
+            if hard_links == 0 {
+                continue;
+            }
+            report.inodes += 1;
+            recorded.insert(inode_id, hard_links);
+            if let Some(dir) = dir {
+                dirs.insert(inode_id, scan_dir(&dir));
+            }
+
+            for block_id in blocks.into_iter().map(|block_id| block_id as usize) {
+                report.blocks += 1;
+                if block_id >= blocks_count {
+                    report
+                        .problems
+                        .push(Problem::BlockOutOfRange { inode_id, block_id });
+                    continue;
+                }
+                if let Some(&first) = owners.get(&block_id) {
+                    report.problems.push(Problem::DuplicateBlock {
+                        block_id,
+                        first,
+                        second: inode_id,
+                    });
+                } else {
+                    owners.insert(block_id, inode_id);
+                }
+                let bg = blockgroups[block_id / blocks_per_group].lock();
+                if !bg.block_used(block_id % blocks_per_group) {
+                    report
+                        .problems
+                        .push(Problem::BlockNotMarked { inode_id, block_id });
+                }
+            }
+        }
+    }
+
+    // 每个 inode 被目录项引用的次数, 以及每个子目录的父目录
+    let mut counted: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut parents = BTreeMap::new();
+    // 根目录的 ".." 指向自身
+    parents.insert(2, 2);
+    for (&dir_id, scan) in &dirs {
+        for (name, inode_id) in &scan.entries {
+            if !recorded.contains_key(inode_id) {
+                report.problems.push(Problem::DanglingEntry {
+                    dir: dir_id,
+                    name: name.clone(),
+                    inode_id: *inode_id,
+                });
+                continue;
+            }
+            *counted.entry(*inode_id).or_default() += 1;
+            if dirs.contains_key(inode_id) {
+                parents.entry(*inode_id).or_insert(dir_id);
+            }
+        }
+        for inode_id in [scan.dot, scan.dot_dot].into_iter().flatten() {
+            if recorded.contains_key(&inode_id) {
+                *counted.entry(inode_id).or_default() += 1;
+            }
+        }
+    }
+
+    for (&dir_id, scan) in &dirs {
+        if scan.dot != Some(dir_id) {
+            report.problems.push(Problem::BadDot {
+                dir: dir_id,
+                found: scan.dot,
+            });
+        }
+        // 没有被任何目录引用的目录只会反映在链接数上
+        if let Some(&expected) = parents.get(&dir_id) {
+            if scan.dot_dot != Some(expected) {
+                report.problems.push(Problem::BadDotDot {
+                    dir: dir_id,
+                    found: scan.dot_dot,
+                    expected,
+                });
+            }
+        }
+    }
+
+    for (&inode_id, &hard_links) in &recorded {
+        let counted = counted.get(&inode_id).copied().unwrap_or(0);
+        if counted != hard_links as usize {
+            report.problems.push(Problem::LinkCount {
+                inode_id,
+                recorded: hard_links,
+                counted,
+            });
+        }
+    }
+    report
+}
+
+fn scan_dir(dir: &Dir) -> DirScan {
+    let mut scan = DirScan {
+        entries: Vec::new(),
+        dot: None,
+        dot_dot: None,
+    };
+    for (_, entry) in dir.split() {
+        if entry.is_unused() {
+            continue;
+        }
+        match entry.name_bytes() {
+            b"." => scan.dot = Some(entry.inode_id()),
+            b".." => scan.dot_dot = Some(entry.inode_id()),
+            name => scan
+                .entries
+                .push((String::from_utf8_lossy(name).to_string(), entry.inode_id())),
+        }
+    }
+    scan
+}
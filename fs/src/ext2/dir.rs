@@ -1,7 +1,9 @@
-use core::fmt::Debug;
+use core::{fmt::Debug, ops::ControlFlow};
 
 use alloc::{
     boxed::Box,
+    collections::BTreeSet,
+    format,
     string::{String, ToString},
     sync::Arc,
     vec::Vec,
@@ -9,15 +11,18 @@ use alloc::{
 use spin::Mutex;
 
 use crate::{
-    block, cast, cast_mut, ceil,
+    block, block_device, cast, cast_mut, ceil, time, try_cast,
     vfs::{
         error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult},
-        meta::VfsFileType,
-        VfsDirEntry, VfsInode, VfsPath,
+        meta::{VfsFileType, VfsMetadata, VfsPermission},
+        DirEntryPlus, VfsDirEntry, VfsInode, VfsPath,
     },
 };
 
-use super::{allocator::Ext2Allocator, disk_inode::Ext2Inode, inode::Inode, layout::Ext2Layout};
+use super::{
+    allocator::Ext2Allocator, disk_inode::Ext2Inode, inode::Inode, layout::Ext2Layout,
+    metadata::Ext2Metadata, symlink::MAX_SYMLINK_HOPS,
+};
 
 #[repr(C)]
 #[derive(Clone)]
@@ -41,19 +46,20 @@ impl Ext2DirEntry {
 
     pub const MAX_FILE_NAME: usize = u8::MAX as usize;
     // 去掉末尾的 name 留下的长度, 有了它就可用从结构体头偏移到 name 起始处
-    const BARE_LEN: usize = 8;
+    pub(crate) const BARE_LEN: usize = 8;
 
     pub fn build_raw<'a>(
         buffer: &'a mut [u8],
         entry_name: &str,
         inode_id: usize,
         filetype: VfsFileType,
+        align: usize,
     ) -> &'a mut Self {
         let entry = cast_mut!(buffer.as_ptr(), Self);
 
         entry.inode_id = inode_id as u32;
         entry.name_len = entry_name.len() as u8;
-        entry.record_len = ceil!(Self::BARE_LEN + entry.name_len as usize, 4) as u16;
+        entry.record_len = entry.regular_len(align) as u16;
         entry.filetype = match filetype {
             VfsFileType::RegularFile => Self::EXT2_FT_REG_FILE,
             VfsFileType::Directory => Self::EXT2_FT_DIR,
@@ -70,14 +76,22 @@ impl Ext2DirEntry {
         entry
     }
 
+    pub fn inode_id(&self) -> usize {
+        self.inode_id as usize
+    }
+
     pub fn is_unused(&self) -> bool {
         self.inode_id == 0
     }
 
-    // record 理论所占空间
-    pub fn regular_len(&self) -> usize {
-        // 4 字节对齐
-        ceil!(Self::BARE_LEN + self.name_len as usize, 4)
+    // record 理论所占空间, 按 align 字节对齐
+    pub fn regular_len(&self, align: usize) -> usize {
+        ceil!(Self::BARE_LEN + self.name_len as usize, align)
+    }
+
+    // 未经检查的 record_len, 用于校验损坏的目录项
+    pub(crate) fn raw_record_len(&self) -> usize {
+        self.record_len as usize
     }
 
     // record 实际所占空间
@@ -86,15 +100,15 @@ impl Ext2DirEntry {
         self.record_len as usize
     }
 
-    pub fn has_free(&self, needed: usize) -> bool {
+    pub fn has_free(&self, needed: usize, align: usize) -> bool {
         // record_len 至少和 regular_len 一样大
-        (self.record_len() - self.regular_len()) >= needed
+        self.record_len().saturating_sub(self.regular_len(align)) >= needed
     }
 
     // 缩小该 record 所占空间, 返回 (期望空间, 释放空间)
-    pub fn rec_narrow(&mut self) -> (usize, usize) {
+    pub fn rec_narrow(&mut self, align: usize) -> (usize, usize) {
         let old_len = self.record_len();
-        self.record_len = self.regular_len() as u16;
+        self.record_len = self.regular_len(align) as u16;
         (self.record_len(), old_len - self.record_len())
     }
 
@@ -105,9 +119,9 @@ impl Ext2DirEntry {
         old_len
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
+    pub fn as_bytes(&self, align: usize) -> &[u8] {
         unsafe {
-            core::slice::from_raw_parts(self as *const _ as *const u8, self.regular_len() as usize)
+            core::slice::from_raw_parts(self as *const _ as *const u8, self.regular_len(align))
         }
     }
 
@@ -130,6 +144,57 @@ impl Ext2DirEntry {
     }
 }
 
+/// 目录数据中的一条原始记录, 由 decode_dir_records 解析得到, 用于排查目录损坏
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirRecord {
+    /// 记录在目录数据中的字节偏移
+    pub offset: usize,
+    pub inode_id: u32,
+    pub record_len: u16,
+    pub name_len: u8,
+    pub filetype: u8,
+    /// 名字中不是 UTF-8 的字节会被替换
+    pub name: String,
+}
+
+impl core::fmt::Display for DirRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:>6}: inode={} rec_len={} name_len={} type={} name={:?}",
+            self.offset, self.inode_id, self.record_len, self.name_len, self.filetype, self.name
+        )
+    }
+}
+
+/// 逐条解析原始目录数据. 遇到放不下的记录头、rec_len 过小或越界时停止,
+/// 名字超出记录的部分被截断, 因此损坏的数据也不会 panic
+pub fn decode_dir_records(raw: &[u8]) -> Vec<DirRecord> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + Ext2DirEntry::BARE_LEN <= raw.len() {
+        let header = &raw[offset..offset + Ext2DirEntry::BARE_LEN];
+        let record_len = u16::from_le_bytes([header[4], header[5]]);
+        let name_len = header[6];
+        let end = offset + record_len as usize;
+        if (record_len as usize) < Ext2DirEntry::BARE_LEN || end > raw.len() {
+            break;
+        }
+        let name_start = offset + Ext2DirEntry::BARE_LEN;
+        let name_end = (name_start + name_len as usize).min(end);
+        records.push(DirRecord {
+            offset,
+            inode_id: u32::from_le_bytes(header[0..4].try_into().unwrap()),
+            record_len,
+            name_len,
+            filetype: header[7],
+            name: String::from_utf8_lossy(&raw[name_start..name_end]).into_owned(),
+        });
+        offset = end;
+    }
+    records
+}
+
 pub struct DirEntry {
     name: String,
     inode_id: usize,
@@ -180,9 +245,101 @@ impl VfsDirEntry for DirEntry {
     }
 }
 
+/// Inode::read_dir_iter 返回的迭代器, 按需逐块读取目录.
+/// ext2 的目录项不会跨块, 因此每个块可以单独解析
+pub struct ReadDirIter {
+    inode: Inode,
+    // 当前块的数据
+    block: Vec<u8>,
+    // 下一个要读的块
+    next_block: usize,
+    blocks: usize,
+    // 下一条记录在当前块中的偏移, 等于块大小时需要读下一块
+    offset: usize,
+    done: bool,
+}
+
+impl ReadDirIter {
+    fn new(inode: Inode) -> Self {
+        // 与 Dir::from_inode 一样, 末尾不完整的块直接舍去
+        let blocks = inode.size() / block::SIZE;
+        Self {
+            inode,
+            block: alloc::vec![0; block::SIZE],
+            next_block: 0,
+            blocks,
+            offset: block::SIZE,
+            done: false,
+        }
+    }
+
+    fn corrupted(&mut self, msg: &str) -> VfsError {
+        self.done = true;
+        let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+        self.inode.layout().error(err.with_additional(format!(
+            "{} at block {} offset {}",
+            msg,
+            self.next_block - 1,
+            self.offset
+        )))
+    }
+}
+
+impl Iterator for ReadDirIter {
+    type Item = VfsResult<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if self.offset >= block::SIZE {
+                if self.next_block >= self.blocks {
+                    self.done = true;
+                    break;
+                }
+                let start = self.next_block * block::SIZE;
+                let block = &mut self.block;
+                self.inode
+                    .read_disk_inode(|ext2_inode| ext2_inode.read_at(start, block));
+                self.next_block += 1;
+                self.offset = 0;
+            }
+
+            let Some(entry) = try_cast::<Ext2DirEntry>(&self.block[self.offset..]) else {
+                return Some(Err(self.corrupted("truncated dir entry")));
+            };
+            let rec_len = entry.record_len as usize;
+            if rec_len < Ext2DirEntry::BARE_LEN || self.offset + rec_len > block::SIZE {
+                return Some(Err(self.corrupted("bad rec_len")));
+            }
+            // 空闲记录只占位, 不是目录项
+            if entry.is_unused() {
+                self.offset += rec_len;
+                continue;
+            }
+            // 名字必须落在记录之内, 否则 name_bytes 会读出块外
+            if Ext2DirEntry::BARE_LEN + entry.name_len as usize > rec_len {
+                return Some(Err(self.corrupted("name_len exceeds rec_len")));
+            }
+            // ext2 的名字是任意字节, 不是 UTF-8 的部分被替换
+            let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+            let inode_id = entry.inode_id();
+            self.offset += rec_len;
+            return Some(Ok(DirEntry::new(
+                inode_id,
+                self.inode.inode_id(),
+                name,
+                self.inode.layout(),
+                self.inode.allocator(),
+            )));
+        }
+        None
+    }
+}
+
 pub struct Dir {
     inode_id: usize,
     buffer: Vec<u8>,
+    // 目录项的对齐字节数
+    align: usize,
     layout: Arc<Ext2Layout>,
     allocator: Arc<Mutex<Ext2Allocator>>,
 }
@@ -194,11 +351,14 @@ impl Dir {
         layout: Arc<Ext2Layout>,
         allocator: Arc<Mutex<Ext2Allocator>>,
     ) -> Self {
-        let mut buffer = alloc::vec![0; ext2_inode.size()];
+        // 目录大小总是块的整数倍, 损坏时末尾不完整的块中的目录项无法可靠解析, 直接舍去
+        let size = ext2_inode.size() / block::SIZE * block::SIZE;
+        let mut buffer = alloc::vec![0; size];
         ext2_inode.read_at(0, &mut buffer);
         Self {
             inode_id,
             buffer,
+            align: layout.dir_align(),
             layout,
             allocator,
         }
@@ -226,8 +386,12 @@ impl Dir {
     pub(crate) fn entries(&self) -> Vec<DirEntry> {
         let mut entries = Vec::new();
         for (_, entry) in self.split() {
+            // 空闲记录只占位, 不是目录项
+            if entry.is_unused() {
+                continue;
+            }
             let entry_id = entry.inode_id as usize;
-            let name = String::from_utf8(entry.name_bytes().to_vec()).unwrap();
+            let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
             entries.push(DirEntry::new(
                 entry_id,
                 self.inode_id(),
@@ -239,19 +403,39 @@ impl Dir {
         entries
     }
 
-    fn split(&self) -> Vec<(usize, &Ext2DirEntry)> {
+    // 按名字找目录项, 返回 (inode 号, 目录项中记录的类型)
+    fn lookup(&self, entry_name: &str) -> Option<(usize, u8)> {
+        self.split()
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .find(|entry| !entry.is_unused() && entry.name_bytes() == entry_name.as_bytes())
+            .map(|entry| (entry.inode_id as usize, entry.filetype))
+    }
+
+    pub(crate) fn split(&self) -> Vec<(usize, &Ext2DirEntry)> {
         self.split_mut()
             .into_iter()
             .map(|(index, entry)| (index, entry as &Ext2DirEntry))
             .collect()
     }
 
+    #[allow(clippy::mut_from_ref)]
     fn split_mut(&self) -> Vec<(usize, &mut Ext2DirEntry)> {
         let mut offset = 0;
         let mut slice = Vec::new();
         while offset < self.buffer.len() {
+            // 剩余空间放不下一个目录项, rec_len 过小或越过末尾, 名字超出记录, 都说明目录已损坏, 不再往下解析
+            if try_cast::<Ext2DirEntry>(&self.buffer[offset..]).is_none() {
+                break;
+            }
             let entry = cast_mut!(self.buffer.as_ptr().add(offset), Ext2DirEntry);
             let rec_len = entry.record_len as usize;
+            if rec_len < Ext2DirEntry::BARE_LEN
+                || offset + rec_len > self.buffer.len()
+                || Ext2DirEntry::BARE_LEN + entry.name_len as usize > rec_len
+            {
+                break;
+            }
             slice.push((offset, entry));
             offset += rec_len;
         }
@@ -259,14 +443,15 @@ impl Dir {
     }
 
     fn place_entry(&mut self, offset: usize, entry: &Ext2DirEntry) {
-        let dst = &mut self.buffer[offset..offset + entry.regular_len()];
-        let src = entry.as_bytes();
+        let dst = &mut self.buffer[offset..offset + entry.regular_len(self.align)];
+        let src = entry.as_bytes(self.align);
         dst.copy_from_slice(src);
     }
 
     fn insert_entry(&mut self, entry_name: &str, inode_id: usize, filetype: VfsFileType) {
         let mut buffer = alloc::vec![0u8; block::SIZE];
-        let new_entry = Ext2DirEntry::build_raw(&mut buffer, entry_name, inode_id, filetype);
+        let new_entry =
+            Ext2DirEntry::build_raw(&mut buffer, entry_name, inode_id, filetype, self.align);
 
         if self.is_empty() {
             new_entry.rec_expand(block::SIZE);
@@ -274,20 +459,38 @@ impl Dir {
             return;
         }
 
+        if !self.try_insert(new_entry) {
+            // 到此处说明 dir 没有空间可用, 需要扩容
+            self.grow();
+            assert!(self.try_insert(new_entry));
+        }
+    }
+
+    // 在已有空间中放入 new_entry, 优先复用空闲记录, 其次拆分有富余的记录
+    fn try_insert(&mut self, new_entry: &mut Ext2DirEntry) -> bool {
+        let needed = new_entry.regular_len(self.align);
         for (offset, entry) in self.split_mut() {
-            if entry.has_free(new_entry.regular_len()) {
-                let (new_len, freed) = entry.rec_narrow();
+            if entry.is_unused() && entry.record_len() >= needed {
+                new_entry.rec_expand(entry.record_len());
+                self.place_entry(offset, new_entry);
+                return true;
+            }
+            if entry.has_free(needed, self.align) {
+                let (new_len, freed) = entry.rec_narrow(self.align);
                 new_entry.rec_expand(freed);
                 self.place_entry(offset + new_len, new_entry);
-                return;
+                return true;
             }
         }
+        false
+    }
 
-        // 到此处说明 dir 没有空间可用, 需要扩容
+    // 扩容一个块, 新块中只有一个跨越整个块的空闲记录 (inode 为 0), 保证新块总能被正确解析
+    fn grow(&mut self) {
         let old_len = self.buffer.len();
         self.buffer.extend(alloc::vec![0u8; block::SIZE]);
-        new_entry.rec_expand(block::SIZE);
-        self.place_entry(old_len, new_entry);
+        let sentinel = cast_mut!(self.buffer.as_ptr().add(old_len), Ext2DirEntry);
+        sentinel.rec_expand(block::SIZE);
     }
 
     fn remove_entry(&mut self, entry_name: &str) {
@@ -309,10 +512,27 @@ impl Dir {
         }
     }
 
+    // 让名为 entry_name 的目录项指向 inode_id
+    fn redirect_entry(&mut self, entry_name: &str, inode_id: usize) {
+        for (_, entry) in self.split_mut() {
+            if !entry.is_unused() && entry.name_bytes() == entry_name.as_bytes() {
+                entry.inode_id = inode_id as u32;
+                return;
+            }
+        }
+    }
+
     /// | prev | current         | other | => | current             | other |
     fn move_to_prev(&mut self, prev_offset: usize, cur_offset: usize) {
         assert_eq!(0, prev_offset % block::SIZE);
-        let prev_entry = cast!(self.buffer.as_ptr().add(prev_offset), Ext2DirEntry);
+        let prev_entry = cast_mut!(self.buffer.as_ptr().add(prev_offset), Ext2DirEntry);
+        // 被删除的是块中唯一的记录, 把它变回空闲记录
+        if cur_offset.is_multiple_of(block::SIZE) {
+            prev_entry.inode_id = 0;
+            prev_entry.name_len = 0;
+            prev_entry.filetype = Ext2DirEntry::EXT2_FT_UNKNOWN;
+            return;
+        }
         let cur_entry = cast_mut!(self.buffer.as_ptr().add(cur_offset), Ext2DirEntry);
         if cur_entry.is_unused() {
             return;
@@ -331,47 +551,202 @@ impl Inode {
         }
 
         Ok(self
-            .inner_read_dir()
+            .inner_read_dir()?
             .into_iter()
             .map(|x| Box::new(x) as Box<dyn VfsDirEntry>)
             .collect())
     }
 
-    fn inner_read_dir(&self) -> Vec<DirEntry> {
+    // 读目录的同时取出每个子项的元数据, 每个子 inode 只读一次
+    pub fn read_dir_plus(&self) -> VfsResult<Vec<DirEntryPlus>> {
+        if !self.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory).into());
+        }
+
+        Ok(self
+            .inner_read_dir()?
+            .into_iter()
+            .map(|entry| {
+                let address = self.layout().inode_address(entry.inode_id());
+                let metadata = block_device::read(
+                    address.block_id(),
+                    address.offset(),
+                    |ext2_inode: &Ext2Inode| Ext2Metadata::from(ext2_inode),
+                );
+                (
+                    entry.name,
+                    entry.inode_id,
+                    Box::new(metadata) as Box<dyn VfsMetadata>,
+                )
+            })
+            .collect())
+    }
+
+    /// 逐块读取目录项, 任何时候都只有一个块在内存中.
+    /// 遇到损坏的目录项时产生一个 Corrupted 错误, 之后结束
+    pub fn read_dir_iter(&self) -> VfsResult<ReadDirIter> {
+        if !self.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory).into());
+        }
+        Ok(ReadDirIter::new(self.clone()))
+    }
+
+    /// 对每个目录项调用 f, f 返回 Break 时立即停止并返回其中的值, 不再读后面的块.
+    /// 遍历完整个目录返回 None, 遇到损坏的目录项返回 Corrupted 错误
+    pub fn for_each_entry<B>(
+        &self,
+        mut f: impl FnMut(&DirEntry) -> ControlFlow<B>,
+    ) -> VfsResult<Option<B>> {
+        for entry in self.read_dir_iter()? {
+            if let ControlFlow::Break(value) = f(&entry?) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    // 遇到损坏的目录项时产生 Corrupted 错误, 调用者不能把它当作目录已经结束
+    fn dir_entries(&self) -> impl Iterator<Item = VfsResult<DirEntry>> {
         assert!(self.is_dir());
+        ReadDirIter::new(self.clone())
+    }
 
-        self.read_disk_inode(|ext2_inode| {
-            let dir = Dir::from_inode(self.inode_id(), ext2_inode, self.layout(), self.allocator());
-            dir.entries()
-        })
+    fn inner_read_dir(&self) -> VfsResult<Vec<DirEntry>> {
+        self.dir_entries().collect()
+    }
+
+    // 递归列出目录下的所有子项(不含自身), 不跟随 symlink.
+    // 损坏的镜像中目录硬链接可能构成环, 因此记录走过的目录, 重复进入时报 Corrupted
+    pub fn walk_dir(&self, path: &VfsPath) -> VfsResult<Vec<(VfsPath, Inode)>> {
+        if !self.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory)
+                .with_path(path)
+                .into());
+        }
+
+        let mut visited = BTreeSet::from([self.inode_id()]);
+        let mut paths = Vec::new();
+        self.inner_walk_dir(path, &mut visited, &mut paths)?;
+        Ok(paths)
+    }
+
+    fn inner_walk_dir(
+        &self,
+        path: &VfsPath,
+        visited: &mut BTreeSet<usize>,
+        paths: &mut Vec<(VfsPath, Inode)>,
+    ) -> VfsResult<()> {
+        for entry in self.inner_read_dir().map_err(|err| err.with_path(path))? {
+            // 跳过 . 和 .. 以及已被删除的项
+            if entry.name == "." || entry.name == ".." || entry.inode_id == 0 {
+                continue;
+            }
+
+            let mut child_path = path.clone();
+            child_path.push(&entry.name);
+            let child = self
+                .layout()
+                .inode_nth(entry.inode_id, self.layout(), self.allocator())
+                .with_parent(self.inode_id());
+
+            if child.is_dir() {
+                if !visited.insert(child.inode_id()) {
+                    let err =
+                        VfsError::from(IOError::new(IOErrorKind::Corrupted).with_path(&child_path));
+                    return Err(self
+                        .layout()
+                        .error(err.with_additional("directory cycle detected")));
+                }
+                child.inner_walk_dir(&child_path, visited, paths)?;
+            }
+            paths.push((child_path, child));
+        }
+        Ok(())
+    }
+
+    // 只凭目录项判断 path 是否存在: 中间的目录不构造 Inode, 终点的 inode 也不读.
+    // 目录项没有记录类型, 遇到 symlink 或需要检查搜索权限时交给 walk
+    pub(crate) fn exists(&self, path: &VfsPath) -> bool {
+        let layout = self.layout();
+        if layout.credentials().is_some() {
+            return self.walk(path).is_ok();
+        }
+        let mut dir_id = self.inode_id();
+        let mut is_dir = self.is_dir();
+        for next in path.iter() {
+            if !is_dir {
+                return false;
+            }
+            let address = layout.inode_address(dir_id);
+            let found = block_device::read(
+                address.block_id(),
+                address.offset(),
+                |ext2_inode: &Ext2Inode| {
+                    Dir::from_inode(dir_id, ext2_inode, layout.clone(), self.allocator())
+                        .lookup(next)
+                },
+            );
+            match found {
+                None => return false,
+                Some((_, Ext2DirEntry::EXT2_FT_UNKNOWN | Ext2DirEntry::EXT2_FT_SYMLINK)) => {
+                    return self.walk(path).is_ok()
+                }
+                Some((inode_id, filetype)) => {
+                    dir_id = inode_id;
+                    is_dir = filetype == Ext2DirEntry::EXT2_FT_DIR;
+                }
+            }
+        }
+        true
     }
 
     // 从 path 一直走到终点, 遇到 symlink 也解析并继续走
     pub(crate) fn walk(&self, path: &VfsPath) -> VfsResult<Inode> {
-        let last = self.goto_last(path)?;
+        self.walk_counted(path, &mut 0)
+    }
+
+    // hops 是这次解析中已经跟随过的 symlink 数, 在所有嵌套的解析之间共享
+    fn walk_counted(&self, path: &VfsPath, hops: &mut usize) -> VfsResult<Inode> {
+        let last = self.goto_last_counted(path, hops)?;
         if last.is_symlink() {
-            let parent_last = last.parent_inode();
-            parent_last.walk(&last.symlink_target(path)?)
+            last.follow_symlink(path, hops)
         } else {
             Ok(last)
         }
     }
 
-    fn goto_last(&self, path: &VfsPath) -> VfsResult<Inode> {
+    // 解析 symlink 本身: 绝对目标从根开始走, 相对目标从 symlink 所在的目录开始走.
+    // 与 Linux 一样最多跟随 MAX_SYMLINK_HOPS 次, 否则 symlink 成环时会一直走下去
+    fn follow_symlink(&self, path: &VfsPath, hops: &mut usize) -> VfsResult<Inode> {
+        *hops += 1;
+        if *hops > MAX_SYMLINK_HOPS {
+            return Err(IOError::new(IOErrorKind::TooManySymlinks)
+                .with_path(path)
+                .into());
+        }
+        let target = self.symlink_target(path)?;
+        if target.is_from_root() {
+            let root = self.layout().root_inode(self.layout(), self.allocator());
+            root.walk_counted(&target, hops)
+        } else {
+            self.parent_inode().walk_counted(&target, hops)
+        }
+    }
+
+    // 走到 path 的终点, 终点本身是 symlink 时不解析
+    pub(crate) fn goto_last(&self, path: &VfsPath) -> VfsResult<Inode> {
+        self.goto_last_counted(path, &mut 0)
+    }
+
+    fn goto_last_counted(&self, path: &VfsPath, hops: &mut usize) -> VfsResult<Inode> {
         let mut current_inode = self.clone();
         let mut next_path = VfsPath::empty(path.is_from_root());
         for next in path.iter() {
             next_path.push(next);
 
+            // 中间的 symlink 必须先解析成目录才能继续往下走
             if current_inode.is_symlink() {
-                let parent = current_inode.parent_inode();
-                let symlink_path = current_inode.symlink_target(path)?;
-                if symlink_path.is_from_root() {
-                    let root = self.layout().root_inode(self.layout(), self.allocator());
-                    current_inode = root.walk(&symlink_path)?;
-                } else {
-                    current_inode = parent.walk(&symlink_path)?;
-                }
+                current_inode = current_inode.follow_symlink(path, hops)?;
             }
 
             if !current_inode.is_dir() {
@@ -380,6 +755,10 @@ impl Inode {
                     .into());
             }
 
+            // 进入目录需要搜索权限
+            current_inode
+                .check_permission(VfsPermission::execute)
+                .map_err(|err| err.with_path(&next_path))?;
             current_inode = current_inode
                 .select_child(next)
                 .map_err(|err| err.with_path(&next_path))?;
@@ -387,8 +766,8 @@ impl Inode {
         Ok(current_inode)
     }
 
-    fn child_inode(&self, entries: &[DirEntry], entry_name: &str) -> VfsResult<Inode> {
-        let chosen = Self::find_single(entries, entry_name);
+    pub(crate) fn select_child(&self, entry_name: &str) -> VfsResult<Inode> {
+        let chosen = self.find_single(entry_name)?;
         if chosen.is_none() {
             return Err(IOError::new(IOErrorKind::NotFound).into());
         }
@@ -399,28 +778,25 @@ impl Inode {
             .with_parent(self.inode_id()))
     }
 
-    pub(crate) fn select_child(&self, entry_name: &str) -> VfsResult<Inode> {
-        assert!(self.is_dir());
-        let entries = self.inner_read_dir();
-        self.child_inode(&entries, entry_name)
-    }
-
-    fn find_single<'a>(entries: &'a [DirEntry], entry_name: &str) -> Option<&'a DirEntry> {
+    // 同名的目录项只能有一个, 出现多个说明镜像已损坏
+    fn find_single(&self, entry_name: &str) -> VfsResult<Option<DirEntry>> {
         let mut found_entry = None;
 
-        for entry in entries {
+        for entry in self.dir_entries() {
+            let entry = entry?;
             if entry.name() == entry_name {
                 if found_entry.is_some() {
-                    panic!(
-                        "Multiple entries found with filename: {}, entries: {:#?}",
-                        entry_name, entries
-                    );
+                    let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+                    return Err(self.layout().error(err.with_additional(format!(
+                        "multiple entries found with filename: {}",
+                        entry_name
+                    ))));
                 }
                 found_entry = Some(entry);
             }
         }
 
-        found_entry
+        Ok(found_entry)
     }
 
     fn check_valid_insert(&self, path: &VfsPath) -> VfsResult<()> {
@@ -436,8 +812,9 @@ impl Inode {
         }
 
         let filename = filename.unwrap();
-        let entries = self.inner_read_dir();
-        let chosen = Self::find_single(&entries, filename);
+        let chosen = self
+            .find_single(filename)
+            .map_err(|err| err.with_path(path))?;
         if chosen.is_some() {
             return Err(IOError::new(IOErrorKind::AlreadyExists)
                 .with_path(path)
@@ -450,7 +827,8 @@ impl Inode {
                 .into());
         }
 
-        Ok(())
+        self.check_modify_entries()
+            .map_err(|err| err.with_path(path))
     }
 
     fn check_valid_remove(&self, path: &VfsPath) -> VfsResult<()> {
@@ -465,15 +843,22 @@ impl Inode {
         }
 
         let filename = filename.unwrap();
-        let entries = self.inner_read_dir();
-        let chosen = Self::find_single(&entries, filename);
+        let chosen = self
+            .find_single(filename)
+            .map_err(|err| err.with_path(path))?;
 
         // 如果没有该 entry
         if chosen.is_none() {
             return Err(IOError::new(IOErrorKind::NotFound).with_path(path).into());
         }
 
-        Ok(())
+        self.check_modify_entries()
+            .map_err(|err| err.with_path(path))
+    }
+
+    // 增删目录项需要对目录同时有写权限和搜索权限
+    fn check_modify_entries(&self) -> VfsResult<()> {
+        self.check_permission(|permission| permission.write() && permission.execute())
     }
 
     // 该函数不会设置权限
@@ -493,7 +878,15 @@ impl Inode {
         match filetype {
             VfsFileType::RegularFile => self.insert_file_entry(entry_name),
             VfsFileType::Directory => self.insert_dir_entry(entry_name),
-            _ => todo!("why got {}", filetype),
+            // 符号链接需要目标, 走 insert_symlink
+            VfsFileType::SymbolicLink => {
+                let err: VfsError = VfsErrorKind::NotSupported.into();
+                Err(err.with_additional("use insert_symlink to create symlinks"))
+            }
+            _ => {
+                let err: VfsError = VfsErrorKind::NotSupported.into();
+                Err(err.with_additional(format!("create {}", filetype)))
+            }
         }
     }
 
@@ -520,6 +913,47 @@ impl Inode {
         Ok(Box::new(inode))
     }
 
+    /// 在当前目录下一次新建多个空的普通文件, 返回它们的 inode 号.
+    /// paths 的最后一级为文件名. inode 一次性分配; 中途目录扩容失败时已建好的文件保留, 剩下的 inode 归还
+    pub fn insert_files(&mut self, paths: &[VfsPath]) -> VfsResult<Vec<usize>> {
+        let mut seen = BTreeSet::new();
+        for path in paths {
+            self.check_valid_insert(path)?;
+            if !seen.insert(path.last().unwrap()) {
+                return Err(IOError::new(IOErrorKind::AlreadyExists)
+                    .with_path(path)
+                    .into());
+            }
+        }
+
+        let inode_ids = self.allocator().lock().alloc_inodes(paths.len(), false)?;
+        for (idx, (path, &inode_id)) in paths.iter().zip(&inode_ids).enumerate() {
+            let name = path.last().unwrap();
+            let inode_id = inode_id as usize;
+            let inode = self.layout().new_inode_nth(
+                inode_id,
+                VfsFileType::RegularFile,
+                self.layout(),
+                self.allocator(),
+            );
+            let inserted = self.modify_disk_inode(|ext2_inode| {
+                let mut dir =
+                    Dir::from_inode(self.inode_id(), ext2_inode, self.layout(), self.allocator());
+                dir.insert_entry(name, inode_id, VfsFileType::RegularFile);
+                dir.write_to_disk(ext2_inode)
+            });
+            if let Err(err) = inserted {
+                self.free_inode(&inode, false)?;
+                for &unused in &inode_ids[idx + 1..] {
+                    self.allocator().lock().dealloc_inode(unused, false)?;
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(inode_ids.into_iter().map(|id| id as usize).collect())
+    }
+
     /// 1. 申请一个 Inode
     /// 2. 在 dirname 下新建两个目录项, 分别是 . 和 .., 注意硬链接变化
     /// 3. 在目录中创建一个目录项
@@ -532,16 +966,11 @@ impl Inode {
             self.allocator(),
         );
 
-        self.modify_disk_inode(|ext2_inode| {
-            let mut dir =
-                Dir::from_inode(self.inode_id(), ext2_inode, self.layout(), self.allocator());
-            // 建立 entry_name -> inode_id 的映射关系
-            dir.insert_entry(dirname, inode_id, VfsFileType::Directory);
-            // 写回磁盘
-            dir.write_to_disk(ext2_inode)
-        })?;
-
-        dir_inode.increase_to(block::SIZE)?;
+        // 先把新目录初始化好再挂到父目录下, 失败时父目录中不会留下指向半成品的目录项
+        if let Err(err) = dir_inode.increase_to(block::SIZE) {
+            self.free_inode(&dir_inode, true)?;
+            return Err(err);
+        }
         dir_inode.modify_disk_inode(|ext2_inode| {
             let mut dir = Dir::from_inode(inode_id, ext2_inode, self.layout(), self.allocator());
             // 建立 . -> inode_id 的映射关系
@@ -554,12 +983,27 @@ impl Inode {
             dir.write_to_disk(ext2_inode)
         })?;
 
+        self.modify_disk_inode(|ext2_inode| {
+            let mut dir =
+                Dir::from_inode(self.inode_id(), ext2_inode, self.layout(), self.allocator());
+            // 建立 entry_name -> inode_id 的映射关系
+            dir.insert_entry(dirname, inode_id, VfsFileType::Directory);
+            // 写回磁盘
+            dir.write_to_disk(ext2_inode)
+        })?;
+
+        // 新目录的硬链接数为 2: 父目录中的名字 (init 时已计入) + 自身的 "."
         dir_inode.modify_disk_inode(|ext2_inode| {
             ext2_inode.inc_hard_links();
         });
+        // 新目录的 ".." 使父目录硬链接加 1
         self.modify_disk_inode(|ext2_inode| {
             ext2_inode.inc_hard_links();
         });
+        debug_assert_eq!(
+            dir_inode.read_disk_inode(|ext2_inode| ext2_inode.hard_links()),
+            2
+        );
 
         Ok(Box::new(dir_inode))
     }
@@ -586,9 +1030,14 @@ impl Inode {
 
     /// to 可能会不存在, 因此不能返回 to 的 inode,
     /// 另外也不能返回 Symlink 的 Inode, 因为这对用户没有意义
-    pub fn insert_symlink(&mut self, path_from: &VfsPath, path_to: &VfsPath) -> VfsResult<()> {
+    pub fn insert_symlink(&mut self, path_from: &VfsPath, path_to: &str) -> VfsResult<()> {
         self.check_valid_insert(path_from)?;
         let filename = path_from.last().unwrap();
+        if filename.len() > u8::MAX as usize {
+            return Err(IOError::new(IOErrorKind::TooLongFileName)
+                .with_path(path_from)
+                .into());
+        }
         let inode_id = self.allocator().lock().alloc_inode(false)? as usize;
         let mut inode = self.layout().new_inode_nth(
             inode_id,
@@ -596,7 +1045,11 @@ impl Inode {
             self.layout(),
             self.allocator(),
         );
-        inode.write_symlink(path_to)?;
+        // 目标没能写入则归还 inode, 不留下悬空的 inode
+        if let Err(err) = inode.write_symlink(path_to) {
+            self.free_inode(&inode, false)?;
+            return Err(err);
+        }
 
         self.modify_disk_inode(|ext2_inode| {
             let mut dir =
@@ -628,13 +1081,214 @@ impl Inode {
         Ok(())
     }
 
-    pub fn remove_entry(&mut self, path: &VfsPath) -> VfsResult<()> {
+    /// 删除非目录的目录项, 目标是目录时返回 IsADirectory.
+    /// 返回目标剩下的硬链接数, 为 0 说明 inode 已被回收
+    pub fn remove_file(&mut self, path: &VfsPath) -> VfsResult<u16> {
+        self.check_valid_remove(path)?;
+        let target_inode = self.select_child(path.last().unwrap())?;
+        if target_inode.filetype().is_dir() {
+            return Err(IOError::new(IOErrorKind::IsADirectory)
+                .with_path(path)
+                .into());
+        }
+        self.check_sticky(&target_inode)
+            .map_err(|err| err.with_path(path))?;
+        // 与 Linux 一致, 追加写的文件也不能删除
+        let protected = target_inode
+            .read_disk_inode(|ext2_inode| ext2_inode.is_immutable() || ext2_inode.is_append_only());
+        if protected {
+            return Err(IOError::new(IOErrorKind::PermissionDenied)
+                .with_path(path)
+                .into());
+        }
+        self.remove_entry(path)
+    }
+
+    /// 删除空目录, 除了 "." 和 ".." 还有其他目录项时返回 DirectoryNotEmpty
+    pub fn remove_dir(&mut self, path: &VfsPath) -> VfsResult<()> {
+        self.check_valid_remove(path)?;
+        let target_inode = self.select_child(path.last().unwrap())?;
+        if !target_inode.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory)
+                .with_path(path)
+                .into());
+        }
+        let has_children = target_inode
+            .inner_read_dir()
+            .map_err(|err| err.with_path(path))?
+            .iter()
+            .any(|entry| entry.name() != "." && entry.name() != "..");
+        if has_children {
+            return Err(IOError::new(IOErrorKind::DirectoryNotEmpty)
+                .with_path(path)
+                .into());
+        }
+        self.check_sticky(&target_inode)
+            .map_err(|err| err.with_path(path))?;
+        self.remove_entry(path)?;
+        Ok(())
+    }
+
+    /// 把当前目录下的 old_path 移到 new_dir 下, 名字为 new_path 的最后一级.
+    /// 目标已存在时先删除; 两者是同一个 inode 时什么也不做.
+    /// 跨目录移动目录时改写它的 "..", 并调整两个父目录的硬链接数
+    pub fn rename(
+        &mut self,
+        old_path: &VfsPath,
+        new_dir: &mut Inode,
+        new_path: &VfsPath,
+    ) -> VfsResult<()> {
+        self.check_valid_remove(old_path)?;
+        if !new_dir.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory)
+                .with_path(new_path)
+                .into());
+        }
+        new_dir
+            .check_modify_entries()
+            .map_err(|err| err.with_path(new_path))?;
+        let (old_name, new_name) = match (old_path.last(), new_path.last()) {
+            (Some(old_name), Some(new_name)) => (old_name, new_name),
+            _ => return Err(VfsErrorKind::InvalidPath(new_path.to_string()).into()),
+        };
+        for (name, path) in [(old_name, old_path), (new_name, new_path)] {
+            if name == "." || name == ".." {
+                return Err(VfsError::new(
+                    path,
+                    VfsErrorKind::InvalidPath(path.to_string()),
+                    "Forbidden to rename '.' or '..'".to_string(),
+                ));
+            }
+        }
+        if new_name.len() > Ext2DirEntry::MAX_FILE_NAME {
+            return Err(IOError::new(IOErrorKind::TooLongFileName)
+                .with_path(new_path)
+                .into());
+        }
+
+        let source = self.select_child(old_name)?;
+        self.check_sticky(&source)
+            .map_err(|err| err.with_path(old_path))?;
+        // 不可变和追加写的 inode 都不能改名
+        source
+            .check_modifiable(None)
+            .map_err(|err| err.with_path(old_path))?;
+        if source.is_dir() {
+            // 目录不能移到自己或自己的子孙目录下, 沿着 ".." 向上检查
+            let mut ancestor = new_dir.clone();
+            loop {
+                if ancestor.inode_id() == source.inode_id() {
+                    let err: VfsError = VfsErrorKind::InvalidPath(new_path.to_string()).into();
+                    return Err(err.with_additional("cannot move a directory into itself"));
+                }
+                let parent = ancestor.select_child("..")?;
+                if parent.inode_id() == ancestor.inode_id() {
+                    break;
+                }
+                ancestor = parent;
+            }
+        }
+
+        let existing = new_dir
+            .find_single(new_name)
+            .map_err(|err| err.with_path(new_path))?;
+        if let Some(existing) = existing {
+            if existing.inode_id() == source.inode_id() {
+                return Ok(());
+            }
+            let target = existing.inode();
+            target
+                .check_modifiable(None)
+                .map_err(|err| err.with_path(new_path))?;
+            match (source.is_dir(), target.is_dir()) {
+                (true, false) => {
+                    return Err(IOError::new(IOErrorKind::NotADirectory)
+                        .with_path(new_path)
+                        .into())
+                }
+                (false, true) => {
+                    return Err(IOError::new(IOErrorKind::IsADirectory)
+                        .with_path(new_path)
+                        .into())
+                }
+                (true, true) => new_dir.remove_dir(new_path)?,
+                (false, false) => {
+                    new_dir.remove_file(new_path)?;
+                }
+            }
+        }
+
+        // 先建新目录项再删旧的, 中途失败时文件仍然可达
+        new_dir.modify_disk_inode(|ext2_inode| {
+            let mut dir = Dir::from_inode(
+                new_dir.inode_id(),
+                ext2_inode,
+                new_dir.layout(),
+                new_dir.allocator(),
+            );
+            dir.insert_entry(new_name, source.inode_id(), source.filetype());
+            dir.write_to_disk(ext2_inode)
+        })?;
+        self.modify_disk_inode(|ext2_inode| {
+            let mut dir =
+                Dir::from_inode(self.inode_id(), ext2_inode, self.layout(), self.allocator());
+            dir.remove_entry(old_name);
+            dir.write_to_disk(ext2_inode)
+        })?;
+
+        if source.is_dir() && self.inode_id() != new_dir.inode_id() {
+            source.modify_disk_inode(|ext2_inode| {
+                let mut dir = Dir::from_inode(
+                    source.inode_id(),
+                    ext2_inode,
+                    self.layout(),
+                    self.allocator(),
+                );
+                dir.redirect_entry("..", new_dir.inode_id());
+                dir.write_to_disk(ext2_inode)
+            })?;
+            self.modify_disk_inode(|ext2_inode| {
+                ext2_inode.dec_hard_links();
+            });
+            new_dir.modify_disk_inode(|ext2_inode| {
+                ext2_inode.inc_hard_links();
+            });
+        }
+        Ok(())
+    }
+
+    // 粘滞目录中只有目录项的所有者, 目录的所有者和 root 可以删除目录项
+    fn check_sticky(&self, target_inode: &Inode) -> VfsResult<()> {
+        let layout = self.layout();
+        let Some(credentials) = layout.credentials() else {
+            return Ok(());
+        };
+        let (sticky, dir_owner) =
+            self.read_disk_inode(|ext2_inode| (ext2_inode.is_sticky(), ext2_inode.uid()));
+        let owner = target_inode.read_disk_inode(|ext2_inode| ext2_inode.uid());
+        if sticky
+            && credentials.uid != 0
+            && credentials.uid != dir_owner
+            && credentials.uid != owner
+        {
+            return Err(IOError::new(IOErrorKind::PermissionDenied).into());
+        }
+        Ok(())
+    }
+
+    /// 删除目录项并返回目标剩下的硬链接数, 目录总是被整个删除, 返回 0
+    pub fn remove_entry(&mut self, path: &VfsPath) -> VfsResult<u16> {
         self.check_valid_remove(path)?;
         let entry_name = path.last().unwrap();
         let mut target_inode = self.select_child(entry_name)?;
 
         match target_inode.filetype() {
-            VfsFileType::RegularFile => self.remove_file_entry(entry_name, &mut target_inode),
+            // 设备文件, 管道和套接字没有数据块, 与普通文件一样只扣除 hardlink
+            VfsFileType::RegularFile
+            | VfsFileType::CharDev
+            | VfsFileType::BlockDev
+            | VfsFileType::FIFO
+            | VfsFileType::Socket => self.remove_file_entry(entry_name, &mut target_inode),
             VfsFileType::SymbolicLink => self.remove_symlink_entry(entry_name, &mut target_inode),
             VfsFileType::Directory => {
                 if entry_name == "." || entry_name == ".." {
@@ -644,35 +1298,36 @@ impl Inode {
                         "Forbidden to remove '.' or '..'".to_string(),
                     ));
                 }
-                self.remove_dir_entry(entry_name, &mut target_inode)
+                self.remove_dir_entry(entry_name, &mut target_inode)?;
+                Ok(0)
             }
-            filetype => todo!("why got {}", filetype),
         }
     }
 
     /// 扣除 hardlink, 到 0 则释放
-    fn remove_file_entry(&mut self, filename: &str, target_inode: &mut Inode) -> VfsResult<()> {
-        let should_remove = self.unlink(filename, target_inode);
-        if should_remove {
+    fn remove_file_entry(&mut self, filename: &str, target_inode: &mut Inode) -> VfsResult<u16> {
+        let remaining = self.unlink(filename, target_inode);
+        if remaining == 0 {
             // 释放目标文件的存储空间
-            target_inode.set_len(0)?;
+            target_inode.resize(0)?;
             // 释放目标文件对应的 inode, 在 bitmap 上清除位后, 对应的 inode 即不可用
-            self.free_inode(target_inode.inode_id(), false)?;
+            self.free_inode(target_inode, false)?;
         };
-        Ok(())
+        Ok(remaining)
     }
 
-    fn remove_symlink_entry(&mut self, filename: &str, target_inode: &mut Inode) -> VfsResult<()> {
-        // symlink 只需要删除目录项 和 inode 即可
-        let should_remove = self.unlink(filename, &target_inode);
-        if should_remove {
-            self.free_inode(target_inode.inode_id(), false)?;
+    fn remove_symlink_entry(&mut self, filename: &str, target_inode: &mut Inode) -> VfsResult<u16> {
+        // symlink 只需要删除目录项和 inode, 慢速符号链接还要释放数据块
+        let remaining = self.unlink(filename, target_inode);
+        if remaining == 0 {
+            target_inode.release_symlink()?;
+            self.free_inode(target_inode, false)?;
         }
-        Ok(())
+        Ok(remaining)
     }
 
     fn remove_dir_entry(&mut self, dirname: &str, target_inode: &mut Inode) -> VfsResult<()> {
-        let dir_entries = target_inode.inner_read_dir();
+        let dir_entries = target_inode.inner_read_dir()?;
         // 将目标目录下的所有目录项都删除
         for entry in &dir_entries {
             if entry.name() == "." || entry.name() == ".." {
@@ -690,6 +1345,7 @@ impl Inode {
             }
         }
 
+        // 与 insert_dir_entry 对称: 依次撤销 ".", "..", 以及父目录中的名字
         // remove .
         target_inode.modify_disk_inode(|ext2_inode| {
             ext2_inode.dec_hard_links();
@@ -699,19 +1355,19 @@ impl Inode {
             ext2_inode.dec_hard_links();
         });
 
-        let should_remove = self.unlink(dirname, target_inode);
-        assert!(should_remove);
+        let remaining = self.unlink(dirname, target_inode);
+        assert_eq!(remaining, 0);
 
         // 释放目录
-        target_inode.set_len(0)?;
+        target_inode.resize(0)?;
         // 释放目标文件对应的 inode, 在 bitmap 上清除位后, 对应的 inode 即不可用
-        self.free_inode(target_inode.inode_id(), true)?;
+        self.free_inode(target_inode, true)?;
 
         Ok(())
     }
 
     // 在当前 dir 下删除 entry -> target_inode 这一 entry 目录项, 该方法会递减 hardlinks
-    fn unlink(&mut self, entry_name: &str, target_inode: &Inode) -> bool {
+    fn unlink(&mut self, entry_name: &str, target_inode: &Inode) -> u16 {
         assert!(self.is_dir());
         // 删除目录项
         self.modify_disk_inode(|ext2_inode| {
@@ -723,13 +1379,23 @@ impl Inode {
             // remove entry 不可能扩容, 因此可以直接 unwarp
             dir.write_to_disk(ext2_inode).unwrap()
         });
-        // 硬链接减1
-        target_inode.modify_disk_inode(|ext2_inode| ext2_inode.dec_hard_links())
+        // 硬链接减1, 返回剩下的个数
+        target_inode.modify_disk_inode(|ext2_inode| {
+            ext2_inode.dec_hard_links();
+            ext2_inode.hard_links
+        })
     }
 
-    fn free_inode(&self, inode_id: usize, is_dir: bool) -> VfsResult<()> {
+    // 记录删除时间后在 bitmap 上释放 inode.
+    // dtime 为 0 的已删除 inode 会被 e2fsck 报错, 没有时钟时退而使用 superblock 的写入时间
+    fn free_inode(&self, target_inode: &Inode, is_dir: bool) -> VfsResult<()> {
+        let dtime = match time::now() {
+            Some(now) => now as u32,
+            None => self.layout().superblock().lock().wtime.max(1),
+        };
+        target_inode.modify_disk_inode(|ext2_inode| ext2_inode.dtime = dtime);
         self.allocator()
             .lock()
-            .dealloc_inode(inode_id as u32, is_dir)
+            .dealloc_inode(target_inode.inode_id() as u32, is_dir)
     }
 }
@@ -3,7 +3,7 @@ use bitflags::bitflags;
 
 use crate::{
     block::{self, DataBlock},
-    block_device, ceil_index,
+    block_device, ceil_index, time,
     vfs::meta::*,
 };
 
@@ -71,6 +71,12 @@ impl Ext2Inode {
     pub const INDIRECT_BOUND: usize = Self::DIRECT_COUNT + Self::INDIRECT_COUNT;
     pub const DOUBLE_COUNT: usize = Self::INDIRECT_COUNT * Self::INDIRECT_COUNT;
     pub const DOUBLE_BOUND: usize = Self::INDIRECT_BOUND + Self::DOUBLE_COUNT;
+    pub const TRIPLE_COUNT: usize = Self::DOUBLE_COUNT * Self::INDIRECT_COUNT;
+    pub const TRIPLE_BOUND: usize = Self::DOUBLE_BOUND + Self::TRIPLE_COUNT;
+    // 读到离直接块末尾不足这么多块时开始预读
+    const READ_AHEAD_WINDOW: usize = 2;
+    // 预读一级间接块时顺带读入它指向的前几个数据块
+    const READ_AHEAD_BLOCKS: usize = 4;
 
     pub fn init(&mut self, filetype: VfsFileType) {
         unsafe {
@@ -122,21 +128,71 @@ impl Ext2Inode {
 
     pub fn set_permissions(&mut self, permissions: &VfsPermissions) {
         self.type_perm.set_permissions(permissions);
+        // 修改权限属于 inode 的修改, 只更新 ctime
+        self.touch_changed();
     }
 
+    /// 内容被修改: 同时更新 mtime 和 ctime. 没有时钟时保留原有的时间
+    pub fn touch_modified(&mut self) {
+        if let Some(now) = time::now() {
+            self.mtime = now as u32;
+            self.ctime = now as u32;
+        }
+    }
+
+    // 只有 inode 本身被修改: 更新 ctime. 没有时钟时保留原有的时间
+    fn touch_changed(&mut self) {
+        if let Some(now) = time::now() {
+            self.ctime = now as u32;
+        }
+    }
+
+    /// 读取后需要更新的 atime, 设置了 DONT_ATIME, 没有时钟或无需变化时为 None
+    pub fn pending_atime(&self) -> Option<u32> {
+        let now = time::now()? as u32;
+        (!self.flags.contains(Flags::DONT_ATIME) && self.atime != now).then_some(now)
+    }
+
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.flags = flags;
+        self.touch_changed();
+    }
+
+    /// 不可修改也不可删除
+    pub fn is_immutable(&self) -> bool {
+        self.flags.contains(Flags::IMMUTABLE)
+    }
+
+    /// 只能在末尾追加, 不可截断也不可删除
+    pub fn is_append_only(&self) -> bool {
+        self.flags.contains(Flags::APPEND_ONLY)
+    }
+
+    pub fn is_sticky(&self) -> bool {
+        self.type_perm.contains(TypePerm::STICKY)
+    }
+
+    pub fn set_sticky(&mut self, sticky: bool) {
+        self.type_perm.set(TypePerm::STICKY, sticky);
+        self.touch_changed();
+    }
+
+    // 普通文件的 size_high 是大小的高 32 位, 目录的 size_high 则是 dir_acl
     pub fn size(&self) -> usize {
         if self.filetype().is_file() {
-            assert_eq!(self.size_high, 0);
+            (self.size_low as u64 | (self.size_high as u64) << 32) as usize
+        } else {
+            self.size_low as usize
         }
-        self.sectors_count = (Self::total_blocks(size) * block::SECTORS_PER_BLOCK) as u32;
-        self.size_low as usize
     }
 
     pub fn set_size(&mut self, size: usize) {
+        self.size_low = size as u32;
         if self.filetype().is_file() {
-            assert_eq!(self.size_high, 0);
+            self.size_high = (size as u64 >> 32) as u32;
+        } else {
+            assert!(size <= u32::MAX as usize, "too large size: {}", size);
         }
-        self.size_low = size as u32;
     }
 
     pub fn timestamp(&self) -> VfsTimeStamp {
@@ -159,6 +215,10 @@ impl Ext2Inode {
         self.hard_links
     }
 
+    pub fn sectors_count(&self) -> usize {
+        self.sectors_count as usize
+    }
+
     pub fn inc_hard_links(&mut self) {
         self.hard_links += 1;
     }
@@ -168,32 +228,140 @@ impl Ext2Inode {
         self.hard_links == 0
     }
 
-    fn block_id_for(&self, inner_idx: u32) -> u32 {
+    /// 顺序读接近直接块的末尾时, 下一次读就要先读一级间接块才能找到数据块.
+    /// last_idx 是本次读到的最后一个块, 落在末尾的窗口内且文件延伸到间接块时,
+    /// 提前把一级间接块以及它指向的前几个数据块读入缓存
+    pub(crate) fn read_ahead(&self, last_idx: usize) {
+        let window = Self::DIRECT_COUNT - Self::READ_AHEAD_WINDOW..Self::DIRECT_COUNT;
+        if !window.contains(&last_idx) || self.indirect_pointer == 0 {
+            return;
+        }
+        let blocks = ceil_index!(self.size(), block::SIZE)
+            .saturating_sub(Self::DIRECT_COUNT)
+            .min(Self::READ_AHEAD_BLOCKS);
+        let data_blocks = block_device::read(
+            self.indirect_pointer as usize,
+            0,
+            |indirect_block: &IndirectBlock| indirect_block[..blocks].to_vec(),
+        );
+        for block_id in data_blocks.into_iter().filter(|&block_id| block_id != 0) {
+            block_device::read(block_id as usize, 0, |_: &DataBlock| ());
+        }
+    }
+
+    // 返回 0 表示该块是空洞. 路径上的间接块没有分配时, 它索引的整个范围都是空洞
+    pub(crate) fn block_id_for(&self, inner_idx: u32) -> u32 {
         let inner_idx = inner_idx as usize;
         if inner_idx < Self::DIRECT_COUNT {
-            self.direct_pointer[inner_idx]
-        } else if inner_idx < Self::INDIRECT_BOUND {
-            block_device::read(
-                self.indirect_pointer as usize,
-                0,
-                |indirect_block: &IndirectBlock| indirect_block[inner_idx - Self::DIRECT_COUNT],
-            )
+            return self.direct_pointer[inner_idx];
+        }
+        Self::pointer_slots(inner_idx).into_iter().fold(
+            self.top_indirect(inner_idx),
+            |block_id, slot| {
+                if block_id == 0 {
+                    return 0;
+                }
+                block_device::read(block_id as usize, 0, |indirect: &IndirectBlock| {
+                    indirect[slot]
+                })
+            },
+        )
+    }
+
+    // inner_idx 所在区域最上层的间接块, 直接块没有
+    fn top_indirect(&self, inner_idx: usize) -> u32 {
+        if inner_idx < Self::INDIRECT_BOUND {
+            self.indirect_pointer
         } else if inner_idx < Self::DOUBLE_BOUND {
-            let last = inner_idx - Self::INDIRECT_BOUND;
-            let indirect = block_device::read(
-                self.doubly_indirect as usize,
-                0,
-                |indirect2: &IndirectBlock| indirect2[last / Self::INDIRECT_COUNT],
-            );
+            self.doubly_indirect
+        } else {
+            self.triply_indirect
+        }
+    }
 
-            block_device::read(indirect as usize, 0, |indirect1: &IndirectBlock| {
-                indirect1[last % Self::INDIRECT_COUNT]
-            })
+    fn top_indirect_mut(&mut self, inner_idx: usize) -> &mut u32 {
+        if inner_idx < Self::INDIRECT_BOUND {
+            &mut self.indirect_pointer
+        } else if inner_idx < Self::DOUBLE_BOUND {
+            &mut self.doubly_indirect
+        } else {
+            &mut self.triply_indirect
+        }
+    }
+
+    // 从最上层的间接块往下, inner_idx 在每一层间接块中的下标, 最后一个是数据块的下标
+    fn pointer_slots(inner_idx: usize) -> Vec<usize> {
+        if inner_idx < Self::INDIRECT_BOUND {
+            alloc::vec![inner_idx - Self::DIRECT_COUNT]
+        } else if inner_idx < Self::DOUBLE_BOUND {
+            let last = inner_idx - Self::INDIRECT_BOUND;
+            alloc::vec![last / Self::INDIRECT_COUNT, last % Self::INDIRECT_COUNT]
+        } else if inner_idx < Self::TRIPLE_BOUND {
+            let last = inner_idx - Self::DOUBLE_BOUND;
+            alloc::vec![
+                last / Self::DOUBLE_COUNT,
+                last / Self::INDIRECT_COUNT % Self::INDIRECT_COUNT,
+                last % Self::INDIRECT_COUNT,
+            ]
         } else {
             panic!("where is the large block from : inner_id = {}", inner_idx);
         }
     }
 
+    /// inner_idx 所在路径上还没有分配的间接块数. Linux 创建的稀疏文件中,
+    /// 整个范围都是空洞的间接块不会被分配
+    pub fn missing_indirects(&self, inner_idx: usize) -> usize {
+        if inner_idx < Self::DIRECT_COUNT {
+            return 0;
+        }
+        let slots = Self::pointer_slots(inner_idx);
+        let mut block_id = self.top_indirect(inner_idx);
+        // 最后一个下标指向数据块, 不是间接块
+        for (depth, &slot) in slots[..slots.len() - 1].iter().enumerate() {
+            if block_id == 0 {
+                return slots.len() - depth;
+            }
+            block_id = block_device::read(block_id as usize, 0, |indirect: &IndirectBlock| {
+                indirect[slot]
+            });
+        }
+        (block_id == 0) as usize
+    }
+
+    /// 从上往下, 用 indirects 中已清零的块补上 inner_idx 所在路径上缺少的间接块.
+    /// indirects 可以比缺少的少, 用完时停下, 更低层的间接块由调用者负责
+    pub fn attach_indirects(&mut self, inner_idx: usize, indirects: &[u32]) {
+        let mut new_blocks = indirects.iter().copied();
+        let top = self.top_indirect_mut(inner_idx);
+        if *top == 0 {
+            match new_blocks.next() {
+                Some(block_id) => *top = block_id,
+                None => return,
+            }
+        }
+        let mut block_id = *top;
+        let slots = Self::pointer_slots(inner_idx);
+        for &slot in &slots[..slots.len() - 1] {
+            block_id =
+                block_device::modify(block_id as usize, 0, |indirect: &mut IndirectBlock| {
+                    if indirect[slot] == 0 {
+                        if let Some(new_block) = new_blocks.next() {
+                            indirect[slot] = new_block;
+                        }
+                    }
+                    indirect[slot]
+                });
+            if block_id == 0 {
+                break;
+            }
+        }
+        assert!(
+            new_blocks.next().is_none(),
+            "more indirect blocks than missing"
+        );
+        self.sectors_count += (indirects.len() * block::SECTORS_PER_BLOCK) as u32;
+    }
+
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let block_size = block::SIZE;
         let mut start = offset;
@@ -211,14 +379,14 @@ impl Ext2Inode {
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
 
-            block_device::read(
-                self.block_id_for(start_block as u32) as usize,
-                0,
-                |data_block: &DataBlock| {
+            match self.block_id_for(start_block as u32) {
+                // 空洞读出 0
+                0 => dst.fill(0),
+                block_id => block_device::read(block_id as usize, 0, |data_block: &DataBlock| {
                     let src = &data_block[start % block_size..start % block_size + block_read_size];
                     dst.copy_from_slice(src);
-                },
-            );
+                }),
+            }
 
             read_size += block_read_size;
             // move to next block
@@ -231,7 +399,7 @@ impl Ext2Inode {
         read_size
     }
 
-    /// 文件长度必须先扩容, 本函数不负责扩容
+    /// 文件长度必须先扩容, 空洞也必须先填上, 本函数不负责
     pub fn write_at(&mut self, offset: usize, buf: &[u8]) -> usize {
         let block_size = block::SIZE;
         let mut start = offset;
@@ -245,16 +413,14 @@ impl Ext2Inode {
 
             // write and update write size
             let block_write_size = end_current_block - start;
-            block_device::modify(
-                self.block_id_for(start_block as u32) as usize,
-                0,
-                |data_block: &mut DataBlock| {
-                    let src = &buf[write_size..write_size + block_write_size];
-                    let dst =
-                        &mut data_block[start % block_size..start % block_size + block_write_size];
-                    dst.copy_from_slice(src);
-                },
-            );
+            let block_id = self.block_id_for(start_block as u32);
+            assert_ne!(block_id, 0, "write to a hole at block {}", start_block);
+            block_device::modify(block_id as usize, 0, |data_block: &mut DataBlock| {
+                let src = &buf[write_size..write_size + block_write_size];
+                let dst =
+                    &mut data_block[start % block_size..start % block_size + block_write_size];
+                dst.copy_from_slice(src);
+            });
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -266,11 +432,161 @@ impl Ext2Inode {
         write_size
     }
 
+    // 用新分配的 block_id 填上空洞, 空洞所在的间接块必须先用 attach_indirects 补上
+    pub fn fill_hole(&mut self, inner_idx: usize, block_id: u32) {
+        assert_ne!(block_id, 0);
+        let old = self.replace_pointer(inner_idx, block_id);
+        assert_eq!(old, 0);
+        self.sectors_count += block::SECTORS_PER_BLOCK as u32;
+    }
+
+    // 把第 inner_idx 块变成空洞, 返回原来的块号由调用者释放. 间接块保留, 以后可以再填上
+    pub fn punch_hole(&mut self, inner_idx: usize) -> u32 {
+        let old = self.replace_pointer(inner_idx, 0);
+        assert_ne!(old, 0);
+        self.sectors_count -= block::SECTORS_PER_BLOCK as u32;
+        old
+    }
+
+    // 把第 inner_idx 块的指针换成 block_id, 返回旧值
+    fn replace_pointer(&mut self, inner_idx: usize, block_id: u32) -> u32 {
+        if inner_idx < Self::DIRECT_COUNT {
+            return core::mem::replace(&mut self.direct_pointer[inner_idx], block_id);
+        }
+        let slots = Self::pointer_slots(inner_idx);
+        let (&idx, parents) = slots.split_last().unwrap();
+        let indirect = parents
+            .iter()
+            .fold(self.top_indirect(inner_idx), |block_id, &slot| {
+                if block_id == 0 {
+                    return 0;
+                }
+                block_device::read(block_id as usize, 0, |indirect: &IndirectBlock| {
+                    indirect[slot]
+                })
+            });
+        assert_ne!(indirect, 0, "no indirect block for block {}", inner_idx);
+        block_device::modify(indirect as usize, 0, |indirect1: &mut IndirectBlock| {
+            core::mem::replace(&mut indirect1[idx], block_id)
+        })
+    }
+
+    // increase_to 依次消费块号的顺序: true 表示间接块, false 表示数据块.
+    // 间接块总是排在它所索引的第一个数据块之前
+    pub fn fill_order(start_block: usize, end_block: usize) -> impl Iterator<Item = bool> {
+        (start_block..end_block).flat_map(|idx| {
+            let indirect1 = idx == Self::DIRECT_COUNT;
+            let indirect2 = idx == Self::INDIRECT_BOUND;
+            let double_indirect1 = (Self::INDIRECT_BOUND..Self::DOUBLE_BOUND).contains(&idx)
+                && (idx - Self::INDIRECT_BOUND) % Self::INDIRECT_COUNT == 0;
+            let indirect3 = idx == Self::DOUBLE_BOUND;
+            let triple_indirect2 =
+                idx >= Self::DOUBLE_BOUND && (idx - Self::DOUBLE_BOUND) % Self::DOUBLE_COUNT == 0;
+            let triple_indirect1 =
+                idx >= Self::DOUBLE_BOUND && (idx - Self::DOUBLE_BOUND) % Self::INDIRECT_COUNT == 0;
+            let metas = [
+                indirect1,
+                indirect2,
+                double_indirect1,
+                indirect3,
+                triple_indirect2,
+                triple_indirect1,
+            ];
+            metas
+                .into_iter()
+                .filter(|&meta| meta)
+                .chain(core::iter::once(false))
+        })
+    }
+
+    // 文件占用的所有块, 包括 indirect1/2 这样的元数据块.
+    // 按逻辑顺序排列, 每个间接块排在它所索引的数据块之前
+    pub fn all_owned_blocks(&self) -> Vec<u32> {
+        // 快速符号链接把目标存在指针区, 没有占用块
+        if self.filetype().is_symlink() && self.sectors_count == 0 {
+            return Vec::new();
+        }
+
+        let data_blocks = Self::data_blocks(self.size());
+        let mut owned = Vec::with_capacity(Self::total_blocks(self.size()));
+        owned.extend(&self.direct_pointer[..data_blocks.min(Self::DIRECT_COUNT)]);
+
+        // 整个范围都是空洞时间接块可以不存在, 这时跳过, 不能把 0 号块当作间接块读
+        if data_blocks > Self::DIRECT_COUNT && self.indirect_pointer != 0 {
+            let count = (data_blocks - Self::DIRECT_COUNT).min(Self::INDIRECT_COUNT);
+            owned.push(self.indirect_pointer);
+            block_device::read(
+                self.indirect_pointer as usize,
+                0,
+                |indirect1: &IndirectBlock| owned.extend(&indirect1[..count]),
+            );
+        }
+
+        if data_blocks > Self::INDIRECT_BOUND && self.doubly_indirect != 0 {
+            let count = (data_blocks - Self::INDIRECT_BOUND).min(Self::DOUBLE_COUNT);
+            owned.push(self.doubly_indirect);
+            block_device::read(
+                self.doubly_indirect as usize,
+                0,
+                |indirect2: &IndirectBlock| {
+                    let used = ceil_index!(count, Self::INDIRECT_COUNT);
+                    for (a, &indirect) in indirect2[..used].iter().enumerate() {
+                        if indirect == 0 {
+                            continue;
+                        }
+                        let b = (count - a * Self::INDIRECT_COUNT).min(Self::INDIRECT_COUNT);
+                        owned.push(indirect);
+                        block_device::read(indirect as usize, 0, |indirect1: &IndirectBlock| {
+                            owned.extend(&indirect1[..b])
+                        });
+                    }
+                },
+            );
+        }
+
+        if data_blocks > Self::DOUBLE_BOUND && self.triply_indirect != 0 {
+            let count = data_blocks - Self::DOUBLE_BOUND;
+            owned.push(self.triply_indirect);
+            let used = ceil_index!(count, Self::DOUBLE_COUNT);
+            let indirect3 = block_device::read(
+                self.triply_indirect as usize,
+                0,
+                |indirect3: &IndirectBlock| indirect3[..used].to_vec(),
+            );
+            for (a, indirect2) in indirect3.into_iter().enumerate() {
+                owned.push(indirect2);
+                if indirect2 == 0 {
+                    continue;
+                }
+                let rest = (count - a * Self::DOUBLE_COUNT).min(Self::DOUBLE_COUNT);
+                let used = ceil_index!(rest, Self::INDIRECT_COUNT);
+                let indirects =
+                    block_device::read(indirect2 as usize, 0, |indirect2: &IndirectBlock| {
+                        indirect2[..used].to_vec()
+                    });
+                for (b, indirect) in indirects.into_iter().enumerate() {
+                    owned.push(indirect);
+                    if indirect == 0 {
+                        continue;
+                    }
+                    let c = (rest - b * Self::INDIRECT_COUNT).min(Self::INDIRECT_COUNT);
+                    block_device::read(indirect as usize, 0, |indirect1: &IndirectBlock| {
+                        owned.extend(&indirect1[..c])
+                    });
+                }
+            }
+        }
+
+        // 空洞不占用块
+        owned.retain(|&block_id| block_id != 0);
+        owned
+    }
+
     pub fn data_blocks(size: usize) -> usize {
         ceil_index!(size, block::SIZE)
     }
 
-    // 计算文件包含的总块数, 包含 indirect1/2
+    // 计算文件包含的总块数, 包含 indirect1/2/3
     pub fn total_blocks(size: usize) -> usize {
         let data_blocks = Self::data_blocks(size);
         let mut total = data_blocks;
@@ -283,9 +599,17 @@ impl Ext2Inode {
         // 需要一个块充当 indirect2
         if data_blocks > Self::INDIRECT_BOUND {
             total += 1;
-            let double_blocks = data_blocks - Self::INDIRECT_BOUND;
+            let double_blocks = (data_blocks - Self::INDIRECT_BOUND).min(Self::DOUBLE_COUNT);
             total += ceil_index!(double_blocks, Self::INDIRECT_COUNT);
         }
+
+        // 需要一个块充当 indirect3, 其下还有若干 indirect2 和 indirect1
+        if data_blocks > Self::DOUBLE_BOUND {
+            total += 1;
+            let triple_blocks = data_blocks - Self::DOUBLE_BOUND;
+            total += ceil_index!(triple_blocks, Self::DOUBLE_COUNT);
+            total += ceil_index!(triple_blocks, Self::INDIRECT_COUNT);
+        }
         total
     }
 
@@ -388,8 +712,58 @@ impl Ext2Inode {
         current + Self::INDIRECT_BOUND
     }
 
+    // triply 区域逐块填充, 需要时依次挂上新的 indirect2 和 indirect1
+    fn fill_from_triple(
+        &mut self,
+        start_block: usize,
+        end_block: usize,
+        blocks: &mut IntoIter<u32>,
+    ) -> usize {
+        if end_block <= Self::DOUBLE_BOUND {
+            return start_block;
+        }
+
+        let end = (end_block - Self::DOUBLE_BOUND).min(Self::TRIPLE_COUNT);
+        let mut current = start_block - Self::DOUBLE_BOUND;
+        if current == 0 {
+            self.triply_indirect = blocks.next().unwrap();
+        }
+
+        while current < end {
+            let a = current / Self::DOUBLE_COUNT;
+            let b = current / Self::INDIRECT_COUNT % Self::INDIRECT_COUNT;
+            let c = current % Self::INDIRECT_COUNT;
+            let indirect2 = block_device::modify(
+                self.triply_indirect as usize,
+                0,
+                |indirect3: &mut IndirectBlock| {
+                    if current.is_multiple_of(Self::DOUBLE_COUNT) {
+                        indirect3[a] = blocks.next().unwrap();
+                    }
+                    indirect3[a]
+                },
+            );
+            let indirect1 =
+                block_device::modify(indirect2 as usize, 0, |indirect2: &mut IndirectBlock| {
+                    if c == 0 {
+                        indirect2[b] = blocks.next().unwrap();
+                    }
+                    indirect2[b]
+                });
+            block_device::modify(indirect1 as usize, 0, |indirect1: &mut IndirectBlock| {
+                indirect1[c] = blocks.next().unwrap();
+            });
+            current += 1;
+        }
+
+        current + Self::DOUBLE_BOUND
+    }
+
+    /// new_blocks 中数据块的位置可以是 0, 表示留下空洞
     pub fn increase_to(&mut self, new_size: usize, new_blocks: Vec<u32>) {
         assert!(new_size > self.size());
+        let allocated = new_blocks.iter().filter(|&&block_id| block_id != 0).count();
+        self.sectors_count += (allocated * block::SECTORS_PER_BLOCK) as u32;
         let mut start_block = Self::data_blocks(self.size());
         self.set_size(new_size);
         let end_block = Self::data_blocks(new_size);
@@ -400,11 +774,16 @@ impl Ext2Inode {
             start_block = self.fill_from_direct(start_block, end_block, &mut blocks_iter);
             start_block = self.fill_from_indirect(start_block, end_block, &mut blocks_iter);
             start_block = self.fill_from_double(start_block, end_block, &mut blocks_iter);
+            start_block = self.fill_from_triple(start_block, end_block, &mut blocks_iter);
         } else if start_block < Self::INDIRECT_BOUND {
             start_block = self.fill_from_indirect(start_block, end_block, &mut blocks_iter);
             start_block = self.fill_from_double(start_block, end_block, &mut blocks_iter);
+            start_block = self.fill_from_triple(start_block, end_block, &mut blocks_iter);
         } else if start_block < Self::DOUBLE_BOUND {
             start_block = self.fill_from_double(start_block, end_block, &mut blocks_iter);
+            start_block = self.fill_from_triple(start_block, end_block, &mut blocks_iter);
+        } else if start_block < Self::TRIPLE_BOUND {
+            start_block = self.fill_from_triple(start_block, end_block, &mut blocks_iter);
         } else {
             panic!("where the ultra-big size(={}) from?", new_size);
         }
@@ -444,12 +823,16 @@ impl Ext2Inode {
         let mut current = start_block - Self::DIRECT_COUNT;
         let free_indirect = current == 0;
 
+        // 没有间接块时整个范围都是空洞
+        if self.indirect_pointer == 0 {
+            return end + Self::DIRECT_COUNT;
+        }
         block_device::modify(
             self.indirect_pointer as usize,
             0,
             |indirect1: &mut IndirectBlock| {
                 while current < end {
-                    blocks.push(indirect1[current]);
+                    blocks.push(core::mem::take(&mut indirect1[current]));
                     current += 1;
                 }
             },
@@ -477,6 +860,10 @@ impl Ext2Inode {
         let mut current = start_block - Self::INDIRECT_BOUND;
         let free_double = current == 0;
 
+        // 没有间接块时整个范围都是空洞
+        if self.doubly_indirect == 0 {
+            return end + Self::INDIRECT_BOUND;
+        }
         // free indirect2 from (a0, b0) -> (a1, b1)
         let mut a0 = current / Self::INDIRECT_COUNT;
         let mut b0 = current % Self::INDIRECT_COUNT;
@@ -487,25 +874,33 @@ impl Ext2Inode {
             0,
             |indirect2: &mut IndirectBlock| {
                 while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        blocks.push(indirect2[a0]);
+                    let a = a0;
+                    let run_end = if a0 < a1 { Self::INDIRECT_COUNT } else { b1 };
+                    let free_indirect1 = b0 == 0;
+                    // indirect1 不存在时它索引的范围都是空洞
+                    if indirect2[a] != 0 {
+                        if free_indirect1 {
+                            blocks.push(indirect2[a]);
+                        }
+                        block_device::modify(
+                            indirect2[a] as usize,
+                            0,
+                            |indirect1: &mut IndirectBlock| {
+                                for slot in &mut indirect1[b0..run_end] {
+                                    blocks.push(core::mem::take(slot));
+                                }
+                            },
+                        );
+                        if free_indirect1 {
+                            indirect2[a] = 0;
+                        }
+                    }
+                    current += run_end - b0;
+                    b0 = run_end;
+                    if b0 == Self::INDIRECT_COUNT {
+                        b0 = 0;
+                        a0 += 1;
                     }
-                    block_device::modify(
-                        indirect2[a0] as usize,
-                        0,
-                        |indirect1: &mut IndirectBlock| {
-                            while (a0 < a1 && b0 < Self::INDIRECT_COUNT) || (a0 == a1 && b0 < b1) {
-                                blocks.push(indirect1[b0]);
-                                b0 += 1;
-                                current += 1;
-                            }
-
-                            if b0 == Self::INDIRECT_COUNT {
-                                b0 = 0;
-                                a0 += 1;
-                            }
-                        },
-                    )
                 }
             },
         );
@@ -518,6 +913,77 @@ impl Ext2Inode {
         current + Self::INDIRECT_BOUND
     }
 
+    // 与 fill_from_triple 对称, 逐块释放, indirect1/2/3 在其范围整个被释放时一并释放
+    fn free_from_triple(
+        &mut self,
+        start_block: usize,
+        end_block: usize,
+        blocks: &mut Vec<u32>,
+    ) -> usize {
+        if end_block <= Self::DOUBLE_BOUND {
+            return start_block;
+        }
+
+        let end = (end_block - Self::DOUBLE_BOUND).min(Self::TRIPLE_COUNT);
+        let mut current = start_block - Self::DOUBLE_BOUND;
+        let free_triple = current == 0;
+
+        // 整个释放的间接块在父块中的位置, 循环中还要经过父块找到它们, 最后再清零.
+        // 不存在的间接块所索引的范围都是空洞, 直接跳过
+        let mut emptied = Vec::new();
+        while current < end {
+            let a = current / Self::DOUBLE_COUNT;
+            let b = current / Self::INDIRECT_COUNT % Self::INDIRECT_COUNT;
+            let c = current % Self::INDIRECT_COUNT;
+            let indirect2 = match self.triply_indirect {
+                0 => 0,
+                indirect3 => {
+                    block_device::read(indirect3 as usize, 0, |indirect3: &IndirectBlock| {
+                        indirect3[a]
+                    })
+                }
+            };
+            if indirect2 == 0 {
+                current = ((a + 1) * Self::DOUBLE_COUNT).min(end);
+                continue;
+            }
+            if current.is_multiple_of(Self::DOUBLE_COUNT) {
+                blocks.push(indirect2);
+                emptied.push((self.triply_indirect, a));
+            }
+            let indirect1 =
+                block_device::read(indirect2 as usize, 0, |indirect2: &IndirectBlock| {
+                    indirect2[b]
+                });
+            if indirect1 == 0 {
+                current = ((current / Self::INDIRECT_COUNT + 1) * Self::INDIRECT_COUNT).min(end);
+                continue;
+            }
+            if c == 0 {
+                blocks.push(indirect1);
+                emptied.push((indirect2, b));
+            }
+            blocks.push(block_device::modify(
+                indirect1 as usize,
+                0,
+                |indirect1: &mut IndirectBlock| core::mem::take(&mut indirect1[c]),
+            ));
+            current += 1;
+        }
+        for (block_id, slot) in emptied {
+            block_device::modify(block_id as usize, 0, |indirect: &mut IndirectBlock| {
+                indirect[slot] = 0
+            });
+        }
+
+        if free_triple {
+            blocks.push(self.triply_indirect);
+            self.triply_indirect = 0;
+        }
+
+        current + Self::DOUBLE_BOUND
+    }
+
     pub fn decrease_to(&mut self, new_size: usize) -> Vec<u32> {
         assert!(new_size < self.size());
         let end_block = Self::data_blocks(self.size());
@@ -529,16 +995,24 @@ impl Ext2Inode {
             start_block = self.free_from_direct(start_block, end_block, &mut freed);
             start_block = self.free_from_indirect(start_block, end_block, &mut freed);
             start_block = self.free_from_double(start_block, end_block, &mut freed);
+            start_block = self.free_from_triple(start_block, end_block, &mut freed);
         } else if start_block < Self::INDIRECT_BOUND {
             start_block = self.free_from_indirect(start_block, end_block, &mut freed);
             start_block = self.free_from_double(start_block, end_block, &mut freed);
+            start_block = self.free_from_triple(start_block, end_block, &mut freed);
         } else if start_block < Self::DOUBLE_BOUND {
             start_block = self.free_from_double(start_block, end_block, &mut freed);
+            start_block = self.free_from_triple(start_block, end_block, &mut freed);
+        } else if start_block < Self::TRIPLE_BOUND {
+            start_block = self.free_from_triple(start_block, end_block, &mut freed);
         } else {
             panic!("where the ultra-big size(={}) from?", new_size);
         }
 
         assert_eq!(start_block, end_block);
+        // 空洞没有占用块
+        freed.retain(|&block_id| block_id != 0);
+        self.sectors_count -= (freed.len() * block::SECTORS_PER_BLOCK) as u32;
         freed
     }
 }
@@ -645,9 +1119,14 @@ impl TypePerm {
         if self.contains(Self::O_EXEC) {
             others |= 0b001;
         }
-        VfsPermissions::inner_new(user, group, others)
+        VfsPermissions::inner_new(user, group, others).with_special(
+            self.contains(Self::SET_UID),
+            self.contains(Self::SET_GID),
+            self.contains(Self::STICKY),
+        )
     }
 
+    // 与 chmod 一致, 特殊位也按给定的值写入, 没有给出的会被清除
     fn set_permissions(&mut self, permissions: &VfsPermissions) {
         let user = permissions.user();
         let group = permissions.group();
@@ -655,6 +1134,9 @@ impl TypePerm {
         self.set_user(user);
         self.set_group(group);
         self.set_others(others);
+        self.set(Self::SET_UID, permissions.setuid());
+        self.set(Self::SET_GID, permissions.setgid());
+        self.set(Self::STICKY, permissions.sticky());
     }
 
     fn set_user(&mut self, user: VfsPermission) {
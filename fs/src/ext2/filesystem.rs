@@ -1,22 +1,102 @@
-use core::fmt::{self, Display};
+use core::{
+    fmt::{self, Display},
+    ops::ControlFlow,
+};
 
-use alloc::{boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use spin::Mutex;
 
+use crate::block;
 use crate::block_device::{self, BlockDevice};
+use crate::util;
 
-use crate::vfs::error::{IOError, IOErrorKind};
-use crate::vfs::{error::VfsResult, meta::*, VfsDirEntry, VfsInode, VfsPath};
+use crate::vfs::error::{IOError, IOErrorKind, VfsError, VfsErrorKind};
+use crate::vfs::{error::VfsResult, meta::*, DirEntryPlus, VfsDirEntry, VfsInode, VfsPath};
 
 use super::{
-    allocator::Ext2Allocator, blockgroup::Ext2BlockGroupDesc, inode::Inode, layout::Ext2Layout,
-    superblock::Superblock,
+    allocator::Ext2Allocator,
+    blockgroup::Ext2BlockGroupDesc,
+    check::{self, CheckReport},
+    dir::{DirEntry, ReadDirIter},
+    disk_inode::Flags,
+    inode::{BlockStream, BlockView, Inode},
+    journal::Journal,
+    layout::{Ext2Layout, InodeHistogram},
+    manifest, mkfs,
+    snapshot::Ext2Snapshot,
+    superblock::{
+        ErrorPolicy, FeaturesOptional, FeaturesROnly, FeaturesRequired, Superblock, FS_CLEAN,
+    },
+    symlink,
 };
 
+/// 挂载选项
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    /// 挂载时按 bitmap 重新统计空闲块和空闲 inode, 与记录的不一致则挂载失败;
+    /// 同时检查 block bitmap 的填充位是否都已置位. 需要读所有 bitmap, 默认关闭
+    pub verify: bool,
+    /// 新建目录项时 record_len 的对齐字节数, 必须是不小于 4 的 2 的幂, 默认 4.
+    /// 只影响写入, 任何对齐的目录都能正常读取
+    pub dir_align: usize,
+    /// 日志需要恢复时仍然挂载, 日志中尚未写回的修改会被忽略
+    pub ignore_journal: bool,
+    /// 日志需要恢复时先重放已提交的事务再挂载. 只支持描述块+数据块+提交块的简单事务,
+    /// 遇到撤销块会挂载失败. 优先于 ignore_journal
+    pub replay_journal: bool,
+    /// 设置后按这个身份检查权限, 没有写权限的文件拒绝写入. 默认为 None, 不做检查
+    pub credentials: Option<Credentials>,
+    /// 挂载时扫描一遍 inode bitmap 并缓存, 之后查询 inode 是否已分配不再读盘. 默认关闭
+    pub cache_inode_bitmap: bool,
+    /// 读取文件时不更新 atime, 相当于给所有 inode 加上 DONT_ATIME. 默认关闭
+    pub noatime: bool,
+    /// 只读挂载: 拒绝所有修改, 挂载和 flush 都不写盘, 因此设备可以是只读的.
+    /// 不能与 replay_journal 同时使用. 默认关闭
+    pub read_only: bool,
+}
+
+/// 挂载时 ext3 日志的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalState {
+    /// 没有日志
+    None,
+    /// 日志为空
+    Clean,
+    /// 日志中有未写回的修改, 以 ignore_journal 挂载
+    Dirty,
+    /// 挂载时重放了这么多个事务
+    Replayed(usize),
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            verify: false,
+            dir_align: 4,
+            ignore_journal: false,
+            replay_journal: false,
+            credentials: None,
+            cache_inode_bitmap: false,
+            noatime: false,
+            read_only: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Ext2FileSystem {
     layout: Arc<Ext2Layout>,
     allocator: Arc<Mutex<Ext2Allocator>>,
+    // 挂载时磁盘上的状态是否为 clean
+    clean: bool,
+    journal: JournalState,
 }
 
 impl Display for Ext2FileSystem {
@@ -27,23 +107,432 @@ impl Display for Ext2FileSystem {
 
 impl Ext2FileSystem {
     pub fn open(block_dev: impl BlockDevice) -> Self {
+        Self::open_with(block_dev, MountOptions::default()).unwrap()
+    }
+
+    pub fn open_with(block_dev: impl BlockDevice, options: MountOptions) -> VfsResult<Self> {
+        Self::check_options(&options)?;
+        block_device::register_block_device(block_dev);
+        Self::mount(options)
+    }
+
+    /// 与 open_with 相同, 但是主 superblock 损坏时改用 1 号块组中的备份, 并用它修复主 superblock.
+    /// 修复需要写盘, 因此只读挂载时主 superblock 损坏会直接报错.
+    /// 块组大小取自损坏的主 superblock, 不可信时按 mkfs 的默认值 (一个 bitmap 块能表示的块数) 查找
+    pub fn open_with_recovery(
+        block_dev: impl BlockDevice,
+        options: MountOptions,
+    ) -> VfsResult<Self> {
+        Self::check_options(&options)?;
+        block_device::register_block_device(block_dev);
+        let recovered = Self::recover_superblock(options.read_only)?;
+        let ext2 = Self::mount(options)?;
+        // 备份中的空闲计数停留在备份写入时, 以块组描述符为准
+        if recovered {
+            ext2.layout.resync_summary();
+        }
+        Ok(ext2)
+    }
+
+    fn check_options(options: &MountOptions) -> VfsResult<()> {
+        let align = options.dir_align;
+        if align < 4 || !align.is_power_of_two() || align > block::SIZE {
+            return Err(VfsErrorKind::Other(format!(
+                "invalid directory entry alignment: {}",
+                align
+            ))
+            .into());
+        }
+        if options.read_only && options.replay_journal {
+            let err: VfsError = VfsErrorKind::NotSupported.into();
+            return Err(err.with_additional("cannot replay the journal on a read-only mount"));
+        }
+        Ok(())
+    }
+
+    // 主 superblock 无效时从备份恢复并写回, 返回是否进行了恢复. 只读挂载时无法写回, 直接报错
+    fn recover_superblock(read_only: bool) -> VfsResult<bool> {
+        let primary = block_device::read(0, 1024, |sb: &Superblock| sb.clone());
+        if primary.is_valid() {
+            return Ok(false);
+        }
+        if read_only {
+            let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+            return Err(err.with_additional(
+                "primary superblock is damaged and cannot be repaired on a read-only mount",
+            ));
+        }
+
+        let mut candidates = alloc::vec![block::BITS];
+        let recorded = primary.blocks_per_group as usize;
+        if recorded != 0 && recorded.is_multiple_of(8) && recorded < block::BITS {
+            candidates.insert(0, recorded);
+        }
+        for blocks_per_group in candidates {
+            // 1 号块组总是有备份, 块大小为 4096 时备份位于块组的第一个块的开头
+            let backup = block_device::read(blocks_per_group, 0, |sb: &Superblock| sb.clone());
+            if backup.is_valid() && backup.blocks_per_group as usize == blocks_per_group {
+                block_device::modify(0, 1024, |sb: &mut Superblock| {
+                    sb.clone_from(&backup);
+                    sb.block_group = 0;
+                });
+                block_device::sync(0);
+                return Ok(true);
+            }
+        }
+        let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+        Err(err.with_additional("primary superblock is damaged and no valid backup was found"))
+    }
+
+    /// 把 block_dev 格式化为空的 ext2 文件系统并挂载.
+    /// 块大小固定为 4096, 镜像只包含根目录和 lost+found.
+    /// blocks_per_group 必须是 8 的倍数且不超过一个 bitmap 块能表示的位数,
+    /// 放不下元数据的最后一个块组会被舍弃
+    pub fn mkfs(
+        block_dev: impl BlockDevice,
+        total_blocks: usize,
+        blocks_per_group: usize,
+    ) -> VfsResult<Self> {
         block_device::register_block_device(block_dev);
-        let superblock = block_device::read(0, 1024, |sb: &Superblock| {
-            sb.check_valid();
-            sb.clone()
-        });
+        mkfs::format(total_blocks, blocks_per_group)?;
+        block_device::sync_all();
+        Self::mount(MountOptions::default())
+    }
+
+    // 在已注册的块设备上挂载
+    fn mount(options: MountOptions) -> VfsResult<Self> {
+        let align = options.dir_align;
+        let mut superblock = block_device::read(0, 1024, |sb: &Superblock| sb.clone());
+        superblock.check_valid()?;
+        // rev 0 没有特性位, 卷标等字段, 其中的内容不可信
+        if !superblock.is_dynamic() {
+            superblock.clear_dynamic_fields();
+        }
+        // 带有不认识的必需特性时无法正确解析镜像, 报出具体的特性名
+        let unsupported = superblock.features_req.unsupported_names();
+        if !unsupported.is_empty() {
+            return Err(VfsErrorKind::UnsupportedFeature(unsupported.join(", ")).into());
+        }
+        // metadata_csum 要求 inode, bitmap, 块组描述符和目录块都带校验和,
+        // 这里不维护任何校验和, 写入会让镜像失效, 只能只读挂载
+        let metadata_csum = superblock
+            .features_ronly
+            .contains(FeaturesROnly::RONLY_METADATA_CSUM);
+        if metadata_csum && !options.read_only {
+            let err: VfsError =
+                VfsErrorKind::UnsupportedFeature("metadata_csum".to_string()).into();
+            return Err(err.with_additional("only read-only mounts are supported"));
+        }
+
+        // ext3 的日志中可能还有没写回的修改, 忽略它们会读到旧数据
+        let mut journal = JournalState::None;
+        if superblock.features_opt.contains(FeaturesOptional::JOURNAL) {
+            journal = JournalState::Clean;
+            let needs_replay = superblock
+                .features_req
+                .contains(FeaturesRequired::REQ_REPLAY_JOURNAL);
+            if needs_replay && options.replay_journal {
+                let replayed = Journal::open(&superblock)?.replay()?;
+                journal = JournalState::Replayed(replayed);
+                // 日志里可能有 superblock 本身
+                superblock = block_device::read(0, 1024, |sb: &Superblock| sb.clone());
+            } else if needs_replay && !options.ignore_journal {
+                let err: VfsError = VfsErrorKind::NotSupported.into();
+                return Err(err.with_additional("journal needs to be replayed"));
+            } else if needs_replay {
+                journal = JournalState::Dirty;
+            }
+        }
 
         let blockgroup_count = superblock.blockgroup_count();
         let blockgroups = Ext2BlockGroupDesc::find(blockgroup_count);
+        // state 是位掩码, Linux 在有错误时正常卸载会写入 FS_CLEAN | FS_ERR
+        let clean = superblock.state & FS_CLEAN != 0;
+        // 挂载期间内存中的 superblock 也是 not clean, flush 写回的同样是 not clean,
+        // 只有 unmount 才写入 clean. 有错误的标记要一直保留到 fsck 修复为止
+        superblock.state &= !FS_CLEAN;
+
+        let layout = Arc::new(Ext2Layout::new(
+            superblock,
+            blockgroups,
+            align,
+            options.credentials,
+            options.noatime,
+            options.read_only,
+        ));
+        if options.verify {
+            layout.verify_free_counts()?;
+            layout.verify_bitmap_padding()?;
+        }
 
-        let layout = Arc::new(Ext2Layout::new(superblock, blockgroups));
-        let allocator = Arc::new(Mutex::new(Ext2Allocator::new(layout.clone())));
+        // 挂载期间磁盘上标记为 not clean, 这样没有正常卸载时, 下一次挂载就能知道需要检查.
+        // 只清掉 clean 位, 错误标记保持不变. 只读时不写盘
+        if !layout.is_read_only() {
+            block_device::modify(0, 1024, |sb: &mut Superblock| sb.state &= !FS_CLEAN);
+            block_device::sync(0);
+        }
+
+        let mut allocator = Ext2Allocator::new(layout.clone());
+        if options.cache_inode_bitmap {
+            allocator.enable_inode_cache();
+        }
+        let allocator = Arc::new(Mutex::new(allocator));
+
+        Ok(Self {
+            layout,
+            allocator,
+            clean,
+            journal,
+        })
+    }
+
+    pub fn blockgroup_count(&self) -> usize {
+        self.layout.blockgroups().len()
+    }
+
+    /// 卷标, rev 0 镜像为空
+    pub fn label(&self) -> String {
+        self.layout.superblock().lock().label()
+    }
+
+    /// 文件系统的 uuid, rev 0 镜像全为 0
+    pub fn uuid(&self) -> String {
+        util::uuid_str(&self.layout.superblock().lock().fs_id)
+    }
+
+    /// 第一个非保留的 inode, rev 0 镜像为 11
+    pub fn first_ino(&self) -> u32 {
+        self.layout.superblock().lock().first_ino()
+    }
+
+    /// 磁盘上每个 inode 的字节数, rev 0 镜像为 128
+    pub fn inode_size(&self) -> usize {
+        self.layout.inode_size()
+    }
+
+    /// 是否带有 ext3 日志. 除了挂载时重放, 日志不会被读取或更新
+    pub fn has_journal(&self) -> bool {
+        self.journal != JournalState::None
+    }
+
+    /// 挂载时日志的状态
+    pub fn journal_state(&self) -> JournalState {
+        self.journal
+    }
+
+    /// 挂载时文件系统是否是被正常卸载的
+    pub fn is_clean(&self) -> bool {
+        self.clean
+    }
 
-        Self { layout, allocator }
+    /// superblock 是否标记为有错误: 挂载前就有, 或者挂载后检测到了损坏.
+    /// 标记会随 flush 写回, 直到 fsck 修复为止
+    pub fn has_errors(&self) -> bool {
+        self.layout.has_errors()
     }
 
+    /// superblock 的 errors 字段规定的, 检测到损坏之后的处理方式
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.layout.superblock().lock().error_policy()
+    }
+
+    /// 是否以只读方式挂载, 或者因为 ErrorPolicy::RemountReadOnly 而拒绝修改
+    pub fn is_read_only(&self) -> bool {
+        self.layout.is_read_only()
+    }
+
+    /// 强制卸载: 丢弃所有还没写回的修改.
+    /// 磁盘上的 superblock 保持 not clean, 下一次挂载可以据此决定是否检查.
+    /// 注意缓存满时被换出的块已经写回, 无法撤销
+    pub fn abandon(self) {
+        block_device::discard();
+    }
+
+    /// 把 superblock 和块组描述符写入缓存, 再把所有脏块写回设备.
+    /// 磁盘上的 superblock 仍然是 not clean
     pub fn flush(&self) {
         self.layout.flush();
+        block_device::sync_all();
+    }
+
+    /// 正常卸载: 写回所有修改, 并在磁盘上把文件系统标记为 clean.
+    /// 之后不应再修改文件系统
+    pub fn unmount(&self) {
+        self.layout.mark_clean();
+        self.flush();
+    }
+
+    /// 先把修改全部写回, 再冻结出一份只读视图
+    pub fn snapshot(&self) -> Ext2Snapshot {
+        self.flush();
+        Ext2Snapshot::freeze(&self.layout)
+    }
+
+    /// 导出整棵目录树的清单, 用于比较两个镜像的逻辑内容.
+    /// 只与路径、类型、大小、权限和内容有关, 与块的布局无关
+    pub fn export_manifest(&self) -> VfsResult<Vec<u8>> {
+        manifest::export(&self.root_inode())
+    }
+
+    /// path 占用的所有块号, 包括间接块
+    pub fn owned_blocks(&self, path: VfsPath) -> VfsResult<Vec<u32>> {
+        let target = self.root_inode().walk(&path)?;
+        Ok(target.all_owned_blocks())
+    }
+
+    /// 目录 inode 的全部原始数据, 不做任何解析, 可以交给 decode_dir_records 逐条查看
+    pub fn dump_dir_raw(&self, path: VfsPath) -> VfsResult<Vec<u8>> {
+        let target = self.root_inode().walk(&path)?;
+        if !target.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory)
+                .with_path(&path)
+                .into());
+        }
+        Ok(target.read_disk_inode(|ext2_inode| {
+            let mut raw = alloc::vec![0u8; ext2_inode.size()];
+            ext2_inode.read_at(0, &mut raw);
+            raw
+        }))
+    }
+
+    /// 设置或清除 path 处的粘滞位, 只有所有者和 root 可以修改
+    pub fn set_sticky(&self, path: VfsPath, sticky: bool) -> VfsResult<()> {
+        self.layout.check_writable()?;
+        let mut target = self.root_inode().walk(&path)?;
+        target
+            .check_owner()
+            .map_err(|err| err.with_path(path.to_string()))?;
+        target.set_sticky(sticky);
+        Ok(())
+    }
+
+    /// inode 在磁盘上的位置 (block_id, 块内偏移)
+    pub fn inode_location(&self, inode_id: usize) -> VfsResult<(usize, usize)> {
+        self.layout.inode_location(inode_id)
+    }
+
+    /// path 处 inode 的属性标志
+    pub fn flags(&self, path: VfsPath) -> VfsResult<Flags> {
+        Ok(self.root_inode().walk(&path)?.flags())
+    }
+
+    /// 设置 path 处 inode 的属性标志, 比如 IMMUTABLE 和 APPEND_ONLY. 只有 root 可以修改
+    pub fn set_flags(&self, path: VfsPath, flags: Flags) -> VfsResult<()> {
+        self.layout.check_writable()?;
+        let mut target = self.root_inode().walk(&path)?;
+        target
+            .check_root()
+            .map_err(|err| err.with_path(path.to_string()))?;
+        target.set_flags(flags);
+        Ok(())
+    }
+
+    /// 在 dir 下一次新建多个空文件, 返回各自的 inode 号. 适合恢复大量文件的场景
+    pub fn create_files(&self, dir: VfsPath, names: &[&str]) -> VfsResult<Vec<usize>> {
+        self.layout.check_writable()?;
+        let mut dir_inode = self.root_inode().walk(&dir)?;
+        let paths = names
+            .iter()
+            .map(|name| {
+                // 借 from_components 检查文件名本身是否合法
+                VfsPath::from_components(&[name])?;
+                let mut path = dir.clone();
+                path.push(name);
+                Ok(path)
+            })
+            .collect::<VfsResult<Vec<_>>>()?;
+        dir_inode.insert_files(&paths)
+    }
+
+    /// inode_id 是否已被分配, 以 cache_inode_bitmap 挂载时不读盘
+    pub fn is_inode_allocated(&self, inode_id: usize) -> bool {
+        self.allocator.lock().is_inode_allocated(inode_id as u32)
+    }
+
+    /// 所有已分配的 inode 号, 从小到大
+    pub fn allocated_inodes(&self) -> Vec<usize> {
+        let inodes = self.allocator.lock().allocated_inodes();
+        inodes
+            .into_iter()
+            .map(|inode_id| inode_id as usize)
+            .collect()
+    }
+
+    /// 释放 path 处文件中内容全为 0 的数据块, 把它们变成空洞. 返回释放的块数
+    pub fn sparsify(&self, path: VfsPath) -> VfsResult<usize> {
+        self.layout.check_writable()?;
+        let mut target = self.root_inode().walk(&path)?;
+        target
+            .sparsify()
+            .map_err(|err| err.with_path(path.to_string()))
+    }
+
+    /// 按 bitmap 重新统计空闲块数和空闲 inode 数, 检查各块组描述符和 superblock 中记录的是否一致
+    pub fn verify(&self) -> VfsResult<()> {
+        self.layout.verify_free_counts()
+    }
+
+    /// 只读的一致性检查, 类似 e2fsck -n: 已分配 inode 的块是否都在 block bitmap 中标记为已用、
+    /// 有没有被重复引用的块, 目录的 "." 和 ".." 是否正确, hard_links 是否等于引用它的目录项数.
+    /// 检查的是缓存中的内容, 尚未写回的修改也会被看到
+    pub fn check(&self) -> CheckReport {
+        check::check(&self.layout, &self.allocator)
+    }
+
+    /// 块和 inode 的总数与空闲数, 取自 superblock. 可用块数扣除了为 root 保留的块
+    pub fn stat_fs(&self) -> VfsStatFs {
+        let sb = self.layout.superblock();
+        let sb = sb.lock();
+        VfsStatFs {
+            block_size: block::SIZE as u64,
+            total_blocks: sb.blocks_count as u64,
+            free_blocks: sb.free_blocks_count as u64,
+            available_blocks: sb.free_blocks_count.saturating_sub(sb.r_blocks_count) as u64,
+            total_inodes: sb.inodes_count as u64,
+            free_inodes: sb.free_inodes_count as u64,
+        }
+    }
+
+    /// 扫描 inode 表, 按寻址层级统计在用的 inode, 用于评估块大小是否合适
+    pub fn inode_size_histogram(&self) -> InodeHistogram {
+        self.layout.inode_histogram()
+    }
+
+    /// 按块读出 path 的 [offset, offset + len)
+    pub fn block_stream(&self, path: VfsPath, offset: usize, len: usize) -> VfsResult<BlockStream> {
+        let target = self.root_inode().walk(&path)?;
+        if !target.is_file() {
+            return Err(IOError::new(IOErrorKind::NotAFile).with_path(&path).into());
+        }
+        Ok(target.block_stream(offset, len))
+    }
+
+    /// path 处文件的只读视图, 见 Inode::map_readonly
+    pub fn map_readonly(&self, path: VfsPath) -> VfsResult<BlockView> {
+        let target = self.root_inode().walk(&path)?;
+        target
+            .map_readonly()
+            .map_err(|err| err.with_path(path.to_string()))
+    }
+
+    /// 逐块读取 path 目录下的目录项, 适合在大目录中查找
+    pub fn read_dir_iter(&self, path: VfsPath) -> VfsResult<ReadDirIter> {
+        let target = self.root_inode().walk(&path)?;
+        target
+            .read_dir_iter()
+            .map_err(|err| err.with_path(path.to_string()))
+    }
+
+    /// 对 path 目录下的每个目录项调用 f, 见 Inode::for_each_entry
+    pub fn for_each_entry<B>(
+        &self,
+        path: VfsPath,
+        f: impl FnMut(&DirEntry) -> ControlFlow<B>,
+    ) -> VfsResult<Option<B>> {
+        let target = self.root_inode().walk(&path)?;
+        target
+            .for_each_entry(f)
+            .map_err(|err| err.with_path(path.to_string()))
     }
 
     fn root_inode(&self) -> Inode {
@@ -52,8 +541,28 @@ impl Ext2FileSystem {
     }
 }
 
-use crate::vfs::FileSystem;
+use crate::vfs::{Capabilities, FileSystem};
 impl FileSystem for Ext2FileSystem {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::READ_DIR
+            | Capabilities::CREATE_FILE
+            | Capabilities::CREATE_DIR
+            | Capabilities::REMOVE_FILE
+            | Capabilities::REMOVE_DIR
+            | Capabilities::SYMLINK
+            | Capabilities::HARDLINK
+            | Capabilities::INODE_ID
+            | Capabilities::RENAME
+            | Capabilities::STAT_FS
+            | Capabilities::READ_LINK
+            | Capabilities::READ_DIR_PLUS
+            | Capabilities::SYMLINK_METADATA
+    }
+
+    fn max_symlink_len(&self) -> usize {
+        symlink::SYMLINK_MAX
+    }
+
     fn read_dir(&self, path: VfsPath) -> VfsResult<Vec<Box<dyn VfsDirEntry>>> {
         let root_inode: Inode = self.root_inode();
         let target = root_inode.walk(&path)?;
@@ -62,10 +571,16 @@ impl FileSystem for Ext2FileSystem {
             .map_err(|err| err.with_path(path.to_string()))
     }
 
+    fn read_dir_plus(&self, path: VfsPath) -> VfsResult<Vec<DirEntryPlus>> {
+        let root_inode: Inode = self.root_inode();
+        let target = root_inode.walk(&path)?;
+        target
+            .read_dir_plus()
+            .map_err(|err| err.with_path(path.to_string()))
+    }
+
     fn exists(&self, path: VfsPath) -> VfsResult<bool> {
-        let root_inode = self.root_inode();
-        let target = root_inode.walk(&path);
-        Ok(target.is_ok())
+        Ok(self.root_inode().exists(&path))
     }
 
     fn metadata(&self, path: VfsPath) -> VfsResult<Box<dyn VfsMetadata>> {
@@ -74,38 +589,98 @@ impl FileSystem for Ext2FileSystem {
         Ok(Box::new(target.metadata()))
     }
 
+    fn metadata_batch(&self, paths: Vec<VfsPath>) -> Vec<VfsResult<Box<dyn VfsMetadata>>> {
+        let root_inode = self.root_inode();
+        // 已经走过的目录, 以各级组件为键, 共享前缀的路径只走一次
+        let mut walked: BTreeMap<Vec<String>, Inode> = BTreeMap::new();
+        let mut walk_prefix = |components: &[String]| -> VfsResult<Inode> {
+            let mut current = root_inode.clone();
+            for depth in 1..=components.len() {
+                let prefix = &components[..depth];
+                current = match walked.get(prefix) {
+                    Some(inode) => inode.clone(),
+                    None => {
+                        let inode = current.walk(&VfsPath::from(prefix[depth - 1].as_str()))?;
+                        walked.insert(prefix.to_vec(), inode.clone());
+                        inode
+                    }
+                };
+            }
+            Ok(current)
+        };
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let target = walk_prefix(&path).map_err(|err| err.with_path(path.to_string()))?;
+                Ok(Box::new(target.metadata()) as Box<dyn VfsMetadata>)
+            })
+            .collect()
+    }
+
+    fn inode_id_of(&self, path: VfsPath) -> VfsResult<usize> {
+        let root_inode = self.root_inode();
+        let target = root_inode.walk(&path)?;
+        Ok(target.inode_id())
+    }
+
+    fn symlink_metadata(&self, path: VfsPath) -> VfsResult<(usize, Box<dyn VfsMetadata>)> {
+        let root_inode = self.root_inode();
+        let target = root_inode.goto_last(&path)?;
+        Ok((target.inode_id(), Box::new(target.metadata())))
+    }
+
+    fn walk_dir(&self, path: VfsPath) -> VfsResult<Vec<String>> {
+        let root_inode = self.root_inode();
+        let target = root_inode.walk(&path)?;
+        let paths = target.walk_dir(&path)?;
+        Ok(paths.iter().map(|(path, _)| path.to_string()).collect())
+    }
+
+    fn read_link(&self, path: VfsPath) -> VfsResult<String> {
+        let root_inode = self.root_inode();
+        let target = root_inode.goto_last(&path)?;
+        if !target.is_symlink() {
+            return Err(IOError::new(IOErrorKind::NotASymlink)
+                .with_path(&path)
+                .into());
+        }
+        Ok(target.read_symlink())
+    }
+
     fn link(&self, to: VfsPath, from: VfsPath) -> VfsResult<()> {
+        self.layout.check_writable()?;
         let root_inode = self.root_inode();
         // to 必须要存在
         let target = root_inode.walk(&to)?;
         let mut dir_inode = root_inode.walk(&from.parent())?;
-        let child = dir_inode.select_child(from.last().unwrap());
-        if child.is_err() {
+        match dir_inode.select_child(from.last().unwrap()) {
             // child 尚不存在, 需要在当前 dir 下新建
-            dir_inode.insert_hardlink(&from, &to, &target)?;
-        } else {
-            let mut child = child.unwrap();
-            if child.is_dir() {
-                // child 已存在且是 dir, 则在该 dir 下新建同名符号链接
-                let mut new_from = from.clone();
-                new_from.push(to.last().unwrap());
-                child.insert_hardlink(&new_from, &to, &target)?;
-            } else {
-                // child 已存在但不是 dir, 则是 AlreadyExists Error
-                return Err(IOError::new(IOErrorKind::AlreadyExists)
-                    .with_path(&from)
-                    .into());
+            Err(_) => dir_inode.insert_hardlink(&from, &to, &target)?,
+            Ok(mut child) => {
+                if child.is_dir() {
+                    // child 已存在且是 dir, 则在该 dir 下新建同名符号链接
+                    let mut new_from = from.clone();
+                    new_from.push(to.last().unwrap());
+                    child.insert_hardlink(&new_from, &to, &target)?;
+                } else {
+                    // child 已存在但不是 dir, 则是 AlreadyExists Error
+                    return Err(IOError::new(IOErrorKind::AlreadyExists)
+                        .with_path(&from)
+                        .into());
+                }
             }
         }
 
         Ok(())
     }
 
-    fn symlink(&self, to: VfsPath, from: VfsPath) -> VfsResult<()> {
+    fn symlink(&self, to: &str, from: VfsPath) -> VfsResult<()> {
+        self.layout.check_writable()?;
         let root_inode = self.root_inode();
         let mut dir_inode = root_inode.walk(&from.parent())?;
 
-        dir_inode.insert_symlink(&from, &to)
+        dir_inode.insert_symlink(&from, to)
     }
 
     fn open_file(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>> {
@@ -118,30 +693,50 @@ impl FileSystem for Ext2FileSystem {
     }
 
     fn create_file(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>> {
+        self.layout.check_writable()?;
         let root_inode = self.root_inode();
         let mut dir_inode = root_inode.walk(&path.parent())?;
         dir_inode.insert_entry(&path, VfsFileType::RegularFile)
     }
 
     fn create_dir(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>> {
+        self.layout.check_writable()?;
         let root_inode = self.root_inode();
         let mut dir_inode = root_inode.walk(&path.parent())?;
         dir_inode.insert_entry(&path, VfsFileType::Directory)
     }
 
-    fn remove_file(&self, path: VfsPath) -> VfsResult<()> {
+    fn remove_file(&self, path: VfsPath) -> VfsResult<u16> {
+        self.layout.check_writable()?;
         let root_inode = self.root_inode();
         let mut dir_inode = root_inode.walk(&path.parent())?;
-        dir_inode.remove_entry(&path)
+        dir_inode.remove_file(&path)
     }
 
     fn remove_dir(&self, path: VfsPath) -> VfsResult<()> {
+        self.layout.check_writable()?;
         let root_inode = self.root_inode();
         let mut dir_inode = root_inode.walk(&path.parent())?;
-        dir_inode.remove_entry(&path)
+        dir_inode.remove_dir(&path)
+    }
+
+    fn rename(&self, from: VfsPath, to: VfsPath) -> VfsResult<()> {
+        self.layout.check_writable()?;
+        let root_inode = self.root_inode();
+        let mut from_dir = root_inode.walk(&from.parent())?;
+        let mut to_dir = root_inode.walk(&to.parent())?;
+        from_dir.rename(&from, &mut to_dir, &to)
+    }
+
+    fn stat_fs(&self) -> VfsResult<VfsStatFs> {
+        Ok(self.stat_fs())
     }
 
     fn flush(&self) {
         self.flush();
     }
+
+    fn unmount(&self) {
+        self.unmount();
+    }
 }
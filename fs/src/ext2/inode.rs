@@ -1,17 +1,23 @@
+use core::cell::RefCell;
+
 use alloc::boxed::Box;
-use alloc::string::String;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::Mutex;
 
-use crate::block;
-use crate::vfs::error::{IOError, IOErrorKind, VfsResult};
-use crate::vfs::meta::{VfsFileType, VfsMetadata, VfsTimeStamp};
-use crate::vfs::VfsInode;
-use crate::{block_device, vfs::meta::VfsPermissions};
+use crate::block::{self, DataBlock};
+use crate::block_device::{self, BlockCache};
+use crate::vfs::error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult};
+use crate::vfs::meta::VfsPermissions;
+use crate::vfs::meta::{VfsFileType, VfsMetadata, VfsPermission, VfsTimeStamp};
+use crate::vfs::{VfsInode, VfsPath};
+use crate::{ceil, ceil_index};
 
 use super::address::Address;
 use super::allocator::Ext2Allocator;
-use super::disk_inode::Ext2Inode;
+use super::disk_inode::{Ext2Inode, Flags};
 use super::layout::Ext2Layout;
 use super::metadata::Ext2Metadata;
 
@@ -131,6 +137,22 @@ impl Inode {
         self.filetype.is_symlink()
     }
 
+    /// 粘滞位, 设置在目录上时只有所有者才能删除其中的目录项
+    pub fn is_sticky(&self) -> bool {
+        self.read_disk_inode(|ext2_inode| ext2_inode.is_sticky())
+    }
+    pub fn set_sticky(&mut self, sticky: bool) {
+        self.modify_disk_inode(|ext2_inode| ext2_inode.set_sticky(sticky));
+    }
+
+    /// inode 的属性标志, 即 chattr 修改的那些
+    pub fn flags(&self) -> Flags {
+        self.read_disk_inode(|ext2_inode| ext2_inode.flags.clone())
+    }
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.modify_disk_inode(|ext2_inode| ext2_inode.set_flags(flags));
+    }
+
     fn block_id(&self) -> usize {
         self.address.block_id()
     }
@@ -142,32 +164,37 @@ impl Inode {
         block_device::read(self.block_id(), self.offset(), f)
     }
 
+    // 所有对磁盘 inode 的修改(写入, 截断, 改权限等)都经过这里, 同时让缓存的元数据失效
     pub(crate) fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut Ext2Inode) -> V) -> V {
-        block_device::modify(self.block_id(), self.offset(), f)
+        let ret = block_device::modify(self.block_id(), self.offset(), f);
+        self.layout.bump_generation();
+        ret
+    }
+
+    /// 元数据的版本号, 与之前读到的不同说明缓存的元数据已经过期
+    pub fn generation(&self) -> u64 {
+        self.layout.generation()
     }
 
     pub(crate) fn sync_disk_inode(&self) {
         block_device::sync(self.block_id());
     }
 
+    pub fn all_owned_blocks(&self) -> Vec<u32> {
+        self.read_disk_inode(|ext2_inode| ext2_inode.all_owned_blocks())
+    }
+
     pub fn metadata(&self) -> Ext2Metadata {
-        self.read_disk_inode(|ext2_inode| {
-            Ext2Metadata::new(
-                ext2_inode.filetype(),
-                ext2_inode.permissions(),
-                ext2_inode.size(),
-                ext2_inode.timestamp(),
-                ext2_inode.uid(),
-                ext2_inode.gid(),
-                ext2_inode.hard_links(),
-            )
-        })
+        self.read_disk_inode(|ext2_inode| Ext2Metadata::from(ext2_inode))
     }
 
+    // 从 old_size 扩大到 new_size 需要新分配的块数, 包括跨过
+    // DIRECT_COUNT / INDIRECT_BOUND / DOUBLE_BOUND 时新增的间接块
     fn blocks_needed(old_size: usize, new_size: usize) -> usize {
         assert!(new_size > old_size);
         Ext2Inode::total_blocks(new_size) - Ext2Inode::total_blocks(old_size)
     }
+    // 从 old_size 缩小到 new_size 释放的块数, 同样包括间接块
     fn blocks_freed(old_size: usize, new_size: usize) -> usize {
         assert!(new_size < old_size);
         Ext2Inode::total_blocks(old_size) - Ext2Inode::total_blocks(new_size)
@@ -183,9 +210,9 @@ impl Inode {
         loop {
             let write_size = if rest < block::SIZE {
                 let vec = alloc::vec![0u8; rest];
-                self.write_at(offset, &vec)?
+                self.write_data(offset, &vec)?
             } else {
-                self.write_at(offset, &buf)?
+                self.write_data(offset, &buf)?
             };
             rest -= write_size;
             if rest == 0 {
@@ -197,20 +224,198 @@ impl Inode {
         Ok(())
     }
 
+    /// 文件内容的只读视图, 访问到的块原地读取, 不复制到调用者的缓冲区
+    pub fn map_readonly(&self) -> VfsResult<BlockView> {
+        if !self.is_file() {
+            return Err(IOError::new(IOErrorKind::NotAFile).into());
+        }
+        self.check_permission(VfsPermission::read)?;
+        Ok(BlockView {
+            inode: self.clone(),
+            size: self.size(),
+            pinned: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    /// 按块依次读出 [offset, offset + len), 超出文件大小的部分被截掉.
+    /// 每次只拷贝一块, 适合边读边发送的场景
+    pub fn block_stream(&self, offset: usize, len: usize) -> BlockStream {
+        let end = offset.saturating_add(len).min(self.size());
+        BlockStream {
+            inode: self.clone(),
+            offset: offset.min(end),
+            end,
+        }
+    }
+
+    // 分配数据块时优先的块组: 文件最后一个数据块所在的块组, 还没有数据块时是 inode 所在的块组
+    fn goal_group(&self) -> usize {
+        let last_block = self.read_disk_inode(|ext2_inode| {
+            // 快速符号链接的块指针里存放的是目标路径
+            let blocks = Ext2Inode::data_blocks(ext2_inode.size());
+            if ext2_inode.sectors_count == 0 || blocks == 0 {
+                return 0;
+            }
+            ext2_inode.block_id_for(blocks as u32 - 1)
+        });
+        if last_block != 0 {
+            last_block as usize / self.layout().blocks_per_group() as usize
+        } else {
+            (self.inode_id() - 1) / self.layout().inodes_per_group() as usize
+        }
+    }
+
     pub fn increase_to(&mut self, new_size: usize) -> VfsResult<()> {
         assert!(self.size() < new_size);
         let cur_offset = self.size();
+        self.prepare_growth(new_size)?;
         let needed_num = Self::blocks_needed(self.size(), new_size);
-        let new_blocks = self.allocator.lock().alloc_data(needed_num)?;
+        let goal_bg = self.goal_group();
+        let new_blocks = self.allocator.lock().alloc_data_near(needed_num, goal_bg)?;
         self.modify_disk_inode(|ext2_inode| {
             ext2_inode.increase_to(new_size, new_blocks);
         });
-        // 扩充的空间用 0 填充
+        // 扩充的空间用 0 填充: 新分配的块可能残留其他文件的数据,
+        // 旧的最后一块在 size 之后也可能残留缩小前的数据, 都不能被读到
         self.clear_from(cur_offset, new_size - cur_offset)?;
 
         Ok(())
     }
 
+    // 只分配间接块, 新扩出来的数据块全部留作空洞
+    fn increase_sparse(&mut self, new_size: usize) -> VfsResult<()> {
+        let old_size = self.size();
+        assert!(old_size < new_size);
+        self.prepare_growth(new_size)?;
+        let start_block = Ext2Inode::data_blocks(old_size);
+        let end_block = Ext2Inode::data_blocks(new_size);
+        let metas = Ext2Inode::fill_order(start_block, end_block)
+            .filter(|&meta| meta)
+            .count();
+        let mut meta_blocks = self.allocator.lock().alloc_data(metas)?.into_iter();
+        let new_blocks = Ext2Inode::fill_order(start_block, end_block)
+            .map(|meta| if meta { meta_blocks.next().unwrap() } else { 0 })
+            .collect();
+        self.modify_disk_inode(|ext2_inode| ext2_inode.increase_to(new_size, new_blocks));
+
+        // 旧的最后一块在 size 之后可能残留缩小前的数据
+        let tail_end = ceil!(old_size, block::SIZE).min(new_size);
+        if tail_end > old_size {
+            self.clear_from(old_size, tail_end - old_size)?;
+        }
+        Ok(())
+    }
+
+    // 扩容时 fill_order 只在间接块所索引范围的起点分配该间接块. Linux 创建的稀疏文件中,
+    // 末尾所在的间接块可能因为整个范围都是空洞而不存在, 需要先补上
+    fn prepare_growth(&mut self, new_size: usize) -> VfsResult<()> {
+        let start_block = Ext2Inode::data_blocks(self.size());
+        if start_block >= Ext2Inode::data_blocks(new_size) {
+            return Ok(());
+        }
+        let allocated_by_growth = Ext2Inode::fill_order(start_block, start_block + 1)
+            .filter(|&meta| meta)
+            .count();
+        let missing = self
+            .read_disk_inode(|ext2_inode| ext2_inode.missing_indirects(start_block))
+            .saturating_sub(allocated_by_growth);
+        self.attach_indirects(start_block, missing)
+    }
+
+    // 从上往下给 inner_idx 所在路径补上 count 个清零的间接块
+    fn attach_indirects(&mut self, inner_idx: usize, count: usize) -> VfsResult<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        let indirects = self.allocator.lock().alloc_data(count)?;
+        for &block_id in &indirects {
+            block_device::modify(block_id as usize, 0, |data: &mut DataBlock| data.fill(0));
+        }
+        self.modify_disk_inode(|ext2_inode| ext2_inode.attach_indirects(inner_idx, &indirects));
+        Ok(())
+    }
+
+    // 为 [start_block, end_block) 中的空洞分配清零的块, 缺少的间接块一并分配
+    fn fill_holes(&mut self, start_block: usize, end_block: usize) -> VfsResult<()> {
+        let holes = self.read_disk_inode(|ext2_inode| {
+            (start_block..end_block)
+                .filter(|&idx| ext2_inode.block_id_for(idx as u32) == 0)
+                .collect::<Vec<_>>()
+        });
+        if holes.is_empty() {
+            return Ok(());
+        }
+
+        let new_blocks = self.allocator.lock().alloc_data_contiguous(holes.len())?;
+        for (idx, block_id) in holes.into_iter().zip(new_blocks) {
+            block_device::modify(block_id as usize, 0, |data: &mut DataBlock| data.fill(0));
+            let missing = self.read_disk_inode(|ext2_inode| ext2_inode.missing_indirects(idx));
+            self.attach_indirects(idx, missing)?;
+            self.modify_disk_inode(|ext2_inode| ext2_inode.fill_hole(idx, block_id));
+        }
+        Ok(())
+    }
+
+    /// 稀疏写: buf 中整块为 0 的部分如果落在空洞上则不分配块, 文件大小仍按完整长度计算
+    pub fn write_at_sparse(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize> {
+        let end = offset + buf.len();
+        if self.size() < end {
+            self.increase_sparse(end)?;
+        }
+
+        let mut start = offset;
+        while start < end {
+            let block_end = ((start / block::SIZE + 1) * block::SIZE).min(end);
+            let chunk = &buf[start - offset..block_end - offset];
+            let inner_idx = start / block::SIZE;
+            let is_hole =
+                self.read_disk_inode(|ext2_inode| ext2_inode.block_id_for(inner_idx as u32) == 0);
+            if !(is_hole && chunk.iter().all(|&x| x == 0)) {
+                self.fill_holes(inner_idx, inner_idx + 1)?;
+                self.modify_disk_inode(|ext2_inode| ext2_inode.write_at(start, chunk));
+            }
+            start = block_end;
+        }
+        Ok(buf.len())
+    }
+
+    /// 把内容全为 0 的数据块变成空洞并释放, 读出的内容不变. 返回释放的块数
+    pub fn sparsify(&mut self) -> VfsResult<usize> {
+        if !self.is_file() {
+            return Err(IOError::new(IOErrorKind::NotAFile).into());
+        }
+        self.check_permission(VfsPermission::write)?;
+        self.check_modifiable(None)?;
+
+        let size = self.size();
+        let zeroed: Vec<usize> = self.read_disk_inode(|ext2_inode| {
+            (0..Ext2Inode::data_blocks(size))
+                .filter(|&idx| match ext2_inode.block_id_for(idx as u32) {
+                    0 => false,
+                    // 最后一块只看文件大小以内的部分
+                    block_id => {
+                        let valid = (size - idx * block::SIZE).min(block::SIZE);
+                        block_device::read(block_id as usize, 0, |data: &DataBlock| {
+                            data[..valid].iter().all(|&x| x == 0)
+                        })
+                    }
+                })
+                .collect()
+        });
+        if zeroed.is_empty() {
+            return Ok(0);
+        }
+
+        let freed: Vec<u32> = self.modify_disk_inode(|ext2_inode| {
+            zeroed
+                .iter()
+                .map(|&idx| ext2_inode.punch_hole(idx))
+                .collect()
+        });
+        self.allocator.lock().dealloc_data(freed)?;
+        Ok(zeroed.len())
+    }
+
     pub fn decrease_to(&mut self, new_size: usize) -> VfsResult<()> {
         assert!(
             self.size() > new_size,
@@ -220,30 +425,61 @@ impl Inode {
         );
         let freed_num = Self::blocks_freed(self.size(), new_size);
         let freed = self.modify_disk_inode(|ext2_inode| ext2_inode.decrease_to(new_size));
-        assert_eq!(freed.len(), freed_num);
+        // 空洞不占用块, 因此可能比按大小算出来的少
+        assert!(freed.len() <= freed_num);
 
         self.allocator.lock().dealloc_data(freed)?;
 
         Ok(())
     }
-}
 
-impl VfsInode for Inode {
-    fn read_at(&self, offset: usize, buf: &mut [u8]) -> VfsResult<usize> {
-        Ok(self.read_disk_inode(|ext2_inode| ext2_inode.read_at(offset, buf)))
-    }
+    // 与 write_at 相同但不做任何检查, 扩容时填 0 也走这里
+    pub(crate) fn write_data(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // 最常见的覆盖写只需要一次 inode 块的查找
+        if let Some(written) = self.write_and_update(offset, buf, Ext2Inode::touch_modified) {
+            return Ok(written);
+        }
 
-    fn write_at(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize> {
         // 如果当前 size 不够则需要先扩容
         let end_offset = offset + buf.len();
         if self.size() < end_offset {
             self.increase_to(end_offset)?;
         }
+        // 写到稀疏文件的空洞上时需要先分配块
+        self.fill_holes(offset / block::SIZE, ceil_index!(end_offset, block::SIZE))?;
 
-        Ok(self.modify_disk_inode(|disk_inode| disk_inode.write_at(offset, buf)))
+        Ok(self
+            .write_and_update(offset, buf, Ext2Inode::touch_modified)
+            .expect("space has been prepared"))
     }
 
-    fn set_len(&mut self, len: usize) -> VfsResult<()> {
+    /// 在同一次 inode 块的 modify 中写入数据并用 update 修改 inode,
+    /// 数据块和 inode 块一起被标记为脏. [offset, offset + buf.len()) 超出文件大小
+    /// 或者含有空洞时什么也不做, 返回 None, 需要先扩容或分配块
+    pub(crate) fn write_and_update(
+        &self,
+        offset: usize,
+        buf: &[u8],
+        update: impl FnOnce(&mut Ext2Inode),
+    ) -> Option<usize> {
+        let end_offset = offset + buf.len();
+        self.modify_disk_inode(|disk_inode| {
+            let prepared = end_offset <= disk_inode.size()
+                && (offset / block::SIZE..ceil_index!(end_offset, block::SIZE))
+                    .all(|idx| disk_inode.block_id_for(idx as u32) != 0);
+            if !prepared {
+                return None;
+            }
+            update(disk_inode);
+            Some(disk_inode.write_at(offset, buf))
+        })
+    }
+
+    // 与 set_len 相同但不检查权限, 删除文件时释放空间用
+    pub(crate) fn resize(&mut self, len: usize) -> VfsResult<()> {
         use core::cmp::Ordering;
         match self.size().cmp(&len) {
             Ordering::Less => self.increase_to(len),
@@ -252,6 +488,112 @@ impl VfsInode for Inode {
         }
     }
 
+    // IMMUTABLE 的文件不能写也不能截断; APPEND_ONLY 的文件只能从末尾写入.
+    // offset 为 None 表示截断
+    pub(crate) fn check_modifiable(&self, offset: Option<usize>) -> VfsResult<()> {
+        self.layout.check_writable()?;
+        let (immutable, append_only) = self
+            .read_disk_inode(|ext2_inode| (ext2_inode.is_immutable(), ext2_inode.is_append_only()));
+        let allowed = !immutable && (!append_only || offset == Some(self.size()));
+        if !allowed {
+            return Err(IOError::new(IOErrorKind::PermissionDenied).into());
+        }
+        Ok(())
+    }
+
+    // 只有所有者和 root 可以修改 inode 的属性, 比如粘滞位. 没有指定身份时不做检查
+    pub(crate) fn check_owner(&self) -> VfsResult<()> {
+        let Some(credentials) = self.layout.credentials() else {
+            return Ok(());
+        };
+        let owner = self.read_disk_inode(|disk_inode| disk_inode.uid());
+        if credentials.uid != 0 && credentials.uid != owner {
+            return Err(IOError::new(IOErrorKind::PermissionDenied).into());
+        }
+        Ok(())
+    }
+
+    // 只有 root 可以修改 IMMUTABLE 和 APPEND_ONLY 这类标志 (CAP_LINUX_IMMUTABLE). 没有指定身份时不做检查
+    pub(crate) fn check_root(&self) -> VfsResult<()> {
+        match self.layout.credentials() {
+            Some(credentials) if credentials.uid != 0 => {
+                Err(IOError::new(IOErrorKind::PermissionDenied).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 挂载时指定了身份则检查对应的权限位, 否则不做检查.
+    // 目录的执行位即搜索权限
+    pub(crate) fn check_permission(&self, allowed: fn(&VfsPermission) -> bool) -> VfsResult<()> {
+        let Some(credentials) = self.layout.credentials() else {
+            return Ok(());
+        };
+        let permission = self.read_disk_inode(|disk_inode| {
+            credentials.permission_for(
+                disk_inode.uid(),
+                disk_inode.gid(),
+                &disk_inode.permissions(),
+            )
+        });
+        if !allowed(&permission) {
+            return Err(IOError::new(IOErrorKind::PermissionDenied).into());
+        }
+        Ok(())
+    }
+}
+
+impl VfsInode for Inode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> VfsResult<usize> {
+        self.check_permission(VfsPermission::read)?;
+        let (read, atime) = self.read_disk_inode(|ext2_inode| {
+            let read = ext2_inode.read_at(offset, buf);
+            if read != 0 {
+                ext2_inode.read_ahead((offset + read - 1) / block::SIZE);
+            }
+            (read, ext2_inode.pending_atime())
+        });
+        // atime 没有变化时不写盘, 避免每次读取都弄脏 inode 所在的块. 只读时读取照常, 只是不记录 atime
+        let writable = self.layout.check_writable().is_ok();
+        if let Some(atime) = atime.filter(|_| !self.layout.noatime() && writable) {
+            self.modify_disk_inode(|ext2_inode| ext2_inode.atime = atime);
+        }
+        Ok(read)
+    }
+
+    fn write_at(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize> {
+        self.check_permission(VfsPermission::write)?;
+        self.check_modifiable(Some(offset))?;
+        self.write_data(offset, buf)
+    }
+
+    fn write_at_sparse(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize> {
+        self.check_permission(VfsPermission::write)?;
+        self.check_modifiable(Some(offset))?;
+        let written = self.write_at_sparse(offset, buf)?;
+        self.modify_disk_inode(|disk_inode| disk_inode.touch_modified());
+        Ok(written)
+    }
+
+    fn append(&mut self, buf: &[u8]) -> VfsResult<usize> {
+        let size = self.size();
+        self.write_at(size, buf)
+    }
+
+    fn set_len(&mut self, len: usize) -> VfsResult<()> {
+        self.check_permission(VfsPermission::write)?;
+        self.check_modifiable(None)?;
+        if self.size() != len {
+            self.resize(len)?;
+            self.modify_disk_inode(|disk_inode| disk_inode.touch_modified());
+        }
+        Ok(())
+    }
+
+    fn inode_id(&self) -> usize {
+        self.inode_id
+    }
+
     fn metadata(&self) -> Box<dyn VfsMetadata> {
         // 有趣的是, 如果函数重名(比如这里的 metadata 和 Inode 的 metadata)
         // 并不会发生冲突, 而是结构体方法优先
@@ -259,6 +601,9 @@ impl VfsInode for Inode {
     }
 
     fn set_permissions(&mut self, permissions: &VfsPermissions) -> VfsResult<()> {
+        self.layout.check_writable()?;
+        // 与 chmod 一样只有所有者和 root 可以修改, 否则可以借此绕过写权限和粘滞位的检查
+        self.check_owner()?;
         self.modify_disk_inode(|disk_inode| disk_inode.set_permissions(permissions));
         Ok(())
     }
@@ -269,4 +614,115 @@ impl VfsInode for Inode {
         }
         Ok(self.read_symlink())
     }
+
+    fn try_clone(&self) -> VfsResult<Box<dyn VfsInode>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn open_at(&self, path: &str) -> VfsResult<Box<dyn VfsInode>> {
+        if path.starts_with('/') {
+            let err: VfsError = VfsErrorKind::InvalidPath(path.to_string()).into();
+            return Err(err.with_additional("open_at expects a relative path"));
+        }
+        if !self.is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory).into());
+        }
+
+        let vpath = VfsPath::from(path);
+        Ok(Box::new(self.walk(&vpath)?))
+    }
+}
+
+/// Inode::block_stream 返回的迭代器, 产生 (文件内偏移, 数据).
+/// 除了首尾两块, 每一项都恰好是一个完整的块
+pub struct BlockStream {
+    inode: Inode,
+    offset: usize,
+    end: usize,
+}
+
+impl Iterator for BlockStream {
+    type Item = (usize, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None;
+        }
+        let inner_idx = self.offset / block::SIZE;
+        let start = self.offset % block::SIZE;
+        let stop = ((inner_idx + 1) * block::SIZE).min(self.end) - inner_idx * block::SIZE;
+        let block_id = self
+            .inode
+            .read_disk_inode(|ext2_inode| ext2_inode.block_id_for(inner_idx as u32));
+        // 空洞读出来是 0
+        let data = if block_id == 0 {
+            alloc::vec![0u8; stop - start]
+        } else {
+            block_device::read(block_id as usize, 0, |data: &DataBlock| {
+                data[start..stop].to_vec()
+            })
+        };
+
+        let file_offset = self.offset;
+        self.offset += data.len();
+        Some((file_offset, data))
+    }
+}
+
+// 空洞在视图中读出的内容
+static ZERO_BLOCK: DataBlock = [0; block::SIZE];
+
+/// Inode::map_readonly 返回的只读视图. 文件大小在建立视图时确定.
+/// 第一次访问某个块时把它的缓存固定下来, 直到视图被 drop 都不会被换出
+pub struct BlockView {
+    inode: Inode,
+    size: usize,
+    // 文件内块号 -> 缓存, None 表示空洞
+    pinned: RefCell<BTreeMap<usize, Option<Arc<Mutex<BlockCache>>>>>,
+}
+
+impl BlockView {
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// 已经固定在缓存中的块数, 不含空洞
+    pub fn pinned_blocks(&self) -> usize {
+        self.pinned.borrow().values().flatten().count()
+    }
+
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.with_block(offset / block::SIZE, |data| {
+            data.get(offset % block::SIZE).copied()
+        })
+        .flatten()
+    }
+
+    /// 以文件内第 inner_idx 块中属于文件的部分调用 f, 越过文件末尾时返回 None
+    pub fn with_block<V>(&self, inner_idx: usize, f: impl FnOnce(&[u8]) -> V) -> Option<V> {
+        let start = inner_idx.checked_mul(block::SIZE)?;
+        if start >= self.size {
+            return None;
+        }
+        let valid = (self.size - start).min(block::SIZE);
+        let cache = self
+            .pinned
+            .borrow_mut()
+            .entry(inner_idx)
+            .or_insert_with(|| {
+                let block_id = self
+                    .inode
+                    .read_disk_inode(|ext2_inode| ext2_inode.block_id_for(inner_idx as u32));
+                (block_id != 0).then(|| block_device::pin(block_id as usize))
+            })
+            .clone();
+        Some(match cache {
+            Some(cache) => cache.lock().read(0, |data: &DataBlock| f(&data[..valid])),
+            None => f(&ZERO_BLOCK[..valid]),
+        })
+    }
 }
@@ -0,0 +1,240 @@
+use alloc::vec::Vec;
+
+use crate::{
+    block::{self, DataBlock},
+    block_device,
+    vfs::error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult},
+};
+
+use super::{
+    blockgroup::Ext2BlockGroupDesc,
+    disk_inode::Ext2Inode,
+    superblock::{FeaturesRequired, Superblock},
+};
+
+// ext3 日志 (JBD) 的最小实现: 只认识描述块和提交块, 不支持撤销块和校验和.
+// 日志中的字段都是大端序
+
+const JBD_MAGIC: u32 = 0xC03B_3998;
+
+const DESCRIPTOR_BLOCK: u32 = 1;
+const COMMIT_BLOCK: u32 = 2;
+const SUPERBLOCK_V1: u32 = 3;
+const SUPERBLOCK_V2: u32 = 4;
+const REVOKE_BLOCK: u32 = 5;
+
+// 数据块开头恰好是 JBD_MAGIC, 写入日志时被清零了
+const TAG_ESCAPE: u32 = 1;
+// 与上一个 tag 属于同一个文件系统, 否则 tag 后面跟着 16 字节的 uuid
+const TAG_SAME_UUID: u32 = 2;
+const TAG_LAST: u32 = 8;
+const TAG_SIZE: usize = 8;
+const UUID_SIZE: usize = 16;
+
+// v2 日志超级块中只认识 revoke 这一个 incompat 特性
+const INCOMPAT_REVOKE: u32 = 1;
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+// 日志块的公共头部: magic, 块类型, 事务序号
+fn header(data: &[u8]) -> Option<(u32, u32)> {
+    (be32(data, 0) == JBD_MAGIC).then(|| (be32(data, 4), be32(data, 8)))
+}
+
+// 一个事务要写回的块: (目标块号, 数据)
+type Writes = Vec<(u32, Vec<u8>)>;
+
+pub(crate) struct Journal {
+    inode: Ext2Inode,
+    // 日志中第一个存放事务的块, 写到末尾后回绕到这里
+    first: u32,
+    maxlen: u32,
+    sequence: u32,
+    // 0 表示日志为空
+    start: u32,
+    // 文件系统的总块数, 重放的目标块号必须小于它
+    blocks_count: u32,
+}
+
+impl Journal {
+    /// 读出日志 inode 和日志超级块, 不修改任何东西
+    pub(crate) fn open(superblock: &Superblock) -> VfsResult<Self> {
+        let corrupted = |msg| -> VfsError {
+            VfsError::from(IOError::new(IOErrorKind::Corrupted)).with_additional(msg)
+        };
+        let journal_inode = superblock.journal_inode;
+        if journal_inode == 0 || journal_inode > superblock.inodes_count {
+            return Err(corrupted("bad journal inode number"));
+        }
+        let inode_idx = journal_inode as usize - 1;
+        let inodes_per_group = superblock.inodes_per_group as usize;
+        let bg_idx = inode_idx / inodes_per_group;
+        let blockgroups = Ext2BlockGroupDesc::find(superblock.blockgroup_count());
+        let offset = (inode_idx % inodes_per_group) * superblock.inode_size();
+        let block_id = blockgroups[bg_idx].inode_table_block as usize + offset / block::SIZE;
+        let inode = block_device::read(block_id, offset % block::SIZE, |inode: &Ext2Inode| {
+            inode.clone()
+        });
+
+        let mut journal = Self {
+            inode,
+            first: 0,
+            maxlen: 0,
+            sequence: 0,
+            start: 0,
+            blocks_count: superblock.blocks_count,
+        };
+        let sb = journal.read(0);
+        match header(&sb) {
+            Some((SUPERBLOCK_V1, _)) => {}
+            Some((SUPERBLOCK_V2, _)) => {
+                if be32(&sb, 40) & !INCOMPAT_REVOKE != 0 {
+                    let err: VfsError = VfsErrorKind::NotSupported.into();
+                    return Err(err.with_additional("unsupported journal features"));
+                }
+            }
+            _ => return Err(corrupted("bad journal superblock")),
+        }
+        if be32(&sb, 12) as usize != block::SIZE {
+            return Err(corrupted("journal block size mismatch"));
+        }
+
+        journal.maxlen = be32(&sb, 16);
+        journal.first = be32(&sb, 20);
+        journal.sequence = be32(&sb, 24);
+        journal.start = be32(&sb, 28);
+        // 日志不能超出日志 inode 的大小, 否则 block_id_for 会越过 inode 能寻址的范围
+        if journal.maxlen as usize > journal.inode.size() / block::SIZE {
+            return Err(corrupted("journal longer than its inode"));
+        }
+        // 0 号块是日志超级块, 事务只能放在 [first, maxlen) 中
+        let in_log = |idx| journal.first <= idx && idx < journal.maxlen;
+        if journal.first == 0
+            || !in_log(journal.first)
+            || (journal.is_dirty() && !in_log(journal.start))
+        {
+            return Err(corrupted("bad journal geometry"));
+        }
+        Ok(journal)
+    }
+
+    /// 日志中是否还有没写回的事务
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.start != 0
+    }
+
+    fn read(&self, idx: u32) -> Vec<u8> {
+        let block_id = self.inode.block_id_for(idx);
+        block_device::read(block_id as usize, 0, |data: &DataBlock| data.to_vec())
+    }
+
+    fn next(&self, idx: u32) -> u32 {
+        if idx + 1 >= self.maxlen {
+            self.first
+        } else {
+            idx + 1
+        }
+    }
+
+    // 从 idx 处的描述块开始读一个事务, 返回 (要写回的块, 提交块之后的位置).
+    // 没有读到提交块说明事务不完整, 返回 None.
+    // 一个事务最多占满整个日志, 读的块数超过日志长度说明日志首尾相接, 没有提交块
+    fn read_transaction(&self, mut idx: u32) -> VfsResult<Option<(Writes, u32)>> {
+        let mut budget = self.maxlen - self.first;
+        let mut read = |idx| {
+            if budget == 0 {
+                let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+                return Err(err.with_additional("journal transaction never commits"));
+            }
+            budget -= 1;
+            Ok(self.read(idx))
+        };
+        let mut writes = Vec::new();
+        loop {
+            let block = read(idx)?;
+            match header(&block) {
+                Some((DESCRIPTOR_BLOCK, seq)) if seq == self.sequence => {
+                    let mut tags = Vec::new();
+                    let mut offset = 12;
+                    while offset + TAG_SIZE <= block::SIZE {
+                        let flags = be32(&block, offset + 4);
+                        tags.push((be32(&block, offset), flags));
+                        offset += TAG_SIZE;
+                        if flags & TAG_SAME_UUID == 0 {
+                            offset += UUID_SIZE;
+                        }
+                        if flags & TAG_LAST != 0 {
+                            break;
+                        }
+                    }
+                    for (target, flags) in tags {
+                        idx = self.next(idx);
+                        let mut data = read(idx)?;
+                        if flags & TAG_ESCAPE != 0 {
+                            data[..4].copy_from_slice(&JBD_MAGIC.to_be_bytes());
+                        }
+                        writes.push((target, data));
+                    }
+                    idx = self.next(idx);
+                }
+                Some((COMMIT_BLOCK, seq)) if seq == self.sequence => {
+                    return Ok(Some((writes, self.next(idx))));
+                }
+                Some((REVOKE_BLOCK, seq)) if seq == self.sequence => {
+                    let err: VfsError = VfsErrorKind::NotSupported.into();
+                    return Err(err.with_additional("journal revoke blocks"));
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// 依次重放已经提交的事务, 然后把日志标记为空. 返回重放的事务数
+    pub(crate) fn replay(&mut self) -> VfsResult<usize> {
+        let corrupted = |msg| -> VfsError {
+            VfsError::from(IOError::new(IOErrorKind::Corrupted)).with_additional(msg)
+        };
+        let mut replayed = 0;
+        // 日志为空时没有事务, 否则一直读到第一个不完整的事务
+        let mut idx = self.is_dirty().then_some(self.start);
+        while let Some(start) = idx {
+            let Some((writes, next)) = self.read_transaction(start)? else {
+                break;
+            };
+            // 每个事务至少占一个块, 事务数超过日志长度说明日志首尾相接, 不会自己结束
+            if replayed >= self.maxlen as usize {
+                return Err(corrupted("journal transactions never end"));
+            }
+            // 先检查整个事务, 不写回一半
+            if writes
+                .iter()
+                .any(|(target, _)| *target >= self.blocks_count)
+            {
+                return Err(corrupted("journal target block out of range"));
+            }
+            for (target, data) in writes {
+                block_device::modify(target as usize, 0, |block: &mut DataBlock| {
+                    block.copy_from_slice(&data)
+                });
+            }
+            replayed += 1;
+            self.sequence = self.sequence.wrapping_add(1);
+            idx = Some(next);
+        }
+
+        // 已提交的都写回了, 剩下不完整的事务直接丢弃
+        self.start = 0;
+        let sb_block = self.inode.block_id_for(0) as usize;
+        block_device::modify(sb_block, 0, |sb: &mut DataBlock| {
+            sb[24..28].copy_from_slice(&self.sequence.to_be_bytes());
+            sb[28..32].copy_from_slice(&self.start.to_be_bytes());
+        });
+        block_device::modify(0, 1024, |sb: &mut Superblock| {
+            sb.features_req.remove(FeaturesRequired::REQ_REPLAY_JOURNAL)
+        });
+        block_device::flush();
+        Ok(replayed)
+    }
+}
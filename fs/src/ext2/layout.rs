@@ -1,25 +1,114 @@
-use alloc::{sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use alloc::{format, sync::Arc, vec::Vec};
 use spin::Mutex;
 
-use crate::{block::DataBlock, block_device, cast_mut, vfs::meta::VfsFileType};
+use crate::{
+    block::DataBlock,
+    block_device, time, try_cast_mut,
+    vfs::{
+        error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult},
+        meta::{Credentials, VfsFileType},
+    },
+};
 
 use super::{
-    allocator::Ext2Allocator, blockgroup::Ext2BlockGroupDesc, inode::Inode, superblock::Superblock,
+    address::Address,
+    allocator::Ext2Allocator,
+    blockgroup::Ext2BlockGroupDesc,
+    disk_inode::Ext2Inode,
+    inode::Inode,
+    superblock::{ErrorPolicy, Superblock, FS_CLEAN, FS_ERR},
 };
 
-#[derive(Debug)]
+/// 按文件大小需要的寻址层级统计 inode 个数, 不含空闲 inode
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InodeHistogram {
+    /// 不占数据块: 空文件, 快速符号链接, 设备文件等
+    pub empty: usize,
+    /// 恰好一个数据块
+    pub one_block: usize,
+    /// 2 到 12 个数据块, 只用直接指针
+    pub direct: usize,
+    /// 用到一级间接块
+    pub indirect: usize,
+    /// 用到二级间接块
+    pub double_indirect: usize,
+    /// 用到三级间接块
+    pub triple_indirect: usize,
+}
+
+impl InodeHistogram {
+    fn add(&mut self, ext2_inode: &Ext2Inode) {
+        // 快速符号链接的目标存放在 inode 内部
+        let data_blocks = if ext2_inode.filetype().is_symlink() && ext2_inode.sectors_count == 0 {
+            0
+        } else {
+            Ext2Inode::data_blocks(ext2_inode.size())
+        };
+        let bucket = match data_blocks {
+            0 => &mut self.empty,
+            1 => &mut self.one_block,
+            n if n <= Ext2Inode::DIRECT_COUNT => &mut self.direct,
+            n if n <= Ext2Inode::INDIRECT_BOUND => &mut self.indirect,
+            n if n <= Ext2Inode::DOUBLE_BOUND => &mut self.double_indirect,
+            _ => &mut self.triple_indirect,
+        };
+        *bucket += 1;
+    }
+}
+
 pub struct Ext2Layout {
     blocks_per_group: u32,
     inodes_per_group: u32,
+    inode_size: usize,
 
     superblock: Arc<Mutex<Superblock>>,
     blockgroups: Arc<Vec<Mutex<Ext2BlockGroupDesc>>>,
+
+    // 新建目录项时的对齐字节数, 读取时不依赖它
+    dir_align: usize,
+    // 检查权限时使用的身份, None 表示不检查
+    credentials: Option<Credentials>,
+    // 读取时是否跳过 atime 的更新
+    noatime: bool,
+
+    // 每次修改磁盘上的 inode 都会递增, 缓存的元数据据此判断是否过期
+    generation: AtomicU64,
+    // 按 ErrorPolicy::RemountReadOnly 处理过错误之后拒绝修改
+    read_only: AtomicBool,
+    // 以只读方式挂载, 拒绝修改, 也不会写回任何内容
+    mounted_read_only: bool,
+}
+
+// generation 只是运行期的计数, 不属于文件系统的内容, 不参与输出
+impl fmt::Debug for Ext2Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ext2Layout")
+            .field("blocks_per_group", &self.blocks_per_group)
+            .field("inodes_per_group", &self.inodes_per_group)
+            .field("inode_size", &self.inode_size)
+            .field("superblock", &self.superblock)
+            .field("blockgroups", &self.blockgroups)
+            .finish()
+    }
 }
 
 impl Ext2Layout {
-    pub fn new(superblock: Superblock, blockgroups: Vec<Ext2BlockGroupDesc>) -> Self {
+    pub fn new(
+        superblock: Superblock,
+        blockgroups: Vec<Ext2BlockGroupDesc>,
+        dir_align: usize,
+        credentials: Option<Credentials>,
+        noatime: bool,
+        mounted_read_only: bool,
+    ) -> Self {
         let blocks_per_group = superblock.blocks_per_group;
         let inodes_per_group = superblock.inodes_per_group;
+        let inode_size = superblock.inode_size();
 
         let superblock = Arc::new(Mutex::new(superblock));
         // 为每一个成员加上锁
@@ -28,24 +117,231 @@ impl Ext2Layout {
         Self {
             blocks_per_group,
             inodes_per_group,
+            inode_size,
             superblock,
             blockgroups,
+            dir_align,
+            credentials,
+            noatime,
+            generation: AtomicU64::new(0),
+            read_only: AtomicBool::new(false),
+            mounted_read_only,
+        }
+    }
+
+    pub(crate) fn dir_align(&self) -> usize {
+        self.dir_align
+    }
+
+    pub(crate) fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    pub(crate) fn noatime(&self) -> bool {
+        self.noatime
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn bump_generation(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// superblock 是否标记为有错误, 包括挂载前就有的
+    pub fn has_errors(&self) -> bool {
+        self.superblock.lock().state & FS_ERR != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.mounted_read_only || self.read_only.load(Ordering::Acquire)
+    }
+
+    // 运行中检测到文件系统损坏: superblock 标记为有错误 (flush 时写回),
+    // 再按 errors 字段的策略处理. 原样返回 err, 方便在 map_err 中使用
+    pub(crate) fn error(&self, err: VfsError) -> VfsError {
+        let policy = {
+            let mut sb = self.superblock.lock();
+            sb.state |= FS_ERR;
+            sb.error_policy()
+        };
+        match policy {
+            ErrorPolicy::Continue => {}
+            ErrorPolicy::RemountReadOnly => self.read_only.store(true, Ordering::Release),
+            ErrorPolicy::Panic => panic!("ext2 file system error: {:?}", err),
+        }
+        err
+    }
+
+    // 出错后变为只读时拒绝修改
+    pub(crate) fn check_writable(&self) -> VfsResult<()> {
+        if self.mounted_read_only {
+            let err: VfsError = IOError::new(IOErrorKind::PermissionDenied).into();
+            return Err(err.with_additional("file system is mounted read-only"));
+        }
+        if self.is_read_only() {
+            let err: VfsError = IOError::new(IOErrorKind::PermissionDenied).into();
+            return Err(err.with_additional("file system is read-only after errors"));
         }
+        Ok(())
+    }
+
+    // 卸载前调用, 之后的 flush 会在磁盘上写入 clean. 错误标记保持不变
+    pub(crate) fn mark_clean(&self) {
+        self.superblock.lock().state |= FS_CLEAN;
     }
 
     pub fn flush(&self) {
+        // 只读挂载时磁盘上的内容保持原样
+        if self.mounted_read_only {
+            return;
+        }
+        // 空闲计数随分配实时更新, 写回时再记下写入时间. 没有时钟时保留原来的写入时间
+        if let Some(now) = time::now() {
+            self.superblock.lock().wtime = now as u32;
+        }
         block_device::modify(0, 1024, |sb: &mut Superblock| {
             sb.clone_from(&self.superblock.lock());
         });
 
-        block_device::modify(1, 0, |data: &mut DataBlock| {
-            let bg_size = core::mem::size_of::<Ext2BlockGroupDesc>();
-            for (idx, bg) in self.blockgroups.iter().enumerate() {
-                let dst = &mut data[idx * bg_size..];
-                let disk_bg = cast_mut!(dst.as_ptr(), Ext2BlockGroupDesc);
-                disk_bg.clone_from(&bg.lock())
+        // 主描述符表和各个备份都要写回
+        let backups = {
+            let sb = self.superblock.lock();
+            (0..self.blockgroups.len())
+                .filter(|&bg_idx| sb.has_backup(bg_idx))
+                .collect::<Vec<_>>()
+        };
+        for bg_idx in backups {
+            self.write_descriptors(bg_idx * self.blocks_per_group as usize);
+        }
+    }
+
+    // 把描述符表写到 group_start 开始的块组中, 描述符表可能跨越多个块
+    fn write_descriptors(&self, group_start: usize) {
+        let bg_size = core::mem::size_of::<Ext2BlockGroupDesc>();
+        for (table_idx, chunk) in self
+            .blockgroups
+            .chunks(Ext2BlockGroupDesc::PER_BLOCK)
+            .enumerate()
+        {
+            let block_id = group_start + Ext2BlockGroupDesc::TABLE_START + table_idx;
+            block_device::modify(block_id, 0, |data: &mut DataBlock| {
+                for (idx, bg) in chunk.iter().enumerate() {
+                    let disk_bg = try_cast_mut::<Ext2BlockGroupDesc>(&mut data[idx * bg_size..])
+                        .expect("block group descriptor out of the descriptor block");
+                    disk_bg.clone_from(&bg.lock())
+                }
+            });
+        }
+    }
+
+    // 按 bitmap 重新统计空闲块和空闲 inode, 与块组描述符以及 superblock 中记录的比较
+    pub(crate) fn verify_free_counts(&self) -> VfsResult<()> {
+        // 分配器先锁块组再锁 superblock, 这里先取出 superblock 中的计数并释放锁, 不能反过来持有
+        let (blocks_count, sb_free_blocks, sb_free_inodes) = {
+            let sb = self.superblock.lock();
+            (
+                sb.blocks_count as usize,
+                sb.free_blocks_count as usize,
+                sb.free_inodes_count as usize,
+            )
+        };
+        let blocks_per_group = self.blocks_per_group as usize;
+
+        let mut mismatched = Vec::new();
+        let (mut total_blocks, mut total_inodes) = (0, 0);
+        for (bg_idx, bg) in self.blockgroups.iter().enumerate() {
+            let bg = bg.lock();
+            // 最后一个块组可能不满
+            let blocks_in_group = (blocks_count - bg_idx * blocks_per_group).min(blocks_per_group);
+            let (free_blocks, free_inodes) =
+                bg.recount_free(blocks_in_group, self.inodes_per_group as usize);
+            if free_blocks != bg.free_blocks_count as usize
+                || free_inodes != bg.free_inodes_count as usize
+            {
+                mismatched.push(bg_idx);
             }
-        });
+            total_blocks += free_blocks;
+            total_inodes += free_inodes;
+        }
+
+        if mismatched.is_empty() && total_blocks == sb_free_blocks && total_inodes == sb_free_inodes
+        {
+            return Ok(());
+        }
+        let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+        Err(err.with_additional(format!(
+            "free counts mismatch bitmaps in block groups {:?} (superblock: {} blocks, {} inodes; bitmaps: {} blocks, {} inodes)",
+            mismatched, sb_free_blocks, sb_free_inodes, total_blocks, total_inodes
+        )))
+    }
+
+    // 各块组描述符中 (空闲块, 空闲 inode) 的总和
+    fn group_free_counts(&self) -> (u32, u32) {
+        self.blockgroups
+            .iter()
+            .fold((0, 0), |(blocks, inodes), bg| {
+                let bg = bg.lock();
+                (
+                    blocks + bg.free_blocks_count as u32,
+                    inodes + bg.free_inodes_count as u32,
+                )
+            })
+    }
+
+    // 用块组描述符中的总和覆盖 superblock 中的空闲计数
+    pub(crate) fn resync_summary(&self) {
+        let (free_blocks, free_inodes) = self.group_free_counts();
+        let mut sb = self.superblock.lock();
+        sb.free_blocks_count = free_blocks;
+        sb.free_inodes_count = free_inodes;
+    }
+
+    // block bitmap 中超出块组实际块数的填充位必须全部置位, 否则可能分配出不存在的块
+    pub(crate) fn verify_bitmap_padding(&self) -> VfsResult<()> {
+        let blocks_count = self.superblock.lock().blocks_count as usize;
+        let blocks_per_group = self.blocks_per_group as usize;
+        let unpadded: Vec<usize> = self
+            .blockgroups
+            .iter()
+            .enumerate()
+            .filter(|(bg_idx, bg)| {
+                let blocks_in_group =
+                    (blocks_count - bg_idx * blocks_per_group).min(blocks_per_group);
+                bg.lock().clear_padding_bits(blocks_in_group) != 0
+            })
+            .map(|(bg_idx, _)| bg_idx)
+            .collect();
+        if unpadded.is_empty() {
+            return Ok(());
+        }
+        let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+        Err(err.with_additional(format!(
+            "padding bits of block bitmaps not set in block groups {:?}",
+            unpadded
+        )))
+    }
+
+    // 扫描每个块组中 bitmap 标记为已用的 inode, 链接数为 0 的保留 inode 不计入
+    pub(crate) fn inode_histogram(&self) -> InodeHistogram {
+        let mut histogram = InodeHistogram::default();
+        for bg in self.blockgroups.iter() {
+            let bg = bg.lock();
+            for inner_idx in bg.used_inodes(self.inodes_per_group as usize) {
+                let address = bg.inode_address(inner_idx, self.inode_size);
+                block_device::read(
+                    address.block_id(),
+                    address.offset(),
+                    |ext2_inode: &Ext2Inode| {
+                        if ext2_inode.hard_links != 0 {
+                            histogram.add(ext2_inode);
+                        }
+                    },
+                );
+            }
+        }
+        histogram
     }
 
     pub fn superblock(&self) -> Arc<Mutex<Superblock>> {
@@ -62,6 +358,9 @@ impl Ext2Layout {
     pub fn inodes_per_group(&self) -> u32 {
         self.inodes_per_group
     }
+    pub fn inode_size(&self) -> usize {
+        self.inode_size
+    }
 
     pub fn root_inode(
         &self,
@@ -95,6 +394,27 @@ impl Ext2Layout {
         bg.new_inode(inode_id, inode_inner_idx, filetype, layout, allocator)
     }
 
+    /// inode 在磁盘上的位置 (block_id, 块内偏移), 供调试工具直接读取原始字节.
+    /// inode_id 从 1 开始, 不能超过 inodes_count
+    pub fn inode_location(&self, inode_id: usize) -> VfsResult<(usize, usize)> {
+        let inodes_count = self.superblock.lock().inodes_count as usize;
+        if inode_id == 0 || inode_id > inodes_count {
+            return Err(VfsErrorKind::Other(format!(
+                "invalid inode id {}, expected 1..={}",
+                inode_id, inodes_count
+            ))
+            .into());
+        }
+        let address = self.inode_address(inode_id);
+        Ok((address.block_id(), address.offset()))
+    }
+
+    pub(crate) fn inode_address(&self, inode_id: usize) -> Address {
+        let (blockgroup_idx, inode_inner_idx) = self.inode_idx(inode_id);
+        let bg = self.blockgroups.get(blockgroup_idx).unwrap().lock();
+        bg.inode_address(inode_inner_idx, self.inode_size)
+    }
+
     fn inode_idx(&self, inode_id: usize) -> (usize, usize) {
         let inode_seq: usize = inode_id - 1;
         let blockgroup_idx = inode_seq / self.inodes_per_group as usize;
@@ -0,0 +1,84 @@
+use alloc::{string::ToString, vec::Vec};
+
+use crate::{
+    block,
+    vfs::{error::VfsResult, meta::VfsFileType, VfsInode, VfsPath},
+};
+
+use super::inode::Inode;
+
+const MAGIC: &[u8; 4] = b"E2MF";
+
+// 清单格式(小端):
+//   MAGIC | 条目数 u32 | 条目...
+// 每个条目:
+//   路径长度 u16 | 路径 | 类型 u8 | 大小 u64 | 权限 u16 | 内容哈希 u64
+// 条目按路径排序. 目录的大小和哈希都记为 0, 因为目录大小取决于块的布局
+pub(crate) fn export(root: &Inode) -> VfsResult<Vec<u8>> {
+    let root_path = VfsPath::empty(true);
+    let mut entries = root.walk_dir(&root_path)?;
+    entries.push((root_path, root.clone()));
+    entries.sort_by_cached_key(|(path, _)| path.to_string());
+
+    let mut manifest = Vec::new();
+    manifest.extend_from_slice(MAGIC);
+    manifest.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (path, inode) in entries {
+        let path = path.to_string();
+        let (filetype, mode) = inode
+            .read_disk_inode(|ext2_inode| (ext2_inode.filetype(), ext2_inode.type_perm.bits()));
+        let (size, hash) = match filetype {
+            VfsFileType::Directory => (0, 0),
+            VfsFileType::SymbolicLink => {
+                let target = inode.read_symlink();
+                (target.len() as u64, fnv1a(FNV_OFFSET, target.as_bytes()))
+            }
+            _ => (inode.size() as u64, content_hash(&inode)?),
+        };
+
+        manifest.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        manifest.extend_from_slice(path.as_bytes());
+        manifest.push(filetype_code(filetype));
+        manifest.extend_from_slice(&size.to_le_bytes());
+        manifest.extend_from_slice(&(mode & 0o7777).to_le_bytes());
+        manifest.extend_from_slice(&hash.to_le_bytes());
+    }
+    Ok(manifest)
+}
+
+fn filetype_code(filetype: VfsFileType) -> u8 {
+    match filetype {
+        VfsFileType::RegularFile => 1,
+        VfsFileType::Directory => 2,
+        VfsFileType::CharDev => 3,
+        VfsFileType::BlockDev => 4,
+        VfsFileType::FIFO => 5,
+        VfsFileType::Socket => 6,
+        VfsFileType::SymbolicLink => 7,
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// 逐块读出内容计算哈希, 空洞按 0 参与计算
+fn content_hash(inode: &Inode) -> VfsResult<u64> {
+    let size = inode.size();
+    let mut hash = FNV_OFFSET;
+    let mut buf = alloc::vec![0u8; block::SIZE];
+    let mut offset = 0;
+    while offset < size {
+        let read = inode.read_at(offset, &mut buf)?;
+        hash = fnv1a(hash, &buf[..read]);
+        offset += read;
+    }
+    Ok(hash)
+}
@@ -2,6 +2,8 @@ use core::fmt::{self, Display};
 
 use crate::vfs::meta::{VfsFileType, VfsMetadata, VfsPermissions, VfsTimeStamp};
 
+use super::disk_inode::Ext2Inode;
+
 #[derive(Debug)]
 pub struct Ext2Metadata {
     filetype: VfsFileType,
@@ -11,29 +13,39 @@ pub struct Ext2Metadata {
     uid: u16,
     gid: u16,
     hard_links: u16,
+    blocks: usize,
 }
-impl Ext2Metadata {
-    pub fn new(
-        filetype: VfsFileType,
-        permissions: VfsPermissions,
-        size: usize,
-        timestamp: VfsTimeStamp,
-        uid: u16,
-        gid: u16,
-        hard_links: u16,
-    ) -> Self {
+impl From<&Ext2Inode> for Ext2Metadata {
+    fn from(ext2_inode: &Ext2Inode) -> Self {
         Self {
-            filetype,
-            permissions,
-            size,
-            timestamp,
-            uid,
-            gid,
-            hard_links,
+            filetype: ext2_inode.filetype(),
+            permissions: ext2_inode.permissions(),
+            size: ext2_inode.size(),
+            timestamp: ext2_inode.timestamp(),
+            uid: ext2_inode.uid(),
+            gid: ext2_inode.gid(),
+            hard_links: ext2_inode.hard_links(),
+            blocks: ext2_inode.sectors_count(),
         }
     }
 }
 
+// ext2 没有创建时间, ctime 是 inode 的修改时间
+impl Ext2Metadata {
+    pub fn change_time(&self) -> u64 {
+        self.timestamp.change_time()
+    }
+    pub fn modify_time(&self) -> u64 {
+        self.timestamp.modify_time()
+    }
+    pub fn access_time(&self) -> u64 {
+        self.timestamp.access_time()
+    }
+    pub fn delete_time(&self) -> u64 {
+        self.timestamp.delete_time()
+    }
+}
+
 impl Display for Ext2Metadata {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Ext2Metadata")
@@ -68,4 +80,8 @@ impl VfsMetadata for Ext2Metadata {
     fn hard_links(&self) -> u16 {
         self.hard_links
     }
+
+    fn blocks(&self) -> u64 {
+        self.blocks as u64
+    }
 }
@@ -0,0 +1,263 @@
+use alloc::{format, vec::Vec};
+
+use crate::{
+    block::{self, DataBlock},
+    block_device, ceil_index, time,
+    vfs::{
+        error::{VfsErrorKind, VfsResult},
+        meta::{VfsFileType, VfsPermissions},
+    },
+};
+
+use super::{
+    blockgroup::{BitmapBlock, Ext2BlockGroupDesc, UNIT_WIDTH},
+    dir::Ext2DirEntry,
+    disk_inode::Ext2Inode,
+    layout::Ext2Layout,
+    superblock::{
+        FeaturesOptional, FeaturesROnly, FeaturesRequired, Superblock, ERR_IGNORE, EXT2_MAGIC,
+        FS_CLEAN, OS_LINUX,
+    },
+};
+
+const ROOT_INO: usize = 2;
+const FIRST_INO: usize = 11;
+// lost+found 占用第一个非保留的 inode
+const LOST_FOUND_INO: usize = FIRST_INO;
+const INODE_SIZE: usize = 128;
+// 每 4 个块配一个 inode
+const BLOCKS_PER_INODE: usize = 4;
+const DIR_ALIGN: usize = 4;
+
+// 一个块组中各部分的位置, 都是全局 block_id
+struct GroupPlan {
+    start: usize,
+    blocks: usize,
+    block_bitmap: usize,
+    inode_bitmap: usize,
+    inode_table: usize,
+    // 从 start 开始的元数据块数, 之后才是数据块
+    overhead: usize,
+}
+
+/// 在已注册的块设备上建立一个空的 ext2 文件系统:
+/// superblock 及其备份, 块组描述符表, bitmap, inode 表, 根目录和 lost+found.
+/// 只写入块缓存, 由调用者负责写回
+pub(crate) fn format(total_blocks: usize, blocks_per_group: usize) -> VfsResult<()> {
+    if blocks_per_group == 0
+        || !blocks_per_group.is_multiple_of(8)
+        || blocks_per_group > block::BITS
+    {
+        return Err(VfsErrorKind::Other(format!(
+            "invalid blocks per group: {}, expected a multiple of 8 in 8..={}",
+            blocks_per_group,
+            block::BITS
+        ))
+        .into());
+    }
+    if total_blocks > u32::MAX as usize {
+        return Err(VfsErrorKind::Other(format!("too many blocks: {}", total_blocks)).into());
+    }
+
+    let inodes_per_block = block::SIZE / INODE_SIZE;
+    let inodes_per_group = (blocks_per_group / BLOCKS_PER_INODE)
+        .next_multiple_of(inodes_per_block)
+        .clamp(inodes_per_block, block::BITS);
+    let table_blocks = inodes_per_group / inodes_per_block;
+
+    let mut sb = Superblock::zeroed();
+    sb.features_ronly = FeaturesROnly::RONLY_SPARSE;
+
+    // 最后一个块组放不下元数据时直接舍弃
+    let mut group_count = ceil_index!(total_blocks, blocks_per_group);
+    let mut plans = Vec::with_capacity(group_count);
+    let gdt_blocks = Ext2BlockGroupDesc::table_blocks(group_count as u32);
+    for bg_idx in 0..group_count {
+        let start = bg_idx * blocks_per_group;
+        let blocks = (total_blocks - start).min(blocks_per_group);
+        let meta = if sb.has_backup(bg_idx) {
+            Ext2BlockGroupDesc::TABLE_START + gdt_blocks
+        } else {
+            0
+        };
+        let overhead = meta + 2 + table_blocks;
+        if blocks <= overhead {
+            break;
+        }
+        plans.push(GroupPlan {
+            start,
+            blocks,
+            block_bitmap: start + meta,
+            inode_bitmap: start + meta + 1,
+            inode_table: start + meta + 2,
+            overhead,
+        });
+    }
+    // 0 号块组还要放下根目录和 lost+found 的数据块
+    if plans.is_empty() || plans[0].blocks < plans[0].overhead + 2 {
+        return Err(VfsErrorKind::Other(format!(
+            "too few blocks for an ext2 file system: {}",
+            total_blocks
+        ))
+        .into());
+    }
+    group_count = plans.len();
+    let blocks_count = plans.iter().map(|plan| plan.blocks).sum::<usize>();
+
+    let now = time::now().unwrap_or(0) as u32;
+    let root_block = plans[0].start + plans[0].overhead;
+    let lost_found_block = root_block + 1;
+
+    let mut blockgroups = Vec::with_capacity(group_count);
+    for (bg_idx, plan) in plans.iter().enumerate() {
+        let (used_blocks, used_inodes, dirs) = if bg_idx == 0 {
+            (plan.overhead + 2, FIRST_INO, 2)
+        } else {
+            (plan.overhead, 0, 0)
+        };
+        write_bitmap(plan.block_bitmap, used_blocks, plan.blocks);
+        write_bitmap(plan.inode_bitmap, used_inodes, inodes_per_group);
+        for block_id in plan.inode_table..plan.inode_table + table_blocks {
+            zero_block(block_id);
+        }
+
+        let mut desc = Ext2BlockGroupDesc::zeroed();
+        desc.block_bitmap_addr = plan.block_bitmap as u32;
+        desc.inode_bitmap_addr = plan.inode_bitmap as u32;
+        desc.inode_table_block = plan.inode_table as u32;
+        desc.free_blocks_count = (plan.blocks - used_blocks) as u16;
+        desc.free_inodes_count = (inodes_per_group - used_inodes) as u16;
+        desc.dirs_count = dirs;
+        blockgroups.push(desc);
+    }
+
+    sb.inodes_count = (inodes_per_group * group_count) as u32;
+    sb.blocks_count = blocks_count as u32;
+    sb.free_blocks_count = blockgroups
+        .iter()
+        .map(|desc| desc.free_blocks_count as u32)
+        .sum();
+    sb.free_inodes_count = blockgroups
+        .iter()
+        .map(|desc| desc.free_inodes_count as u32)
+        .sum();
+    sb.first_data_block = 0;
+    sb.log_block_size = (block::LOG_SIZE - 10) as u32;
+    sb.log_frag_size = (block::LOG_SIZE - 10) as i32;
+    sb.blocks_per_group = blocks_per_group as u32;
+    sb.frags_per_group = blocks_per_group as u32;
+    sb.inodes_per_group = inodes_per_group as u32;
+    sb.wtime = now;
+    sb.max_mnt_count = -1;
+    sb.magic = EXT2_MAGIC;
+    sb.state = FS_CLEAN;
+    sb.errors = ERR_IGNORE;
+    sb.lastcheck = now;
+    sb.creator_os = OS_LINUX;
+    sb.rev_major = 1;
+    sb.first_inode = FIRST_INO as u32;
+    sb.inode_size = INODE_SIZE as u16;
+    sb.features_opt = FeaturesOptional::empty();
+    sb.features_req = FeaturesRequired::REQ_DIRECTORY_TYPE;
+    sb.fs_id = gen_uuid(now as u64 ^ blocks_count as u64);
+
+    // superblock 的 0 号块中前 1024 字节不属于文件系统, 一并清零
+    zero_block(0);
+    let layout = Ext2Layout::new(sb.clone(), blockgroups, DIR_ALIGN, None, false, false);
+    layout.flush();
+    for plan in plans.iter().skip(1) {
+        let bg_idx = plan.start / blocks_per_group;
+        if sb.has_backup(bg_idx) {
+            zero_block(plan.start);
+            block_device::modify(plan.start, 0, |backup: &mut Superblock| {
+                backup.clone_from(&sb);
+                backup.block_group = bg_idx as u16;
+            });
+        }
+    }
+
+    // 根目录: ".", ".." 都指向自己, 另有 lost+found
+    write_dir_inode(&layout, ROOT_INO, root_block, 3, now);
+    write_dir_block(
+        root_block,
+        &[
+            (".", ROOT_INO),
+            ("..", ROOT_INO),
+            ("lost+found", LOST_FOUND_INO),
+        ],
+    );
+    write_dir_inode(&layout, LOST_FOUND_INO, lost_found_block, 2, now);
+    write_dir_block(lost_found_block, &[(".", LOST_FOUND_INO), ("..", ROOT_INO)]);
+    Ok(())
+}
+
+fn zero_block(block_id: usize) {
+    block_device::modify(block_id, 0, |data: &mut DataBlock| data.fill(0));
+}
+
+// 前 used 位标记为已用, 超出 valid 的填充位也标记为已用, 防止被分配出去
+fn write_bitmap(block_id: usize, used: usize, valid: usize) {
+    block_device::modify(block_id, 0, |bitmap: &mut BitmapBlock| {
+        bitmap.fill(0);
+        for bit in (0..used).chain(valid..block::BITS) {
+            bitmap[bit / UNIT_WIDTH] |= 1 << (bit % UNIT_WIDTH);
+        }
+    });
+}
+
+fn write_dir_inode(layout: &Ext2Layout, inode_id: usize, data_block: usize, links: u16, now: u32) {
+    let address = layout.inode_address(inode_id);
+    block_device::modify(
+        address.block_id(),
+        address.offset(),
+        |ext2_inode: &mut Ext2Inode| {
+            ext2_inode.init(VfsFileType::Directory);
+            ext2_inode.set_permissions(&VfsPermissions::new(0o755));
+            ext2_inode.uid = 0;
+            ext2_inode.gid = 0;
+            ext2_inode.hard_links = links;
+            ext2_inode.set_size(block::SIZE);
+            ext2_inode.sectors_count = (block::SIZE / 512) as u32;
+            ext2_inode.direct_pointer[0] = data_block as u32;
+            ext2_inode.atime = now;
+            ext2_inode.ctime = now;
+            ext2_inode.mtime = now;
+        },
+    );
+}
+
+// 依次写入目录项, 最后一项延伸到块尾
+fn write_dir_block(block_id: usize, entries: &[(&str, usize)]) {
+    block_device::modify(block_id, 0, |data: &mut DataBlock| {
+        data.fill(0);
+        let mut offset = 0;
+        for (idx, &(name, inode_id)) in entries.iter().enumerate() {
+            let entry = Ext2DirEntry::build_raw(
+                &mut data[offset..],
+                name,
+                inode_id,
+                VfsFileType::Directory,
+                DIR_ALIGN,
+            );
+            if idx + 1 == entries.len() {
+                entry.rec_expand(block::SIZE - offset);
+            }
+            offset += entry.record_len();
+        }
+    });
+}
+
+// 没有随机数来源, 用 xorshift 从 seed 生成一个 version 4 格式的 uuid
+fn gen_uuid(seed: u64) -> [u8; 16] {
+    let mut state = seed | 1;
+    let mut uuid = [0u8; 16];
+    for byte in uuid.iter_mut() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *byte = (state >> 32) as u8;
+    }
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
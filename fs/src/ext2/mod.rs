@@ -1,13 +1,26 @@
 mod address;
 mod allocator;
 mod blockgroup;
+mod check;
 mod dir;
 mod disk_inode;
 mod filesystem;
 mod inode;
+mod journal;
 mod layout;
+mod manifest;
 mod metadata;
+mod mkfs;
+mod snapshot;
 mod superblock;
 mod symlink;
 
-pub use filesystem::Ext2FileSystem;
+pub use address::Address;
+pub use check::{CheckReport, Problem};
+pub use dir::{decode_dir_records, DirEntry, DirRecord, ReadDirIter};
+pub use disk_inode::Flags;
+pub use filesystem::{Ext2FileSystem, JournalState, MountOptions};
+pub use inode::{BlockStream, BlockView};
+pub use layout::InodeHistogram;
+pub use snapshot::Ext2Snapshot;
+pub use superblock::ErrorPolicy;
@@ -0,0 +1,261 @@
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    block::{self, DataBlock},
+    block_device, cast, try_cast,
+    vfs::{
+        error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult},
+        meta::VfsMetadata,
+        VfsPath,
+    },
+};
+
+use super::{
+    address::Address,
+    blockgroup::{BitmapBlock, UNIT_WIDTH},
+    dir::Ext2DirEntry,
+    disk_inode::Ext2Inode,
+    layout::Ext2Layout,
+    metadata::Ext2Metadata,
+};
+
+type IndirectBlock = [u32; Ext2Inode::INDIRECT_COUNT];
+
+/// 文件系统的只读快照
+///
+/// 创建时把所有已分配的块拷贝一份, 之后对原文件系统的写入不会影响快照.
+/// 快照不解析符号链接: 末端的符号链接按其本身返回, 中途遇到则视为非目录
+pub struct Ext2Snapshot {
+    inodes_count: usize,
+    inodes_per_group: usize,
+    inode_size: usize,
+    inode_tables: Vec<usize>,
+    blocks: BTreeMap<usize, Box<DataBlock>>,
+}
+
+impl Ext2Snapshot {
+    // 调用前需要保证内存中的修改已经全部写回缓存
+    pub(crate) fn freeze(layout: &Ext2Layout) -> Self {
+        let (blocks_count, blocks_per_group, inodes_count) = {
+            let sb = layout.superblock();
+            let sb = sb.lock();
+            (
+                sb.blocks_count as usize,
+                sb.blocks_per_group as usize,
+                sb.inodes_count as usize,
+            )
+        };
+
+        let mut inode_tables = Vec::new();
+        let mut blocks = BTreeMap::new();
+        for (bg_idx, bg) in layout.blockgroups().iter().enumerate() {
+            let bg = bg.lock();
+            inode_tables.push(bg.inode_table_block as usize);
+
+            // 只拷贝 bitmap 中标记为已使用的块
+            let used =
+                block_device::read(bg.block_bitmap_addr as usize, 0, |bitmap: &BitmapBlock| {
+                    (0..blocks_per_group)
+                        .filter(|&bit| bitmap[bit / UNIT_WIDTH] & (1u64 << (bit % UNIT_WIDTH)) != 0)
+                        .map(|bit| bg_idx * blocks_per_group + bit)
+                        .filter(|&block_id| block_id < blocks_count)
+                        .collect::<Vec<_>>()
+                });
+
+            for block_id in used {
+                let data = block_device::read(block_id, 0, |data: &DataBlock| Box::new(*data));
+                blocks.insert(block_id, data);
+            }
+        }
+
+        Self {
+            inodes_count,
+            inodes_per_group: layout.inodes_per_group() as usize,
+            inode_size: layout.inode_size(),
+            inode_tables,
+            blocks,
+        }
+    }
+
+    pub fn read_dir<T: AsRef<str>>(&self, path: T) -> VfsResult<Vec<(String, usize)>> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        let inode_id = self.walk(&vpath)?;
+        if !self.disk_inode(inode_id)?.filetype().is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory)
+                .with_path(&vpath)
+                .into());
+        }
+        self.entries(inode_id)
+    }
+
+    pub fn metadata<T: AsRef<str>>(&self, path: T) -> VfsResult<Box<dyn VfsMetadata>> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        let ext2_inode = self.disk_inode(self.walk(&vpath)?)?;
+        Ok(Box::new(Ext2Metadata::from(ext2_inode)))
+    }
+
+    pub fn read_at<T: AsRef<str>>(
+        &self,
+        path: T,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> VfsResult<usize> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        let inode_id = self.walk(&vpath)?;
+        let ext2_inode = self.disk_inode(inode_id)?;
+        if !ext2_inode.filetype().is_file() {
+            return Err(IOError::new(IOErrorKind::NotAFile).with_path(&vpath).into());
+        }
+        Ok(self.inner_read_at(ext2_inode, offset, buf))
+    }
+
+    fn parse_path(path: &str) -> VfsResult<VfsPath> {
+        if !path.starts_with('/') {
+            return Err(VfsErrorKind::InvalidPath(path.to_string()).into());
+        }
+        Ok(VfsPath::from(path))
+    }
+
+    fn block(&self, block_id: usize) -> Option<&DataBlock> {
+        self.blocks.get(&block_id).map(|data| data.as_ref())
+    }
+
+    fn corrupted(msg: String) -> VfsError {
+        VfsError::from(IOError::new(IOErrorKind::Corrupted)).with_additional(msg)
+    }
+
+    // inode 号来自目录项, 损坏的镜像中可能越界
+    fn disk_inode(&self, inode_id: usize) -> VfsResult<&Ext2Inode> {
+        if inode_id == 0 || inode_id > self.inodes_count {
+            return Err(Self::corrupted(format!("inode {} out of range", inode_id)));
+        }
+        let inode_seq = inode_id - 1;
+        let address = Address::new(self.inode_tables[inode_seq / self.inodes_per_group], 0)
+            .advance(((inode_seq % self.inodes_per_group) * self.inode_size) as isize);
+        let data = self.block(address.block_id()).ok_or_else(|| {
+            Self::corrupted(format!("inode table of inode {} not captured", inode_id))
+        })?;
+        Ok(cast!(data[address.offset()..].as_ptr(), Ext2Inode))
+    }
+
+    // 间接块不存在 (为 0) 时, 它索引的范围都是空洞
+    fn indirect(&self, block_id: u32, idx: usize) -> u32 {
+        if block_id == 0 {
+            return 0;
+        }
+        self.block(block_id as usize)
+            .map_or(0, |data| cast!(data.as_ptr(), IndirectBlock)[idx])
+    }
+
+    fn block_id_for(&self, ext2_inode: &Ext2Inode, inner_idx: usize) -> u32 {
+        if inner_idx < Ext2Inode::DIRECT_COUNT {
+            ext2_inode.direct_pointer[inner_idx]
+        } else if inner_idx < Ext2Inode::INDIRECT_BOUND {
+            self.indirect(
+                ext2_inode.indirect_pointer,
+                inner_idx - Ext2Inode::DIRECT_COUNT,
+            )
+        } else if inner_idx < Ext2Inode::DOUBLE_BOUND {
+            let last = inner_idx - Ext2Inode::INDIRECT_BOUND;
+            let indirect =
+                self.indirect(ext2_inode.doubly_indirect, last / Ext2Inode::INDIRECT_COUNT);
+            self.indirect(indirect, last % Ext2Inode::INDIRECT_COUNT)
+        } else {
+            let last = inner_idx - Ext2Inode::DOUBLE_BOUND;
+            let indirect2 =
+                self.indirect(ext2_inode.triply_indirect, last / Ext2Inode::DOUBLE_COUNT);
+            let indirect = self.indirect(
+                indirect2,
+                last / Ext2Inode::INDIRECT_COUNT % Ext2Inode::INDIRECT_COUNT,
+            );
+            self.indirect(indirect, last % Ext2Inode::INDIRECT_COUNT)
+        }
+    }
+
+    fn inner_read_at(&self, ext2_inode: &Ext2Inode, offset: usize, buf: &mut [u8]) -> usize {
+        let end = (offset + buf.len()).min(ext2_inode.size());
+        let mut start = offset;
+        while start < end {
+            let block_end = ((start / block::SIZE + 1) * block::SIZE).min(end);
+            let dst = &mut buf[start - offset..block_end - offset];
+            let block_id = self.block_id_for(ext2_inode, start / block::SIZE);
+            match self.block(block_id as usize).filter(|_| block_id != 0) {
+                Some(data) => {
+                    dst.copy_from_slice(&data[start % block::SIZE..start % block::SIZE + dst.len()])
+                }
+                // 空洞读出 0
+                None => dst.fill(0),
+            }
+            start = block_end;
+        }
+        end.saturating_sub(offset)
+    }
+
+    // 与 ReadDirIter 做同样的检查, 损坏的目录项返回 Corrupted
+    fn entries(&self, inode_id: usize) -> VfsResult<Vec<(String, usize)>> {
+        let ext2_inode = self.disk_inode(inode_id)?;
+        let mut buffer = alloc::vec![0u8; ext2_inode.size()];
+        self.inner_read_at(ext2_inode, 0, &mut buffer);
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let Some(entry) = try_cast::<Ext2DirEntry>(&buffer[offset..]) else {
+                return Err(Self::corrupted(format!(
+                    "truncated dir entry in inode {} offset {}",
+                    inode_id, offset
+                )));
+            };
+            let rec_len = entry.raw_record_len();
+            if rec_len < Ext2DirEntry::BARE_LEN
+                || rec_len % 4 != 0
+                || offset + rec_len > buffer.len()
+            {
+                return Err(Self::corrupted(format!(
+                    "bad rec_len in inode {} offset {}",
+                    inode_id, offset
+                )));
+            }
+            if !entry.is_unused() {
+                // 名字必须落在记录之内, 否则 name_bytes 会读出记录外
+                if entry.regular_len(1) > rec_len {
+                    return Err(Self::corrupted(format!(
+                        "name_len exceeds rec_len in inode {} offset {}",
+                        inode_id, offset
+                    )));
+                }
+                let name = String::from_utf8_lossy(entry.name_bytes()).to_string();
+                entries.push((name, entry.inode_id()));
+            }
+            offset += rec_len;
+        }
+        Ok(entries)
+    }
+
+    fn walk(&self, path: &VfsPath) -> VfsResult<usize> {
+        let mut current = 2;
+        let mut next_path = VfsPath::empty(true);
+        for next in path.iter() {
+            next_path.push(next);
+            if !self.disk_inode(current)?.filetype().is_dir() {
+                return Err(IOError::new(IOErrorKind::NotADirectory)
+                    .with_path(&next_path)
+                    .into());
+            }
+
+            current = self
+                .entries(current)?
+                .into_iter()
+                .find(|(name, _)| name == next)
+                .map(|(_, inode_id)| inode_id)
+                .ok_or_else(|| IOError::new(IOErrorKind::NotFound).with_path(&next_path))?;
+        }
+        Ok(current)
+    }
+}
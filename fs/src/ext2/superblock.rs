@@ -1,8 +1,18 @@
-use alloc::string::ToString;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 use bitflags::bitflags;
 use core::fmt::{self, Debug};
 
-use crate::{block, ceil_index, ext2::disk_inode::Ext2Inode, time::LocalTime, util};
+use crate::{
+    block, ceil_index,
+    ext2::disk_inode::Ext2Inode,
+    time::LocalTime,
+    util,
+    vfs::error::{IOError, IOErrorKind, VfsError, VfsResult},
+};
 
 pub const EXT2_MAGIC: u16 = 0xef53;
 
@@ -12,6 +22,13 @@ pub const FS_CLEAN: u16 = 1;
 /// Filesystem has errors
 pub const FS_ERR: u16 = 2;
 
+/// rev 0 中第一个非保留的 inode
+pub const EXT2_GOOD_OLD_FIRST_INO: u32 = 11;
+/// rev 0 中 inode 的大小
+pub const EXT2_GOOD_OLD_INODE_SIZE: u16 = 128;
+/// 从这个版本起 superblock 带有 first_inode 之后的动态字段
+pub const EXT2_DYNAMIC_REV: u32 = 1;
+
 /// Ignore errors
 pub const ERR_IGNORE: u16 = 1;
 /// Remount as read-only on error
@@ -30,6 +47,17 @@ pub const OS_FREEBSD: u32 = 3;
 /// Creator OS is a BSD4.4-Lite derivative
 pub const OS_LITE: u32 = 4;
 
+/// superblock 中 errors 字段规定的, 检测到文件系统损坏之后的行为
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// 返回错误, 继续运行 (ERR_IGNORE)
+    Continue,
+    /// 之后拒绝一切修改 (ERR_RONLY)
+    RemountReadOnly,
+    /// 直接 panic (ERR_PANIC)
+    Panic,
+}
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct Superblock {
@@ -180,7 +208,7 @@ impl Debug for Superblock {
             .field("features_req", &self.features_req)
             .field("features_ronly", &self.features_ronly)
             .field("fs_id", &util::uuid_str(&self.fs_id))
-            .field("volume_name", &util::bytes_to_str(&self.volume_name))
+            .field("volume_name", &self.label())
             .field("last_mnt_path", &util::bytes_to_str(&self.last_mnt_path))
             .field("compression", &self.compression)
             .field("prealloc_blocks_files", &self.prealloc_blocks_files)
@@ -190,6 +218,12 @@ impl Debug for Superblock {
 }
 
 impl Superblock {
+    /// 全 0 的 superblock, 供 mkfs 逐项填写
+    pub(crate) fn zeroed() -> Self {
+        // 所有字段都是整数, 字节数组或 bitflags, 全 0 是合法的值
+        unsafe { core::mem::zeroed() }
+    }
+
     #[inline]
     pub fn block_size(&self) -> usize {
         1024 << self.log_block_size
@@ -200,16 +234,105 @@ impl Superblock {
         1024 << self.log_frag_size
     }
 
+    /// inode 表中每个 inode 占用的字节数, rev 0 固定为 128
     #[inline]
     pub fn inode_size(&self) -> usize {
-        self.inode_size as usize
+        if self.rev_major == 0 {
+            EXT2_GOOD_OLD_INODE_SIZE as usize
+        } else {
+            self.inode_size as usize
+        }
+    }
+
+    /// first_inode 及之后的字段是否有效, rev 0 中它们可能是任意值
+    pub fn is_dynamic(&self) -> bool {
+        self.rev_major >= EXT2_DYNAMIC_REV
+    }
+
+    /// 把 rev 0 中无意义的动态字段清零, 之后按 rev 0 的默认值解释
+    pub fn clear_dynamic_fields(&mut self) {
+        self.first_inode = 0;
+        self.inode_size = 0;
+        self.block_group = 0;
+        self.features_opt = FeaturesOptional::empty();
+        self.features_req = FeaturesRequired::empty();
+        self.features_ronly = FeaturesROnly::empty();
+        self.fs_id = [0; 16];
+        self.volume_name = [0; 16];
+        self.last_mnt_path = [0; 64];
+        self.compression = 0;
+        self.prealloc_blocks_files = 0;
+        self.prealloc_blocks_dirs = 0;
+        self.journal_id = [0; 16];
+        self.journal_inode = 0;
+        self.journal_dev = 0;
+        self.journal_orphan_head = 0;
+    }
+
+    /// 卷标, 非 utf-8 的字节按替换字符显示
+    pub fn label(&self) -> String {
+        let len = self
+            .volume_name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(self.volume_name.len());
+        String::from_utf8_lossy(&self.volume_name[..len]).into_owned()
+    }
+
+    /// 检测到错误时的处理方式, 未知的值按 Continue 处理
+    pub fn error_policy(&self) -> ErrorPolicy {
+        match self.errors {
+            ERR_RONLY => ErrorPolicy::RemountReadOnly,
+            ERR_PANIC => ErrorPolicy::Panic,
+            _ => ErrorPolicy::Continue,
+        }
+    }
+
+    /// 第一个非保留的 inode, rev 0 固定为 11
+    pub fn first_ino(&self) -> u32 {
+        if self.rev_major == 0 {
+            EXT2_GOOD_OLD_FIRST_INO
+        } else {
+            self.first_inode
+        }
+    }
+
+    /// 块组中是否有 superblock 和描述符表的备份.
+    /// 开启 sparse_super 时只有 0, 1 以及 3, 5, 7 的幂次块组才有
+    pub fn has_backup(&self, bg_idx: usize) -> bool {
+        if !self.features_ronly.contains(FeaturesROnly::RONLY_SPARSE) {
+            return true;
+        }
+        let is_power_of = |base: usize| {
+            let mut n = base;
+            while n < bg_idx {
+                n *= base;
+            }
+            n == bg_idx
+        };
+        bg_idx <= 1 || is_power_of(3) || is_power_of(5) || is_power_of(7)
+    }
+
+    /// superblock 的内容是否可信: magic, 块大小, inode 大小以及块组的划分
+    pub fn is_valid(&self) -> bool {
+        self.magic == EXT2_MAGIC
+            && self.log_block_size == (block::LOG_SIZE - 10) as u32
+            && self.inode_size() >= core::mem::size_of::<Ext2Inode>()
+            && self.inode_size().is_power_of_two()
+            && self.inode_size() <= block::SIZE
+            && self.blocks_per_group != 0
+            && self.inodes_per_group != 0
+            && ceil_index!(self.blocks_count, self.blocks_per_group)
+                == ceil_index!(self.inodes_count, self.inodes_per_group)
     }
 
-    pub fn check_valid(&self) {
-        assert_eq!(self.magic, EXT2_MAGIC);
-        assert_ne!(self.state, FS_ERR);
-        assert_eq!(self.block_size(), block::SIZE);
-        assert_eq!(self.inode_size(), core::mem::size_of::<Ext2Inode>());
+    /// 同 is_valid, 无效时返回 Corrupted 错误
+    pub fn check_valid(&self) -> VfsResult<()> {
+        if self.is_valid() {
+            return Ok(());
+        }
+        let err: VfsError = IOError::new(IOErrorKind::Corrupted).into();
+        Err(err.with_additional("invalid superblock"))
     }
 
     // 统计有多少 group
@@ -253,6 +376,73 @@ bitflags! {
         const REQ_REPLAY_JOURNAL = 0x0004;
         /// File system uses a journal device
         const REQ_JOURNAL_DEVICE = 0x0008;
+        /// Block group descriptors are spread across meta block groups
+        const REQ_META_BG = 0x0010;
+        /// Files use extent trees instead of block maps
+        const REQ_EXTENTS = 0x0040;
+        /// Block numbers are 64 bits wide
+        const REQ_64BIT = 0x0080;
+        /// Multiple mount protection
+        const REQ_MMP = 0x0100;
+        /// Metadata of several block groups is packed together
+        const REQ_FLEX_BG = 0x0200;
+        /// Large extended attribute values are stored in their own inodes
+        const REQ_EA_INODE = 0x0400;
+        /// Directory entries carry extra data
+        const REQ_DIRDATA = 0x1000;
+        /// Checksum seed is stored in the superblock
+        const REQ_CSUM_SEED = 0x2000;
+        /// Directories may exceed 2 GiB or use 3-level htrees
+        const REQ_LARGEDIR = 0x4000;
+        /// Small files are stored inside the inode
+        const REQ_INLINE_DATA = 0x8000;
+        /// Files are encrypted
+        const REQ_ENCRYPT = 0x10000;
+        /// Directories may be case-insensitive
+        const REQ_CASEFOLD = 0x20000;
+    }
+}
+
+impl FeaturesRequired {
+    // 本实现能够处理的特性, 需要重放的日志在挂载时单独处理
+    const SUPPORTED: Self = Self::REQ_DIRECTORY_TYPE
+        .union(Self::REQ_REPLAY_JOURNAL)
+        .union(Self::REQ_CSUM_SEED);
+
+    // 与 e2fsprogs 中的名字一致
+    const NAMES: [(Self, &'static str); 16] = [
+        (Self::REQ_COMPRESSION, "compression"),
+        (Self::REQ_DIRECTORY_TYPE, "filetype"),
+        (Self::REQ_REPLAY_JOURNAL, "needs_recovery"),
+        (Self::REQ_JOURNAL_DEVICE, "journal_dev"),
+        (Self::REQ_META_BG, "meta_bg"),
+        (Self::REQ_EXTENTS, "extent"),
+        (Self::REQ_64BIT, "64bit"),
+        (Self::REQ_MMP, "mmp"),
+        (Self::REQ_FLEX_BG, "flex_bg"),
+        (Self::REQ_EA_INODE, "ea_inode"),
+        (Self::REQ_DIRDATA, "dirdata"),
+        (Self::REQ_CSUM_SEED, "metadata_csum_seed"),
+        (Self::REQ_LARGEDIR, "large_dir"),
+        (Self::REQ_INLINE_DATA, "inline_data"),
+        (Self::REQ_ENCRYPT, "encrypt"),
+        (Self::REQ_CASEFOLD, "casefold"),
+    ];
+
+    /// 不支持的特性的名字, 没有名字的位记为 FEATURE_I 加位号, 与 e2fsprogs 相同
+    pub fn unsupported_names(&self) -> Vec<String> {
+        let unsupported = self.bits() & !Self::SUPPORTED.bits();
+        (0..u32::BITS)
+            .map(|bit| 1u32 << bit)
+            .filter(|&flag| unsupported & flag != 0)
+            .map(|flag| {
+                Self::NAMES
+                    .iter()
+                    .find(|(feature, _)| feature.bits() == flag)
+                    .map(|(_, name)| name.to_string())
+                    .unwrap_or_else(|| format!("FEATURE_I{}", flag.trailing_zeros()))
+            })
+            .collect()
     }
 }
 
@@ -266,5 +456,7 @@ bitflags! {
         const RONLY_FILE_SIZE_64 = 0x0002;
         /// Directory contents are stored in the form of a Binary Tree
         const RONLY_BTREE_DIRECTORY = 0x0004;
+        /// Metadata blocks carry crc32c checksums
+        const RONLY_METADATA_CSUM = 0x0400;
     }
 }
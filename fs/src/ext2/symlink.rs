@@ -1,17 +1,45 @@
-use alloc::string::{String, ToString};
+use alloc::{
+    string::{String, ToString},
+    vec,
+};
 
-use crate::vfs::{
-    error::{IOError, IOErrorKind, VfsResult},
-    VfsPath,
+use crate::{
+    block,
+    vfs::{
+        error::{IOError, IOErrorKind, VfsResult},
+        VfsInode, VfsPath,
+    },
 };
 
 use super::inode::Inode;
 
+// 不超过 60 字节的目标直接存放在 inode 的块指针区, 称为快速符号链接
+pub(crate) const FAST_SYMLINK_MAX: usize = 60;
+// 更长的目标存放在一个数据块中
+pub(crate) const SYMLINK_MAX: usize = block::SIZE;
+// 解析一个路径时最多跟随的符号链接数, 与 Linux 的 MAXSYMLINKS 相同
+pub(crate) const MAX_SYMLINK_HOPS: usize = 40;
+
 impl Inode {
+    // 快速符号链接没有占用数据块
+    fn is_fast_symlink(&self) -> bool {
+        self.read_disk_inode(|ext2_inode| ext2_inode.sectors_count == 0)
+    }
+
     pub fn read_symlink(&self) -> String {
+        let symlink_len = self.size();
+        assert!(
+            symlink_len <= SYMLINK_MAX,
+            "Too long symlink: {}",
+            symlink_len
+        );
+        if !self.is_fast_symlink() {
+            let mut target = vec![0u8; symlink_len];
+            self.read_disk_inode(|ext2_inode| ext2_inode.read_at(0, &mut target));
+            return String::from_utf8(target).unwrap();
+        }
+
         self.read_disk_inode(|ext2_inode| {
-            let symlink_len = ext2_inode.size();
-            assert!(symlink_len <= 60, "Too long symlink: {}", symlink_len);
             let slice = unsafe {
                 let start_ptr = (ext2_inode as *const _ as *const u8).add(40);
                 core::slice::from_raw_parts(start_ptr, symlink_len)
@@ -20,32 +48,41 @@ impl Inode {
         })
     }
 
-    pub fn write_symlink(&mut self, path_to: &VfsPath) -> VfsResult<()> {
+    pub fn write_symlink(&mut self, path_to: &str) -> VfsResult<()> {
         if !self.is_symlink() {
             return Err(IOError::new(IOErrorKind::NotASymlink)
                 .with_path(path_to)
                 .into());
         }
 
-        let path_to = path_to.to_string();
+        let symlink_len = path_to.len();
+        if symlink_len > SYMLINK_MAX {
+            return Err(IOError::new(IOErrorKind::TooLongTargetSymlink)
+                .with_path(path_to)
+                .into());
+        }
+        if symlink_len > FAST_SYMLINK_MAX {
+            self.write_data(0, path_to.as_bytes())?;
+            return Ok(());
+        }
+
         self.modify_disk_inode(|ext2_inode| {
-            let symlink_len = path_to.len();
             ext2_inode.set_size(symlink_len);
-
-            if symlink_len > 60 {
-                return Err(IOError::new(IOErrorKind::TooLongTargetSymlink)
-                    .with_path(path_to)
-                    .into());
-            }
-
             unsafe {
                 let start_ptr = (ext2_inode as *mut _ as *mut u8).add(40);
                 let slice = core::slice::from_raw_parts_mut(start_ptr, symlink_len);
                 slice.copy_from_slice(path_to.as_bytes());
             };
+        });
+        Ok(())
+    }
 
-            Ok(())
-        })
+    // 释放符号链接占用的数据块, 快速符号链接没有数据块
+    pub(crate) fn release_symlink(&mut self) -> VfsResult<()> {
+        if self.is_fast_symlink() {
+            return Ok(());
+        }
+        self.resize(0)
     }
 
     pub fn symlink_target(&self, path: &VfsPath) -> VfsResult<VfsPath> {
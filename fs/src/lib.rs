@@ -1,8 +1,8 @@
 #![no_std]
-#![feature(error_in_core)]
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 #![allow(dead_code)]
+#![allow(clippy::macro_metavars_in_unsafe)]
 
 extern crate alloc;
 
@@ -13,6 +13,8 @@ pub mod vfs;
 
 mod util;
 
+pub use util::{try_cast, try_cast_mut};
+
 const SECTOR_SIZE: usize = 512;
 
 pub mod block {
@@ -1,5 +1,26 @@
 use core::fmt::Display;
 
+use spin::Mutex;
+
+// no_std 下没有时钟, 当前时间由使用者注入
+static CLOCK: Mutex<Option<fn() -> u64>> = Mutex::new(None);
+
+/// 设置时钟, clock 返回当前的 posix 时间 (秒)
+pub fn set_clock(clock: fn() -> u64) {
+    *CLOCK.lock() = Some(clock);
+}
+
+/// 移除已设置的时钟, 之后 now 返回 None
+pub fn clear_clock() {
+    *CLOCK.lock() = None;
+}
+
+/// 当前的 posix 时间, 没有设置时钟时为 None.
+/// 调用者应当在 None 时保留原有的时间戳, 而不是写入 0
+pub fn now() -> Option<u64> {
+    CLOCK.lock().map(|clock| clock())
+}
+
 pub struct TimeUnit;
 
 impl TimeUnit {
@@ -14,7 +35,7 @@ impl TimeUnit {
 }
 
 fn is_leap_year(year: u32) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
 }
 
 fn days_in_month(year: u32, month: u32) -> u32 {
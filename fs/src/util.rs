@@ -23,6 +23,25 @@ pub fn uuid_str(uuid_slice: &[u8]) -> String {
     uuid_str
 }
 
+/// 带检查的 cast!: buf 不够长或者没有对齐时返回 None, 而不是产生未定义行为
+pub fn try_cast<T>(buf: &[u8]) -> Option<&T> {
+    if buf.len() < core::mem::size_of::<T>()
+        || buf.as_ptr().align_offset(core::mem::align_of::<T>()) != 0
+    {
+        return None;
+    }
+    Some(unsafe { &*(buf.as_ptr() as *const T) })
+}
+
+pub fn try_cast_mut<T>(buf: &mut [u8]) -> Option<&mut T> {
+    if buf.len() < core::mem::size_of::<T>()
+        || buf.as_ptr().align_offset(core::mem::align_of::<T>()) != 0
+    {
+        return None;
+    }
+    Some(unsafe { &mut *(buf.as_mut_ptr() as *mut T) })
+}
+
 #[macro_export]
 macro_rules! cast {
     ($addr:expr, $T:ty) => {
@@ -40,13 +59,13 @@ macro_rules! cast_mut {
 #[macro_export]
 macro_rules! ceil_index {
     ($index:expr, $size:expr) => {
-        ($index + $size - 1) / $size
+        ($index).div_ceil($size)
     };
 }
 
 #[macro_export]
 macro_rules! ceil {
     ($index:expr, $bound:expr) => {
-        (($index + $bound - 1) / $bound) * $bound
+        ($index).div_ceil($bound) * $bound
     };
 }
@@ -1,6 +1,9 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String};
 
-use super::VfsInode;
+use super::{meta::VfsMetadata, VfsInode};
+
+/// (name, inode_id, metadata)
+pub type DirEntryPlus = (String, usize, Box<dyn VfsMetadata>);
 
 pub trait VfsDirEntry {
     fn name(&self) -> &str;
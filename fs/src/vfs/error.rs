@@ -86,7 +86,7 @@ pub enum VfsErrorKind {
     /// The file or directory at the given path could not be found
     FileNotFound,
 
-    /// The given path is invalid, e.g. because contains '.' or '..'
+    /// The given path is invalid, e.g. because it is not absolute or has an empty component
     InvalidPath(String),
 
     /// There is already a directory at the given path
@@ -98,6 +98,9 @@ pub enum VfsErrorKind {
     /// Functionality not supported by this filesystem
     NotSupported,
 
+    /// The on-disk image requires features this implementation does not support, by name
+    UnsupportedFeature(String),
+
     /// Generic error variant
     Other(String),
 }
@@ -123,6 +126,9 @@ impl fmt::Display for VfsErrorKind {
             VfsErrorKind::NotSupported => {
                 write!(f, "Functionality not supported by this filesystem")
             }
+            VfsErrorKind::UnsupportedFeature(features) => {
+                write!(f, "Unsupported feature: {}", features)
+            }
             VfsErrorKind::DirectoryExists => {
                 write!(f, "Directory already exists")
             }
@@ -171,12 +177,16 @@ pub enum IOErrorKind {
     NotAFile,
     NotASymlink,
     TooLongTargetSymlink,
+    EmptyTargetSymlink,
     DirectoryNotEmpty,
     IsADirectory,
     TooLargeFile,
     TooLongFileName,
     TooManyLinks,
+    // 解析路径时跟随的符号链接太多, 通常是 symlink 成环 (ELOOP)
+    TooManySymlinks,
     InvalidFilename,
     NoFreeBlocks,
     NoFreeInodes,
+    Corrupted,
 }
@@ -0,0 +1,56 @@
+use alloc::boxed::Box;
+
+use super::{
+    error::VfsResult,
+    io::{Position, SeekFrom},
+    VfsInode,
+};
+
+/// 带游标的文件句柄, 读写从当前位置开始并自动前进, 与 std::fs::File 的用法一致.
+/// 越过文件末尾写入时中间的空隙读出为 0
+#[derive(Debug)]
+pub struct File {
+    inode: Box<dyn VfsInode>,
+    pos: Position,
+}
+
+impl File {
+    pub fn new(inode: Box<dyn VfsInode>) -> Self {
+        Self {
+            inode,
+            pos: Position::default(),
+        }
+    }
+
+    /// 从当前位置读, 返回读到的字节数, 到达文件末尾时为 0
+    pub fn read(&mut self, buf: &mut [u8]) -> VfsResult<usize> {
+        let read = self.inode.read_at(self.pos.get() as usize, buf)?;
+        self.pos.advance(read);
+        Ok(read)
+    }
+
+    /// 从当前位置写, 返回写入的字节数
+    pub fn write(&mut self, buf: &[u8]) -> VfsResult<usize> {
+        let written = self.inode.write_at(self.pos.get() as usize, buf)?;
+        self.pos.advance(written);
+        Ok(written)
+    }
+
+    /// 移动游标, 返回新的位置. 移到 0 之前时停在 0, 可以越过文件末尾
+    pub fn seek(&mut self, from: SeekFrom) -> VfsResult<u64> {
+        let len = self.inode.metadata().size();
+        Ok(self.pos.seek(from, len))
+    }
+
+    pub fn stream_position(&self) -> u64 {
+        self.pos.get()
+    }
+
+    pub fn inode(&self) -> &dyn VfsInode {
+        self.inode.as_ref()
+    }
+
+    pub fn into_inner(self) -> Box<dyn VfsInode> {
+        self.inode
+    }
+}
@@ -1,26 +1,164 @@
 use core::fmt::{Debug, Display};
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::BTreeSet,
+    string::{String, ToString},
+    vec::Vec,
+};
+use bitflags::bitflags;
 
 use super::{
-    dir::VfsDirEntry,
-    error::{VfsErrorKind, VfsResult},
+    dir::{DirEntryPlus, VfsDirEntry},
+    error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult},
     inode::VfsInode,
-    meta::VfsMetadata,
+    meta::{VfsMetadata, VfsStatFs},
     path::VfsPath,
 };
 
+bitflags! {
+    /// 文件系统实现了哪些可选操作, 未实现的操作会返回 NotSupported
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Capabilities: u32 {
+        const READ_DIR = 1 << 0;
+        const CREATE_FILE = 1 << 1;
+        const CREATE_DIR = 1 << 2;
+        const REMOVE_FILE = 1 << 3;
+        const REMOVE_DIR = 1 << 4;
+        const SYMLINK = 1 << 5;
+        const HARDLINK = 1 << 6;
+        const INODE_ID = 1 << 7;
+        const RENAME = 1 << 8;
+        const STAT_FS = 1 << 9;
+        const READ_LINK = 1 << 10;
+        const READ_DIR_PLUS = 1 << 11;
+        const SYMLINK_METADATA = 1 << 12;
+    }
+}
+
 pub trait FileSystem: Debug + Display + Sync + Send + 'static {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::empty()
+    }
+
+    // 只有 read_dir 和 exists 是必须实现的, 其余操作默认返回 NotSupported
     fn read_dir(&self, path: VfsPath) -> VfsResult<Vec<Box<dyn VfsDirEntry>>>;
     fn exists(&self, path: VfsPath) -> VfsResult<bool>;
-    fn metadata(&self, path: VfsPath) -> VfsResult<Box<dyn VfsMetadata>>;
-    fn link(&self, to: VfsPath, from: VfsPath) -> VfsResult<()>;
-    fn symlink(&self, to: VfsPath, from: VfsPath) -> VfsResult<()>;
-    fn open_file(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>>;
-    fn create_file(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>>;
-    fn remove_file(&self, path: VfsPath) -> VfsResult<()>;
-    fn create_dir(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>>;
-    fn remove_dir(&self, path: VfsPath) -> VfsResult<()>;
+
+    fn metadata(&self, path: VfsPath) -> VfsResult<Box<dyn VfsMetadata>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    fn link(&self, to: VfsPath, from: VfsPath) -> VfsResult<()> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 新建指向 to 的符号链接 from, to 按原样保存
+    fn symlink(&self, to: &str, from: VfsPath) -> VfsResult<()> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    fn open_file(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    fn create_file(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 返回剩下的硬链接数, 为 0 说明 inode 已被回收
+    fn remove_file(&self, path: VfsPath) -> VfsResult<u16> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    fn create_dir(&self, path: VfsPath) -> VfsResult<Box<dyn VfsInode>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    fn remove_dir(&self, path: VfsPath) -> VfsResult<()> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 与 rm -rf 一致, 删除 path 处的目录以及其下的所有内容.
+    /// 不跟随符号链接: 遇到符号链接 (包括 path 本身) 时只删除链接.
+    /// 支持 INODE_ID 时记录走过的目录, 目录硬链接构成环时返回 Corrupted
+    fn remove_dir_all(&self, path: VfsPath) -> VfsResult<()> {
+        if self.read_link(path.clone()).is_ok() {
+            return self.remove_file(path).map(|_| ());
+        }
+        inner_remove_dir_all(self, path, &mut BTreeSet::new())
+    }
+
+    /// 与 mkdir -p 一致, 逐级创建 path 上缺少的目录, 已存在的目录直接跳过.
+    /// 某一级已存在但不是目录时返回 NotADirectory
+    fn create_dir_all(&self, path: VfsPath) -> VfsResult<()> {
+        let mut current = VfsPath::empty(path.is_from_root());
+        for component in path.iter() {
+            current.push(component);
+            if !self.exists(current.clone())? {
+                self.create_dir(current.clone())?;
+            } else if !self.metadata(current.clone())?.filetype().is_dir() {
+                return Err(IOError::new(IOErrorKind::NotADirectory)
+                    .with_path(&current)
+                    .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// 符号链接目标允许的最大字节数
+    fn max_symlink_len(&self) -> usize {
+        usize::MAX
+    }
+
+    /// 检查目标后再创建符号链接: 目标不能为空, 也不能超过 max_symlink_len
+    fn create_symlink(&self, to: &str, from: VfsPath) -> VfsResult<()> {
+        let target_len = to.len();
+        if target_len == 0 {
+            return Err(IOError::new(IOErrorKind::EmptyTargetSymlink)
+                .with_path(&from)
+                .into());
+        }
+        if target_len > self.max_symlink_len() {
+            return Err(IOError::new(IOErrorKind::TooLongTargetSymlink)
+                .with_path(&from)
+                .into());
+        }
+        self.symlink(to, from)
+    }
+
+    fn read_dir_plus(&self, path: VfsPath) -> VfsResult<Vec<DirEntryPlus>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 一次取出多个路径的元数据, 结果与 paths 一一对应.
+    /// 实现可以让共享前缀的路径只走一次
+    fn metadata_batch(&self, paths: Vec<VfsPath>) -> Vec<VfsResult<Box<dyn VfsMetadata>>> {
+        paths.into_iter().map(|path| self.metadata(path)).collect()
+    }
+
+    fn inode_id_of(&self, path: VfsPath) -> VfsResult<usize> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 类似 lstat: path 处的 inode 号和元数据, 不跟随最后一级符号链接
+    fn symlink_metadata(&self, path: VfsPath) -> VfsResult<(usize, Box<dyn VfsMetadata>)> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    fn walk_dir(&self, path: VfsPath) -> VfsResult<Vec<String>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 读出 path 处符号链接的目标, 不跟随最后一级符号链接
+    fn read_link(&self, path: VfsPath) -> VfsResult<String> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 把 from 改名为 to, 可以跨目录. to 已存在时被替换
+    fn rename(&self, from: VfsPath, to: VfsPath) -> VfsResult<()> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
 
     fn move_file(&self, src: &str, dest: &str) -> VfsResult<()> {
         Err(VfsErrorKind::NotSupported.into())
@@ -37,5 +175,43 @@ pub trait FileSystem: Debug + Display + Sync + Send + 'static {
     //     Err(VfsErrorKind::NotSupported.into())
     // }
 
-    fn flush(&self);
+    fn stat_fs(&self) -> VfsResult<VfsStatFs> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 没有缓存的实现什么也不用做
+    fn flush(&self) {}
+
+    /// 卸载前调用, 默认只是 flush. 需要记录是否正常卸载的实现在这里写入标记
+    fn unmount(&self) {
+        self.flush();
+    }
+}
+
+fn inner_remove_dir_all<F: FileSystem + ?Sized>(
+    fs: &F,
+    path: VfsPath,
+    visited: &mut BTreeSet<usize>,
+) -> VfsResult<()> {
+    if fs.capabilities().contains(Capabilities::INODE_ID)
+        && !visited.insert(fs.inode_id_of(path.clone())?)
+    {
+        let err: VfsError = IOError::new(IOErrorKind::Corrupted).with_path(&path).into();
+        return Err(err.with_additional("directory cycle detected"));
+    }
+    for entry in fs.read_dir(path.clone())? {
+        let name = entry.name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let mut child = path.clone();
+        child.push(name);
+        let is_symlink = fs.read_link(child.clone()).is_ok();
+        if !is_symlink && fs.metadata(child.clone())?.filetype().is_dir() {
+            inner_remove_dir_all(fs, child, visited)?;
+        } else {
+            fs.remove_file(child)?;
+        }
+    }
+    fs.remove_dir(path)
 }
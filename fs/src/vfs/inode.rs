@@ -3,7 +3,7 @@ use core::fmt::Debug;
 use alloc::{boxed::Box, string::String};
 
 use super::{
-    error::VfsResult,
+    error::{VfsErrorKind, VfsResult},
     meta::{VfsMetadata, VfsPermissions},
 };
 
@@ -12,8 +12,33 @@ pub trait VfsInode: Debug {
     fn write_at(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize>;
     fn set_len(&mut self, len: usize) -> VfsResult<()>;
 
+    /// 句柄背后的 inode 号, 与 VfsDirEntry::inode_id 对应, 可用来判断两个句柄是否指向同一文件
+    fn inode_id(&self) -> usize;
+
+    /// 稀疏写, 整块为 0 的部分尽量留作空洞. 不支持空洞的实现直接写入
+    fn write_at_sparse(&mut self, offset: usize, buf: &[u8]) -> VfsResult<usize> {
+        self.write_at(offset, buf)
+    }
+
+    /// 在当前文件末尾写入并增长文件, 返回写入的字节数.
+    /// 只是先取大小再写, 多个句柄同时追加时不保证原子性
+    fn append(&mut self, buf: &[u8]) -> VfsResult<usize> {
+        let size = self.metadata().size() as usize;
+        self.write_at(size, buf)
+    }
+
     fn metadata(&self) -> Box<dyn VfsMetadata>;
 
     fn set_permissions(&mut self, permissions: &VfsPermissions) -> VfsResult<()>;
     fn read_symlink(&self) -> VfsResult<String>;
+
+    /// 复制出一个指向同一 inode 的独立句柄, 两者共享磁盘上的状态
+    fn try_clone(&self) -> VfsResult<Box<dyn VfsInode>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
+
+    /// 以当前目录为起点打开相对路径, 类似 openat(2)
+    fn open_at(&self, path: &str) -> VfsResult<Box<dyn VfsInode>> {
+        Err(VfsErrorKind::NotSupported.into())
+    }
 }
@@ -0,0 +1,45 @@
+//! 读写位置相关的定义, 供带游标的文件句柄使用
+
+/// 与 std::io::SeekFrom 语义一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// 文件内的读写位置
+///
+/// 位置不会小于 0, 但允许越过文件末尾: 越过 EOF 读出 0 字节,
+/// 越过 EOF 写入则中间的空隙读出为 0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position(u64);
+
+impl Position {
+    pub fn new(pos: u64) -> Self {
+        Self(pos)
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+
+    // len 是当前文件长度, 返回新的位置
+    pub fn seek(&mut self, from: SeekFrom, len: u64) -> u64 {
+        let (base, delta) = match from {
+            SeekFrom::Start(pos) => (pos, 0),
+            SeekFrom::End(delta) => (len, delta),
+            SeekFrom::Current(delta) => (self.0, delta),
+        };
+        self.0 = base.saturating_add_signed(delta);
+        self.0
+    }
+
+    pub fn advance(&mut self, n: usize) {
+        self.0 += n as u64;
+    }
+
+    pub fn is_eof(&self, len: u64) -> bool {
+        self.0 >= len
+    }
+}
@@ -1,6 +1,8 @@
 use core::fmt::Debug;
 use core::fmt::Display;
 
+use alloc::vec::Vec;
+
 pub trait VfsMetadata: Debug + Display + 'static {
     fn filetype(&self) -> VfsFileType;
     fn permissions(&self) -> VfsPermissions;
@@ -9,6 +11,18 @@ pub trait VfsMetadata: Debug + Display + 'static {
     fn uid(&self) -> u16;
     fn gid(&self) -> u16;
     fn hard_links(&self) -> u16;
+    /// 占用的 512 字节扇区数, 包括间接块
+    fn blocks(&self) -> u64;
+
+    fn is_file(&self) -> bool {
+        self.filetype().is_file()
+    }
+    fn is_dir(&self) -> bool {
+        self.filetype().is_dir()
+    }
+    fn is_symlink(&self) -> bool {
+        self.filetype().is_symlink()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -36,16 +50,17 @@ impl VfsFileType {
 
 impl Display for VfsFileType {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        match self {
-            VfsFileType::RegularFile => write!(f, "."),
-            VfsFileType::Directory => write!(f, "d"),
-            VfsFileType::FIFO => write!(f, "f"),
-            VfsFileType::SymbolicLink => write!(f, "l"),
-            _ => todo!(),
-            // VfsFileType::CharDev => write!(f, "CharDev"),
-            // VfsFileType::BlockDev => write!(f, "BlockDev"),
-            // VfsFileType::Socket => write!(f, "Socket"),
-        }
+        // 与 ls -l 第一列一致
+        let c = match self {
+            VfsFileType::RegularFile => '-',
+            VfsFileType::Directory => 'd',
+            VfsFileType::SymbolicLink => 'l',
+            VfsFileType::CharDev => 'c',
+            VfsFileType::BlockDev => 'b',
+            VfsFileType::FIFO => 'p',
+            VfsFileType::Socket => 's',
+        };
+        write!(f, "{}", c)
     }
 }
 
@@ -54,6 +69,10 @@ pub struct VfsPermissions {
     user: VfsPermission,
     group: VfsPermission,
     others: VfsPermission,
+    // setuid, setgid, sticky 三个特殊位
+    setuid: bool,
+    setgid: bool,
+    sticky: bool,
 }
 
 impl VfsPermissions {
@@ -62,6 +81,9 @@ impl VfsPermissions {
             user: user.into(),
             group: group.into(),
             others: others.into(),
+            setuid: false,
+            setgid: false,
+            sticky: false,
         }
     }
 
@@ -71,6 +93,9 @@ impl VfsPermissions {
             user: VfsPermission::from(((ugo >> 6) & 0o7) as u8),
             group: VfsPermission::from(((ugo >> 3) & 0o7) as u8),
             others: VfsPermission::from((ugo & 0o7) as u8),
+            setuid: false,
+            setgid: false,
+            sticky: false,
         }
     }
 
@@ -79,6 +104,9 @@ impl VfsPermissions {
             user: VfsPermission::empty(),
             group: VfsPermission::empty(),
             others: VfsPermission::empty(),
+            setuid: false,
+            setgid: false,
+            sticky: false,
         }
     }
 
@@ -87,6 +115,9 @@ impl VfsPermissions {
             user: VfsPermission::all(),
             group: VfsPermission::all(),
             others: VfsPermission::all(),
+            setuid: false,
+            setgid: false,
+            sticky: false,
         }
     }
 
@@ -119,13 +150,112 @@ impl VfsPermissions {
     pub fn others(&self) -> VfsPermission {
         self.others
     }
+
+    /// 设置 setuid, setgid, sticky 三个特殊位
+    pub fn with_special(self, setuid: bool, setgid: bool, sticky: bool) -> Self {
+        Self {
+            setuid,
+            setgid,
+            sticky,
+            ..self
+        }
+    }
+
+    pub fn setuid(&self) -> bool {
+        self.setuid
+    }
+    pub fn setgid(&self) -> bool {
+        self.setgid
+    }
+    pub fn sticky(&self) -> bool {
+        self.sticky
+    }
 }
 
+// rwxr-xr-x 形式. 特殊位占用对应的 x 位: 有 x 时为小写 s/t, 否则为大写 S/T
 impl Display for VfsPermissions {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{}", self.user)?;
-        write!(f, "{}", self.group)?;
-        write!(f, "{}", self.others)
+        let triples = [
+            (self.user, self.setuid, 's'),
+            (self.group, self.setgid, 's'),
+            (self.others, self.sticky, 't'),
+        ];
+        for (perm, special, mark) in triples {
+            let exec = match (special, perm.execute()) {
+                (true, true) => mark,
+                (true, false) => mark.to_ascii_uppercase(),
+                (false, true) => 'x',
+                (false, false) => '-',
+            };
+            write!(
+                f,
+                "{}{}{}",
+                if perm.read() { "r" } else { "-" },
+                if perm.write() { "w" } else { "-" },
+                exec
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 文件系统的空间统计, 与 statfs(2) 对应. 块数都以 block_size 为单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsStatFs {
+    pub block_size: u64,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    /// 普通用户可用的空闲块, 不含为 root 保留的部分
+    pub available_blocks: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+}
+
+/// 执行操作的用户身份, 用于权限检查
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u16,
+    pub gid: u16,
+    // 附加组
+    pub groups: Vec<u16>,
+}
+
+impl Credentials {
+    pub fn new(uid: u16, gid: u16) -> Self {
+        Self {
+            uid,
+            gid,
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn with_groups(mut self, groups: &[u16]) -> Self {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    /// gid 是主组或附加组之一
+    pub fn in_group(&self, gid: u16) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// 对属于 owner:group 的文件适用的那组权限位: 所有者, 同组, 其他人依次匹配,
+    /// 只看第一个匹配上的. root 不受权限位限制
+    pub fn permission_for(
+        &self,
+        owner: u16,
+        group: u16,
+        permissions: &VfsPermissions,
+    ) -> VfsPermission {
+        if self.uid == 0 {
+            VfsPermission::all()
+        } else if self.uid == owner {
+            permissions.user()
+        } else if self.in_group(group) {
+            permissions.group()
+        } else {
+            permissions.others()
+        }
     }
 }
 
@@ -223,4 +353,22 @@ impl VfsTimeStamp {
     pub fn dtime(&self) -> u64 {
         self.dtime
     }
+
+    /// inode 最后一次被修改的时间 (ctime), 包括权限、链接数等元数据的修改.
+    /// 注意这不是创建时间, ext2 不记录文件的创建时间
+    pub fn change_time(&self) -> u64 {
+        self.ctime
+    }
+    /// 文件内容最后一次被修改的时间 (mtime)
+    pub fn modify_time(&self) -> u64 {
+        self.mtime
+    }
+    /// 文件最后一次被访问的时间 (atime)
+    pub fn access_time(&self) -> u64 {
+        self.atime
+    }
+    /// inode 被删除的时间 (dtime), 未删除时为 0
+    pub fn delete_time(&self) -> u64 {
+        self.dtime
+    }
 }
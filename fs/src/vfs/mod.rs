@@ -1,31 +1,42 @@
 mod dir;
+mod file;
 mod filesystem;
 mod inode;
-mod io;
 mod path;
 
 pub mod error;
+pub mod io;
 pub mod meta;
 
 use core::fmt::Display;
 
-use alloc::{boxed::Box, string::ToString, vec::Vec};
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 
-pub use dir::VfsDirEntry;
-pub use filesystem::FileSystem;
+pub use dir::{DirEntryPlus, VfsDirEntry};
+pub use file::File;
+pub use filesystem::{Capabilities, FileSystem};
 pub use inode::VfsInode;
-pub use path::VfsPath;
+pub use path::{VfsPath, VfsPathBuilder};
 
 use crate::block_device;
 
 use self::{
-    error::{VfsError, VfsErrorKind, VfsResult},
-    meta::VfsMetadata,
+    error::{IOError, IOErrorKind, VfsError, VfsErrorKind, VfsResult},
+    meta::{VfsMetadata, VfsPermissions, VfsStatFs},
 };
 
 #[derive(Debug)]
 pub struct VFS {
     fs: Box<dyn FileSystem>,
+    // 根目录的 inode 号, 文件系统不支持 inode_id_of 时为 None
+    root_id: Option<usize>,
+    // 另外指定的一个也被视为根的目录, 为叠加多个文件系统做准备
+    alt_root_id: Option<usize>,
 }
 
 impl Display for VFS {
@@ -36,7 +47,43 @@ impl Display for VFS {
 
 impl VFS {
     pub fn new(fs: impl FileSystem) -> VFS {
-        VFS { fs: Box::new(fs) }
+        let root_id = fs.inode_id_of(VfsPath::empty(true)).ok();
+        VFS {
+            fs: Box::new(fs),
+            root_id,
+            alt_root_id: None,
+        }
+    }
+
+    /// 根目录的 inode 号
+    pub fn root_id(&self) -> Option<usize> {
+        self.root_id
+    }
+
+    /// 把 path 处的目录也视为根, 之后 is_root 对它返回 true
+    pub fn set_alt_root<T: AsRef<str>>(&mut self, path: T) -> VfsResult<()> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        if !self.fs.metadata(vpath.clone())?.filetype().is_dir() {
+            return Err(IOError::new(IOErrorKind::NotADirectory)
+                .with_path(path.as_ref())
+                .into());
+        }
+        self.alt_root_id = Some(self.fs.inode_id_of(vpath)?);
+        Ok(())
+    }
+
+    /// path 是否指向根目录或 set_alt_root 指定的目录.
+    /// 按 inode 号比较, 因此 "/dir/.." 这样绕回根的路径也算
+    pub fn is_root<T: AsRef<str>>(&self, path: T) -> VfsResult<bool> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        if vpath.is_empty() {
+            return Ok(true);
+        }
+        if self.root_id.is_none() && self.alt_root_id.is_none() {
+            return Ok(false);
+        }
+        let id = Some(self.fs.inode_id_of(vpath)?);
+        Ok(id == self.root_id || id == self.alt_root_id)
     }
 
     fn parse_path(path: &str) -> VfsResult<VfsPath> {
@@ -47,11 +94,25 @@ impl VFS {
         Ok(VfsPath::from(path))
     }
 
+    pub fn capabilities(&self) -> Capabilities {
+        self.fs.capabilities()
+    }
+
+    /// 空间和 inode 的使用情况
+    pub fn stat_fs(&self) -> VfsResult<VfsStatFs> {
+        self.fs.stat_fs()
+    }
+
     pub fn read_dir<T: AsRef<str>>(&self, path: T) -> VfsResult<Vec<Box<dyn VfsDirEntry>>> {
         let vpath = Self::parse_path(path.as_ref())?;
         self.fs.read_dir(vpath)
     }
 
+    pub fn read_dir_plus<T: AsRef<str>>(&self, path: T) -> VfsResult<Vec<DirEntryPlus>> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        self.fs.read_dir_plus(vpath)
+    }
+
     pub fn exists<T: AsRef<str>>(&self, path: T) -> VfsResult<bool> {
         let vpath = Self::parse_path(path.as_ref())?;
         self.fs.exists(vpath)
@@ -62,16 +123,61 @@ impl VFS {
         self.fs.metadata(vpath)
     }
 
+    /// 批量取元数据, 结果与 paths 一一对应, 某个路径出错不影响其他路径
+    pub fn metadata_batch<T: AsRef<str>>(
+        &self,
+        paths: &[T],
+    ) -> Vec<VfsResult<Box<dyn VfsMetadata>>> {
+        let parsed: Vec<_> = paths
+            .iter()
+            .map(|path| Self::parse_path(path.as_ref()))
+            .collect();
+        let valid = parsed
+            .iter()
+            .filter_map(|path| path.as_ref().ok().cloned())
+            .collect();
+        let mut metadata = self.fs.metadata_batch(valid).into_iter();
+        parsed
+            .into_iter()
+            .map(|path| path.and_then(|_| metadata.next().unwrap()))
+            .collect()
+    }
+
+    pub fn inode_id_of<T: AsRef<str>>(&self, path: T) -> VfsResult<usize> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        self.fs.inode_id_of(vpath)
+    }
+
+    /// 不跟随最后一级符号链接, 返回 inode 号和元数据
+    pub fn symlink_metadata<T: AsRef<str>>(
+        &self,
+        path: T,
+    ) -> VfsResult<(usize, Box<dyn VfsMetadata>)> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        self.fs.symlink_metadata(vpath)
+    }
+
+    pub fn walk_dir<T: AsRef<str>>(&self, path: T) -> VfsResult<Vec<String>> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        self.fs.walk_dir(vpath)
+    }
+
+    pub fn read_link<T: AsRef<str>>(&self, path: T) -> VfsResult<String> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        self.fs.read_link(vpath)
+    }
+
     pub fn link<T: AsRef<str>>(&self, to_path: T, from_path: T) -> VfsResult<()> {
         let vpath_to = Self::parse_path(to_path.as_ref())?;
         let vpath_from = Self::parse_path(from_path.as_ref())?;
         self.fs.link(vpath_to, vpath_from)
     }
 
+    /// 符号链接的目标可以是相对路径, 相对于链接所在的目录
     pub fn symlink<T: AsRef<str>>(&self, to_path: T, from_path: T) -> VfsResult<()> {
-        let vpath_to = Self::parse_path(to_path.as_ref())?;
+        // 目标原样保存, 不做规范化: "../a" 和 "a//b" 这样的写法也要原样读回
         let vpath_from = Self::parse_path(from_path.as_ref())?;
-        self.fs.symlink(vpath_to, vpath_from)
+        self.fs.create_symlink(to_path.as_ref(), vpath_from)
     }
 
     pub fn open_file<T: AsRef<str>>(&self, path: T) -> VfsResult<Box<dyn VfsInode>> {
@@ -79,17 +185,74 @@ impl VFS {
         self.fs.open_file(vpath)
     }
 
+    /// 与 open_file 相同, 但是返回带游标的 File
+    pub fn open<T: AsRef<str>>(&self, path: T) -> VfsResult<File> {
+        self.open_file(path).map(File::new)
+    }
+
     pub fn create_file<T: AsRef<str>>(&self, path: T) -> VfsResult<Box<dyn VfsInode>> {
         let vpath = Self::parse_path(path.as_ref())?;
         self.fs.create_file(vpath)
     }
 
+    /// 新建文件, 写入 contents 并把权限设为 mode, 任何一步失败都会删掉新建的文件.
+    /// 先写内容再改权限, 这样只读的 mode 也不会挡住初始内容的写入
+    pub fn create_file_with<T: AsRef<str>>(
+        &self,
+        path: T,
+        contents: &[u8],
+        mode: u16,
+    ) -> VfsResult<Box<dyn VfsInode>> {
+        // 权限位加上 setuid, setgid, sticky
+        if mode > 0o7777 {
+            return Err(VfsErrorKind::Other(format!("invalid mode: {:o}", mode)).into());
+        }
+        let permissions = VfsPermissions::new(mode & 0o777).with_special(
+            mode & 0o4000 != 0,
+            mode & 0o2000 != 0,
+            mode & 0o1000 != 0,
+        );
+        let mut file = self.create_file(path.as_ref())?;
+        let filled = file
+            .write_at(0, contents)
+            .and_then(|_| file.set_permissions(&permissions));
+        if let Err(err) = filled {
+            drop(file);
+            // 清理失败也返回最初的错误, 它才是调用者关心的
+            let _ = self.remove_file(path.as_ref());
+            return Err(err);
+        }
+        Ok(file)
+    }
+
     pub fn create_dir<T: AsRef<str>>(&self, path: T) -> VfsResult<Box<dyn VfsInode>> {
         let vpath = Self::parse_path(path.as_ref())?;
         self.fs.create_dir(vpath)
     }
 
-    pub fn remove_file<T: AsRef<str>>(&self, path: T) -> VfsResult<()> {
+    /// 创建 path 以及所有缺少的上级目录, 已存在的目录不报错
+    pub fn create_dir_all<T: AsRef<str>>(&self, path: T) -> VfsResult<()> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        self.fs.create_dir_all(vpath)
+    }
+
+    /// 与 truncate(1) 一致: 把 path 处的文件截断或扩展到 len, 扩展出的部分读出为 0.
+    /// 完成后写回设备
+    pub fn truncate<T: AsRef<str>>(&self, path: T, len: usize) -> VfsResult<()> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        if self.fs.metadata(vpath.clone())?.filetype().is_dir() {
+            return Err(IOError::new(IOErrorKind::IsADirectory)
+                .with_path(&vpath)
+                .into());
+        }
+        let mut file = self.fs.open_file(vpath)?;
+        file.set_len(len)?;
+        self.flush();
+        Ok(())
+    }
+
+    /// 返回剩下的硬链接数, 为 0 说明文件已被回收
+    pub fn remove_file<T: AsRef<str>>(&self, path: T) -> VfsResult<u16> {
         let vpath = Self::parse_path(path.as_ref())?;
         self.fs.remove_file(vpath)
     }
@@ -104,14 +267,39 @@ impl VFS {
         self.fs.remove_dir(vpath)
     }
 
+    /// 与 rename(2) 一致, 目标已存在时被替换: 目录只能替换空目录, 非目录只能替换非目录
+    pub fn rename<T: AsRef<str>>(&self, from: T, to: T) -> VfsResult<()> {
+        let vpath_from = Self::parse_path(from.as_ref())?;
+        let vpath_to = Self::parse_path(to.as_ref())?;
+        self.fs.rename(vpath_from, vpath_to)
+    }
+
+    /// 递归删除 path 处的目录, 不跟随符号链接. 与 remove_dir 一样不允许删除根目录
+    pub fn remove_dir_all<T: AsRef<str>>(&self, path: T) -> VfsResult<()> {
+        let vpath = Self::parse_path(path.as_ref())?;
+        if vpath.is_empty() {
+            let err: VfsError = VfsErrorKind::InvalidPath(path.as_ref().to_string()).into();
+            return Err(err.with_additional("Forbidden to remove root directory!"));
+        }
+        self.fs.remove_dir_all(vpath)
+    }
+
+    pub fn move_file<T: AsRef<str>>(&self, src: T, dest: T) -> VfsResult<()> {
+        // 先检查两个路径的合法性, 再交给文件系统
+        Self::parse_path(src.as_ref())?;
+        Self::parse_path(dest.as_ref())?;
+        self.fs.move_file(src.as_ref(), dest.as_ref())
+    }
+
     pub fn flush(&self) {
         self.fs.flush();
-        block_device::flush();
+        block_device::sync_all();
     }
 }
 
 impl Drop for VFS {
     fn drop(&mut self) {
-        self.flush();
+        self.fs.unmount();
+        block_device::sync_all();
     }
 }
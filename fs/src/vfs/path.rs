@@ -1,4 +1,5 @@
 use core::fmt::Display;
+use core::hash::{Hash, Hasher};
 
 use alloc::{
     string::{String, ToString},
@@ -6,6 +7,8 @@ use alloc::{
 };
 use core::ops::Deref;
 
+use super::error::{VfsErrorKind, VfsResult};
+
 #[derive(Debug, Clone)]
 pub struct VfsPath {
     from_root: bool,
@@ -19,6 +22,22 @@ impl VfsPath {
             inner: Vec::new(),
         }
     }
+    pub fn builder(from_root: bool) -> VfsPathBuilder {
+        VfsPathBuilder {
+            path: Self::empty(from_root),
+        }
+    }
+
+    /// 由各级组件构造一个从根开始的路径
+    pub fn from_components(components: &[&str]) -> VfsResult<VfsPath> {
+        components
+            .iter()
+            .try_fold(Self::builder(true), |builder, component| {
+                builder.push(component)
+            })
+            .map(VfsPathBuilder::build)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &str> {
         self.inner.iter().map(|x| x.as_str())
     }
@@ -31,6 +50,25 @@ impl VfsPath {
         self.inner.push(next.to_string());
     }
 
+    // 去掉 "." 并消去 "..", 从根开始的路径不会越过根
+    fn normalized(&self) -> Vec<&str> {
+        let mut components = Vec::new();
+        for component in self.iter() {
+            match component {
+                "." => {}
+                ".." => match components.last() {
+                    Some(&last) if last != ".." => {
+                        components.pop();
+                    }
+                    _ if self.from_root => {}
+                    _ => components.push(".."),
+                },
+                other => components.push(other),
+            }
+        }
+        components
+    }
+
     pub fn parent(&self) -> Self {
         if self.is_from_root() {
             let mut new_inner = self.inner.clone();
@@ -45,6 +83,25 @@ impl VfsPath {
     }
 }
 
+pub struct VfsPathBuilder {
+    path: VfsPath,
+}
+
+impl VfsPathBuilder {
+    // 组件不能为空, 也不能含有 '/' 或 '\0'
+    pub fn push(mut self, component: &str) -> VfsResult<Self> {
+        if component.is_empty() || component.contains(['/', '\0']) {
+            return Err(VfsErrorKind::InvalidPath(component.to_string()).into());
+        }
+        self.path.push(component);
+        Ok(self)
+    }
+
+    pub fn build(self) -> VfsPath {
+        self.path
+    }
+}
+
 // 实现 display 也实现了 to_string
 impl Display for VfsPath {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
@@ -57,6 +114,22 @@ impl Display for VfsPath {
     }
 }
 
+// 比较的是规范化之后的路径, 因此 "/a/./b" == "/a/b", 但 "/a/b" != "a/b"
+impl PartialEq for VfsPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.from_root == other.from_root && self.normalized() == other.normalized()
+    }
+}
+
+impl Eq for VfsPath {}
+
+impl Hash for VfsPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.from_root.hash(state);
+        self.normalized().hash(state);
+    }
+}
+
 impl Deref for VfsPath {
     type Target = Vec<String>;
 
@@ -65,17 +138,23 @@ impl Deref for VfsPath {
     }
 }
 
+// 解析时就消去 "." 和 "..", 走目录时不会把它们当作目录项去查找.
+// 相对路径开头的 ".." 无处可消, 保留下来
 impl From<&str> for VfsPath {
     fn from(path: &str) -> Self {
         let from_root = path.starts_with('/');
-
-        Self {
+        let raw = Self {
             from_root,
             inner: path
                 .split('/')
                 .filter(|mid| !mid.is_empty())
                 .map(String::from)
                 .collect(),
+        };
+
+        Self {
+            from_root,
+            inner: raw.normalized().into_iter().map(String::from).collect(),
         }
     }
 }
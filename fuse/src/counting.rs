@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use fs::block_device::BlockDevice;
+use spin::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read(usize),
+    Write(usize),
+}
+
+impl Access {
+    fn block_id(&self) -> usize {
+        match self {
+            Access::Read(block_id) | Access::Write(block_id) => *block_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceCounts {
+    pub reads: usize,
+    pub writes: usize,
+    /// 与上一次访问不连续的次数
+    pub seeks: usize,
+}
+
+#[derive(Default)]
+struct AccessLog {
+    counts: DeviceCounts,
+    accesses: Vec<Access>,
+}
+
+impl AccessLog {
+    fn record(&mut self, access: Access) {
+        let contiguous = self
+            .accesses
+            .last()
+            .is_some_and(|last| last.block_id() + 1 == access.block_id());
+        if !contiguous {
+            self.counts.seeks += 1;
+        }
+        match access {
+            Access::Read(_) => self.counts.reads += 1,
+            Access::Write(_) => self.counts.writes += 1,
+        }
+        self.accesses.push(access);
+    }
+}
+
+/// 记录设备访问次数的 BlockDevice, 用调用次数而不是耗时来衡量性能.
+/// clone 出来的句柄共享同一份记录, 因此可以一份交给文件系统, 一份留给测试读取
+pub struct CountingDevice<D: BlockDevice> {
+    inner: Arc<D>,
+    log: Arc<Mutex<AccessLog>>,
+}
+
+impl<D: BlockDevice> Clone for CountingDevice<D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            log: self.log.clone(),
+        }
+    }
+}
+
+impl<D: BlockDevice> CountingDevice<D> {
+    pub fn wrapping(inner: D) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            log: Arc::new(Mutex::new(AccessLog::default())),
+        }
+    }
+
+    pub fn counts(&self) -> DeviceCounts {
+        self.log.lock().counts
+    }
+
+    pub fn accesses(&self) -> Vec<Access> {
+        self.log.lock().accesses.clone()
+    }
+
+    pub fn reset(&self) {
+        *self.log.lock() = AccessLog::default();
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for CountingDevice<D> {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.log.lock().record(Access::Read(block_id));
+        self.inner.read_block(block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.log.lock().record(Access::Write(block_id));
+        self.inner.write_block(block_id, buf);
+    }
+}
@@ -0,0 +1,108 @@
+// 把 VfsInode 接到 std::io 上, 这样就可以使用 std::io::copy, BufReader 等工具
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use fs::vfs::{
+    error::{IOErrorKind, VfsError, VfsErrorKind},
+    VfsInode,
+};
+
+/// 带游标的文件, 实现了 Read, Write 和 Seek
+#[derive(Debug)]
+pub struct IoFile {
+    inode: Box<dyn VfsInode>,
+    pos: u64,
+}
+
+impl IoFile {
+    pub fn new(inode: Box<dyn VfsInode>) -> Self {
+        Self { inode, pos: 0 }
+    }
+
+    /// 截断或扩展文件, 游标位置不变
+    pub fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.inode.set_len(len as usize).map_err(io_error)
+    }
+
+    pub fn into_inner(self) -> Box<dyn VfsInode> {
+        self.inode
+    }
+}
+
+impl Read for IoFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self
+            .inode
+            .read_at(self.pos as usize, buf)
+            .map_err(io_error)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for IoFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self
+            .inode
+            .write_at(self.pos as usize, buf)
+            .map_err(io_error)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    // 写入直接进入块缓存, 写回设备由文件系统的 flush 负责
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for IoFile {
+    // 与 std::fs::File 一样, 可以越过文件末尾, 但不能移到 0 之前
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(pos) => (pos, 0),
+            SeekFrom::End(delta) => (self.inode.metadata().size(), delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        self.pos = base.checked_add_signed(delta).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// 按错误的含义转为 std::io::Error, 附带的信息保留在错误描述中
+pub fn io_error(err: VfsError) -> io::Error {
+    let kind = match err.kind() {
+        VfsErrorKind::IOError(io_err) => match io_err.kind() {
+            IOErrorKind::NotFound => io::ErrorKind::NotFound,
+            IOErrorKind::PermissionDenied => io::ErrorKind::PermissionDenied,
+            IOErrorKind::AlreadyExists => io::ErrorKind::AlreadyExists,
+            IOErrorKind::NotADirectory => io::ErrorKind::NotADirectory,
+            IOErrorKind::IsADirectory => io::ErrorKind::IsADirectory,
+            IOErrorKind::DirectoryNotEmpty => io::ErrorKind::DirectoryNotEmpty,
+            IOErrorKind::TooLargeFile => io::ErrorKind::FileTooLarge,
+            IOErrorKind::TooManyLinks => io::ErrorKind::TooManyLinks,
+            IOErrorKind::TooManySymlinks => io::ErrorKind::Other,
+            IOErrorKind::NoFreeBlocks | IOErrorKind::NoFreeInodes => io::ErrorKind::StorageFull,
+            IOErrorKind::Corrupted => io::ErrorKind::InvalidData,
+            IOErrorKind::NotAFile
+            | IOErrorKind::NotASymlink
+            | IOErrorKind::InvalidFilename
+            | IOErrorKind::TooLongFileName
+            | IOErrorKind::TooLongTargetSymlink
+            | IOErrorKind::EmptyTargetSymlink => io::ErrorKind::InvalidInput,
+        },
+        VfsErrorKind::FileNotFound => io::ErrorKind::NotFound,
+        VfsErrorKind::InvalidPath(_) => io::ErrorKind::InvalidInput,
+        VfsErrorKind::DirectoryExists | VfsErrorKind::FileExists => io::ErrorKind::AlreadyExists,
+        VfsErrorKind::NotSupported | VfsErrorKind::UnsupportedFeature(_) => {
+            io::ErrorKind::Unsupported
+        }
+        VfsErrorKind::Other(_) => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, err.to_string())
+}
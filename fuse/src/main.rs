@@ -4,10 +4,14 @@
 
 use fs::block;
 use fs::block_device::BlockDevice;
+use fs::ext2::{Ext2FileSystem, MountOptions};
+use fs::vfs::VFS;
+use fuser::MountOption;
 use spin::Mutex;
 use std::{
     fs::{File, OpenOptions},
     io::{Read, Seek, SeekFrom, Write},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 struct BlockFile(Mutex<File>);
@@ -22,9 +26,18 @@ impl BlockFile {
                 .unwrap()
         }))
     }
+
+    // 只读打开, 任何写入都会失败, 用于只读挂载
+    fn open_read_only(filename: &str) -> Self {
+        BlockFile(Mutex::new(
+            OpenOptions::new().read(true).open(filename).unwrap(),
+        ))
+    }
 }
 
 const SECTOR_SIZE: usize = 512;
+// 设备按扇区读写, 文件系统按块寻址, 一个块必须恰好由整数个扇区组成
+const _: () = assert!(SECTOR_SIZE * block::SECTORS_PER_BLOCK == block::SIZE);
 
 impl BlockDevice for BlockFile {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
@@ -50,8 +63,47 @@ impl BlockDevice for BlockFile {
     }
 }
 
+mod counting;
+mod io_file;
+mod mount;
 mod test;
 
 fn main() {
-    todo!()
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <image> <mountpoint>", args[0]);
+        std::process::exit(2);
+    }
+    let (image, mountpoint) = (&args[1], &args[2]);
+
+    // 镜像末尾不完整的块无法按扇区读出
+    let len = std::fs::metadata(image).expect("cannot stat image").len();
+    if len % block::SIZE as u64 != 0 {
+        eprintln!(
+            "{}: size {} is not a multiple of the block size {}",
+            image,
+            len,
+            block::SIZE
+        );
+        std::process::exit(1);
+    }
+
+    // 文件系统本身没有时钟, 不设置时不会更新任何时间戳
+    fs::time::set_clock(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    });
+
+    // 只读挂载: 镜像以只读方式打开, 挂载和卸载都不写盘, 读文件时也不要改写 atime
+    let ext2_options = MountOptions {
+        noatime: true,
+        read_only: true,
+        ..MountOptions::default()
+    };
+    let ext2 = Ext2FileSystem::open_with(BlockFile::open_read_only(image), ext2_options)
+        .expect("mount failed");
+    let vfs = VFS::new(ext2);
+    let options = [MountOption::RO, MountOption::FSName("ext2".to_string())];
+    fuser::mount2(mount::Ext2Fuse::new(vfs), mountpoint, &options).expect("mount failed");
 }
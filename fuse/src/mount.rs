@@ -0,0 +1,278 @@
+// 把 VFS 挂载到 FUSE 上, 目前只读, 卸载时也不写回.
+// VFS 以路径为中心, 而 FUSE 以 inode 号为中心, 因此在 lookup 和 readdir 时记下 inode 号到路径的映射
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fs::{
+    block,
+    vfs::{
+        error::{IOErrorKind, VfsError, VfsErrorKind},
+        meta::{VfsFileType, VfsMetadata, VfsPermission},
+        VFS,
+    },
+};
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen,
+    ReplyStatfs, Request, FUSE_ROOT_ID,
+};
+
+// 内核缓存属性和目录项的时间
+const TTL: Duration = Duration::from_secs(1);
+// ext2 的根目录是 2 号 inode, 而 FUSE 的根固定为 1.
+// 1 号 inode 在 ext2 中是坏块表, 不会出现在目录中, 所以可以直接互换
+const EXT2_ROOT_INO: usize = 2;
+// ext2 目录项中文件名的最大长度
+const NAME_MAX: u32 = 255;
+
+pub struct Ext2Fuse {
+    vfs: VFS,
+    paths: HashMap<u64, String>,
+}
+
+impl Ext2Fuse {
+    pub fn new(vfs: VFS) -> Self {
+        let mut paths = HashMap::new();
+        paths.insert(FUSE_ROOT_ID, "/".to_string());
+        Self { vfs, paths }
+    }
+
+    fn fuse_ino(ext2_ino: usize) -> u64 {
+        if ext2_ino == EXT2_ROOT_INO {
+            FUSE_ROOT_ID
+        } else {
+            ext2_ino as u64
+        }
+    }
+
+    fn path_of(&self, ino: u64) -> Result<String, i32> {
+        self.paths.get(&ino).cloned().ok_or(libc::ENOENT)
+    }
+
+    fn child_path(parent: &str, name: &str) -> String {
+        if parent == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", parent, name)
+        }
+    }
+
+    // 类似 lstat: 符号链接本身的元数据. VFS 的 metadata 会跟随符号链接,
+    // 这里只在父目录中查找这一项, 再读出它指向的 inode
+    fn stat(&self, path: &str) -> Result<(u64, Box<dyn VfsMetadata>), i32> {
+        let (ext2_ino, metadata) = self.vfs.symlink_metadata(path).map_err(errno)?;
+        Ok((Self::fuse_ino(ext2_ino), metadata))
+    }
+
+    pub(crate) fn lookup_attr(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+        let name = name.to_str().ok_or(libc::ENOENT)?;
+        let path = Self::child_path(&self.path_of(parent)?, name);
+        let (ino, metadata) = self.stat(&path)?;
+        // "." 和 ".." 不记录, 否则会覆盖掉规范的路径
+        if name != "." && name != ".." {
+            self.paths.insert(ino, path);
+        }
+        Ok(file_attr(ino, metadata.as_ref()))
+    }
+
+    pub(crate) fn getattr_of(&self, ino: u64) -> Result<FileAttr, i32> {
+        let (_, metadata) = self.stat(&self.path_of(ino)?)?;
+        Ok(file_attr(ino, metadata.as_ref()))
+    }
+
+    pub(crate) fn read_of(&self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let file = self.vfs.open_file(self.path_of(ino)?).map_err(errno)?;
+        if !file.metadata().filetype().is_file() {
+            return Err(libc::EISDIR);
+        }
+        let mut buf = vec![0u8; size as usize];
+        let read = file.read_at(offset as usize, &mut buf).map_err(errno)?;
+        buf.truncate(read);
+        Ok(buf)
+    }
+
+    // 从第 offset 项开始的目录项 (ino, 下一项的 offset, 类型, 名字), 同时记下子项的路径
+    pub(crate) fn readdir_of(
+        &mut self,
+        ino: u64,
+        offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, i32> {
+        let path = self.path_of(ino)?;
+        let entries = self.vfs.read_dir_plus(&path).map_err(errno)?;
+        let mut listed = Vec::new();
+        for (idx, (name, ext2_ino, metadata)) in
+            entries.into_iter().enumerate().skip(offset as usize)
+        {
+            let child = Self::fuse_ino(ext2_ino);
+            if name != "." && name != ".." {
+                self.paths
+                    .entry(child)
+                    .or_insert_with(|| Self::child_path(&path, &name));
+            }
+            listed.push((child, idx as i64 + 1, file_type(metadata.filetype()), name));
+        }
+        Ok(listed)
+    }
+}
+
+impl Filesystem for Ext2Fuse {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_attr(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.getattr_of(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self
+            .path_of(ino)
+            .and_then(|path| self.vfs.read_link(path).map_err(errno))
+        {
+            Ok(target) => reply.data(target.as_bytes()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        // 只读挂载
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+            return;
+        }
+        match self.getattr_of(ino) {
+            Ok(_) => reply.opened(0, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.read_of(ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        // offset 是下一个要返回的目录项的序号
+        let entries = match self.readdir_of(ino, offset) {
+            Ok(entries) => entries,
+            Err(err) => return reply.error(err),
+        };
+        for (child, next, kind, name) in entries {
+            if reply.add(child, next, kind, &name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        match self.vfs.stat_fs() {
+            Ok(stat) => reply.statfs(
+                stat.total_blocks,
+                stat.free_blocks,
+                stat.available_blocks,
+                stat.total_inodes,
+                stat.free_inodes,
+                stat.block_size as u32,
+                NAME_MAX,
+                stat.block_size as u32,
+            ),
+            Err(err) => reply.error(errno(err)),
+        }
+    }
+}
+
+fn file_type(filetype: VfsFileType) -> FileType {
+    match filetype {
+        VfsFileType::RegularFile => FileType::RegularFile,
+        VfsFileType::Directory => FileType::Directory,
+        VfsFileType::CharDev => FileType::CharDevice,
+        VfsFileType::BlockDev => FileType::BlockDevice,
+        VfsFileType::FIFO => FileType::NamedPipe,
+        VfsFileType::Socket => FileType::Socket,
+        VfsFileType::SymbolicLink => FileType::Symlink,
+    }
+}
+
+fn perm_bits(permission: VfsPermission) -> u16 {
+    (permission.read() as u16) << 2 | (permission.write() as u16) << 1 | permission.execute() as u16
+}
+
+fn system_time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn file_attr(ino: u64, metadata: &dyn VfsMetadata) -> FileAttr {
+    let permissions = metadata.permissions();
+    let timestamp = metadata.timestamp();
+    FileAttr {
+        ino,
+        size: metadata.size(),
+        blocks: metadata.blocks(),
+        atime: system_time(timestamp.atime()),
+        mtime: system_time(timestamp.mtime()),
+        ctime: system_time(timestamp.ctime()),
+        crtime: UNIX_EPOCH,
+        kind: file_type(metadata.filetype()),
+        perm: (permissions.setuid() as u16) << 11
+            | (permissions.setgid() as u16) << 10
+            | (permissions.sticky() as u16) << 9
+            | perm_bits(permissions.user()) << 6
+            | perm_bits(permissions.group()) << 3
+            | perm_bits(permissions.others()),
+        nlink: metadata.hard_links() as u32,
+        uid: metadata.uid() as u32,
+        gid: metadata.gid() as u32,
+        rdev: 0,
+        // 文件系统的块大小, 与设备的扇区大小无关
+        blksize: block::SIZE as u32,
+        flags: 0,
+    }
+}
+
+fn errno(err: VfsError) -> i32 {
+    match err.kind() {
+        VfsErrorKind::IOError(io_err) => match io_err.kind() {
+            IOErrorKind::NotFound => libc::ENOENT,
+            IOErrorKind::PermissionDenied => libc::EACCES,
+            IOErrorKind::NotADirectory => libc::ENOTDIR,
+            IOErrorKind::IsADirectory => libc::EISDIR,
+            IOErrorKind::NotAFile | IOErrorKind::NotASymlink => libc::EINVAL,
+            IOErrorKind::TooLongFileName => libc::ENAMETOOLONG,
+            IOErrorKind::TooManySymlinks => libc::ELOOP,
+            _ => libc::EIO,
+        },
+        VfsErrorKind::FileNotFound => libc::ENOENT,
+        VfsErrorKind::InvalidPath(_) => libc::EINVAL,
+        VfsErrorKind::NotSupported => libc::ENOSYS,
+        _ => libc::EIO,
+    }
+}
@@ -1,19 +1,189 @@
-use std::{fs::OpenOptions, sync::Arc};
+#![allow(clippy::print_literal, clippy::bool_assert_comparison)]
+
+use std::{
+    ffi::OsStr,
+    fs::OpenOptions,
+    ops::{ControlFlow, Deref, DerefMut},
+    sync::{Arc, MutexGuard},
+};
 
 use fs::{
     block,
-    ext2::Ext2FileSystem,
+    block_device::BlockDevice,
+    ext2::{
+        decode_dir_records, Address, ErrorPolicy, Ext2FileSystem, Flags, InodeHistogram,
+        JournalState, MountOptions, Problem,
+    },
     time::LocalTime,
-    vfs::{meta::VfsPermissions, VfsPath, VFS},
+    vfs::{
+        error::{VfsError, VfsErrorKind, VfsResult},
+        meta::{Credentials, VfsFileType, VfsMetadata, VfsPermissions},
+        Capabilities, FileSystem, VfsDirEntry, VfsPath, VFS,
+    },
 };
+use fuser::{FileType, FUSE_ROOT_ID};
 use spin::Mutex;
 
-use crate::BlockFile;
+use crate::{
+    counting::{Access, CountingDevice},
+    io_file::IoFile,
+    mount::Ext2Fuse,
+    BlockFile,
+};
+
+// 块缓存是全局唯一的, 因此测试之间必须串行;
+// 另外每个测试都在 ext2.img 的副本上进行, 互不影响
+static IMAGE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+struct TestEnv<T> {
+    inner: T,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl<T> Deref for TestEnv<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for TestEnv<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for TestEnv<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+// 安装全局时钟, 离开作用域时移除, 测试失败时也不会影响之后的测试.
+// 需要在 TestEnv 之后创建, 这样会先于 IMAGE_LOCK 释放
+struct ClockGuard;
+
+impl ClockGuard {
+    fn set(clock: fn() -> u64) -> Self {
+        fs::time::set_clock(clock);
+        ClockGuard
+    }
+}
+
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        fs::time::clear_clock();
+    }
+}
+
+fn image_copy() -> String {
+    let name = std::thread::current()
+        .name()
+        .unwrap_or("main")
+        .replace("::", "_");
+    let path = std::env::temp_dir().join(format!("ext2fs_{}.img", name));
+    std::fs::copy("ext2.img", &path).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn gen_ext2() -> TestEnv<Ext2FileSystem> {
+    gen_ext2_on(|block_file| block_file)
+}
+
+// wrap 可以在 BlockFile 之外再包一层设备, 比如 CountingDevice
+fn gen_ext2_on<D: BlockDevice>(wrap: impl FnOnce(BlockFile) -> D) -> TestEnv<Ext2FileSystem> {
+    let guard = IMAGE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let block_file = BlockFile::create(&image_copy());
+    TestEnv {
+        inner: Ext2FileSystem::open(wrap(block_file)),
+        _guard: guard,
+    }
+}
+
+// 挂载前先直接修改镜像的原始字节
+fn gen_ext2_patched(patch: impl FnOnce(&mut [u8])) -> TestEnv<Ext2FileSystem> {
+    let guard = IMAGE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let path = image_copy();
+    let mut image = std::fs::read(&path).unwrap();
+    patch(&mut image);
+    std::fs::write(&path, image).unwrap();
+    TestEnv {
+        inner: Ext2FileSystem::open(BlockFile::create(&path)),
+        _guard: guard,
+    }
+}
+
+// inode 在镜像中的字节偏移, 用于在挂载前直接修改 inode
+fn inode_byte_offset(device: &impl BlockDevice, inode_id: usize) -> usize {
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    let inodes_per_group = u32::from_le_bytes(sector[40..44].try_into().unwrap()) as usize;
+    let inode_size = u16::from_le_bytes([sector[88], sector[89]]) as usize;
+    let group = (inode_id - 1) / inodes_per_group;
+    device.read_block(block::SECTORS_PER_BLOCK, &mut sector);
+    let table = u32::from_le_bytes(sector[group * 32 + 8..group * 32 + 12].try_into().unwrap());
+    table as usize * block::SIZE + (inode_id - 1) % inodes_per_group * inode_size
+}
+
+// 用 mke2fs 现场生成镜像, 用于 ext2.img 覆盖不到的布局
+fn gen_ext2_mkfs(
+    size: &str,
+    args: &[&str],
+) -> (TestEnv<Ext2FileSystem>, CountingDevice<BlockFile>) {
+    let (guard, path) = mke2fs_image(size, args);
+    let device = CountingDevice::wrapping(BlockFile::create(&path));
+    let ext2 = TestEnv {
+        inner: Ext2FileSystem::open(device.clone()),
+        _guard: guard,
+    };
+    (ext2, device)
+}
+
+// 用 mke2fs 生成指定大小的镜像但不挂载, 返回镜像的路径
+fn mke2fs_image(size: &str, args: &[&str]) -> (MutexGuard<'static, ()>, String) {
+    let guard = IMAGE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let path = image_copy();
+    let status = std::process::Command::new("truncate")
+        .args(["-s", size, &path])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = std::process::Command::new("mke2fs")
+        .args(["-q", "-F", "-t", "ext2", "-b", "4096", "-I", "128"])
+        .args(args)
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    (guard, path)
+}
+
+fn gen_counting_ext2() -> (TestEnv<Ext2FileSystem>, CountingDevice<BlockFile>) {
+    let mut device = None;
+    let ext2 = gen_ext2_on(|block_file| {
+        let counting = CountingDevice::wrapping(block_file);
+        device = Some(counting.clone());
+        counting
+    });
+    (ext2, device.unwrap())
+}
+
+fn gen_counting_vfs() -> (TestEnv<VFS>, CountingDevice<BlockFile>) {
+    let (TestEnv { inner, _guard }, device) = gen_counting_ext2();
+    let vfs = TestEnv {
+        inner: VFS::new(inner),
+        _guard,
+    };
+    (vfs, device)
+}
 
-fn gen_vfs() -> VFS {
-    let block_file = BlockFile::create("ext2.img");
-    let ext2 = Ext2FileSystem::open(block_file);
-    VFS::new(ext2)
+fn gen_vfs() -> TestEnv<VFS> {
+    let TestEnv { inner, _guard } = gen_ext2();
+    TestEnv {
+        inner: VFS::new(inner),
+        _guard,
+    }
 }
 
 #[test]
@@ -40,7 +210,7 @@ fn test_read_dir() {
                 entry.inode().read_symlink().unwrap()
             )
         } else {
-            format!("{}", entry.name())
+            entry.name().to_string()
         };
 
         println!(
@@ -94,7 +264,7 @@ fn test_rw() {
         use rand;
         // random digit
         for _ in 0..len {
-            str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
+            str.push(char::from(b'0' + rand::random::<u8>() % 10));
         }
         file.write_at(0, str.as_bytes()).unwrap();
         let mut read_buffer = [0u8; 8192];
@@ -143,25 +313,251 @@ fn test_create_file() {
 #[test]
 fn test_create_dir() {
     let vfs = gen_vfs();
+    // ext2.img 的根目录下已经有 /new_dir0 这些目录, 放到新目录下
+    vfs.create_dir("/created").unwrap();
     for i in 0..11 {
-        let path = format!("/new_dir{}", i);
+        let path = format!("/created/new_dir{}", i);
         let mut dir = vfs.create_dir(path).unwrap();
         let permissions = VfsPermissions::new(0o644);
         dir.set_permissions(&permissions).unwrap();
     }
 }
 
+#[test]
+fn test_create_dir_dot_entries() {
+    let (ext2, device) = gen_counting_ext2();
+    let dirs_count = |ext2: &Ext2FileSystem| {
+        ext2.flush();
+        fs::block_device::flush();
+        // 第 0 组描述符中的 dirs_count 位于偏移 16
+        let mut sector = [0u8; 512];
+        device.read_block(block::SECTORS_PER_BLOCK, &mut sector);
+        u16::from_le_bytes([sector[16], sector[17]])
+    };
+    let root_links =
+        |ext2: &Ext2FileSystem| ext2.metadata(VfsPath::from("/")).unwrap().hard_links();
+    let dirs_before = dirs_count(&ext2);
+    let links_before = root_links(&ext2);
+
+    ext2.create_dir(VfsPath::from("/foo")).unwrap();
+    let foo_id = ext2.inode_id_of(VfsPath::from("/foo")).unwrap();
+    let entries = ext2.read_dir(VfsPath::from("/foo")).unwrap();
+    let names: Vec<_> = entries
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, [".", ".."]);
+    assert_eq!(entries[0].inode_id(), foo_id);
+    assert_eq!(entries[1].inode_id(), 2);
+    assert_eq!(ext2.inode_id_of(VfsPath::from("/foo/.")).unwrap(), foo_id);
+    assert_eq!(ext2.inode_id_of(VfsPath::from("/foo/..")).unwrap(), 2);
+
+    let metadata = ext2.metadata(VfsPath::from("/foo")).unwrap();
+    assert!(metadata.filetype().is_dir());
+    assert_eq!(metadata.hard_links(), 2);
+    assert_eq!(metadata.size(), block::SIZE as u64);
+    assert_eq!(root_links(&ext2), links_before + 1);
+    assert_eq!(dirs_count(&ext2), dirs_before + 1);
+}
+
+#[test]
+fn test_dir_size_not_block_aligned() {
+    let (ext2, device) = gen_counting_ext2();
+    let dir = VfsPath::from("/odd");
+    ext2.create_dir(dir.clone()).unwrap();
+    // 名字足够长, 让目录项写满第一个块并进入第二个块
+    let names: Vec<_> = (0..80).map(|i| format!("{:0>60}", i)).collect();
+    for name in &names {
+        let path = format!("/odd/{}", name);
+        ext2.create_file(VfsPath::from(path.as_str())).unwrap();
+    }
+    assert_eq!(
+        ext2.metadata(dir.clone()).unwrap().size(),
+        2 * block::SIZE as u64
+    );
+    assert_eq!(ext2.read_dir(dir.clone()).unwrap().len(), names.len() + 2);
+    let inode_id = ext2.inode_id_of(dir.clone()).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+
+    // 直接把目录 inode 的 size_low (偏移 4) 改成不是块的整数倍
+    let byte = inode_byte_offset(&device, inode_id);
+    let mut sector = [0u8; 512];
+    device.read_block(byte / 512, &mut sector);
+    let size = (block::SIZE + 100) as u32;
+    sector[byte % 512 + 4..byte % 512 + 8].copy_from_slice(&size.to_le_bytes());
+    device.write_block(byte / 512, &sector);
+    drop(ext2);
+
+    // 末尾不完整的块被舍去, 只剩第一个块中的目录项, 且不会 panic
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    let entries = ext2.read_dir(dir.clone()).unwrap();
+    assert!(entries.len() < names.len() + 2);
+    assert_eq!(entries[0].name(), ".");
+    assert_eq!(entries[1].name(), "..");
+    for entry in &entries[2..] {
+        assert!(names.iter().any(|name| name == entry.name()));
+    }
+    let first = format!("/odd/{}", names[0]);
+    assert!(ext2.metadata(VfsPath::from(first.as_str())).is_ok());
+}
+
+#[test]
+fn test_dir_growth_sentinel() {
+    let (ext2, device) = gen_counting_ext2();
+    let dir = VfsPath::from("/grow");
+    ext2.create_dir(dir.clone()).unwrap();
+    let dir_size = || ext2.metadata(dir.clone()).unwrap().size() as usize;
+    // 不断加入长名字的文件, 直到目录扩容出第二个块
+    let mut count = 0;
+    let mut last = String::new();
+    while dir_size() == block::SIZE {
+        last = format!("/grow/{:0>60}", count);
+        ext2.create_file(VfsPath::from(last.as_str())).unwrap();
+        count += 1;
+    }
+    assert_eq!(dir_size(), 2 * block::SIZE);
+    let second = ext2.owned_blocks(dir.clone()).unwrap()[1] as usize;
+    let raw_block = |block_id: usize| {
+        ext2.flush();
+        fs::block_device::flush();
+        let mut data = vec![0u8; block::SIZE];
+        for (i, sector) in data.chunks_mut(512).enumerate() {
+            device.read_block(block_id * block::SECTORS_PER_BLOCK + i, sector);
+        }
+        data
+    };
+    // (inode_id, record_len)
+    let first_record = |data: &[u8]| {
+        (
+            u32::from_le_bytes(data[0..4].try_into().unwrap()),
+            u16::from_le_bytes([data[4], data[5]]) as usize,
+        )
+    };
+
+    // 删掉第二个块中唯一的目录项, 该块剩下一个覆盖整个块的空闲记录
+    ext2.remove_file(VfsPath::from(last.as_str())).unwrap();
+    assert_eq!(first_record(&raw_block(second)), (0, block::SIZE));
+    assert_eq!(ext2.read_dir(dir.clone()).unwrap().len(), count - 1 + 2);
+
+    // 空闲记录可以直接复用, 不会再次扩容
+    ext2.create_file(VfsPath::from(last.as_str())).unwrap();
+    assert_eq!(dir_size(), 2 * block::SIZE);
+    let (inode_id, record_len) = first_record(&raw_block(second));
+    assert_ne!(inode_id, 0);
+    assert_eq!(record_len, block::SIZE);
+    assert_eq!(ext2.read_dir(dir).unwrap().len(), count + 2);
+}
+
+#[test]
+fn test_dump_dir_raw() {
+    let ext2 = gen_ext2();
+    let raw = ext2.dump_dir_raw(VfsPath::from("/")).unwrap();
+    assert_eq!(raw.len() % block::SIZE, 0);
+    let records = decode_dir_records(&raw);
+    assert_eq!(records[0].offset, 0);
+    assert_eq!(records[0].inode_id, 2);
+    assert_eq!(records[0].name, ".");
+    assert_eq!(records[0].name_len, 1);
+    assert_eq!(records[0].record_len, 12);
+    assert_eq!(records[0].filetype, 2);
+    assert_eq!(records[1].offset, 12);
+    assert_eq!(records[1].inode_id, 2);
+    assert_eq!(records[1].name, "..");
+    assert_eq!(records[1].name_len, 2);
+    assert_eq!(records[1].filetype, 2);
+    // 每个块中的记录首尾相接, 正好铺满整个块
+    let covered: usize = records
+        .iter()
+        .map(|record| record.record_len as usize)
+        .sum();
+    assert_eq!(covered, raw.len());
+    assert!(records[1].to_string().contains("name=\"..\""));
+
+    // 损坏的记录只会截断解析结果
+    let mut broken = raw.clone();
+    broken[16..18].copy_from_slice(&0u16.to_le_bytes());
+    assert_eq!(decode_dir_records(&broken).len(), 1);
+    assert!(ext2.dump_dir_raw(VfsPath::from("/new_file.c")).is_err());
+}
+
 #[test]
 fn test_remove_file() {
     let vfs = gen_vfs();
-    vfs.remove_file("/new_file_longlonglonglonglong91.c")
-        .unwrap();
+    let path = "/new_file_longlonglonglonglong91.c";
+    vfs.create_file(path).unwrap();
+    vfs.remove_file(path).unwrap();
+    assert!(!vfs.exists(path).unwrap());
+}
+
+#[test]
+fn test_remove_file_frees_space() {
+    let (ext2, device) = gen_counting_ext2();
+    let initial = disk_free_blocks(&ext2, &device);
+    let path = VfsPath::from("/doomed");
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, &vec![7u8; 3 * block::SIZE]).unwrap();
+    let inode_id = ext2.inode_id_of(path.clone()).unwrap();
+    let blocks = ext2.owned_blocks(path.clone()).unwrap();
+    drop(file);
+
+    let err = ext2.remove_file(VfsPath::from("/new_dir")).unwrap_err();
+    assert!(err.to_string().contains("IsADirectory"), "{}", err);
+    assert!(ext2.remove_file(VfsPath::from("/none")).is_err());
+
+    ext2.remove_file(path.clone()).unwrap();
+    assert!(ext2.metadata(path.clone()).is_err());
+    assert_eq!(disk_free_blocks(&ext2, &device), initial);
+
+    // 释放的 inode 和数据块可以再次分配
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, &vec![8u8; 3 * block::SIZE]).unwrap();
+    assert_eq!(ext2.inode_id_of(path.clone()).unwrap(), inode_id);
+    assert_eq!(ext2.owned_blocks(path).unwrap(), blocks);
 }
 
 #[test]
 fn test_remove_dir() {
     let vfs = gen_vfs();
-    vfs.remove_dir("/new_dir").unwrap();
+    // /new_dir 中还有文件, 只能删除空目录
+    let err = vfs.remove_dir("/new_dir").unwrap_err();
+    assert!(err.to_string().contains("DirectoryNotEmpty"), "{}", err);
+    assert!(vfs.exists("/new_dir/new.c").unwrap());
+}
+
+#[test]
+fn test_remove_empty_dir() {
+    let (ext2, device) = gen_counting_ext2();
+    let dirs_count = |ext2: &Ext2FileSystem| {
+        ext2.flush();
+        fs::block_device::flush();
+        let mut sector = [0u8; 512];
+        device.read_block(block::SECTORS_PER_BLOCK, &mut sector);
+        u16::from_le_bytes([sector[16], sector[17]])
+    };
+    let root = VfsPath::from("/");
+    let root_links = ext2.metadata(root.clone()).unwrap().hard_links();
+    let dirs_before = dirs_count(&ext2);
+    let free_before = disk_free_blocks(&ext2, &device);
+
+    let path = VfsPath::from("/empty");
+    ext2.create_dir(path.clone()).unwrap();
+    let inode_id = ext2.inode_id_of(path.clone()).unwrap();
+    ext2.create_file(VfsPath::from("/empty/file")).unwrap();
+    let err = ext2.remove_dir(path.clone()).unwrap_err();
+    assert!(err.to_string().contains("DirectoryNotEmpty"), "{}", err);
+    let err = ext2.remove_dir(VfsPath::from("/new_file.c")).unwrap_err();
+    assert!(err.to_string().contains("NotADirectory"), "{}", err);
+
+    ext2.remove_file(VfsPath::from("/empty/file")).unwrap();
+    ext2.remove_dir(path.clone()).unwrap();
+    assert!(ext2.metadata(path.clone()).is_err());
+    assert_eq!(ext2.metadata(root).unwrap().hard_links(), root_links);
+    assert_eq!(dirs_count(&ext2), dirs_before);
+    assert_eq!(disk_free_blocks(&ext2, &device), free_before);
+    // inode 被释放, 可以重新分配
+    ext2.create_dir(path.clone()).unwrap();
+    assert_eq!(ext2.inode_id_of(path).unwrap(), inode_id);
 }
 
 #[test]
@@ -180,6 +576,206 @@ fn test_symlink() {
         .unwrap();
 }
 
+#[test]
+fn test_symlink_target_len() {
+    let vfs = gen_vfs();
+    let read_link = |name: &str| {
+        let entries = vfs.read_dir("/").unwrap();
+        let entry = entries.iter().find(|entry| entry.name() == name).unwrap();
+        let metadata = entry.inode().metadata();
+        (entry.inode().read_symlink().unwrap(), metadata.blocks())
+    };
+
+    let err = vfs.symlink("", "/empty").unwrap_err();
+    assert!(err.to_string().contains("EmptyTargetSymlink"), "{}", err);
+    assert!(!vfs.exists("/empty").unwrap());
+
+    // 60 字节以内存放在 inode 中, 不占用数据块
+    let fast = format!("/{}", "f".repeat(59));
+    vfs.symlink(fast.as_str(), "/fast").unwrap();
+    assert_eq!(read_link("fast"), (fast, 0));
+
+    // 更长的目标存放在数据块中
+    let slow = format!("/{}", "s".repeat(4095));
+    vfs.symlink(slow.as_str(), "/slow").unwrap();
+    let (target, blocks) = read_link("slow");
+    assert_eq!(target, slow);
+    assert_eq!(blocks, block::SECTORS_PER_BLOCK as u64);
+
+    let too_long = format!("/{}", "t".repeat(4096));
+    let err = vfs.symlink(too_long.as_str(), "/too_long").unwrap_err();
+    assert!(err.to_string().contains("TooLongTargetSymlink"), "{}", err);
+
+    vfs.remove_file("/slow").unwrap();
+    assert!(vfs
+        .read_dir("/")
+        .unwrap()
+        .iter()
+        .all(|entry| entry.name() != "slow"));
+}
+
+#[test]
+fn test_relative_symlink() {
+    let vfs = gen_vfs();
+    let content = |path: &str| {
+        let file = vfs.open_file(path).unwrap();
+        let mut buf = vec![0u8; file.metadata().size() as usize];
+        file.read_at(0, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    vfs.create_dir("/ra").unwrap();
+    vfs.create_dir("/ra/b").unwrap();
+    vfs.create_dir("/rd").unwrap();
+    for (path, data) in [
+        ("/rc", "c"),
+        ("/ra/x", "a/x"),
+        ("/ra/b/x", "a/b/x"),
+        ("/rd/f", "d/f"),
+    ] {
+        vfs.create_file(path)
+            .unwrap()
+            .write_at(0, data.as_bytes())
+            .unwrap();
+    }
+
+    // 多级 "..": 从 symlink 所在的 /ra/b 开始往上走两级
+    vfs.symlink("../../rc", "/ra/b/link").unwrap();
+    assert_eq!(content("/ra/b/link"), "c");
+
+    // 相对目标的起点是 symlink 所在目录, 而不是根目录或上一级
+    vfs.symlink("x", "/ra/b/here").unwrap();
+    assert_eq!(content("/ra/b/here"), "a/b/x");
+    vfs.symlink("../x", "/ra/b/up").unwrap();
+    assert_eq!(content("/ra/b/up"), "a/x");
+
+    // 目标本身又经过另一个相对 symlink
+    vfs.symlink("../rc", "/ra/to_c").unwrap();
+    vfs.symlink("../to_c", "/ra/b/chain").unwrap();
+    assert_eq!(content("/ra/b/chain"), "c");
+
+    // 路径中间的相对 symlink 指向目录
+    vfs.symlink("../../rd", "/ra/b/dir").unwrap();
+    assert_eq!(content("/ra/b/dir/f"), "d/f");
+
+    // 绝对目标总是从根开始
+    vfs.symlink("/rc", "/ra/b/abs").unwrap();
+    assert_eq!(content("/ra/b/abs"), "c");
+
+    // 成环的 symlink 在跟随 40 次之后报错, 而不是一直走下去
+    let is_loop = |err: VfsError| format!("{:?}", err).contains("TooManySymlinks");
+    vfs.symlink("/self_loop", "/self_loop").unwrap();
+    assert!(!vfs.exists("/self_loop").unwrap());
+    assert!(is_loop(vfs.metadata("/self_loop").err().unwrap()));
+    assert!(is_loop(vfs.open_file("/self_loop").err().unwrap()));
+    vfs.symlink("ping", "/ra/pong").unwrap();
+    vfs.symlink("pong", "/ra/ping").unwrap();
+    assert!(is_loop(vfs.open_file("/ra/ping/x").err().unwrap()));
+    // 40 级的链本身是允许的
+    vfs.symlink("/rc", "/ra/chain0").unwrap();
+    for i in 1..40 {
+        let target = format!("chain{}", i - 1);
+        vfs.symlink(&target, &format!("/ra/chain{}", i)).unwrap();
+    }
+    assert_eq!(content("/ra/chain39"), "c");
+    vfs.symlink("chain39", "/ra/chain40").unwrap();
+    assert!(is_loop(vfs.open_file("/ra/chain40").err().unwrap()));
+}
+
+#[test]
+fn test_read_link() {
+    let vfs = gen_vfs();
+    vfs.create_file("/link_target").unwrap();
+    vfs.symlink("/link_target", "/abs_link").unwrap();
+    vfs.symlink("link_target", "/rel_link").unwrap();
+    assert_eq!(vfs.read_link("/abs_link").unwrap(), "/link_target");
+    assert_eq!(vfs.read_link("/rel_link").unwrap(), "link_target");
+
+    // 目标原样保存, 不做规范化, 解析时仍按路径处理
+    vfs.create_dir("/link_dir").unwrap();
+    for (link, target) in [
+        ("/link_dir/up", "../link_target"),
+        ("/link_dir/dotted", "./../link_target"),
+        ("/slashes", "link_dir//..//link_target/"),
+    ] {
+        vfs.symlink(target, link).unwrap();
+        assert_eq!(vfs.read_link(link).unwrap(), target);
+        assert!(vfs.open_file(link).is_ok(), "{}", link);
+    }
+
+    let err = vfs.read_link("/link_target").unwrap_err();
+    assert!(err.to_string().contains("NotASymlink"), "{}", err);
+    assert!(vfs.read_link("/no_such_link").is_err());
+}
+
+#[test]
+fn test_change_time() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    let vfs = gen_vfs();
+    let _clock = ClockGuard::set(|| NOW.load(Ordering::SeqCst));
+
+    let mut file = vfs.create_file("/timed").unwrap();
+    file.write_at(0, b"data").unwrap();
+    let before = file.metadata().timestamp();
+
+    // chmod 只修改 inode, 不修改内容
+    NOW.store(1_700_000_000, Ordering::SeqCst);
+    file.set_permissions(&VfsPermissions::new(0o640)).unwrap();
+    let after = file.metadata().timestamp();
+    assert_eq!(after.change_time(), 1_700_000_000);
+    assert!(after.change_time() > before.change_time());
+    assert_eq!(after.modify_time(), before.modify_time());
+    assert_eq!(after.ctime(), after.change_time());
+}
+
+#[test]
+fn test_metadata_batch() {
+    let (vfs, device) = gen_counting_vfs();
+    // 足够深的目录链, 一次完整的查找就会把缓存挤满
+    let mut dir = String::new();
+    for depth in 0..40 {
+        dir.push_str(&format!("/d{}", depth));
+        vfs.create_dir(&dir).unwrap();
+    }
+    let paths: Vec<String> = (1..=3).map(|i| format!("{}/{}", dir, i)).collect();
+    for path in &paths {
+        vfs.create_file(path).unwrap();
+    }
+    let mut mixed = paths.clone();
+    mixed.push(format!("{}/missing", dir));
+    mixed.push("relative".to_string());
+    let results = vfs.metadata_batch(&mixed);
+    assert_eq!(results.len(), 5);
+    assert!(results[..3]
+        .iter()
+        .all(|meta| meta.as_ref().unwrap().filetype().is_file()));
+    assert!(results[3].is_err() && results[4].is_err());
+
+    let cold_reads = |stat: &dyn Fn()| {
+        vfs.flush();
+        fs::block_device::register_block_device(device.clone());
+        device.reset();
+        stat();
+        device.counts().reads
+    };
+    let separate = cold_reads(&|| {
+        for path in &paths {
+            vfs.metadata(path).unwrap();
+        }
+    });
+    let batched = cold_reads(&|| {
+        for meta in vfs.metadata_batch(&paths) {
+            meta.unwrap();
+        }
+    });
+    assert!(
+        batched < separate,
+        "batched {} separate {}",
+        batched,
+        separate
+    );
+}
+
 fn tttt(vfs: &VFS) {
     let dir = vfs.read_dir("/").unwrap();
     println!(
@@ -196,7 +792,7 @@ fn tttt(vfs: &VFS) {
                 entry.inode().read_symlink().unwrap()
             )
         } else {
-            format!("{}", entry.name())
+            entry.name().to_string()
         };
 
         println!(
@@ -234,5 +830,4109 @@ fn test_syntax() {
     }
     tttt(&vfs);
 
-    let mut file = vfs.open_file("/new_dir").unwrap();
+    // 目录不能当作文件打开
+    let err = vfs.open_file("/new_dir").err().unwrap();
+    assert!(format!("{:?}", err).contains("NotAFile"), "{:?}", err);
+}
+
+#[test]
+fn test_snapshot() {
+    let ext2 = gen_ext2();
+    let snapshot = ext2.snapshot();
+    let mut old = [0u8; 64];
+    let old_len = snapshot.read_at("/new_file.c", 0, &mut old).unwrap();
+
+    let content = b"written after the snapshot was taken";
+    let mut file = ext2.open_file(VfsPath::from("/new_file.c")).unwrap();
+    file.write_at(0, content).unwrap();
+    ext2.flush();
+
+    // 快照仍然读到旧内容
+    let mut buf = [0u8; 64];
+    assert_eq!(
+        snapshot.read_at("/new_file.c", 0, &mut buf).unwrap(),
+        old_len
+    );
+    assert_eq!(buf, old);
+    assert_eq!(
+        snapshot.metadata("/new_file.c").unwrap().size(),
+        old_len as u64
+    );
+
+    // 原文件系统读到新内容
+    let mut buf = [0u8; 64];
+    file.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf[..content.len()], content);
+
+    let names: Vec<_> = snapshot
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert!(names.iter().any(|name| name == "new_file.c"));
+}
+
+#[test]
+fn test_path_eq() {
+    use std::collections::HashSet;
+
+    assert_eq!(VfsPath::from("/a/b/"), VfsPath::from("/a/b"));
+    assert_eq!(VfsPath::from("/a/./b"), VfsPath::from("/a/b"));
+    assert_eq!(VfsPath::from("/a/c/../b"), VfsPath::from("/a/b"));
+    assert_eq!(VfsPath::from("/../a"), VfsPath::from("/a"));
+    assert_ne!(VfsPath::from("/a/b"), VfsPath::from("a/b"));
+    assert_ne!(VfsPath::from("../a"), VfsPath::from("a"));
+
+    let mut set = HashSet::new();
+    set.insert(VfsPath::from("/a/b"));
+    assert!(set.contains(&VfsPath::from("/a/./b/")));
+    assert!(!set.contains(&VfsPath::from("a/b")));
+}
+
+#[test]
+fn test_path_normalization() {
+    let components = |path: &str| {
+        VfsPath::from(path)
+            .iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(components("/a/./b"), ["a", "b"]);
+    assert_eq!(components("/a/../b"), ["b"]);
+    assert_eq!(components("/../a"), ["a"]);
+    assert_eq!(VfsPath::from("/a/..").to_string(), "/");
+    // 相对路径开头的 ".." 留给解析符号链接时处理
+    assert_eq!(components("../a/./b/.."), ["..", "a"]);
+
+    let vfs = gen_vfs();
+    vfs.create_dir("/na").unwrap();
+    vfs.create_dir("/na/./nb").unwrap();
+    assert!(vfs.exists("/na/./nb").unwrap());
+    vfs.create_file("/na/../nc").unwrap();
+    assert!(vfs.exists("/nc").unwrap());
+    assert!(vfs.exists("/../na").unwrap());
+    assert_eq!(
+        vfs.inode_id_of("/na/nb/../..").unwrap(),
+        vfs.root_id().unwrap()
+    );
+}
+
+#[test]
+fn test_path_builder() {
+    let path = VfsPath::builder(true)
+        .push("a")
+        .unwrap()
+        .push("b")
+        .unwrap()
+        .push("c")
+        .unwrap()
+        .build();
+    assert_eq!(path.to_string(), "/a/b/c");
+    assert_eq!(path, VfsPath::from("/a/b/c"));
+    assert_eq!(
+        VfsPath::from_components(&["a", "b", "c"]).unwrap(),
+        VfsPath::from("/a/b/c")
+    );
+
+    assert!(VfsPath::builder(true).push("a/b").is_err());
+    assert!(VfsPath::builder(true).push("a\0b").is_err());
+    assert!(VfsPath::from_components(&["a", "b/c"]).is_err());
+}
+
+#[test]
+fn test_boxed_metadata() {
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/meta").unwrap();
+    file.write_at(0, b"hello").unwrap();
+    file.set_permissions(&VfsPermissions::new(0o640)).unwrap();
+
+    // 只通过 dyn VfsMetadata 访问
+    let metadata: Box<dyn VfsMetadata> = vfs.metadata("/meta").unwrap();
+    assert!(metadata.is_file());
+    assert!(!metadata.is_dir());
+    assert_eq!(metadata.size(), 5);
+    assert_eq!(metadata.permissions().to_string(), "rw-r-----");
+    assert_eq!(metadata.hard_links(), 1);
+    assert_eq!(metadata.blocks(), block::SECTORS_PER_BLOCK as u64);
+    assert_eq!(metadata.timestamp().dtime(), 0);
+    let _ = (metadata.uid(), metadata.gid());
+
+    assert!(vfs.metadata("/").unwrap().is_dir());
+    assert!(file.metadata().is_file());
+}
+
+#[test]
+fn test_is_root() {
+    let mut vfs = gen_vfs();
+    assert_eq!(vfs.root_id(), Some(2));
+    assert!(vfs.is_root("/").unwrap());
+    assert!(vfs.is_root("/new_dir/..").unwrap());
+    assert!(!vfs.is_root("/new_dir").unwrap());
+    assert!(!vfs.is_root("/new_file.c").unwrap());
+    assert!(vfs.is_root("/none").is_err());
+
+    // 另外指定的根
+    assert!(vfs.set_alt_root("/new_file.c").is_err());
+    vfs.set_alt_root("/new_dir").unwrap();
+    assert!(vfs.is_root("/new_dir").unwrap());
+    assert!(vfs.is_root("/new_dir/new_dir2/..").unwrap());
+    assert!(!vfs.is_root("/new_dir/new_dir2").unwrap());
+}
+
+#[test]
+fn test_inode_id_of() {
+    let vfs = gen_vfs();
+    assert_eq!(vfs.inode_id_of("/").unwrap(), 2);
+
+    let entry = vfs
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name() == "new_file.c")
+        .unwrap();
+    assert_eq!(vfs.inode_id_of("/new_file.c").unwrap(), entry.inode_id());
+    assert!(vfs.inode_id_of("/none").is_err());
+}
+
+#[test]
+fn test_dir_hard_links() {
+    let vfs = gen_vfs();
+    let root_links = vfs.metadata("/").unwrap().hard_links();
+
+    // 父目录中的名字 + 自身的 "."
+    vfs.create_dir("/links_dir").unwrap();
+    assert_eq!(vfs.metadata("/links_dir").unwrap().hard_links(), 2);
+    // 子目录的 ".." 指向父目录
+    assert_eq!(vfs.metadata("/").unwrap().hard_links(), root_links + 1);
+
+    vfs.create_dir("/links_dir/sub").unwrap();
+    assert_eq!(vfs.metadata("/links_dir/sub").unwrap().hard_links(), 2);
+    assert_eq!(vfs.metadata("/links_dir").unwrap().hard_links(), 3);
+
+    vfs.remove_dir("/links_dir/sub").unwrap();
+    assert_eq!(vfs.metadata("/links_dir").unwrap().hard_links(), 2);
+    vfs.remove_dir("/links_dir").unwrap();
+    assert_eq!(vfs.metadata("/").unwrap().hard_links(), root_links);
+}
+
+#[test]
+fn test_set_len_zero_fill() {
+    let vfs = gen_vfs();
+    let mut file = vfs.open_file("/new_file.c").unwrap();
+    let data = vec![0xAAu8; 3 * block::SIZE];
+    file.write_at(0, &data).unwrap();
+
+    // 缩到 0 再长回来, 不能读到旧数据
+    file.set_len(0).unwrap();
+    file.set_len(data.len()).unwrap();
+    let mut buf = vec![0xFFu8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert!(buf.iter().all(|&x| x == 0));
+
+    // 缩到块中间, 块内残留部分同样要清零
+    file.write_at(0, &data).unwrap();
+    file.set_len(100).unwrap();
+    file.set_len(data.len()).unwrap();
+    file.read_at(0, &mut buf).unwrap();
+    assert!(buf[..100].iter().all(|&x| x == 0xAA));
+    assert!(buf[100..].iter().all(|&x| x == 0));
+}
+
+#[test]
+fn test_direct_to_indirect() {
+    let (ext2, device) = gen_counting_ext2();
+    let path = VfsPath::from("/new_file.c");
+    let mut file = ext2.open_file(path.clone()).unwrap();
+    let sectors = |blocks: u64| blocks * (block::SIZE / 512) as u64;
+    // 磁盘上 inode 的 indirect_pointer, 位于块指针区的第 12 项, 读之前先写回
+    let indirect_pointer = |inode_id: usize| {
+        ext2.flush();
+        fs::block_device::flush();
+        let byte = inode_byte_offset(&device, inode_id) + 40 + 12 * 4;
+        let mut sector = [0u8; 512];
+        device.read_block(byte / 512, &mut sector);
+        let at = byte % 512;
+        u32::from_le_bytes(sector[at..at + 4].try_into().unwrap())
+    };
+
+    file.set_len(12 * block::SIZE).unwrap();
+    assert_eq!(file.metadata().blocks(), sectors(12));
+    assert_eq!(indirect_pointer(file.inode_id()), 0);
+    let free_direct = disk_free_blocks(&ext2, &device);
+
+    // 第 13 块需要额外分配一个 indirect 块
+    let data = vec![0x5Au8; block::SIZE];
+    file.write_at(12 * block::SIZE, &data).unwrap();
+    assert_eq!(file.metadata().blocks(), sectors(13 + 1));
+    assert_eq!(disk_free_blocks(&ext2, &device), free_direct - 2);
+    let indirect = indirect_pointer(file.inode_id());
+    assert_ne!(indirect, 0);
+    let owned = ext2.owned_blocks(path.clone()).unwrap();
+    assert_eq!(owned.len(), 13 + 1);
+    assert!(owned.contains(&indirect));
+    let mut buf = vec![0u8; block::SIZE];
+    file.read_at(12 * block::SIZE, &mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    // 缩回 12 块时 indirect 块随之释放
+    file.set_len(12 * block::SIZE).unwrap();
+    assert_eq!(file.metadata().blocks(), sectors(12));
+    assert_eq!(disk_free_blocks(&ext2, &device), free_direct);
+    assert_eq!(indirect_pointer(file.inode_id()), 0);
+    assert!(!ext2.owned_blocks(path.clone()).unwrap().contains(&indirect));
+
+    // 释放的块可以被重新分配
+    file.set_len(13 * block::SIZE).unwrap();
+    assert_eq!(file.metadata().blocks(), sectors(13 + 1));
+    assert_eq!(disk_free_blocks(&ext2, &device), free_direct - 2);
+    file.read_at(12 * block::SIZE, &mut buf).unwrap();
+    assert!(buf.iter().all(|&x| x == 0));
+}
+
+#[test]
+fn test_counting_sequential_read() {
+    let (vfs, device) = gen_counting_vfs();
+    let mut file = vfs.open_file("/new_file.c").unwrap();
+    let blocks = 8;
+    file.write_at(0, &vec![1u8; blocks * block::SIZE]).unwrap();
+    vfs.flush();
+
+    // 在同一设备上重新挂载, 让缓存是冷的
+    let vfs = VFS::new(Ext2FileSystem::open(device.clone()));
+    let file = vfs.open_file("/new_file.c").unwrap();
+    device.reset();
+
+    let mut buf = vec![0u8; block::SIZE];
+    for i in 0..blocks {
+        file.read_at(i * block::SIZE, &mut buf).unwrap();
+    }
+    let counts = device.counts();
+    // 每个数据块各读一次, 每块由 SECTORS_PER_BLOCK 个扇区组成
+    assert_eq!(counts.reads, blocks * block::SECTORS_PER_BLOCK);
+    assert_eq!(counts.writes, 0);
+
+    // 再读一遍全部命中缓存
+    device.reset();
+    for i in 0..blocks {
+        file.read_at(i * block::SIZE, &mut buf).unwrap();
+    }
+    assert_eq!(device.counts().reads, 0);
+}
+
+#[test]
+fn test_capabilities() {
+    use fs::vfs::Capabilities;
+
+    let vfs = gen_vfs();
+    let expected = Capabilities::READ_DIR
+        | Capabilities::CREATE_FILE
+        | Capabilities::SYMLINK
+        | Capabilities::HARDLINK
+        | Capabilities::STAT_FS
+        | Capabilities::READ_LINK
+        | Capabilities::READ_DIR_PLUS
+        | Capabilities::SYMLINK_METADATA;
+    assert!(vfs.capabilities().contains(expected));
+    // 声明了的可选操作都能调用
+    assert!(vfs.stat_fs().is_ok());
+    assert!(vfs.read_link("/symlink").is_ok());
+    assert!(vfs.read_dir_plus("/").is_ok());
+    let (_, metadata) = vfs.symlink_metadata("/symlink").unwrap();
+    assert!(metadata.is_symlink());
+}
+
+#[test]
+fn test_open_at() {
+    let vfs = gen_vfs();
+    let dir = vfs.create_dir("/openat_dir").unwrap();
+    let mut file = vfs.create_file("/openat_dir/file").unwrap();
+    file.write_at(0, b"hello openat").unwrap();
+
+    let opened = dir.open_at("file").unwrap();
+    let mut buf = [0u8; 12];
+    opened.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"hello openat");
+
+    assert!(dir.open_at("/openat_dir/file").is_err());
+    assert!(dir.open_at("none").is_err());
+    assert!(opened.open_at("file").is_err());
+}
+
+#[test]
+fn test_read_dir_plus() {
+    let (vfs, device) = gen_counting_vfs();
+
+    // 先逐项 stat, 记录冷缓存下的设备读次数
+    let vfs = VFS::new(Ext2FileSystem::open(device.clone()));
+    device.reset();
+    let entries = vfs.read_dir("/").unwrap();
+    let expected: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let metadata = entry.inode().metadata();
+            (entry.name().to_string(), entry.inode_id(), metadata.size())
+        })
+        .collect();
+    let naive_reads = device.counts().reads;
+
+    // 冷缓存下再用 read_dir_plus
+    let vfs = VFS::new(Ext2FileSystem::open(device.clone()));
+    device.reset();
+    let plus: Vec<_> = vfs
+        .read_dir_plus("/")
+        .unwrap()
+        .into_iter()
+        .map(|(name, inode_id, metadata)| (name, inode_id, metadata.size()))
+        .collect();
+    let plus_reads = device.counts().reads;
+
+    assert_eq!(plus, expected);
+    assert!(plus_reads <= naive_reads);
+}
+
+#[test]
+fn test_seek_from() {
+    use fs::vfs::io::{Position, SeekFrom};
+
+    let len = 100;
+    let mut pos = Position::default();
+    assert_eq!(pos.seek(SeekFrom::Start(10), len), 10);
+    assert_eq!(pos.seek(SeekFrom::Current(5), len), 15);
+    assert_eq!(pos.seek(SeekFrom::Current(-20), len), 0);
+    assert_eq!(pos.seek(SeekFrom::End(-10), len), 90);
+    assert_eq!(pos.seek(SeekFrom::End(-200), len), 0);
+    assert!(!pos.is_eof(len));
+    assert_eq!(pos.seek(SeekFrom::End(16), len), 116);
+    assert!(pos.is_eof(len));
+
+    // 越过 EOF 读不到数据, 越过 EOF 写入后中间的空隙读出为 0
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/seek_file").unwrap();
+    file.write_at(0, &[7u8; 100]).unwrap();
+    let mut buf = [0xFFu8; 16];
+    assert_eq!(file.read_at(pos.get() as usize, &mut buf).unwrap(), 0);
+
+    file.write_at(pos.get() as usize, b"tail").unwrap();
+    pos.advance(4);
+    assert_eq!(file.metadata().size(), pos.get());
+    assert_eq!(file.read_at(100, &mut buf).unwrap(), 16);
+    assert_eq!(&buf[..16], &[0u8; 16]);
+}
+
+#[test]
+fn test_abandon() {
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_counting_ext2();
+    assert!(ext2.is_clean());
+
+    let path = VfsPath::from("/new_file.c");
+    let mut original = vec![0u8; 64];
+    let len = ext2
+        .open_file(path.clone())
+        .unwrap()
+        .read_at(0, &mut original)
+        .unwrap();
+
+    // 挂载期间的 flush 不能把 clean 写到磁盘上
+    ext2.flush();
+    let mut file = ext2.open_file(path.clone()).unwrap();
+    file.write_at(0, b"abandoned").unwrap();
+    drop(file);
+    ext2.abandon();
+
+    // 重新挂载: 修改没有落盘, 且 superblock 不是 clean
+    let ext2 = Ext2FileSystem::open(device.clone());
+    assert!(!ext2.is_clean());
+    let mut buf = vec![0u8; 64];
+    let file = ext2.open_file(path).unwrap();
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), len);
+    assert_eq!(buf, original);
+
+    // 正常卸载之后又是 clean
+    VFS::new(ext2);
+    assert!(Ext2FileSystem::open(device).is_clean());
+}
+
+#[test]
+fn test_alloc_skips_reserved_inodes() {
+    const INODES: usize = 2560;
+    const FREE: u32 = 5;
+    // 只留最后 5 个 inode 空闲, 同时清掉保留 inode 1~10 的位, 模拟一个不标记保留区的 mkfs
+    let ext2 = gen_ext2_patched(|image| {
+        let bitmap = &mut image[3 * block::SIZE..3 * block::SIZE + INODES / 8];
+        bitmap.fill(0xFF);
+        bitmap[0] = 0;
+        bitmap[1] &= !0b11;
+        *bitmap.last_mut().unwrap() = 0b0000_0111;
+        // superblock 和块组描述符中的 free_inodes_count
+        image[1024 + 16..1024 + 20].copy_from_slice(&(FREE + 10).to_le_bytes());
+        image[block::SIZE + 14..block::SIZE + 16]
+            .copy_from_slice(&(FREE as u16 + 10).to_le_bytes());
+    });
+
+    let mut allocated = Vec::new();
+    loop {
+        let path = VfsPath::from(format!("/reserved_{}", allocated.len()).as_str());
+        match ext2.create_file(path.clone()) {
+            Ok(_) => allocated.push(ext2.inode_id_of(path).unwrap()),
+            Err(err) => {
+                assert!(err.to_string().contains("NoFreeInodes"), "{}", err);
+                break;
+            }
+        }
+    }
+
+    assert_eq!(allocated.len(), FREE as usize);
+    assert!(allocated.iter().all(|&inode_id| inode_id >= 11));
+}
+
+#[test]
+fn test_walk_dir_cycle() {
+    let (vfs, device) = gen_counting_vfs();
+    vfs.create_dir("/cycle_a").unwrap();
+    vfs.create_dir("/cycle_a/cycle_b").unwrap();
+    vfs.create_file("/cycle_a/cycle_b/cycle_back").unwrap();
+    let mut walked = vfs.walk_dir("/cycle_a").unwrap();
+    walked.sort();
+    assert_eq!(walked, ["/cycle_a/cycle_b", "/cycle_a/cycle_b/cycle_back"]);
+    let cycle_a = vfs.inode_id_of("/cycle_a").unwrap() as u32;
+    let TestEnv { inner: vfs, _guard } = vfs;
+    drop(vfs);
+
+    // 直接改写磁盘上的目录项, 让 cycle_back 变成指回 cycle_a 的目录硬链接
+    let pattern = b"\x0a\x01cycle_back";
+    let mut sector = [0u8; 512];
+    let sector_id = (0..)
+        .find(|&sector_id| {
+            device.read_block(sector_id, &mut sector);
+            sector.windows(pattern.len()).any(|w| w == pattern)
+        })
+        .unwrap();
+    let pos = sector
+        .windows(pattern.len())
+        .position(|w| w == pattern)
+        .unwrap();
+    sector[pos - 6..pos - 2].copy_from_slice(&cycle_a.to_le_bytes());
+    sector[pos + 1] = 2;
+    device.write_block(sector_id, &sector);
+
+    let vfs = VFS::new(Ext2FileSystem::open(device));
+    let err = vfs.walk_dir("/cycle_a").unwrap_err();
+    assert!(err.to_string().contains("Corrupted"), "{}", err);
+    // rm -rf 同样不会沿着环一直走下去
+    let err = vfs.remove_dir_all("/cycle_a").unwrap_err();
+    assert!(err.to_string().contains("Corrupted"), "{}", err);
+    assert!(vfs.exists("/cycle_a/cycle_b").unwrap());
+}
+
+#[test]
+fn test_export_manifest() {
+    let ext2 = gen_ext2();
+    let data: Vec<u8> = (0..3 * block::SIZE).map(|i| (i % 251) as u8).collect();
+    let write_data = |ext2: &Ext2FileSystem| {
+        let mut file = ext2
+            .create_file(VfsPath::from("/manifest_dir/data"))
+            .unwrap();
+        file.write_at(0, &data).unwrap();
+    };
+    ext2.create_dir(VfsPath::from("/manifest_dir")).unwrap();
+    write_data(&ext2);
+    let manifest = ext2.export_manifest().unwrap();
+    assert_eq!(manifest, ext2.export_manifest().unwrap());
+
+    // 同样的内容换到别的块上, 清单不变
+    let mut filler = ext2.create_file(VfsPath::from("/filler")).unwrap();
+    filler.set_len(5 * block::SIZE).unwrap();
+    ext2.remove_file(VfsPath::from("/manifest_dir/data"))
+        .unwrap();
+    write_data(&ext2);
+    ext2.remove_file(VfsPath::from("/filler")).unwrap();
+    assert_eq!(manifest, ext2.export_manifest().unwrap());
+
+    // 内容变化则清单变化
+    let mut file = ext2.open_file(VfsPath::from("/manifest_dir/data")).unwrap();
+    file.write_at(block::SIZE, b"x").unwrap();
+    assert_ne!(manifest, ext2.export_manifest().unwrap());
+}
+
+#[test]
+fn test_all_owned_blocks() {
+    let (ext2, device) = gen_counting_ext2();
+    let path = VfsPath::from("/new_file.c");
+    let data_blocks = 12 + block::SIZE / 4 + 2;
+    let mut file = ext2.open_file(path.clone()).unwrap();
+    file.write_at(0, &vec![0x5Au8; data_blocks * block::SIZE])
+        .unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+
+    // indirect1, indirect2 以及 indirect2 下的一个 indirect1
+    let owned = ext2.owned_blocks(path).unwrap();
+    assert_eq!(owned.len(), data_blocks + 3);
+    assert_eq!(
+        owned
+            .iter()
+            .collect::<std::collections::BTreeSet<_>>()
+            .len(),
+        owned.len()
+    );
+    assert_eq!(
+        file.metadata().blocks(),
+        (owned.len() * block::SECTORS_PER_BLOCK) as u64
+    );
+
+    // 不是数据的块就是元数据块, 其中的指针都指向文件自己的块
+    let read_block = |block_id: u32| {
+        let mut data = vec![0u8; block::SIZE];
+        for (i, sector) in data.chunks_mut(512).enumerate() {
+            device.read_block(block_id as usize * block::SECTORS_PER_BLOCK + i, sector);
+        }
+        data
+    };
+    let metadata: Vec<_> = owned
+        .iter()
+        .map(|&block_id| read_block(block_id))
+        .filter(|data| data.iter().any(|&x| x != 0x5A))
+        .collect();
+    assert_eq!(metadata.len(), 3);
+    for data in metadata {
+        let pointers = data
+            .chunks(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+            .filter(|&x| x != 0);
+        pointers.for_each(|x| assert!(owned.contains(&x)));
+    }
+}
+
+#[test]
+fn test_try_cast() {
+    #[repr(C)]
+    struct Pair {
+        a: u32,
+        b: u32,
+    }
+
+    let words = [1u32.to_le(), 2u32.to_le(), 3u32.to_le()];
+    let bytes: &[u8] = unsafe { std::slice::from_raw_parts(words.as_ptr().cast(), 12) };
+    let pair = fs::try_cast::<Pair>(bytes).unwrap();
+    assert_eq!((pair.a, pair.b), (1, 2));
+    // 长度不足
+    assert!(fs::try_cast::<Pair>(&bytes[..7]).is_none());
+    assert!(fs::try_cast::<Pair>(&[]).is_none());
+    // 没有对齐
+    assert!(fs::try_cast::<Pair>(&bytes[1..]).is_none());
+    assert!(fs::try_cast::<Pair>(&bytes[4..]).is_some());
+}
+
+// 256 块一组, 共 135 组, 描述符表需要占用两个块
+const MULTI_TABLE_ARGS: &[&str] = &[
+    "-g",
+    "256",
+    "-N",
+    "4128",
+    "-O",
+    "^resize_inode,^dir_index,^ext_attr",
+];
+
+#[test]
+fn test_descriptor_table_two_blocks() {
+    let (ext2, _) = gen_ext2_mkfs("135M", MULTI_TABLE_ARGS);
+    assert_eq!(ext2.blockgroup_count(), 135);
+    assert!(ext2.read_dir(VfsPath::from("/")).is_ok());
+}
+
+#[test]
+fn test_descriptor_table_write_back() {
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_ext2_mkfs("135M", MULTI_TABLE_ARGS);
+    let mut file = ext2.create_file(VfsPath::from("/file")).unwrap();
+    file.write_at(0, &vec![1u8; 4 * block::SIZE]).unwrap();
+    // 卸载时会刷新 wtime, 比较时去掉这一行
+    let describe = |ext2: &Ext2FileSystem| {
+        format!("{}", ext2)
+            .lines()
+            .filter(|line| !line.contains("wtime"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let before = describe(&ext2);
+    // 卸载时写回整张描述符表, 重新挂载后所有块组都一致
+    drop(VFS::new(ext2));
+
+    let ext2 = Ext2FileSystem::open(device);
+    assert_eq!(ext2.blockgroup_count(), 135);
+    assert_eq!(describe(&ext2), before);
+}
+
+#[test]
+fn test_descriptor_sync() {
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_ext2_mkfs("135M", MULTI_TABLE_ARGS);
+    let read_block = |block_id: usize| {
+        let mut data = vec![0u8; block::SIZE];
+        for (i, sector) in data.chunks_mut(512).enumerate() {
+            device.read_block(block_id * block::SECTORS_PER_BLOCK + i, sector);
+        }
+        data
+    };
+    // 描述符中 free_blocks_count 位于偏移 12
+    let free_blocks = |table: &[u8], bg_idx: usize| {
+        let offset = bg_idx * 32 + 12;
+        u16::from_le_bytes([table[offset], table[offset + 1]])
+    };
+    let group2 = read_block(2 * 256 + 1);
+    let free_before = free_blocks(&read_block(1), 0);
+
+    let mut file = ext2.create_file(VfsPath::from("/file")).unwrap();
+    file.write_at(0, &vec![1u8; 4 * block::SIZE]).unwrap();
+    drop(VFS::new(ext2));
+
+    // 重新挂载: 块组自己的 free_blocks_count 也写回了, 而不仅仅是 superblock 的
+    drop(Ext2FileSystem::open(device.clone()));
+    let primary = [read_block(1), read_block(2)];
+    assert_eq!(free_blocks(&primary[0], 0), free_before - 4);
+
+    // sparse_super 下 1, 3, 5, 7, 9 号块组有备份, 2 号块组没有
+    for bg_idx in [1, 3, 5, 7, 9] {
+        let start = bg_idx * 256;
+        assert_eq!(read_block(start + 1), primary[0]);
+        assert_eq!(read_block(start + 2), primary[1]);
+    }
+    assert_eq!(read_block(2 * 256 + 1), group2);
+}
+
+#[test]
+fn test_verify_free_counts() {
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_counting_ext2();
+    drop(ext2);
+    let verify = MountOptions {
+        verify: true,
+        ..Default::default()
+    };
+    assert!(Ext2FileSystem::open_with(device.clone(), verify.clone()).is_ok());
+
+    // 把 0 号块组的 free_blocks_count 改掉 (描述符表在 1 号块, 偏移 12)
+    let sector_id = block::SECTORS_PER_BLOCK;
+    let mut sector = [0u8; 512];
+    device.read_block(sector_id, &mut sector);
+    let skewed = u16::from_le_bytes([sector[12], sector[13]]) - 1;
+    sector[12..14].copy_from_slice(&skewed.to_le_bytes());
+    device.write_block(sector_id, &sector);
+
+    let err = Ext2FileSystem::open_with(device.clone(), verify).unwrap_err();
+    assert!(err.to_string().contains("Corrupted"), "{}", err);
+    assert!(err.to_string().contains("[0]"), "{}", err);
+    // 不检查时照常挂载
+    assert!(Ext2FileSystem::open_with(device, MountOptions::default()).is_ok());
+}
+
+#[test]
+fn test_free_counts_consistent() {
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_ext2_mkfs("135M", MULTI_TABLE_ARGS);
+    // 300 块跨过 0 号块组, 分配会落到多个块组中
+    let data: Vec<u8> = (0..300 * block::SIZE)
+        .map(|i| (i / block::SIZE) as u8)
+        .collect();
+    let mut file = ext2.create_file(VfsPath::from("/file")).unwrap();
+    file.write_at(0, &data).unwrap();
+    let mut buf = vec![0u8; data.len()];
+    file.read_at(0, &mut buf).unwrap();
+    assert!(buf == data);
+    drop(VFS::new(ext2));
+
+    // superblock 中的总数等于各块组之和, 且都与 bitmap 一致
+    let verify = MountOptions {
+        verify: true,
+        ..Default::default()
+    };
+    let ext2 = Ext2FileSystem::open_with(device.clone(), verify.clone()).unwrap();
+    ext2.remove_file(VfsPath::from("/file")).unwrap();
+    drop(VFS::new(ext2));
+    assert!(Ext2FileSystem::open_with(device, verify).is_ok());
+}
+
+#[test]
+fn test_alloc_inode_exhausts_groups() {
+    // 2 个块组, 每组 32 个 inode
+    let args = [
+        "-g",
+        "1024",
+        "-N",
+        "64",
+        "-O",
+        "^resize_inode,^dir_index,^ext_attr",
+    ];
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_ext2_mkfs("8M", &args);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+    // 第 0 组描述符的 free_blocks_count (偏移 12) 清零: 没有空闲块的块组仍然可以分配 inode
+    let mut sector = [0u8; 512];
+    device.read_block(block::SECTORS_PER_BLOCK, &mut sector);
+    sector[12..14].copy_from_slice(&0u16.to_le_bytes());
+    device.write_block(block::SECTORS_PER_BLOCK, &sector);
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+
+    // 第 0 组中 1~11 已被占用, 之后依次用完两个块组
+    let mut ids = Vec::new();
+    for i in 0.. {
+        let path = format!("/f{}", i);
+        match ext2.create_file(VfsPath::from(path.as_str())) {
+            Ok(_) => ids.push(ext2.inode_id_of(VfsPath::from(path.as_str())).unwrap()),
+            Err(err) => {
+                assert!(err.to_string().contains("NoFreeInodes"), "{}", err);
+                break;
+            }
+        }
+    }
+    assert_eq!(ids, (12..=64).collect::<Vec<_>>());
+
+    ext2.flush();
+    fs::block_device::flush();
+    device.read_block(block::SECTORS_PER_BLOCK, &mut sector);
+    for group in 0..2 {
+        // free_inodes_count 位于描述符偏移 14
+        let offset = group * 32 + 14;
+        assert_eq!(u16::from_le_bytes([sector[offset], sector[offset + 1]]), 0);
+    }
+}
+
+#[test]
+fn test_dealloc_inode_in_second_group() {
+    let args = [
+        "-g",
+        "1024",
+        "-N",
+        "64",
+        "-O",
+        "^resize_inode,^dir_index,^ext_attr",
+    ];
+    let (ext2, device) = gen_ext2_mkfs("8M", &args);
+    // 第 1 组的 (free_inodes_count, dirs_count), 以及 superblock 中的 free_inodes_count
+    let counts = || {
+        ext2.flush();
+        fs::block_device::flush();
+        let mut sector = [0u8; 512];
+        device.read_block(block::SECTORS_PER_BLOCK, &mut sector);
+        let group = (
+            u16::from_le_bytes([sector[32 + 14], sector[32 + 15]]),
+            u16::from_le_bytes([sector[32 + 16], sector[32 + 17]]),
+        );
+        device.read_block(2, &mut sector);
+        (
+            group,
+            u32::from_le_bytes(sector[16..20].try_into().unwrap()),
+        )
+    };
+
+    // 先用完第 0 组的 21 个 inode
+    for i in 0..21 {
+        let path = format!("/f{}", i);
+        ext2.create_file(VfsPath::from(path.as_str())).unwrap();
+    }
+    let before = counts();
+    assert_eq!(before.0, (32, 0));
+
+    let dir = VfsPath::from("/in_second");
+    ext2.create_dir(dir.clone()).unwrap();
+    assert_eq!(ext2.inode_id_of(dir.clone()).unwrap(), 33);
+    assert_eq!(counts(), ((31, 1), before.1 - 1));
+
+    ext2.remove_dir(dir.clone()).unwrap();
+    assert_eq!(counts(), before);
+    // 释放的位可以再次分配
+    ext2.create_dir(dir.clone()).unwrap();
+    assert_eq!(ext2.inode_id_of(dir).unwrap(), 33);
+}
+
+#[test]
+fn test_alloc_resumes_from_last_group() {
+    let (ext2, _device) = gen_ext2_mkfs("135M", MULTI_TABLE_ARGS);
+    let group_of = |block_id: u32| block_id as usize / 256;
+    // 先在 0 号块组里占几个块, 之后释放出来
+    let hole = VfsPath::from("/hole");
+    let mut file = ext2.create_file(hole.clone()).unwrap();
+    file.write_at(0, &vec![1u8; 8 * block::SIZE]).unwrap();
+    let hole_blocks = ext2.owned_blocks(hole.clone()).unwrap();
+    assert!(hole_blocks.iter().all(|&block_id| group_of(block_id) == 0));
+
+    // 填满 0 ~ 3 号块组, 最后一次分配停在 4 号块组
+    let mut big = ext2.create_file(VfsPath::from("/big")).unwrap();
+    big.write_at(0, &vec![1u8; 1100 * block::SIZE]).unwrap();
+    let last = *ext2
+        .owned_blocks(VfsPath::from("/big"))
+        .unwrap()
+        .last()
+        .unwrap();
+    assert_eq!(group_of(last), 4);
+    ext2.remove_file(hole).unwrap();
+
+    // 没有 goal 的分配 (目录扩容, 填空洞) 从上一次的块组开始, 不会回到 0 号块组
+    let root_blocks = ext2.owned_blocks(VfsPath::from("/")).unwrap();
+    assert_eq!(root_blocks.len(), 1);
+    for i in 0..20 {
+        let name = format!("/{}{}", i, "x".repeat(200));
+        ext2.create_file(VfsPath::from(name.as_str())).unwrap();
+    }
+    let root_blocks = ext2.owned_blocks(VfsPath::from("/")).unwrap();
+    assert_eq!(root_blocks.len(), 2);
+    assert_eq!(group_of(root_blocks[1]), 4);
+
+    let sparse = VfsPath::from("/sparse");
+    let mut file = ext2.create_file(sparse.clone()).unwrap();
+    file.write_at_sparse(4 * block::SIZE, b"tail").unwrap();
+    file.write_at(0, b"head").unwrap();
+    let sparse_blocks = ext2.owned_blocks(sparse).unwrap();
+    assert!(sparse_blocks
+        .iter()
+        .all(|&block_id| group_of(block_id) == 4));
+    assert!(!sparse_blocks
+        .iter()
+        .any(|block_id| hole_blocks.contains(block_id)));
+}
+
+#[test]
+fn test_inode_size_histogram() {
+    let (ext2, _device) = gen_ext2_mkfs("32M", &["-O", "^resize_inode,^dir_index,^ext_attr"]);
+    // 新建的文件系统只有根目录 (1 块) 和 lost+found (4 块), 其余保留 inode 链接数为 0
+    let fresh = ext2.inode_size_histogram();
+    assert_eq!(
+        fresh,
+        InodeHistogram {
+            one_block: 1,
+            direct: 1,
+            ..Default::default()
+        }
+    );
+
+    for (name, blocks) in [("/empty", 0), ("/one", 1), ("/five", 5), ("/twenty", 20)] {
+        let mut file = ext2.create_file(VfsPath::from(name)).unwrap();
+        if blocks != 0 {
+            file.write_at(0, &vec![1u8; blocks * block::SIZE]).unwrap();
+        }
+    }
+    // 稀疏写入让大小落在二级间接块区域
+    let mut sparse = ext2.create_file(VfsPath::from("/sparse")).unwrap();
+    sparse
+        .write_at_sparse((12 + 1024) * block::SIZE, &[1u8; 8])
+        .unwrap();
+    ext2.symlink("/one", VfsPath::from("/link")).unwrap();
+
+    assert_eq!(
+        ext2.inode_size_histogram(),
+        InodeHistogram {
+            empty: 2,
+            one_block: 2,
+            direct: 2,
+            indirect: 1,
+            double_indirect: 1,
+            triple_indirect: 0,
+        }
+    );
+}
+
+#[test]
+fn test_block_stream() {
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/stream");
+    let data: Vec<u8> = (0..3 * block::SIZE).map(|i| (i % 251) as u8).collect();
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, &data).unwrap();
+
+    // 不从块边界开始, 跨过两个块边界, 在第三块中间结束
+    let (offset, len) = (100, 2 * block::SIZE + 50);
+    let chunks: Vec<(usize, Vec<u8>)> = ext2
+        .block_stream(path.clone(), offset, len)
+        .unwrap()
+        .collect();
+    let lens: Vec<usize> = chunks.iter().map(|(_, chunk)| chunk.len()).collect();
+    assert_eq!(lens, [block::SIZE - 100, block::SIZE, 150]);
+    let offsets: Vec<usize> = chunks.iter().map(|(offset, _)| *offset).collect();
+    assert_eq!(offsets, [100, block::SIZE, 2 * block::SIZE]);
+    let joined: Vec<u8> = chunks.into_iter().flat_map(|(_, chunk)| chunk).collect();
+    assert!(joined == data[offset..offset + len]);
+
+    // 块内的一小段只产生一项, 超出文件末尾的部分被截掉
+    let mut inner = ext2.block_stream(path.clone(), 10, 20).unwrap();
+    assert_eq!(inner.next(), Some((10, data[10..30].to_vec())));
+    assert_eq!(inner.next(), None);
+    let tail: Vec<_> = ext2
+        .block_stream(path, data.len() - 5, 100)
+        .unwrap()
+        .collect();
+    assert_eq!(tail, [(data.len() - 5, data[data.len() - 5..].to_vec())]);
+}
+
+#[test]
+fn test_dir_entry_align() {
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_counting_ext2();
+    drop(ext2);
+    let bad = MountOptions {
+        dir_align: 6,
+        ..Default::default()
+    };
+    assert!(Ext2FileSystem::open_with(device.clone(), bad).is_err());
+
+    let options = MountOptions {
+        dir_align: 8,
+        ..Default::default()
+    };
+    let ext2 = Ext2FileSystem::open_with(device.clone(), options).unwrap();
+    ext2.create_dir(VfsPath::from("/aligned")).unwrap();
+    for name in ["a", "bcdef", "ghijklmnopq", "r"] {
+        ext2.create_file(VfsPath::from(format!("/aligned/{}", name).as_str()))
+            .unwrap();
+    }
+    ext2.flush();
+    fs::block_device::flush();
+
+    // 直接解析目录的数据块: 每个 record_len 都是 8 的倍数
+    let block_id = ext2.owned_blocks(VfsPath::from("/aligned")).unwrap()[0] as usize;
+    let mut data = vec![0u8; block::SIZE];
+    for (i, sector) in data.chunks_mut(512).enumerate() {
+        device.read_block(block_id * block::SECTORS_PER_BLOCK + i, sector);
+    }
+    let mut offset = 0;
+    let mut names = Vec::new();
+    while offset < block::SIZE {
+        let record_len = u16::from_le_bytes([data[offset + 4], data[offset + 5]]) as usize;
+        let name_len = data[offset + 6] as usize;
+        assert_eq!(record_len % 8, 0, "record_len {}", record_len);
+        names.push(String::from_utf8(data[offset + 8..offset + 8 + name_len].to_vec()).unwrap());
+        offset += record_len;
+    }
+    assert_eq!(names, [".", "..", "a", "bcdef", "ghijklmnopq", "r"]);
+}
+
+#[test]
+fn test_increase_across_tiers() {
+    // 与 Ext2Inode 中的常量一致
+    const DIRECT_COUNT: usize = 12;
+    const INDIRECT_COUNT: usize = block::SIZE / 4;
+    const INDIRECT_BOUND: usize = DIRECT_COUNT + INDIRECT_COUNT;
+    const DOUBLE_BOUND: usize = INDIRECT_BOUND + INDIRECT_COUNT * INDIRECT_COUNT;
+
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/tiers");
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    let fill = |idx: usize| vec![(idx % 251) as u8 + 1; block::SIZE];
+    let grow_to = |file: &mut Box<dyn fs::vfs::VfsInode>, from: usize, to: usize| {
+        for idx in from..to {
+            file.write_at(idx * block::SIZE, &fill(idx)).unwrap();
+        }
+    };
+    let owned = |path: &VfsPath| ext2.owned_blocks(path.clone()).unwrap().len();
+
+    // 12 -> 13 块: 第一次用到 indirect1
+    grow_to(&mut file, 0, DIRECT_COUNT);
+    assert_eq!(owned(&path), DIRECT_COUNT);
+    grow_to(&mut file, DIRECT_COUNT, DIRECT_COUNT + 1);
+    assert_eq!(owned(&path), DIRECT_COUNT + 1 + 1);
+
+    // INDIRECT_BOUND -> +1: 用到 indirect2 和它下面的第一个 indirect1
+    grow_to(&mut file, DIRECT_COUNT + 1, INDIRECT_BOUND);
+    assert_eq!(owned(&path), INDIRECT_BOUND + 1);
+    grow_to(&mut file, INDIRECT_BOUND, INDIRECT_BOUND + 1);
+    assert_eq!(owned(&path), (INDIRECT_BOUND + 1) + 3);
+    let mut buf = vec![0u8; block::SIZE];
+    for idx in [
+        0,
+        DIRECT_COUNT - 1,
+        DIRECT_COUNT,
+        INDIRECT_BOUND - 1,
+        INDIRECT_BOUND,
+    ] {
+        file.read_at(idx * block::SIZE, &mut buf).unwrap();
+        assert!(buf == fill(idx), "block {}", idx);
+    }
+
+    // DOUBLE_BOUND -> +1: 超过 4G, 用稀疏写只分配间接块, 用到 indirect3
+    let path = VfsPath::from("/huge");
+    let mut huge = ext2.create_file(path.clone()).unwrap();
+    let offset = DOUBLE_BOUND * block::SIZE;
+    huge.write_at_sparse(offset - block::SIZE, &fill(0))
+        .unwrap();
+    let before = owned(&path);
+    huge.write_at_sparse(offset, &fill(1)).unwrap();
+    // indirect3 + indirect2 + indirect1 + 数据块
+    assert_eq!(owned(&path), before + 4);
+    assert_eq!(huge.metadata().size(), (offset + block::SIZE) as u64);
+    huge.read_at(offset, &mut buf).unwrap();
+    assert!(buf == fill(1));
+    huge.read_at(offset - block::SIZE, &mut buf).unwrap();
+    assert!(buf == fill(0));
+    huge.read_at(0, &mut buf).unwrap();
+    assert!(buf.iter().all(|&x| x == 0));
+}
+
+// 写回后从磁盘上的 superblock 读出 free_blocks_count (位于 1024 + 12)
+fn disk_free_blocks(ext2: &Ext2FileSystem, device: &CountingDevice<BlockFile>) -> u32 {
+    ext2.flush();
+    fs::block_device::flush();
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    u32::from_le_bytes(sector[12..16].try_into().unwrap())
+}
+
+fn disk_free_inodes(ext2: &Ext2FileSystem, device: &CountingDevice<BlockFile>) -> u32 {
+    ext2.flush();
+    fs::block_device::flush();
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    u32::from_le_bytes(sector[16..20].try_into().unwrap())
+}
+
+#[test]
+fn test_blocks_needed_at_boundaries() {
+    const DIRECT_COUNT: usize = 12;
+    const INDIRECT_BOUND: usize = DIRECT_COUNT + block::SIZE / 4;
+
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_counting_ext2();
+    let mut file = ext2.create_file(VfsPath::from("/boundary")).unwrap();
+    // (原块数, 新块数, 期间新增或释放的块数, 含间接块)
+    let cases = [
+        (0, 1, 1),
+        (11, 12, 1),
+        (11, 14, 3 + 1),
+        (12, 13, 1 + 1),
+        (13, 20, 7),
+        (INDIRECT_BOUND - 1, INDIRECT_BOUND, 1),
+        (INDIRECT_BOUND - 1, INDIRECT_BOUND + 1, 2 + 2),
+        (INDIRECT_BOUND, INDIRECT_BOUND + 2, 2 + 2),
+        (10, INDIRECT_BOUND + 1, INDIRECT_BOUND + 1 - 10 + 3),
+    ];
+    for (from, to, expected) in cases {
+        file.set_len(from * block::SIZE).unwrap();
+        let before = disk_free_blocks(&ext2, &device);
+        file.set_len(to * block::SIZE).unwrap();
+        let grown = before - disk_free_blocks(&ext2, &device);
+        assert_eq!(grown as usize, expected, "{} -> {} blocks", from, to);
+        // 缩回去释放同样多的块
+        file.set_len(from * block::SIZE).unwrap();
+        let shrunk = disk_free_blocks(&ext2, &device) - (before - grown);
+        assert_eq!(shrunk as usize, expected, "{} -> {} blocks", to, from);
+    }
+}
+
+#[test]
+fn test_decrease_across_tiers() {
+    const DIRECT_COUNT: usize = 12;
+    const INDIRECT_COUNT: usize = block::SIZE / 4;
+    const INDIRECT_BOUND: usize = DIRECT_COUNT + INDIRECT_COUNT;
+    const DOUBLE_BOUND: usize = INDIRECT_BOUND + INDIRECT_COUNT * INDIRECT_COUNT;
+
+    let (
+        TestEnv {
+            inner: ext2,
+            _guard,
+        },
+        device,
+    ) = gen_counting_ext2();
+    let free_blocks = || disk_free_blocks(&ext2, &device);
+    let initial = free_blocks();
+    // 磁盘上第 block_id 块中的第 slot 个指针, 读之前先写回
+    let pointer = |block_id: u32, slot: usize| {
+        ext2.flush();
+        fs::block_device::flush();
+        let byte = block_id as usize * block::SIZE + slot * 4;
+        let mut sector = [0u8; 512];
+        device.read_block(byte / 512, &mut sector);
+        let at = byte % 512;
+        u32::from_le_bytes(sector[at..at + 4].try_into().unwrap())
+    };
+    // inode 的块指针区中第 idx 个指针
+    let inode_pointer = |inode_id: usize, idx: usize| {
+        let byte = inode_byte_offset(&device, inode_id) + 40 + idx * 4;
+        pointer((byte / block::SIZE) as u32, byte % block::SIZE / 4)
+    };
+
+    let path = VfsPath::from("/shrink");
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    let owned = |path: &VfsPath| ext2.owned_blocks(path.clone()).unwrap().len();
+    file.write_at(0, &vec![7u8; (INDIRECT_BOUND + 3) * block::SIZE])
+        .unwrap();
+    // 截到 indirect2 范围的中间, 被释放的指针清零
+    file.set_len((INDIRECT_BOUND + 1) * block::SIZE).unwrap();
+    let indirect1 = pointer(inode_pointer(file.inode_id(), 13), 0);
+    assert_ne!(pointer(indirect1, 0), 0);
+    assert_eq!((pointer(indirect1, 1), pointer(indirect1, 2)), (0, 0));
+    assert_eq!(owned(&path), INDIRECT_BOUND + 1 + 3);
+
+    // 恰好截到 INDIRECT_BOUND: 释放最后一块、indirect2 和它下面唯一的 indirect1
+    file.set_len(INDIRECT_BOUND * block::SIZE).unwrap();
+    assert_eq!(owned(&path), INDIRECT_BOUND + 1);
+    assert_eq!(free_blocks(), initial - (INDIRECT_BOUND + 1) as u32);
+    // 恰好截到 DIRECT_COUNT: indirect1 也被释放
+    file.set_len(DIRECT_COUNT * block::SIZE).unwrap();
+    assert_eq!(owned(&path), DIRECT_COUNT);
+    assert_eq!(free_blocks(), initial - DIRECT_COUNT as u32);
+    // 截到块中间保留该块
+    file.set_len(block::SIZE + 1).unwrap();
+    assert_eq!(owned(&path), 2);
+    file.set_len(0).unwrap();
+    assert_eq!(owned(&path), 0);
+    assert_eq!(free_blocks(), initial);
+
+    // 用到 indirect3 的稀疏大文件
+    let path = VfsPath::from("/huge");
+    let mut huge = ext2.create_file(path.clone()).unwrap();
+    let offset = DOUBLE_BOUND * block::SIZE;
+    huge.write_at_sparse(offset - block::SIZE, &[1u8; block::SIZE])
+        .unwrap();
+    let double_only = owned(&path);
+    let used = initial - free_blocks();
+    huge.write_at_sparse(offset + 5 * block::SIZE, &[2u8; block::SIZE])
+        .unwrap();
+    assert_eq!(owned(&path), double_only + 4);
+    // 截到 indirect3 范围的中间, 间接块仍然保留, 被释放的指针清零
+    let indirect2 = pointer(inode_pointer(huge.inode_id(), 14), 0);
+    let indirect1 = pointer(indirect2, 0);
+    assert_ne!(pointer(indirect1, 5), 0);
+    huge.set_len(offset + block::SIZE).unwrap();
+    assert_eq!(owned(&path), double_only + 3);
+    assert_eq!(pointer(indirect1, 5), 0);
+    // 恰好截到 DOUBLE_BOUND: indirect3 和其下的块都被释放
+    huge.set_len(offset).unwrap();
+    assert_eq!(owned(&path), double_only);
+    assert_eq!(initial - free_blocks(), used);
+    let mut buf = [0u8; block::SIZE];
+    huge.read_at(offset - block::SIZE, &mut buf).unwrap();
+    assert!(buf.iter().all(|&x| x == 1));
+    huge.set_len(0).unwrap();
+    assert_eq!(owned(&path), 0);
+    assert_eq!(free_blocks(), initial);
+}
+
+#[test]
+fn test_journal_detected() {
+    let (ext2, _) = gen_ext2_mkfs("32M", &["-O", "^resize_inode,^dir_index,^ext_attr"]);
+    assert!(!ext2.has_journal());
+    drop(ext2);
+
+    let (
+        TestEnv {
+            inner: ext3,
+            _guard,
+        },
+        device,
+    ) = gen_ext2_mkfs(
+        "32M",
+        &["-O", "has_journal,^resize_inode,^dir_index,^ext_attr"],
+    );
+    assert!(ext3.has_journal());
+    assert!(ext3.read_dir(VfsPath::from("/")).is_ok());
+    drop(VFS::new(ext3));
+
+    // 标记日志需要恢复 (feature_incompat 位于 superblock 偏移 96)
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    sector[96] |= 0x4;
+    device.write_block(2, &sector);
+    let err = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("journal"), "{}", err);
+    let ignore = MountOptions {
+        ignore_journal: true,
+        ..Default::default()
+    };
+    assert!(Ext2FileSystem::open_with(device, ignore)
+        .unwrap()
+        .has_journal());
+}
+
+#[test]
+fn test_journal_replay() {
+    let (
+        TestEnv {
+            inner: ext3,
+            _guard,
+        },
+        device,
+    ) = gen_ext2_mkfs(
+        "32M",
+        &["-O", "has_journal,^resize_inode,^dir_index,^ext_attr"],
+    );
+    assert_eq!(ext3.journal_state(), JournalState::Clean);
+    let path = VfsPath::from("/f");
+    let mut file = ext3.create_file(path.clone()).unwrap();
+    file.write_at(0, &[1u8; block::SIZE]).unwrap();
+    let target = ext3.owned_blocks(path.clone()).unwrap()[0];
+    ext3.flush();
+    fs::block_device::flush();
+    drop(file);
+    drop(ext3);
+
+    let read_block = |block_id: usize| {
+        let mut data = vec![0u8; block::SIZE];
+        for (i, sector) in data.chunks_mut(512).enumerate() {
+            device.read_block(block_id * block::SECTORS_PER_BLOCK + i, sector);
+        }
+        data
+    };
+    let write_block = |block_id: usize, data: &[u8]| {
+        for (i, sector) in data.chunks(512).enumerate() {
+            device.write_block(block_id * block::SECTORS_PER_BLOCK + i, sector);
+        }
+    };
+    let be = |x: u32| x.to_be_bytes();
+
+    // 日志 inode (8 号) 在第 0 组 inode 表中, mke2fs 给它分配的是连续块
+    let inode_table = u32::from_le_bytes(read_block(1)[8..12].try_into().unwrap()) as usize;
+    let inode = &read_block(inode_table)[7 * 128..8 * 128];
+    let journal = u32::from_le_bytes(inode[40..44].try_into().unwrap()) as usize;
+
+    // 日志超级块: s_start 指向第一个事务
+    let mut sb = read_block(journal);
+    let sequence = u32::from_be_bytes(sb[24..28].try_into().unwrap());
+    sb[28..32].copy_from_slice(&be(1));
+    write_block(journal, &sb);
+    // 描述块: 一个 tag, 后面跟着 uuid
+    let header = |kind: u32| {
+        let mut data = vec![0u8; block::SIZE];
+        data[0..4].copy_from_slice(&be(0xC03B_3998));
+        data[4..8].copy_from_slice(&be(kind));
+        data[8..12].copy_from_slice(&be(sequence));
+        data
+    };
+    let mut descriptor = header(1);
+    descriptor[12..16].copy_from_slice(&be(target));
+    descriptor[16..20].copy_from_slice(&be(8));
+    write_block(journal + 1, &descriptor);
+    write_block(journal + 2, &[2u8; block::SIZE]);
+    write_block(journal + 3, &header(2));
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    sector[96] |= 0x4;
+    device.write_block(2, &sector);
+
+    let replay = MountOptions {
+        replay_journal: true,
+        ..Default::default()
+    };
+    let corrupted = |detail: &str| {
+        let err = Ext2FileSystem::open_with(device.clone(), replay.clone()).unwrap_err();
+        assert!(err.to_string().contains(detail), "{}", err);
+    };
+    // 目标块超出文件系统时不重放
+    let blocks_count = u32::from_le_bytes(sector[4..8].try_into().unwrap());
+    let mut out_of_range = descriptor.clone();
+    out_of_range[12..16].copy_from_slice(&be(blocks_count));
+    write_block(journal + 1, &out_of_range);
+    corrupted("out of range");
+    write_block(journal + 1, &descriptor);
+    // superblock 中的日志 inode 号为 0
+    let mut no_inode = sector;
+    no_inode[224..228].fill(0);
+    device.write_block(2, &no_inode);
+    corrupted("journal inode");
+    device.write_block(2, &sector);
+    // 日志长度超出日志 inode
+    let mut too_long = sb.clone();
+    too_long[16..20].copy_from_slice(&be(u32::MAX));
+    write_block(journal, &too_long);
+    corrupted("longer than its inode");
+    // 日志只有两个块, 都是当前事务的描述块, 回绕之后永远读不到提交块
+    let mut endless = sb.clone();
+    endless[16..20].copy_from_slice(&be(3));
+    write_block(journal, &endless);
+    let mut looping = header(1);
+    looping[16..20].copy_from_slice(&be(8 | 2));
+    write_block(journal + 1, &looping);
+    write_block(journal + 2, &looping);
+    corrupted("never commits");
+    write_block(journal, &sb);
+    write_block(journal + 1, &descriptor);
+    write_block(journal + 2, &[2u8; block::SIZE]);
+
+    let ext3 = Ext2FileSystem::open_with(device.clone(), replay).unwrap();
+    assert_eq!(ext3.journal_state(), JournalState::Replayed(1));
+    let file = ext3.open_file(path).unwrap();
+    let mut buf = vec![0u8; block::SIZE];
+    file.read_at(0, &mut buf).unwrap();
+    assert!(buf.iter().all(|&x| x == 2));
+    ext3.flush();
+    drop(file);
+    drop(ext3);
+
+    // 日志已清空, 不带选项也能挂载
+    let ext3 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    assert_eq!(ext3.journal_state(), JournalState::Clean);
+}
+
+#[test]
+fn test_append() {
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/log").unwrap();
+    assert_eq!(file.append(b"").unwrap(), 0);
+    let chunks: [&[u8]; 3] = [b"first line\n", &[b'x'; 5000], b"third\n"];
+    for chunk in chunks {
+        assert_eq!(file.append(chunk).unwrap(), chunk.len());
+    }
+    let expected = chunks.concat();
+    assert_eq!(file.metadata().size(), expected.len() as u64);
+
+    // 另一个句柄看到的是同一个文件末尾
+    let mut other = vfs.open_file("/log").unwrap();
+    other.append(b"!").unwrap();
+    let mut buf = vec![0u8; expected.len() + 1];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), buf.len());
+    assert_eq!(&buf[..expected.len()], &expected[..]);
+    assert_eq!(buf[expected.len()], b'!');
+}
+
+#[test]
+fn test_sparse_write() {
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/sparse");
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    let sectors = |blocks: usize| (blocks * block::SECTORS_PER_BLOCK) as u64;
+
+    // 中间一块全为 0, 留作空洞
+    let mut data = vec![0xA5u8; 3 * block::SIZE];
+    data[block::SIZE..2 * block::SIZE].fill(0);
+    assert_eq!(file.write_at_sparse(0, &data).unwrap(), data.len());
+    assert_eq!(file.metadata().size(), data.len() as u64);
+    assert_eq!(file.metadata().blocks(), sectors(2));
+    assert_eq!(ext2.owned_blocks(path.clone()).unwrap().len(), 2);
+    let mut buf = vec![0xFFu8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert!(buf == data);
+
+    // 普通写入空洞时才分配
+    file.write_at(block::SIZE + 10, b"fill").unwrap();
+    assert_eq!(file.metadata().blocks(), sectors(3));
+    file.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf[block::SIZE + 10..block::SIZE + 14], b"fill");
+    assert!(buf[block::SIZE..block::SIZE + 10].iter().all(|&x| x == 0));
+
+    // 跨入间接块区域的空洞, 截断时只释放真正分配的块
+    let far = 20 * block::SIZE;
+    file.write_at_sparse(far, &[0u8; 8]).unwrap();
+    assert_eq!(file.metadata().size(), (far + 8) as u64);
+    // 多出来的只有 indirect 块
+    assert_eq!(file.metadata().blocks(), sectors(4));
+    file.set_len(block::SIZE).unwrap();
+    assert_eq!(file.metadata().blocks(), sectors(1));
+    ext2.remove_file(path).unwrap();
+}
+
+// Linux 创建的稀疏文件中, 整个范围都是空洞的间接块不会被分配
+#[test]
+fn test_sparse_without_indirect_blocks() {
+    let (guard, path) = mke2fs_image("32M", &[]);
+    let host = std::env::temp_dir().join("ext2fs_sparse_host");
+    let copy_in = |blocks: usize, name: &str| {
+        std::fs::write(&host, b"head").unwrap();
+        let file = OpenOptions::new().write(true).open(&host).unwrap();
+        file.set_len((blocks * block::SIZE) as u64).unwrap();
+        let status = std::process::Command::new("debugfs")
+            .args(["-w", "-R"])
+            .arg(format!("write {} {}", host.display(), name))
+            .arg(&path)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    };
+    // 只有第 0 块有数据, 一级到二级间接块的范围全是空洞
+    copy_in(2000, "sparse");
+    copy_in(20, "short");
+    let ext2 = TestEnv {
+        inner: Ext2FileSystem::open(BlockFile::create(&path)),
+        _guard: guard,
+    };
+
+    let sparse = VfsPath::from("/sparse");
+    assert_eq!(ext2.owned_blocks(sparse.clone()).unwrap().len(), 1);
+    let mut file = ext2.open_file(sparse.clone()).unwrap();
+    let mut buf = [0xFFu8; 8];
+    for idx in [20, 1500] {
+        file.read_at(idx * block::SIZE, &mut buf).unwrap();
+        assert_eq!(buf, [0u8; 8]);
+    }
+
+    // 写入空洞时补上缺少的间接块, 而不是改写 0 号块
+    file.write_at(20 * block::SIZE, b"indirect").unwrap();
+    file.write_at(1500 * block::SIZE, b"double").unwrap();
+    file.read_at(20 * block::SIZE, &mut buf).unwrap();
+    assert_eq!(&buf, b"indirect");
+    file.read_at(1500 * block::SIZE, &mut buf[..6]).unwrap();
+    assert_eq!(&buf[..6], b"double");
+    file.read_at(0, &mut buf[..4]).unwrap();
+    assert_eq!(&buf[..4], b"head");
+    // 数据块 3 个, 间接块: 一级 1 个, 二级 2 个
+    assert_eq!(ext2.owned_blocks(sparse.clone()).unwrap().len(), 6);
+    file.set_len(100 * block::SIZE).unwrap();
+    assert_eq!(ext2.owned_blocks(sparse.clone()).unwrap().len(), 3);
+
+    // 末尾所在的间接块不存在时扩容
+    let mut short = ext2.open_file(VfsPath::from("/short")).unwrap();
+    short.append(b"tail").unwrap();
+    short.read_at(20 * block::SIZE, &mut buf[..4]).unwrap();
+    assert_eq!(&buf[..4], b"tail");
+    short.set_len(block::SIZE).unwrap();
+
+    ext2.flush();
+    drop((file, short, ext2));
+    let output = std::process::Command::new("e2fsck")
+        .args(["-fn", &path])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+// debugfs 创建的设备文件和管道没有数据块, 删除时与普通文件一样处理
+#[test]
+fn test_remove_special_files() {
+    use std::io::Write;
+
+    let (guard, path) = mke2fs_image("32M", &[]);
+    // debugfs 的 mknod 只能在当前目录下创建
+    let mut debugfs = std::process::Command::new("debugfs")
+        .args(["-w", "-f", "-", &path])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let script =
+        "mkdir dev\ncd dev\nmknod null c 1 3\nmknod sda b 8 0\nmknod pipe p\ncd /\nmknod pipe p\n";
+    debugfs
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    assert!(debugfs.wait().unwrap().success());
+    let device = CountingDevice::wrapping(BlockFile::create(&path));
+    let ext2 = TestEnv {
+        inner: Ext2FileSystem::open(device.clone()),
+        _guard: guard,
+    };
+    let free_before = disk_free_inodes(&ext2, &device);
+
+    let filetype = |p: &str| ext2.metadata(VfsPath::from(p)).unwrap().filetype();
+    assert_eq!(filetype("/dev/null"), VfsFileType::CharDev);
+    assert_eq!(filetype("/dev/sda"), VfsFileType::BlockDev);
+    assert_eq!(filetype("/pipe"), VfsFileType::FIFO);
+
+    assert_eq!(ext2.remove_file(VfsPath::from("/pipe")).unwrap(), 0);
+    ext2.remove_dir_all(VfsPath::from("/dev")).unwrap();
+    assert_eq!(ext2.read_dir(VfsPath::from("/")).unwrap().len(), 3);
+    // 4 个特殊文件和 1 个目录的 inode 都被释放
+    assert_eq!(disk_free_inodes(&ext2, &device), free_before + 5);
+
+    drop(ext2);
+    fs::block_device::flush();
+    let output = std::process::Command::new("e2fsck")
+        .args(["-fn", &path])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_try_clone() {
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/clone_file").unwrap();
+    let mut cloned = file.try_clone().unwrap();
+
+    file.write_at(0, b"hello").unwrap();
+    let mut buf = [0u8; 5];
+    cloned.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    cloned.write_at(5, b" world").unwrap();
+    let mut buf = [0u8; 11];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 11);
+    assert_eq!(&buf, b"hello world");
+    assert_eq!(file.metadata().size(), 11);
+}
+
+#[test]
+fn test_metadata_after_write() {
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/meta_file").unwrap();
+    let before = vfs.metadata("/meta_file").unwrap();
+    assert_eq!(before.size(), 0);
+
+    file.write_at(0, &[1u8; 100]).unwrap();
+    assert_eq!(vfs.metadata("/meta_file").unwrap().size(), 100);
+    assert_eq!(file.metadata().size(), 100);
+
+    file.set_len(10).unwrap();
+    assert_eq!(vfs.metadata("/meta_file").unwrap().size(), 10);
+
+    file.set_permissions(&VfsPermissions::new(0o600)).unwrap();
+    let perm = vfs.metadata("/meta_file").unwrap().permissions();
+    assert_eq!(perm.to_string(), "rw-------");
+}
+
+#[test]
+fn test_write_permission_denied() {
+    let (ext2, device) = gen_counting_ext2();
+    let path = VfsPath::from("/shared");
+    // 新建的 inode 属于 1000:100
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, b"owner data").unwrap();
+    file.set_permissions(&VfsPermissions::new(0o644)).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(file);
+    drop(ext2);
+
+    let mount_as = |uid, gid| {
+        let options = MountOptions {
+            credentials: Some(Credentials::new(uid, gid)),
+            ..MountOptions::default()
+        };
+        Ext2FileSystem::open_with(device.clone(), options).unwrap()
+    };
+
+    // 其他人只有读权限
+    let ext2 = mount_as(2000, 2000);
+    let mut file = ext2.open_file(path.clone()).unwrap();
+    let err = file.write_at(0, b"intruder").unwrap_err();
+    assert!(format!("{:?}", err).contains("PermissionDenied"));
+    assert!(file.set_len(0).is_err());
+    let mut buf = [0u8; 10];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 10);
+    assert_eq!(&buf, b"owner data");
+    // 也不能先 chmod 再写
+    let err = file
+        .set_permissions(&VfsPermissions::new(0o666))
+        .unwrap_err();
+    assert!(format!("{:?}", err).contains("PermissionDenied"));
+    let sticky = VfsPermissions::new(0o644).with_special(false, false, true);
+    assert!(file.set_permissions(&sticky).is_err());
+    assert_eq!(file.metadata().permissions().to_string(), "rw-r--r--");
+    drop(file);
+    drop(ext2);
+
+    // 所有者和 root 可以写, 也可以 chmod
+    for (uid, gid) in [(1000, 1000), (0, 0)] {
+        let ext2 = mount_as(uid, gid);
+        let mut file = ext2.open_file(path.clone()).unwrap();
+        assert_eq!(file.write_at(0, b"OWNER").unwrap(), 5);
+        file.set_permissions(&VfsPermissions::new(0o644)).unwrap();
+    }
+}
+
+#[test]
+fn test_owner_group_other_access() {
+    let (ext2, device) = gen_counting_ext2();
+    let dir = VfsPath::from("/private");
+    let path = VfsPath::from("/private/tool");
+    // 新建的 inode 属于 1000:100
+    ext2.create_dir(dir.clone())
+        .unwrap()
+        .set_permissions(&VfsPermissions::new(0o750))
+        .unwrap();
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, b"#!/bin/sh\n").unwrap();
+    file.set_permissions(&VfsPermissions::new(0o750)).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(file);
+    drop(ext2);
+
+    let owner = Credentials::new(1000, 1000);
+    let member = Credentials::new(2000, 2000).with_groups(&[50, 100]);
+    let other = Credentials::new(3000, 3000).with_groups(&[50]);
+
+    let permissions = VfsPermissions::new(0o750);
+    let access = |credentials: &Credentials| {
+        let permission = credentials.permission_for(1000, 100, &permissions);
+        (permission.read(), permission.write(), permission.execute())
+    };
+    assert_eq!(access(&owner), (true, true, true));
+    assert_eq!(access(&member), (true, false, true));
+    assert_eq!(access(&other), (false, false, false));
+
+    let mount_as = |credentials: Credentials| {
+        let options = MountOptions {
+            credentials: Some(credentials),
+            ..MountOptions::default()
+        };
+        Ext2FileSystem::open_with(device.clone(), options).unwrap()
+    };
+    let is_denied =
+        |err: fs::vfs::error::VfsError| format!("{:?}", err).contains("PermissionDenied");
+    let mut buf = [0u8; 10];
+
+    // 所有者可以读写
+    let ext2 = mount_as(owner);
+    let mut file = ext2.open_file(path.clone()).unwrap();
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 10);
+    assert_eq!(file.write_at(0, b"#").unwrap(), 1);
+    drop(file);
+    drop(ext2);
+
+    // 附加组中的成员可以进入目录并读, 但不能写
+    let ext2 = mount_as(member);
+    let mut file = ext2.open_file(path.clone()).unwrap();
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 10);
+    assert!(is_denied(file.write_at(0, b"#").unwrap_err()));
+    drop(file);
+    drop(ext2);
+
+    // 其他人连目录都进不去, 但目录本身的元数据仍然可见
+    let ext2 = mount_as(other);
+    assert!(is_denied(ext2.open_file(path.clone()).unwrap_err()));
+    assert!(ext2.metadata(dir).is_ok());
+}
+
+#[test]
+fn test_dir_write_permission() {
+    let (ext2, device) = gen_counting_ext2();
+    // 新建的 inode 属于 1000:100, /locked 只有所有者可以写
+    for (dir, mode) in [("/locked", 0o755), ("/open", 0o777)] {
+        ext2.create_dir(VfsPath::from(dir))
+            .unwrap()
+            .set_permissions(&VfsPermissions::new(mode))
+            .unwrap();
+    }
+    for name in ["/locked/victim", "/locked/moveme", "/open/incoming"] {
+        ext2.create_file(VfsPath::from(name)).unwrap();
+    }
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let mount_as = |credentials: Credentials| {
+        let options = MountOptions {
+            credentials: Some(credentials),
+            ..MountOptions::default()
+        };
+        Ext2FileSystem::open_with(device.clone(), options).unwrap()
+    };
+    let is_denied = |err: VfsError| format!("{:?}", err).contains("PermissionDenied");
+
+    // 同组成员和其他人都只有 r-x, 不能增删或改名
+    let member = Credentials::new(2000, 2000).with_groups(&[100]);
+    let other = Credentials::new(3000, 3000);
+    for credentials in [member, other] {
+        let ext2 = mount_as(credentials);
+        let path = |name: &str| VfsPath::from(name);
+        assert!(is_denied(
+            ext2.create_file(path("/locked/new")).err().unwrap()
+        ));
+        assert!(is_denied(
+            ext2.create_dir(path("/locked/sub")).err().unwrap()
+        ));
+        assert!(is_denied(
+            ext2.symlink("/locked/victim", path("/locked/link"))
+                .unwrap_err()
+        ));
+        assert!(is_denied(
+            ext2.link(path("/locked/victim"), path("/locked/link"))
+                .unwrap_err()
+        ));
+        assert!(is_denied(
+            ext2.remove_file(path("/locked/victim")).unwrap_err()
+        ));
+        assert!(is_denied(
+            ext2.rename(path("/locked/moveme"), path("/locked/moved"))
+                .unwrap_err()
+        ));
+        // 跨目录改名时两个目录都要可写
+        assert!(is_denied(
+            ext2.rename(path("/locked/moveme"), path("/open/moved"))
+                .unwrap_err()
+        ));
+        assert!(is_denied(
+            ext2.rename(path("/open/incoming"), path("/locked/incoming"))
+                .unwrap_err()
+        ));
+        // 可写的目录不受影响
+        ext2.create_file(path("/open/mine")).unwrap();
+        ext2.remove_file(path("/open/mine")).unwrap();
+        drop(ext2);
+    }
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let mut names: Vec<String> = ext2
+        .read_dir(VfsPath::from("/locked"))
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, [".", "..", "moveme", "victim"]);
+    drop(ext2);
+
+    // 所有者可以增删和改名
+    let ext2 = mount_as(Credentials::new(1000, 1000));
+    ext2.create_file(VfsPath::from("/locked/new")).unwrap();
+    ext2.rename(
+        VfsPath::from("/locked/moveme"),
+        VfsPath::from("/locked/moved"),
+    )
+    .unwrap();
+    ext2.rename(
+        VfsPath::from("/open/incoming"),
+        VfsPath::from("/locked/incoming"),
+    )
+    .unwrap();
+    ext2.remove_file(VfsPath::from("/locked/victim")).unwrap();
+    assert!(ext2.exists(VfsPath::from("/locked/incoming")).unwrap());
+}
+
+#[test]
+fn test_symlink_short_and_long_targets() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_dir(VfsPath::from("/targets")).unwrap();
+    // 20 字节的目标存放在 inode 中, 200 字节的目标需要一个数据块
+    let short = format!("/targets/{}", "s".repeat(11));
+    let long = format!("/targets/{}", "l".repeat(191));
+    assert_eq!((short.len(), long.len()), (20, 200));
+    for (target, link) in [(&short, "/short"), (&long, "/long")] {
+        let mut file = ext2.create_file(VfsPath::from(target.as_str())).unwrap();
+        file.write_at(0, link.as_bytes()).unwrap();
+        ext2.symlink(target.as_str(), VfsPath::from(link)).unwrap();
+    }
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    let blocks_of = |name: &str| {
+        let entries = ext2.read_dir_plus(VfsPath::from("/")).unwrap();
+        let (_, _, metadata) = entries.into_iter().find(|(n, _, _)| n == name).unwrap();
+        metadata.blocks()
+    };
+    assert_eq!(blocks_of("short"), 0);
+    assert_eq!(blocks_of("long"), block::SECTORS_PER_BLOCK as u64);
+    for (target, link) in [(&short, "/short"), (&long, "/long")] {
+        assert_eq!(&ext2.read_link(VfsPath::from(link)).unwrap(), target);
+        // 通过链接打开的是目标文件
+        let file = ext2.open_file(VfsPath::from(link)).unwrap();
+        let mut buf = vec![0u8; link.len()];
+        file.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf, link.as_bytes());
+    }
+}
+
+#[test]
+fn test_symlink_round_trip() {
+    let (ext2, device) = gen_counting_ext2();
+    let mut target = ext2.create_file(VfsPath::from("/target")).unwrap();
+    target.write_at(0, b"through the link").unwrap();
+    ext2.symlink("/target", VfsPath::from("/link")).unwrap();
+
+    // 名字过长时不创建链接
+    let long_name = format!("/{}", "n".repeat(256));
+    let err = ext2
+        .symlink("/target", VfsPath::from(long_name.as_str()))
+        .unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("TooLongFileName"),
+        "{:?}",
+        err
+    );
+    ext2.flush();
+    fs::block_device::flush();
+    drop(target);
+    drop(ext2);
+
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    assert_eq!(ext2.read_link(VfsPath::from("/link")).unwrap(), "/target");
+    let link = ext2.open_file(VfsPath::from("/link")).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(link.read_at(0, &mut buf).unwrap(), 16);
+    assert_eq!(&buf, b"through the link");
+    assert!(!ext2.exists(VfsPath::from(long_name.as_str())).unwrap());
+}
+
+#[test]
+fn test_sticky_dir_delete() {
+    let (ext2, device) = gen_counting_ext2();
+    let shared = VfsPath::from("/shared");
+    ext2.create_dir(shared.clone())
+        .unwrap()
+        .set_permissions(&VfsPermissions::new(0o777))
+        .unwrap();
+    ext2.set_sticky(shared.clone(), true).unwrap();
+    for name in ["/shared/alice", "/shared/bob", "/shared/bob2"] {
+        ext2.create_file(VfsPath::from(name)).unwrap();
+    }
+    let bob = ext2.inode_id_of(VfsPath::from("/shared/bob")).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 新建的 inode 都属于 uid 1000, 把 bob 的所有者 (偏移 2) 改成 2000
+    let byte = inode_byte_offset(&device, bob);
+    let mut sector = [0u8; 512];
+    device.read_block(byte / 512, &mut sector);
+    sector[byte % 512 + 2..byte % 512 + 4].copy_from_slice(&2000u16.to_le_bytes());
+    device.write_block(byte / 512, &sector);
+
+    let mount_as = |uid, gid| {
+        let options = MountOptions {
+            credentials: Some(Credentials::new(uid, gid)),
+            ..MountOptions::default()
+        };
+        Ext2FileSystem::open_with(device.clone(), options).unwrap()
+    };
+
+    // 2000 只能删除自己的文件, 即使目录对所有人可写
+    let ext2 = mount_as(2000, 2000);
+    let err = ext2
+        .remove_file(VfsPath::from("/shared/alice"))
+        .unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("PermissionDenied"),
+        "{:?}",
+        err
+    );
+    ext2.remove_file(VfsPath::from("/shared/bob")).unwrap();
+    drop(ext2);
+
+    // 只有目录的所有者和 root 可以修改粘滞位
+    let ext2 = mount_as(2000, 2000);
+    let err = ext2.set_sticky(shared.clone(), false).unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("PermissionDenied"),
+        "{:?}",
+        err
+    );
+    drop(ext2);
+    mount_as(0, 0).set_sticky(shared.clone(), false).unwrap();
+
+    // 清除粘滞位后不再限制
+    let ext2 = mount_as(2000, 2000);
+    ext2.remove_file(VfsPath::from("/shared/bob2")).unwrap();
+    drop(ext2);
+
+    // 目录的所有者可以删除任何人的文件
+    let ext2 = mount_as(1000, 100);
+    ext2.set_sticky(shared, true).unwrap();
+    ext2.remove_file(VfsPath::from("/shared/alice")).unwrap();
+    assert!(ext2.read_dir(VfsPath::from("/shared")).unwrap().len() == 2);
+}
+
+#[test]
+fn test_exists_reads_less() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_dir(VfsPath::from("/a")).unwrap();
+    ext2.create_dir(VfsPath::from("/a/b")).unwrap();
+    // 让最后一个文件的 inode 落在 inode 表的另一个块中
+    for i in 0..40 {
+        let name = format!("/a/b/f{}", i);
+        ext2.create_file(VfsPath::from(name.as_str())).unwrap();
+    }
+    ext2.symlink("/a/b/f39", VfsPath::from("/link")).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let target = VfsPath::from("/a/b/f39");
+    // 每次都重新挂载, 从冷缓存开始计数
+    let reads_of = |probe: &dyn Fn(&Ext2FileSystem)| {
+        let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+        let before = device.counts().reads;
+        probe(&ext2);
+        device.counts().reads - before
+    };
+    let fast = reads_of(&|ext2| assert!(ext2.exists(target.clone()).unwrap()));
+    let walk = reads_of(&|ext2| assert!(ext2.metadata(target.clone()).is_ok()));
+    assert!(fast < walk, "exists: {} reads, walk: {} reads", fast, walk);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    for (path, expected) in [
+        ("/a/b", true),
+        ("/a/b/f0", true),
+        ("/a/b/missing", false),
+        ("/a/b/f0/under_file", false),
+        ("/link", true),
+        ("/a/b/..", true),
+    ] {
+        assert_eq!(
+            ext2.exists(VfsPath::from(path)).unwrap(),
+            expected,
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_duplicate_entry_is_error() {
+    let (ext2, device) = gen_counting_ext2();
+    let dir = VfsPath::from("/dup");
+    ext2.create_dir(dir.clone()).unwrap();
+    ext2.create_file(VfsPath::from("/dup/aa")).unwrap();
+    ext2.create_file(VfsPath::from("/dup/bb")).unwrap();
+    let data_block = ext2.owned_blocks(dir.clone()).unwrap()[0] as usize;
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 把 bb 改名为 aa, 目录中出现两个同名目录项
+    let first_sector = data_block * block::SECTORS_PER_BLOCK;
+    let mut raw = vec![0u8; block::SIZE];
+    for (i, sector) in raw.chunks_mut(512).enumerate() {
+        device.read_block(first_sector + i, sector);
+    }
+    let record = decode_dir_records(&raw)
+        .into_iter()
+        .find(|record| record.name == "bb")
+        .unwrap();
+    raw[record.offset + 8..record.offset + 10].copy_from_slice(b"aa");
+    let sector_id = record.offset / 512;
+    device.write_block(
+        first_sector + sector_id,
+        &raw[sector_id * 512..(sector_id + 1) * 512],
+    );
+
+    // 不 panic, 而是返回 Corrupted
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    let is_corrupted = |err: fs::vfs::error::VfsError| format!("{:?}", err).contains("Corrupted");
+    assert!(is_corrupted(
+        ext2.metadata(VfsPath::from("/dup/aa")).unwrap_err()
+    ));
+    assert!(is_corrupted(
+        ext2.create_file(VfsPath::from("/dup/aa")).unwrap_err()
+    ));
+    assert!(is_corrupted(
+        ext2.remove_file(VfsPath::from("/dup/aa")).unwrap_err()
+    ));
+    assert_eq!(ext2.read_dir(dir).unwrap().len(), 4);
+}
+
+#[test]
+fn test_read_dir_iter() {
+    let (ext2, device) = gen_counting_ext2();
+    let dir = VfsPath::from("/many");
+    ext2.create_dir(dir.clone()).unwrap();
+    // 长名字让目录占好几个块
+    let names: Vec<String> = (0..200).map(|i| format!("{:0>60}", i)).collect();
+    for name in &names {
+        let path = format!("/many/{}", name);
+        ext2.create_file(VfsPath::from(path.as_str())).unwrap();
+    }
+    assert!(ext2.metadata(dir.clone()).unwrap().size() >= 3 * block::SIZE as u64);
+
+    // 与 read_dir 的结果一致
+    let listed: Vec<String> = ext2
+        .read_dir(dir.clone())
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    let iterated: Vec<String> = ext2
+        .read_dir_iter(dir.clone())
+        .unwrap()
+        .map(|entry| entry.unwrap().name().to_string())
+        .collect();
+    assert_eq!(listed, iterated);
+    let err = ext2
+        .read_dir_iter(VfsPath::from(format!("/many/{}", names[1]).as_str()))
+        .err()
+        .unwrap();
+    assert!(format!("{:?}", err).contains("NotADirectory"));
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 找到第一个文件就停下, 不需要读完整个目录
+    let reads_of = |probe: &dyn Fn(&Ext2FileSystem)| {
+        let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+        let before = device.counts().reads;
+        probe(&ext2);
+        device.counts().reads - before
+    };
+    let lazy = reads_of(&|ext2| {
+        let found = ext2
+            .read_dir_iter(dir.clone())
+            .unwrap()
+            .find(|entry| entry.as_ref().unwrap().name() == names[0]);
+        assert!(found.is_some());
+    });
+    let full = reads_of(&|ext2| assert_eq!(ext2.read_dir(dir.clone()).unwrap().len(), 202));
+    assert!(
+        lazy < full,
+        "iter: {} reads, read_dir: {} reads",
+        lazy,
+        full
+    );
+}
+
+// 改写目录 dir 的第一个数据块, patch 拿到整个块和其中解析出的记录
+fn patch_dir_block(
+    ext2: TestEnv<Ext2FileSystem>,
+    device: &CountingDevice<BlockFile>,
+    dir: &str,
+    patch: impl FnOnce(&mut [u8], &[fs::ext2::DirRecord]),
+) -> MutexGuard<'static, ()> {
+    let data_block = ext2.owned_blocks(VfsPath::from(dir)).unwrap()[0] as usize;
+    ext2.flush();
+    fs::block_device::flush();
+    let TestEnv { inner, _guard } = ext2;
+    drop(inner);
+
+    let first_sector = data_block * block::SECTORS_PER_BLOCK;
+    let mut raw = vec![0u8; block::SIZE];
+    for (i, sector) in raw.chunks_mut(512).enumerate() {
+        device.read_block(first_sector + i, sector);
+    }
+    let records = decode_dir_records(&raw);
+    patch(&mut raw, &records);
+    for (i, sector) in raw.chunks(512).enumerate() {
+        device.write_block(first_sector + i, sector);
+    }
+    _guard
+}
+
+#[test]
+fn test_corrupt_dir_entry_is_error() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_dir(VfsPath::from("/bad")).unwrap();
+    ext2.create_file(VfsPath::from("/bad/aa")).unwrap();
+    ext2.create_file(VfsPath::from("/bad/bb")).unwrap();
+    // 让 aa 的名字越过记录末尾
+    let _guard = patch_dir_block(ext2, &device, "/bad", |raw, records| {
+        let record = records.iter().find(|record| record.name == "aa").unwrap();
+        raw[record.offset + 6] = u8::MAX;
+    });
+
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    let is_corrupted = |err: VfsError| format!("{:?}", err).contains("Corrupted");
+    assert!(is_corrupted(ext2.snapshot().read_dir("/bad").unwrap_err()));
+    let mut iter = ext2.read_dir_iter(VfsPath::from("/bad")).unwrap();
+    assert!(iter.any(|entry| entry.is_err()));
+    assert!(is_corrupted(
+        ext2.read_dir(VfsPath::from("/bad")).err().unwrap()
+    ));
+    // 损坏之后的目录项不能被当作不存在
+    assert!(is_corrupted(
+        ext2.create_file(VfsPath::from("/bad/bb")).unwrap_err()
+    ));
+    assert!(is_corrupted(
+        ext2.remove_dir(VfsPath::from("/bad")).unwrap_err()
+    ));
+}
+
+#[test]
+fn test_snapshot_bad_inode_id() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_dir(VfsPath::from("/bad")).unwrap();
+    ext2.create_file(VfsPath::from("/bad/aa")).unwrap();
+    // aa 指向超出 inodes_count 的 inode
+    let _guard = patch_dir_block(ext2, &device, "/bad", |raw, records| {
+        let record = records.iter().find(|record| record.name == "aa").unwrap();
+        raw[record.offset..record.offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    });
+
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    let snapshot = ext2.snapshot();
+    let err = snapshot.metadata("/bad/aa").unwrap_err();
+    assert!(format!("{:?}", err).contains("Corrupted"), "{:?}", err);
+    let mut buf = [0u8; 4];
+    assert!(snapshot.read_at("/bad/aa", 0, &mut buf).is_err());
+    // 目录项本身仍然可以列出
+    assert_eq!(snapshot.read_dir("/bad").unwrap().len(), 3);
+}
+
+#[test]
+fn test_non_utf8_name() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_dir(VfsPath::from("/raw")).unwrap();
+    ext2.create_file(VfsPath::from("/raw/aa")).unwrap();
+    let _guard = patch_dir_block(ext2, &device, "/raw", |raw, records| {
+        let record = records.iter().find(|record| record.name == "aa").unwrap();
+        raw[record.offset + 8..record.offset + 10].copy_from_slice(b"\xff\xfe");
+    });
+
+    // 合法的名字, 不是损坏
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    let names: Vec<String> = ext2
+        .read_dir(VfsPath::from("/raw"))
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert!(
+        names.contains(&"\u{fffd}\u{fffd}".to_string()),
+        "{:?}",
+        names
+    );
+    let iterated = ext2
+        .read_dir_iter(VfsPath::from("/raw"))
+        .unwrap()
+        .collect::<VfsResult<Vec<_>>>()
+        .unwrap();
+    assert_eq!(iterated.len(), names.len());
+    assert_eq!(ext2.has_errors(), false);
+    assert!(ext2.snapshot().read_dir("/raw").is_ok());
+}
+
+// 只实现了必需方法的文件系统
+#[derive(Debug)]
+struct MinimalFs;
+
+impl std::fmt::Display for MinimalFs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "minimal")
+    }
+}
+
+impl FileSystem for MinimalFs {
+    fn read_dir(&self, _path: VfsPath) -> VfsResult<Vec<Box<dyn VfsDirEntry>>> {
+        Ok(Vec::new())
+    }
+
+    fn exists(&self, path: VfsPath) -> VfsResult<bool> {
+        Ok(path.is_empty())
+    }
+}
+
+#[test]
+fn test_minimal_filesystem() {
+    let minimal: Box<dyn FileSystem> = Box::new(MinimalFs);
+    let path = || VfsPath::from("/file");
+    assert!(minimal.read_dir(VfsPath::from("/")).unwrap().is_empty());
+    assert!(minimal.exists(VfsPath::from("/")).unwrap());
+    assert!(!minimal.exists(path()).unwrap());
+    assert_eq!(minimal.capabilities(), Capabilities::empty());
+    minimal.flush();
+
+    let not_supported = |err: VfsError| matches!(err.kind(), VfsErrorKind::NotSupported);
+    assert!(not_supported(minimal.metadata(path()).unwrap_err()));
+    assert!(not_supported(minimal.open_file(path()).unwrap_err()));
+    assert!(not_supported(minimal.create_file(path()).unwrap_err()));
+    assert!(not_supported(minimal.create_dir(path()).unwrap_err()));
+    assert!(not_supported(minimal.remove_file(path()).unwrap_err()));
+    assert!(not_supported(minimal.remove_dir(path()).unwrap_err()));
+    assert!(not_supported(
+        minimal.link(path(), VfsPath::from("/hard")).unwrap_err()
+    ));
+    assert!(not_supported(
+        minimal
+            .symlink("/file", VfsPath::from("/soft"))
+            .unwrap_err()
+    ));
+    assert!(not_supported(minimal.read_link(path()).unwrap_err()));
+    assert!(not_supported(minimal.inode_id_of(path()).unwrap_err()));
+    assert!(not_supported(minimal.walk_dir(path()).unwrap_err()));
+    assert!(not_supported(minimal.read_dir_plus(path()).unwrap_err()));
+    assert!(not_supported(
+        minimal.move_file("/file", "/moved").unwrap_err()
+    ));
+    assert!(not_supported(
+        minimal
+            .rename(path(), VfsPath::from("/renamed"))
+            .unwrap_err()
+    ));
+}
+
+#[test]
+fn test_remove_file_returns_links() {
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/twice").unwrap();
+    file.write_at(0, b"shared").unwrap();
+    vfs.link("/twice", "/twice_link").unwrap();
+    assert_eq!(vfs.metadata("/twice").unwrap().hard_links(), 2);
+    let inode_id = vfs.inode_id_of("/twice").unwrap();
+
+    // 还剩一个名字, inode 没有被回收
+    assert_eq!(vfs.remove_file("/twice").unwrap(), 1);
+    assert!(!vfs.exists("/twice").unwrap());
+    assert_eq!(vfs.inode_id_of("/twice_link").unwrap(), inode_id);
+    let mut buf = [0u8; 6];
+    let link = vfs.open_file("/twice_link").unwrap();
+    assert_eq!(link.read_at(0, &mut buf).unwrap(), 6);
+    assert_eq!(&buf, b"shared");
+    assert_eq!(link.metadata().hard_links(), 1);
+
+    assert_eq!(vfs.remove_file("/twice_link").unwrap(), 0);
+    assert!(!vfs.exists("/twice_link").unwrap());
+}
+
+#[test]
+fn test_open_file_inode_id() {
+    let vfs = gen_vfs();
+    vfs.create_file("/origin").unwrap();
+    vfs.link("/origin", "/origin_link").unwrap();
+    vfs.create_file("/other").unwrap();
+
+    let origin = vfs.open_file("/origin").unwrap();
+    let link = vfs.open_file("/origin_link").unwrap();
+    let other = vfs.open_file("/other").unwrap();
+    assert_eq!(origin.inode_id(), link.inode_id());
+    assert_ne!(origin.inode_id(), other.inode_id());
+
+    // 与目录项中记录的 inode 号一致
+    let entry = vfs
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name() == "origin_link")
+        .unwrap();
+    assert_eq!(entry.inode_id(), link.inode_id());
+}
+
+#[test]
+fn test_inode_bitmap_cache() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let cached = MountOptions {
+        cache_inode_bitmap: true,
+        ..MountOptions::default()
+    };
+    let ext2 = Ext2FileSystem::open_with(device.clone(), cached).unwrap();
+    let before = ext2.allocated_inodes();
+    let mut ids = Vec::new();
+    for i in 0..20 {
+        let path = format!("/cached{}", i);
+        if i % 4 == 0 {
+            ext2.create_dir(VfsPath::from(path.as_str())).unwrap();
+        } else {
+            ext2.create_file(VfsPath::from(path.as_str())).unwrap();
+        }
+        ids.push(ext2.inode_id_of(VfsPath::from(path.as_str())).unwrap());
+    }
+    for i in (0..20).step_by(3) {
+        let path = format!("/cached{}", i);
+        if i % 4 == 0 {
+            ext2.remove_dir(VfsPath::from(path.as_str())).unwrap();
+        } else {
+            ext2.remove_file(VfsPath::from(path.as_str())).unwrap();
+        }
+    }
+    // 释放后再分配, 会复用刚释放的 inode
+    ext2.create_file(VfsPath::from("/reused")).unwrap();
+    let reused = ext2.inode_id_of(VfsPath::from("/reused")).unwrap();
+
+    // 查询只用缓存, 不读盘
+    let reads = device.counts().reads;
+    for (i, &id) in ids.iter().enumerate() {
+        let expected = i % 3 != 0 || id == reused;
+        assert_eq!(ext2.is_inode_allocated(id), expected, "inode {}", id);
+    }
+    assert!(!ext2.is_inode_allocated(0));
+    assert_eq!(device.counts().reads, reads);
+    let cached_inodes = ext2.allocated_inodes();
+    assert_eq!(cached_inodes.len(), before.len() + 20 - 7 + 1);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 与直接扫描 bitmap 的结果一致
+    let ext2 = Ext2FileSystem::open_with(device, MountOptions::default()).unwrap();
+    assert_eq!(ext2.allocated_inodes(), cached_inodes);
+    for &id in &ids {
+        assert_eq!(
+            ext2.is_inode_allocated(id),
+            cached_inodes.contains(&id),
+            "inode {}",
+            id
+        );
+    }
+}
+
+#[test]
+fn test_set_permissions_keeps_type_bits() {
+    let (ext2, device) = gen_counting_ext2();
+    let file = VfsPath::from("/perm_file");
+    let dir = VfsPath::from("/perm_dir");
+    let mut file_inode = ext2.create_file(file.clone()).unwrap();
+    let mut dir_inode = ext2.create_dir(dir.clone()).unwrap();
+    ext2.set_sticky(dir.clone(), true).unwrap();
+    file_inode
+        .set_permissions(&VfsPermissions::new(0o755))
+        .unwrap();
+    dir_inode
+        .set_permissions(&VfsPermissions::new(0o755))
+        .unwrap();
+    drop((file_inode, dir_inode));
+    let ids = [
+        ext2.inode_id_of(file.clone()).unwrap(),
+        ext2.inode_id_of(dir.clone()).unwrap(),
+    ];
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 读回来是 0o755, 类型位没有被覆盖, 粘滞位与 chmod 一样被清除
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    for (path, expected) in [(&file, "rwxr-xr-x"), (&dir, "rwxr-xr-x")] {
+        assert_eq!(
+            ext2.metadata(path.clone())
+                .unwrap()
+                .permissions()
+                .to_string(),
+            expected
+        );
+    }
+    assert!(ext2.metadata(file).unwrap().is_file());
+    assert!(ext2.metadata(dir).unwrap().is_dir());
+    let mode_of = |inode_id| {
+        let byte = inode_byte_offset(&device, inode_id);
+        let mut sector = [0u8; 512];
+        device.read_block(byte / 512, &mut sector);
+        u16::from_le_bytes([sector[byte % 512], sector[byte % 512 + 1]])
+    };
+    assert_eq!(mode_of(ids[0]), 0o100755);
+    assert_eq!(mode_of(ids[1]), 0o40755);
+}
+
+#[test]
+fn test_set_permissions_clears_special_bits() {
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/setuid_file");
+    let mut inode = ext2.create_file(path.clone()).unwrap();
+    // 0o4755
+    let setuid = VfsPermissions::new(0o755).with_special(true, false, false);
+    inode.set_permissions(&setuid).unwrap();
+    let permissions = ext2.metadata(path.clone()).unwrap().permissions();
+    assert!(permissions.setuid());
+    assert_eq!(permissions.to_string(), "rwsr-xr-x");
+
+    // 再次 chmod 0o755 必须去掉 setuid
+    inode.set_permissions(&VfsPermissions::new(0o755)).unwrap();
+    let permissions = ext2.metadata(path).unwrap().permissions();
+    assert!(!permissions.setuid());
+    assert_eq!(permissions.to_string(), "rwxr-xr-x");
+}
+
+#[test]
+fn test_create_file_with() {
+    let vfs = gen_vfs();
+    let contents = b"#!/bin/sh\necho hello\n";
+    let file = vfs.create_file_with("/script.sh", contents, 0o750).unwrap();
+    assert_eq!(file.metadata().size(), contents.len() as u64);
+
+    let metadata = vfs.metadata("/script.sh").unwrap();
+    assert!(metadata.is_file());
+    assert_eq!(metadata.permissions().to_string(), "rwxr-x---");
+    let mut buf = vec![0u8; contents.len()];
+    let file = vfs.open_file("/script.sh").unwrap();
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), contents.len());
+    assert_eq!(&buf, contents);
+
+    // 只读的 mode 不影响初始内容的写入
+    vfs.create_file_with("/readonly", b"fixed", 0o444).unwrap();
+    assert_eq!(vfs.metadata("/readonly").unwrap().size(), 5);
+
+    // 特殊位是合法的 mode
+    vfs.create_file_with("/setuid", b"", 0o4755).unwrap();
+    let permissions = vfs.metadata("/setuid").unwrap().permissions();
+    assert_eq!(permissions.to_string(), "rwsr-xr-x");
+    vfs.create_file_with("/sticky", b"", 0o1777).unwrap();
+    let permissions = vfs.metadata("/sticky").unwrap().permissions();
+    assert_eq!(permissions.to_string(), "rwxrwxrwt");
+
+    assert!(vfs.create_file_with("/bad_mode", b"", 0o17777).is_err());
+    assert!(!vfs.exists("/bad_mode").unwrap());
+}
+
+#[test]
+fn test_create_file_with_rollback() {
+    let vfs = gen_vfs();
+    let links = vfs.metadata("/").unwrap().hard_links();
+    // 内容比整个镜像还大, 写入必然失败
+    let huge = vec![0xAAu8; 16 * 1024 * 1024];
+    let err = vfs.create_file_with("/huge", &huge, 0o644).unwrap_err();
+    assert!(format!("{:?}", err).contains("NoFreeBlocks"), "{:?}", err);
+    assert!(!vfs.exists("/huge").unwrap());
+    assert_eq!(vfs.metadata("/").unwrap().hard_links(), links);
+
+    // 回滚后同名文件可以重新创建
+    vfs.create_file_with("/huge", b"small", 0o644).unwrap();
+    assert_eq!(vfs.metadata("/huge").unwrap().size(), 5);
+}
+
+#[test]
+fn test_create_files_batch() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_dir(VfsPath::from("/bulk")).unwrap();
+    let free_before = disk_free_inodes(&ext2, &device);
+    let allocated_before = ext2.allocated_inodes().len();
+
+    let names: Vec<String> = (0..100).map(|i| format!("f{}", i)).collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let ids = ext2.create_files(VfsPath::from("/bulk"), &names).unwrap();
+
+    assert_eq!(ids.len(), 100);
+    let unique: std::collections::BTreeSet<_> = ids.iter().collect();
+    assert_eq!(unique.len(), 100);
+    assert_eq!(disk_free_inodes(&ext2, &device), free_before - 100);
+    assert_eq!(ext2.allocated_inodes().len(), allocated_before + 100);
+    for (name, &id) in names.iter().zip(&ids) {
+        assert!(ext2.is_inode_allocated(id));
+        let path = format!("/bulk/{}", name);
+        assert_eq!(ext2.inode_id_of(VfsPath::from(path.as_str())).unwrap(), id);
+    }
+    assert_eq!(
+        ext2.read_dir(VfsPath::from("/bulk")).unwrap().len(),
+        100 + 2
+    );
+
+    // 重名时一个 inode 也不分配
+    let free_before = disk_free_inodes(&ext2, &device);
+    assert!(ext2
+        .create_files(VfsPath::from("/bulk"), &["new", "f3"])
+        .is_err());
+    assert!(ext2
+        .create_files(VfsPath::from("/bulk"), &["dup", "dup"])
+        .is_err());
+    assert_eq!(disk_free_inodes(&ext2, &device), free_before);
+}
+
+#[test]
+fn test_display_filetype_and_permissions() {
+    let types = [
+        (VfsFileType::RegularFile, "-"),
+        (VfsFileType::Directory, "d"),
+        (VfsFileType::SymbolicLink, "l"),
+        (VfsFileType::CharDev, "c"),
+        (VfsFileType::BlockDev, "b"),
+        (VfsFileType::FIFO, "p"),
+        (VfsFileType::Socket, "s"),
+    ];
+    for (filetype, expected) in types {
+        assert_eq!(filetype.to_string(), expected);
+    }
+
+    assert_eq!(VfsPermissions::new(0o755).to_string(), "rwxr-xr-x");
+    assert_eq!(VfsPermissions::new(0o640).to_string(), "rw-r-----");
+    assert_eq!(VfsPermissions::empty().to_string(), "---------");
+    let special = VfsPermissions::new(0o755).with_special(true, true, true);
+    assert_eq!(special.to_string(), "rwsr-sr-t");
+    let special = VfsPermissions::new(0o644).with_special(true, true, true);
+    assert_eq!(special.to_string(), "rwSr-Sr-T");
+
+    // 磁盘上的 sticky 位也会显示出来
+    let ext2 = gen_ext2();
+    let dir = VfsPath::from("/tmp_sticky");
+    ext2.create_dir(dir.clone())
+        .unwrap()
+        .set_permissions(&VfsPermissions::new(0o777))
+        .unwrap();
+    ext2.set_sticky(dir.clone(), true).unwrap();
+    let metadata = ext2.metadata(dir).unwrap();
+    assert_eq!(
+        format!("{}{}", metadata.filetype(), metadata.permissions()),
+        "drwxrwxrwt"
+    );
+}
+
+#[test]
+fn test_first_ino_and_inode_size() {
+    let ext2 = gen_ext2();
+    assert_eq!(ext2.first_ino(), 11);
+    assert_eq!(ext2.inode_size(), 128);
+    drop(ext2);
+
+    // 256 字节的 inode: 步长按 superblock 走, 读写都落在正确的位置
+    let (ext2, device) = gen_ext2_mkfs("32M", &["-I", "256"]);
+    assert_eq!(ext2.first_ino(), 11);
+    assert_eq!(ext2.inode_size(), 256);
+    let before = ext2.allocated_inodes();
+    for i in 0..4 {
+        let path = format!("/wide{}", i);
+        let mut file = ext2.create_file(VfsPath::from(path.as_str())).unwrap();
+        file.write_at(0, path.as_bytes()).unwrap();
+    }
+    let id = ext2.inode_id_of(VfsPath::from("/wide3")).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let byte = inode_byte_offset(&device, id);
+    let mut sector = [0u8; 512];
+    device.read_block(byte / 512, &mut sector);
+    let mode = u16::from_le_bytes([sector[byte % 512], sector[byte % 512 + 1]]);
+    assert_eq!(mode & 0xF000, 0x8000);
+    let size = u32::from_le_bytes(sector[byte % 512 + 4..byte % 512 + 8].try_into().unwrap());
+    assert_eq!(size, 6);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    assert_eq!(ext2.allocated_inodes().len(), before.len() + 4);
+    for i in 0..4 {
+        let path = format!("/wide{}", i);
+        let file = ext2.open_file(VfsPath::from(path.as_str())).unwrap();
+        let mut buf = [0u8; 16];
+        let n = file.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf[..n], path.as_bytes());
+    }
+}
+
+#[test]
+fn test_file_times_follow_io() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    let (ext2, device) = gen_counting_ext2();
+    let _clock = ClockGuard::set(|| NOW.load(Ordering::SeqCst));
+    let path = VfsPath::from("/times");
+    let at = |now: u64| NOW.store(now, Ordering::SeqCst);
+    let times = |ext2: &Ext2FileSystem| {
+        let stamp = ext2.metadata(path.clone()).unwrap().timestamp();
+        (stamp.atime(), stamp.mtime(), stamp.ctime())
+    };
+
+    at(100);
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    at(200);
+    file.write_at(0, b"hello").unwrap();
+    assert_eq!(times(&ext2).1, 200);
+    assert_eq!(times(&ext2).2, 200);
+
+    // 读取只改 atime
+    at(300);
+    file.read_at(0, &mut [0u8; 5]).unwrap();
+    assert_eq!(times(&ext2), (300, 200, 200));
+
+    at(400);
+    file.set_len(2).unwrap();
+    assert_eq!(times(&ext2), (300, 400, 400));
+
+    // chmod 只改 ctime
+    at(500);
+    file.set_permissions(&VfsPermissions::new(0o600)).unwrap();
+    assert_eq!(times(&ext2), (300, 400, 500));
+    let id = ext2.inode_id_of(path.clone()).unwrap();
+    drop(file);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 设置 DONT_ATIME (i_flags 偏移 32) 后读取不再更新 atime
+    let byte = inode_byte_offset(&device, id);
+    let mut sector = [0u8; 512];
+    device.read_block(byte / 512, &mut sector);
+    sector[byte % 512 + 32..byte % 512 + 36].copy_from_slice(&0x80u32.to_le_bytes());
+    device.write_block(byte / 512, &sector);
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    at(600);
+    let file = ext2.open_file(path.clone()).unwrap();
+    file.read_at(0, &mut [0u8; 2]).unwrap();
+    assert_eq!(times(&ext2), (300, 400, 500));
+    drop((file, ext2));
+
+    // 以 noatime 挂载同样不更新 atime
+    let noatime = MountOptions {
+        noatime: true,
+        ..MountOptions::default()
+    };
+    let ext2 = Ext2FileSystem::open_with(device.clone(), noatime).unwrap();
+    at(700);
+    let other = VfsPath::from("/times_other");
+    let mut file = ext2.create_file(other.clone()).unwrap();
+    file.write_at(0, b"x").unwrap();
+    at(800);
+    file.read_at(0, &mut [0u8; 1]).unwrap();
+    let stamp = ext2.metadata(other).unwrap().timestamp();
+    assert_eq!((stamp.atime(), stamp.mtime()), (0, 700));
+}
+
+#[test]
+fn test_times_kept_without_clock() {
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/clockless");
+    let times = |ext2: &Ext2FileSystem| {
+        let stamp = ext2.metadata(path.clone()).unwrap().timestamp();
+        (stamp.atime(), stamp.mtime(), stamp.ctime())
+    };
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    {
+        let _clock = ClockGuard::set(|| 1_700_000_000);
+        file.write_at(0, b"hello").unwrap();
+        file.read_at(0, &mut [0u8; 5]).unwrap();
+    }
+    let stamped = times(&ext2);
+    assert_eq!(stamped, (1_700_000_000, 1_700_000_000, 1_700_000_000));
+
+    // 没有时钟时读写和 chmod 都不会把时间戳改成 0
+    file.write_at(5, b" world").unwrap();
+    file.read_at(0, &mut [0u8; 11]).unwrap();
+    file.set_permissions(&VfsPermissions::new(0o600)).unwrap();
+    assert_eq!(times(&ext2), stamped);
+}
+
+#[test]
+fn test_rev0_superblock_defaults() {
+    let ext2 = gen_ext2();
+    assert_eq!(ext2.label(), "");
+    assert_ne!(ext2.uuid(), "00000000-0000-0000-0000-000000000000");
+    drop(ext2);
+
+    let (ext2, device) = gen_ext2_mkfs("32M", &["-r", "0"]);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // rev 0 的动态字段区域填入垃圾: inode 大小, 特性位, uuid, 卷标
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    assert_eq!(u32::from_le_bytes(sector[76..80].try_into().unwrap()), 0);
+    sector[84..136].fill(0xFF);
+    device.write_block(2, &sector);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    assert_eq!(ext2.label(), "");
+    assert_eq!(ext2.uuid(), "00000000-0000-0000-0000-000000000000");
+    assert_eq!(ext2.first_ino(), 11);
+    assert_eq!(ext2.inode_size(), 128);
+    assert!(!ext2.has_journal());
+    let names: Vec<String> = ext2
+        .read_dir(VfsPath::from("/"))
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert!(names.iter().any(|name| name == "lost+found"), "{:?}", names);
+}
+
+#[test]
+fn test_immutable_and_append_only() {
+    let ext2 = gen_ext2();
+    let frozen = VfsPath::from("/frozen");
+    let log = VfsPath::from("/log");
+    ext2.create_file(frozen.clone())
+        .unwrap()
+        .write_at(0, b"frozen")
+        .unwrap();
+    ext2.create_file(log.clone())
+        .unwrap()
+        .write_at(0, b"line1\n")
+        .unwrap();
+    ext2.set_flags(frozen.clone(), Flags::IMMUTABLE).unwrap();
+    ext2.set_flags(log.clone(), Flags::APPEND_ONLY).unwrap();
+    assert!(ext2
+        .flags(frozen.clone())
+        .unwrap()
+        .contains(Flags::IMMUTABLE));
+
+    let denied = |result: VfsResult<()>| {
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("PermissionDenied"), "{}", err);
+    };
+
+    // 不可变: 不能写, 不能截断, 不能删除
+    let mut file = ext2.open_file(frozen.clone()).unwrap();
+    denied(file.write_at(0, b"x").map(|_| ()));
+    denied(file.append(b"x").map(|_| ()));
+    denied(file.set_len(0));
+    denied(ext2.remove_file(frozen.clone()).map(|_| ()));
+    assert_eq!(file.metadata().size(), 6);
+
+    // 追加写: 只能从末尾写入
+    let mut file = ext2.open_file(log.clone()).unwrap();
+    denied(file.write_at(0, b"LINE").map(|_| ()));
+    denied(file.set_len(2));
+    denied(ext2.remove_file(log.clone()).map(|_| ()));
+    file.append(b"line2\n").unwrap();
+    file.write_at(12, b"line3\n").unwrap();
+    let mut buf = [0u8; 18];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 18);
+    assert_eq!(&buf, b"line1\nline2\nline3\n");
+
+    // 清除标志后恢复正常
+    ext2.set_flags(frozen.clone(), Flags::empty()).unwrap();
+    ext2.set_flags(log.clone(), Flags::empty()).unwrap();
+    ext2.open_file(frozen.clone())
+        .unwrap()
+        .write_at(0, b"F")
+        .unwrap();
+    ext2.remove_file(frozen).unwrap();
+    ext2.remove_file(log).unwrap();
+}
+
+#[test]
+fn test_rename_respects_flags() {
+    let ext2 = gen_ext2();
+    let frozen = VfsPath::from("/frozen");
+    let log = VfsPath::from("/log");
+    let plain = VfsPath::from("/plain");
+    for path in [&frozen, &log, &plain] {
+        ext2.create_file(path.clone()).unwrap();
+    }
+    ext2.set_flags(frozen.clone(), Flags::IMMUTABLE).unwrap();
+    ext2.set_flags(log.clone(), Flags::APPEND_ONLY).unwrap();
+
+    let denied = |result: VfsResult<()>| {
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("PermissionDenied"), "{}", err);
+    };
+    // 受保护的 inode 既不能改名, 也不能被改名覆盖
+    denied(ext2.rename(frozen.clone(), VfsPath::from("/moved")));
+    denied(ext2.rename(log.clone(), VfsPath::from("/moved")));
+    denied(ext2.rename(plain.clone(), frozen.clone()));
+    denied(ext2.rename(plain.clone(), log.clone()));
+    assert!(ext2.exists(plain.clone()).unwrap());
+    assert!(!ext2.exists(VfsPath::from("/moved")).unwrap());
+
+    ext2.set_flags(frozen.clone(), Flags::empty()).unwrap();
+    ext2.set_flags(log.clone(), Flags::empty()).unwrap();
+    ext2.rename(plain, frozen.clone()).unwrap();
+    ext2.remove_file(frozen).unwrap();
+    ext2.remove_file(log).unwrap();
+}
+
+#[test]
+fn test_set_flags_requires_root() {
+    let (ext2, device) = gen_counting_ext2();
+    let path = VfsPath::from("/frozen");
+    // 新建的 inode 属于 1000:100
+    ext2.create_file(path.clone()).unwrap();
+    ext2.set_flags(path.clone(), Flags::IMMUTABLE).unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let mount_as = |uid, gid| {
+        let options = MountOptions {
+            credentials: Some(Credentials::new(uid, gid)),
+            ..MountOptions::default()
+        };
+        Ext2FileSystem::open_with(device.clone(), options).unwrap()
+    };
+
+    // 所有者也不能清除 IMMUTABLE
+    let ext2 = mount_as(1000, 100);
+    let err = ext2.set_flags(path.clone(), Flags::empty()).unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("PermissionDenied"),
+        "{:?}",
+        err
+    );
+    assert!(ext2.flags(path.clone()).unwrap().contains(Flags::IMMUTABLE));
+    drop(ext2);
+
+    let ext2 = mount_as(0, 0);
+    ext2.set_flags(path.clone(), Flags::empty()).unwrap();
+    ext2.remove_file(path).unwrap();
+}
+
+#[test]
+fn test_inode_location() {
+    let (ext2, device) = gen_counting_ext2();
+    let (block_id, offset) = ext2.inode_location(2).unwrap();
+    assert_eq!(
+        block_id * block::SIZE + offset,
+        inode_byte_offset(&device, 2)
+    );
+
+    // 直接读出来的原始字节就是根目录的 inode
+    ext2.flush();
+    fs::block_device::flush();
+    let byte = block_id * block::SIZE + offset;
+    let mut sector = [0u8; 512];
+    device.read_block(byte / 512, &mut sector);
+    let mode = u16::from_le_bytes([sector[byte % 512], sector[byte % 512 + 1]]);
+    assert_eq!(mode & 0xF000, 0x4000);
+
+    device.read_block(2, &mut sector);
+    let inodes_count = u32::from_le_bytes(sector[0..4].try_into().unwrap()) as usize;
+    assert!(ext2.inode_location(inodes_count).is_ok());
+    assert!(ext2.inode_location(0).is_err());
+    assert!(ext2.inode_location(inodes_count + 1).is_err());
+}
+
+#[test]
+fn test_block_cache_lru() {
+    use fs::block::DataBlock;
+    use fs::block_device::{self, BLOCK_CACHE_SIZE};
+
+    let (ext2, device) = gen_counting_ext2();
+    ext2.flush();
+    block_device::flush();
+    block_device::set_cache_capacity(4);
+    assert!(block_device::cached_blocks() <= 4);
+
+    let touch = |block_id: usize| block_device::read(block_id, 0, |_: &DataBlock| ());
+    let reads_of = |block_id: usize| {
+        device.reset();
+        touch(block_id);
+        device.counts().reads
+    };
+    for block_id in 100..104 {
+        touch(block_id);
+    }
+    // 重新访问 100, 最久没用的变成 101
+    touch(100);
+    touch(104);
+    assert_eq!(block_device::cached_blocks(), 4);
+    assert_eq!(reads_of(100), 0);
+    assert_eq!(reads_of(101), block::SECTORS_PER_BLOCK);
+
+    // 被淘汰的脏块写回设备, 不需要 flush
+    block_device::modify(110, 0, |data: &mut DataBlock| data[0] = 0xAB);
+    device.reset();
+    for block_id in 120..124 {
+        touch(block_id);
+    }
+    assert_eq!(device.counts().writes, block::SECTORS_PER_BLOCK);
+    let mut sector = [0u8; 512];
+    device.read_block(110 * block::SECTORS_PER_BLOCK, &mut sector);
+    assert_eq!(sector[0], 0xAB);
+
+    block_device::set_cache_capacity(BLOCK_CACHE_SIZE);
+}
+
+#[test]
+fn test_address_advance() {
+    let start = Address::new(7, 4000);
+    let next = start.advance(200);
+    assert_eq!((next.block_id(), next.offset()), (8, 104));
+    // 恰好落在块边界上
+    let edge = start.advance(96);
+    assert_eq!((edge.block_id(), edge.offset()), (8, 0));
+    let far = start.advance(3 * block::SIZE as isize);
+    assert_eq!((far.block_id(), far.offset()), (10, 4000));
+    // 向前移动同样会借位
+    let back = next.advance(-200);
+    assert_eq!(back, start);
+
+    // 越界的偏移不再被悄悄折算
+    assert!(std::panic::catch_unwind(|| Address::new(7, block::SIZE as isize)).is_err());
+    assert!(std::panic::catch_unwind(|| Address::new(7, -1)).is_err());
+}
+
+#[test]
+fn test_sync_all_persists() {
+    let (ext2, device) = gen_counting_ext2();
+    let path = VfsPath::from("/synced");
+    ext2.create_file(path.clone())
+        .unwrap()
+        .write_at(0, b"persisted")
+        .unwrap();
+    ext2.flush();
+    // 丢弃缓存而不写回, 数据必须已经在设备上
+    fs::block_device::discard();
+    drop(ext2);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let file = ext2.open_file(path.clone()).unwrap();
+    let mut buf = [0u8; 9];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 9);
+    assert_eq!(&buf, b"persisted");
+
+    // 没有 sync 的修改被丢弃
+    ext2.create_file(VfsPath::from("/unsynced")).unwrap();
+    fs::block_device::discard();
+    drop((file, ext2));
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    assert!(!ext2.exists(VfsPath::from("/unsynced")).unwrap());
+    assert!(ext2.exists(path).unwrap());
+}
+
+#[test]
+fn test_write_dirties_data_and_inode_block() {
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/rmw");
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, &vec![1u8; 3 * block::SIZE]).unwrap();
+    let id = ext2.inode_id_of(path.clone()).unwrap();
+    let (inode_block, _) = ext2.inode_location(id).unwrap();
+    let data_block = ext2.owned_blocks(path.clone()).unwrap()[1] as usize;
+    ext2.flush();
+    assert!(fs::block_device::dirty_blocks().is_empty());
+
+    // 覆盖写: 只有数据块和 inode 块变脏
+    file.write_at(block::SIZE + 10, b"xyz").unwrap();
+    let mut expected = vec![data_block, inode_block];
+    expected.sort();
+    assert_eq!(fs::block_device::dirty_blocks(), expected);
+
+    let mut buf = [0u8; 5];
+    file.read_at(block::SIZE + 9, &mut buf).unwrap();
+    assert_eq!(&buf, b"\x01xyz\x01");
+}
+
+#[test]
+fn test_mkfs_blank_device() {
+    let _guard = IMAGE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let path = image_copy();
+    std::fs::File::create(&path)
+        .unwrap()
+        .set_len(16384 * block::SIZE as u64)
+        .unwrap();
+    let device = CountingDevice::wrapping(BlockFile::create(&path));
+
+    let err = Ext2FileSystem::mkfs(device.clone(), 16384, 12).unwrap_err();
+    assert!(matches!(err.kind(), VfsErrorKind::Other(_)), "{:?}", err);
+    assert!(Ext2FileSystem::mkfs(device.clone(), 8, 8192).is_err());
+
+    let ext2 = Ext2FileSystem::mkfs(device.clone(), 16384, 8192).unwrap();
+    assert_eq!(ext2.blockgroup_count(), 2);
+    assert_eq!(ext2.first_ino(), 11);
+    let mut names: Vec<String> = ext2
+        .read_dir(VfsPath::from("/"))
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, [".", "..", "lost+found"]);
+
+    let file_path = VfsPath::from("/hello");
+    ext2.create_file(file_path.clone())
+        .unwrap()
+        .write_at(0, b"fresh")
+        .unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let ext2 = Ext2FileSystem::open_with(
+        device.clone(),
+        MountOptions {
+            verify: true,
+            ..MountOptions::default()
+        },
+    )
+    .unwrap();
+    let mut buf = [0u8; 5];
+    let file = ext2.open_file(file_path).unwrap();
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 5);
+    assert_eq!(&buf, b"fresh");
+    ext2.flush();
+    drop((file, ext2));
+
+    let output = std::process::Command::new("e2fsck")
+        .args(["-fn", &path])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_for_each_entry_stops_early() {
+    let (ext2, device) = gen_counting_ext2();
+    let dir = VfsPath::from("/large");
+    ext2.create_dir(dir.clone()).unwrap();
+    let names: Vec<String> = (0..300).map(|i| format!("{:0>60}", i)).collect();
+    for name in &names {
+        let path = format!("/large/{}", name);
+        ext2.create_file(VfsPath::from(path.as_str())).unwrap();
+    }
+
+    // 不提前停止时访问到所有目录项
+    let mut visited = 0;
+    let found = ext2
+        .for_each_entry(dir.clone(), |_| {
+            visited += 1;
+            ControlFlow::<()>::Continue(())
+        })
+        .unwrap();
+    assert_eq!(found, None);
+    assert_eq!(visited, 302);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let before = device.counts().reads;
+    let mut visited = 0;
+    let found = ext2
+        .for_each_entry(dir.clone(), |entry| {
+            visited += 1;
+            if entry.name() == names[3] {
+                ControlFlow::Break(entry.inode_id())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+    let lazy = device.counts().reads - before;
+    assert_eq!(
+        found,
+        Some(
+            ext2.inode_id_of(VfsPath::from(format!("/large/{}", names[3]).as_str()))
+                .unwrap()
+        )
+    );
+    // "." ".." 加上前 4 个文件
+    assert_eq!(visited, 6);
+    drop(ext2);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let before = device.counts().reads;
+    assert_eq!(ext2.read_dir(dir).unwrap().len(), 302);
+    let full = device.counts().reads - before;
+    assert!(
+        lazy < full,
+        "callback: {} reads, read_dir: {} reads",
+        lazy,
+        full
+    );
+}
+
+#[test]
+fn test_large_write_allocates_contiguous_blocks() {
+    let ext2 = gen_ext2();
+    // 交替删除小文件, 在空闲空间中留下大量单块的空洞
+    for i in 0..200 {
+        let path = VfsPath::from(format!("/small{}", i).as_str());
+        ext2.create_file(path).unwrap().write_at(0, b"x").unwrap();
+    }
+    for i in (0..200).step_by(2) {
+        ext2.remove_file(VfsPath::from(format!("/small{}", i).as_str()))
+            .unwrap();
+    }
+
+    let path = VfsPath::from("/large");
+    let data: Vec<u8> = (0..256 * block::SIZE).map(|i| (i % 251) as u8).collect();
+    ext2.create_file(path.clone())
+        .unwrap()
+        .write_at(0, &data)
+        .unwrap();
+
+    let mut blocks = ext2.owned_blocks(path.clone()).unwrap();
+    blocks.sort();
+    let adjacent = blocks
+        .windows(2)
+        .filter(|pair| pair[0] + 1 == pair[1])
+        .count();
+    assert!(
+        adjacent * 100 >= (blocks.len() - 1) * 95,
+        "{} of {} blocks adjacent",
+        adjacent + 1,
+        blocks.len()
+    );
+
+    let mut buf = vec![0u8; data.len()];
+    let file = ext2.open_file(path).unwrap();
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert!(buf == data);
+}
+
+#[test]
+fn test_flush_updates_wtime() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    let (ext2, device) = gen_counting_ext2();
+    let clock = ClockGuard::set(|| NOW.load(Ordering::SeqCst));
+    let disk_wtime = || {
+        let mut sector = [0u8; 512];
+        device.read_block(2, &mut sector);
+        u32::from_le_bytes(sector[48..52].try_into().unwrap())
+    };
+
+    NOW.store(1_700_000_000, Ordering::SeqCst);
+    let free_before = disk_free_blocks(&ext2, &device);
+    assert_eq!(disk_wtime(), 1_700_000_000);
+
+    // 写入时间和空闲计数一起写回
+    NOW.store(1_700_000_123, Ordering::SeqCst);
+    ext2.create_file(VfsPath::from("/stamped"))
+        .unwrap()
+        .write_at(0, &[7u8; 3 * block::SIZE])
+        .unwrap();
+    assert_eq!(disk_free_blocks(&ext2, &device), free_before - 3);
+    assert_eq!(disk_wtime(), 1_700_000_123);
+
+    // 没有时钟时写回不会把 wtime 改成 0
+    drop(clock);
+    ext2.create_file(VfsPath::from("/unstamped")).unwrap();
+    assert_eq!(disk_free_blocks(&ext2, &device), free_before - 3);
+    assert_eq!(disk_wtime(), 1_700_000_123);
+}
+
+#[test]
+fn test_blocks_follow_inode_group() {
+    // 4 个块组, 每组 32 个 inode, 文件多了之后 inode 会落到后面的块组
+    let (ext2, _) = gen_ext2_mkfs("64M", &["-g", "4096", "-N", "128"]);
+    assert_eq!(ext2.blockgroup_count(), 4);
+    let mut groups = std::collections::BTreeSet::new();
+    for i in 0..60 {
+        let path = VfsPath::from(format!("/f{}", i).as_str());
+        let mut file = ext2.create_file(path.clone()).unwrap();
+        file.write_at(0, &[1u8; 2 * block::SIZE]).unwrap();
+        // 追加的块同样留在原来的块组
+        file.write_at(2 * block::SIZE, &[2u8; block::SIZE]).unwrap();
+
+        let inode_group = (ext2.inode_id_of(path.clone()).unwrap() - 1) / 32;
+        groups.insert(inode_group);
+        for block_id in ext2.owned_blocks(path).unwrap() {
+            assert_eq!(block_id as usize / 4096, inode_group, "file {}", i);
+        }
+    }
+    assert!(groups.len() > 1, "{:?}", groups);
+}
+
+#[test]
+fn test_errors_state_and_policy() {
+    let (ext2, device) = gen_counting_ext2();
+    assert!(!ext2.has_errors());
+    assert_eq!(ext2.error_policy(), ErrorPolicy::Continue);
+    let dir = VfsPath::from("/bad");
+    ext2.create_dir(dir.clone()).unwrap();
+    ext2.create_file(VfsPath::from("/bad/file")).unwrap();
+    let dir_block = ext2.owned_blocks(dir.clone()).unwrap()[0] as usize;
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // superblock 标记为有错误, 出错时改为只读
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    sector[58..60].copy_from_slice(&2u16.to_le_bytes());
+    sector[60..62].copy_from_slice(&2u16.to_le_bytes());
+    device.write_block(2, &sector);
+    // 目录的第一条记录 rec_len 为 0
+    device.write_block(dir_block * block::SECTORS_PER_BLOCK, &[0u8; 512]);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    assert!(ext2.has_errors());
+    assert!(!ext2.is_clean());
+    assert_eq!(ext2.error_policy(), ErrorPolicy::RemountReadOnly);
+    assert!(!ext2.is_read_only());
+    ext2.create_file(VfsPath::from("/before")).unwrap();
+
+    let err = ext2
+        .for_each_entry(dir, |_| ControlFlow::<()>::Continue(()))
+        .unwrap_err();
+    assert!(format!("{:?}", err).contains("Corrupted"), "{:?}", err);
+    assert!(ext2.is_read_only());
+    let err = ext2.create_file(VfsPath::from("/after")).err().unwrap();
+    assert!(
+        format!("{:?}", err).contains("PermissionDenied"),
+        "{:?}",
+        err
+    );
+    let mut file = ext2.open_file(VfsPath::from("/before")).unwrap();
+    assert!(file.write_at(0, b"data").is_err());
+    // 元数据的修改同样被拒绝
+    let err = file
+        .set_permissions(&VfsPermissions::new(0o600))
+        .unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("PermissionDenied"),
+        "{:?}",
+        err
+    );
+    assert!(ext2.set_sticky(VfsPath::from("/before"), true).is_err());
+    assert!(ext2
+        .set_flags(VfsPath::from("/before"), Flags::IMMUTABLE)
+        .is_err());
+    assert!(ext2.read_dir(VfsPath::from("/")).is_ok());
+
+    // 错误标记随 flush 写回
+    ext2.flush();
+    device.read_block(2, &mut sector);
+    assert_eq!(u16::from_le_bytes(sector[58..60].try_into().unwrap()), 2);
+}
+
+#[test]
+fn test_errors_state_bitmask() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // Linux 在有错误时正常卸载写入的是 FS_CLEAN | FS_ERR
+    let state_on_disk = || {
+        let mut sector = [0u8; 512];
+        device.read_block(2, &mut sector);
+        u16::from_le_bytes(sector[58..60].try_into().unwrap())
+    };
+    let mut sector = [0u8; 512];
+    device.read_block(2, &mut sector);
+    sector[58..60].copy_from_slice(&3u16.to_le_bytes());
+    device.write_block(2, &sector);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    assert!(ext2.has_errors());
+    assert!(ext2.is_clean());
+    // 挂载期间只清掉 clean 位, 没有正常卸载也不会丢失错误标记
+    assert_eq!(state_on_disk(), 2);
+    ext2.abandon();
+    assert_eq!(state_on_disk(), 2);
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    assert!(ext2.has_errors());
+    assert!(!ext2.is_clean());
+    ext2.flush();
+    assert_eq!(state_on_disk() & 2, 2);
+}
+
+#[test]
+fn test_file_handle_cursor() {
+    use fs::vfs::{io::SeekFrom, File};
+
+    let vfs = gen_vfs();
+    vfs.create_file("/cursor").unwrap();
+    let mut file = vfs.open("/cursor").unwrap();
+    assert_eq!(file.write(b"hello ").unwrap(), 6);
+    assert_eq!(file.write(b"world").unwrap(), 5);
+    assert_eq!(file.stream_position(), 11);
+
+    assert_eq!(file.seek(SeekFrom::Start(6)).unwrap(), 6);
+    let mut buf = [0u8; 8];
+    assert_eq!(file.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"world");
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+
+    // 越过 EOF 写入, 空隙补 0
+    assert_eq!(file.seek(SeekFrom::End(block::SIZE as i64)).unwrap(), 4107);
+    file.write(b"!").unwrap();
+    assert_eq!(file.inode().metadata().size(), 4108);
+    assert_eq!(file.seek(SeekFrom::Current(-4098)).unwrap(), 10);
+    let mut gap = vec![0xFFu8; 4098];
+    assert_eq!(file.read(&mut gap).unwrap(), 4098);
+    assert_eq!(gap[0], b'd');
+    assert!(gap[1..4097].iter().all(|&byte| byte == 0));
+    assert_eq!(gap[4097], b'!');
+
+    // 可以包装任意 VfsInode
+    let mut reopened = File::new(file.into_inner());
+    let mut head = [0u8; 5];
+    assert_eq!(reopened.read(&mut head).unwrap(), 5);
+    assert_eq!(&head, b"hello");
+}
+
+#[test]
+fn test_metadata_csum_mounts_read_only() {
+    let (_guard, path) = mke2fs_image("32M", &["-O", "metadata_csum,^dir_index"]);
+    let device = CountingDevice::wrapping(BlockFile::create(&path));
+
+    // 不维护任何校验和, 可写挂载会让镜像失效
+    let err = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("metadata_csum"), "{}", err);
+
+    let ext2 = Ext2FileSystem::open_with(
+        device.clone(),
+        MountOptions {
+            read_only: true,
+            ..MountOptions::default()
+        },
+    )
+    .unwrap();
+    // 末尾的校验和记录 inode 为 0, 读目录时当作空闲记录跳过
+    let raw = ext2.dump_dir_raw(VfsPath::from("/")).unwrap();
+    assert_eq!(raw.len(), block::SIZE);
+    assert_eq!(
+        &raw[block::SIZE - 12..block::SIZE - 4],
+        &[0, 0, 0, 0, 12, 0, 0, 0xDE]
+    );
+    let names = ext2.read_dir(VfsPath::from("/")).unwrap();
+    assert_eq!(names.len(), 3);
+    assert!(ext2.create_file(VfsPath::from("/a")).is_err());
+    ext2.flush();
+    drop(ext2);
+    fs::block_device::flush();
+
+    let output = std::process::Command::new("e2fsck")
+        .args(["-fn", &path])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_io_file_adapters() {
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+    let vfs = gen_vfs();
+    let contents: Vec<u8> = (0..3 * block::SIZE + 100)
+        .map(|i| (i % 253) as u8)
+        .collect();
+    let mut writer = IoFile::new(vfs.create_file("/copied").unwrap());
+    writer.write_all(&contents).unwrap();
+    assert_eq!(writer.stream_position().unwrap(), contents.len() as u64);
+
+    // std::io::copy 读出整个文件
+    let mut reader = IoFile::new(vfs.open_file("/copied").unwrap());
+    let mut copied = Vec::new();
+    let n = std::io::copy(&mut reader, &mut copied).unwrap();
+    assert_eq!(n, contents.len() as u64);
+    assert!(copied == contents);
+
+    assert_eq!(
+        reader.seek(SeekFrom::End(-4)).unwrap(),
+        contents.len() as u64 - 4
+    );
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &contents[contents.len() - 4..]);
+    let err = reader.seek(SeekFrom::Current(-(contents.len() as i64) - 1));
+    assert_eq!(err.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+    // BufReader 逐行读取
+    let mut lines = IoFile::new(vfs.create_file("/lines").unwrap());
+    lines.write_all(b"first\nsecond\nthird").unwrap();
+    lines.rewind().unwrap();
+    let read: Vec<String> = BufReader::new(lines).lines().map(Result::unwrap).collect();
+    assert_eq!(read, ["first", "second", "third"]);
+
+    // set_len 截断后只剩前面的部分
+    let mut truncated = IoFile::new(vfs.open_file("/copied").unwrap());
+    truncated.set_len(10).unwrap();
+    let mut rest = Vec::new();
+    truncated.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &contents[..10]);
+
+    // 错误转为对应的 ErrorKind
+    let err = crate::io_file::io_error(vfs.open_file("/missing").unwrap_err());
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_block_view() {
+    use fs::block::DataBlock;
+    use fs::block_device::{self, BLOCK_CACHE_SIZE};
+
+    let (ext2, device) = gen_counting_ext2();
+    let data: Vec<u8> = (0..3 * block::SIZE).map(|i| (i % 251) as u8).collect();
+    let mut file = ext2.create_file(VfsPath::from("/mapped")).unwrap();
+    file.write_at(0, &data).unwrap();
+    ext2.flush();
+    block_device::flush();
+    drop(ext2);
+
+    // 重新挂载, 让缓存是冷的
+    let ext2 = Ext2FileSystem::open(device.clone());
+    let view = ext2.map_readonly(VfsPath::from("/mapped")).unwrap();
+    assert_eq!(view.len(), data.len());
+    assert_eq!(view.pinned_blocks(), 0);
+
+    // 跨过 0/1 块边界读取, 每块只从设备读一次
+    device.reset();
+    let boundary = block::SIZE;
+    let bytes: Vec<u8> = (boundary - 8..boundary + 8)
+        .map(|offset| view.byte(offset).unwrap())
+        .collect();
+    assert_eq!(bytes, &data[boundary - 8..boundary + 8]);
+    assert_eq!(view.pinned_blocks(), 2);
+    assert_eq!(device.counts().reads, 2 * block::SECTORS_PER_BLOCK);
+    assert!(view
+        .with_block(1, |bytes| bytes == &data[boundary..2 * boundary])
+        .unwrap());
+    assert_eq!(view.byte(data.len()), None);
+    assert!(view.with_block(3, |_| ()).is_none());
+
+    // 缓存很小时被视图持有的块也不会被换出, 再次访问不读设备
+    block_device::set_cache_capacity(2);
+    for block_id in 100..104 {
+        block_device::read(block_id, 0, |_: &DataBlock| ());
+    }
+    device.reset();
+    assert_eq!(view.byte(0), Some(data[0]));
+    assert_eq!(view.byte(boundary), Some(data[boundary]));
+    assert_eq!(device.counts().reads, 0);
+    block_device::set_cache_capacity(BLOCK_CACHE_SIZE);
+
+    // 目录不能映射
+    assert!(ext2.map_readonly(VfsPath::from("/")).is_err());
+}
+
+#[test]
+fn test_rename_in_same_dir() {
+    let vfs = gen_vfs();
+    let mut file = vfs.create_file("/old_name").unwrap();
+    file.write_at(0, b"renamed").unwrap();
+    let inode_id = vfs.inode_id_of("/old_name").unwrap();
+
+    vfs.rename("/old_name", "/new_name").unwrap();
+    assert!(!vfs.exists("/old_name").unwrap());
+    assert_eq!(vfs.inode_id_of("/new_name").unwrap(), inode_id);
+    let mut buf = [0u8; 7];
+    vfs.open_file("/new_name")
+        .unwrap()
+        .read_at(0, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"renamed");
+
+    // 改名为自己什么也不做
+    vfs.rename("/new_name", "/new_name").unwrap();
+    assert_eq!(vfs.inode_id_of("/new_name").unwrap(), inode_id);
+
+    // 覆盖已存在的文件, 被覆盖的文件被回收
+    let victim = vfs.create_file("/victim").unwrap();
+    vfs.rename("/new_name", "/victim").unwrap();
+    assert!(!vfs.exists("/new_name").unwrap());
+    assert_eq!(vfs.inode_id_of("/victim").unwrap(), inode_id);
+    assert_eq!(victim.metadata().hard_links(), 0);
+
+    // 文件不能覆盖目录, 源不存在时报错
+    vfs.create_dir("/dir").unwrap();
+    let err = vfs.rename("/victim", "/dir").unwrap_err();
+    assert!(err.to_string().contains("IsADirectory"), "{}", err);
+    assert!(vfs.rename("/missing", "/other").is_err());
+}
+
+#[test]
+fn test_rename_moves_dir_across_parents() {
+    let path = image_copy();
+    let (ext2, _device) = gen_ext2_mkfs("8M", &[]);
+    let links = |path: &str| ext2.metadata(VfsPath::from(path)).unwrap().hard_links();
+    ext2.create_dir(VfsPath::from("/src")).unwrap();
+    ext2.create_dir(VfsPath::from("/dst")).unwrap();
+    ext2.create_dir(VfsPath::from("/src/moved")).unwrap();
+    ext2.create_file(VfsPath::from("/src/moved/file")).unwrap();
+    assert_eq!((links("/src"), links("/dst")), (3, 2));
+
+    ext2.rename(VfsPath::from("/src/moved"), VfsPath::from("/dst/moved"))
+        .unwrap();
+    assert!(!ext2.exists(VfsPath::from("/src/moved")).unwrap());
+    assert!(ext2.exists(VfsPath::from("/dst/moved/file")).unwrap());
+    assert_eq!((links("/src"), links("/dst")), (2, 3));
+    // ".." 指向新的父目录
+    assert_eq!(
+        ext2.inode_id_of(VfsPath::from("/dst/moved/..")).unwrap(),
+        ext2.inode_id_of(VfsPath::from("/dst")).unwrap()
+    );
+
+    // 目录不能移到自己下面, 只能覆盖空目录
+    assert!(ext2
+        .rename(VfsPath::from("/dst"), VfsPath::from("/dst/moved/inner"))
+        .is_err());
+    ext2.create_dir(VfsPath::from("/src/empty")).unwrap();
+    ext2.rename(VfsPath::from("/dst/moved"), VfsPath::from("/src/empty"))
+        .unwrap();
+    assert!(ext2.exists(VfsPath::from("/src/empty/file")).unwrap());
+    assert_eq!((links("/src"), links("/dst")), (3, 2));
+    let err = ext2
+        .rename(VfsPath::from("/dst"), VfsPath::from("/src"))
+        .unwrap_err();
+    assert!(err.to_string().contains("DirectoryNotEmpty"), "{}", err);
+
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+    let output = std::process::Command::new("e2fsck")
+        .args(["-fn", &path])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_partial_group_padding_bits() {
+    use std::os::unix::fs::FileExt;
+
+    let path = image_copy();
+    // 2348 个块分成 1024 + 1024 + 300 三个块组
+    let blocks_count = 2348;
+    let size = (blocks_count * block::SIZE).to_string();
+    let (ext2, device) = gen_ext2_mkfs(&size, &["-g", "1024"]);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 清掉最后一个块组 block bitmap 中的填充位
+    let output = std::process::Command::new("dumpe2fs")
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let bitmap: usize = stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("Block bitmap at "))
+        .next_back()
+        .unwrap()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .unwrap();
+    let valid = blocks_count - 2 * 1024;
+    let mut data = vec![0u8; block::SIZE];
+    let image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    image
+        .read_exact_at(&mut data, (bitmap * block::SIZE) as u64)
+        .unwrap();
+    data[valid / 8] &= (1u8 << (valid % 8)) - 1;
+    data[valid / 8 + 1..].fill(0);
+    image
+        .write_all_at(&data, (bitmap * block::SIZE) as u64)
+        .unwrap();
+    drop(image);
+
+    let verified = MountOptions {
+        verify: true,
+        ..MountOptions::default()
+    };
+    let err = Ext2FileSystem::open_with(device.clone(), verified).unwrap_err();
+    assert!(err.to_string().contains("padding bits"), "{}", err);
+
+    // 用尽所有空闲块, 分配出的块都不超出文件系统
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let chunk = vec![0x5Au8; 64 * block::SIZE];
+    let mut paths = Vec::new();
+    for i in 0.. {
+        let path = VfsPath::from(format!("/fill{}", i).as_str());
+        let written = ext2
+            .create_file(path.clone())
+            .and_then(|mut file| file.write_at(0, &chunk));
+        paths.push(path);
+        if written.is_err() {
+            break;
+        }
+    }
+    // 在前面的块组中挖出两个不相邻的空洞, 再从最后一个块组接着扩展最后写满的文件.
+    // 需要的块数超过最后一个块组剩下的空闲块, 也找不到这么长的连续空闲块
+    ext2.remove_file(paths[0].clone()).unwrap();
+    ext2.remove_file(paths[2].clone()).unwrap();
+    let mut last = ext2.open_file(paths[paths.len() - 2].clone()).unwrap();
+    let extra = vec![0xA5u8; 100 * block::SIZE];
+    last.write_at(chunk.len(), &extra).unwrap();
+    let owned: Vec<u32> = paths
+        .into_iter()
+        .filter(|path| ext2.exists(path.clone()).unwrap())
+        .flat_map(|path| ext2.owned_blocks(path).unwrap())
+        .collect();
+    assert!(owned.iter().any(|&block_id| block_id as usize >= 2 * 1024));
+    assert!(owned
+        .iter()
+        .all(|&block_id| (block_id as usize) < blocks_count));
+}
+
+#[test]
+fn test_create_dir_all() {
+    let vfs = gen_vfs();
+    let is_dir = |path: &str| vfs.metadata(path).unwrap().filetype().is_dir();
+
+    // 全都不存在
+    vfs.create_dir_all("/a/b/c").unwrap();
+    assert!(is_dir("/a") && is_dir("/a/b") && is_dir("/a/b/c"));
+
+    // 全都已存在, 什么也不做
+    let inode_id = vfs.inode_id_of("/a/b/c").unwrap();
+    vfs.create_dir_all("/a/b/c").unwrap();
+    assert_eq!(vfs.inode_id_of("/a/b/c").unwrap(), inode_id);
+
+    // 部分已存在
+    vfs.create_dir_all("/a/b/d/e").unwrap();
+    assert!(is_dir("/a/b/d") && is_dir("/a/b/d/e"));
+    assert_eq!(vfs.inode_id_of("/a/b/c").unwrap(), inode_id);
+
+    // 中间一级是文件时报错, 不会在它下面创建
+    vfs.create_file("/a/file").unwrap();
+    let err = vfs.create_dir_all("/a/file/x").unwrap_err();
+    assert!(err.to_string().contains("NotADirectory"), "{}", err);
+    let err = vfs.create_dir_all("/a/file").unwrap_err();
+    assert!(err.to_string().contains("NotADirectory"), "{}", err);
+}
+
+#[test]
+fn test_truncate_by_path() {
+    let (vfs, device) = gen_counting_vfs();
+    let contents: Vec<u8> = (0..block::SIZE + 100).map(|i| (i % 251) as u8).collect();
+    vfs.create_file("/trunc")
+        .unwrap()
+        .write_at(0, &contents)
+        .unwrap();
+    let read_all = || {
+        let file = vfs.open_file("/trunc").unwrap();
+        let mut buf = vec![0u8; file.metadata().size() as usize];
+        file.read_at(0, &mut buf).unwrap();
+        buf
+    };
+
+    // 缩短后只剩前面的部分, 并且已经写回设备
+    vfs.truncate("/trunc", 10).unwrap();
+    assert!(fs::block_device::dirty_blocks().is_empty());
+    assert_eq!(read_all(), &contents[..10]);
+
+    // 扩展出的部分读出为 0
+    device.reset();
+    vfs.truncate("/trunc", 2 * block::SIZE).unwrap();
+    let grown = read_all();
+    assert_eq!(grown.len(), 2 * block::SIZE);
+    assert_eq!(&grown[..10], &contents[..10]);
+    assert!(grown[10..].iter().all(|&byte| byte == 0));
+    assert!(device.counts().writes > 0);
+
+    let err = vfs.truncate("/", 0).unwrap_err();
+    assert!(err.to_string().contains("IsADirectory"), "{}", err);
+    assert!(vfs.truncate("/missing", 0).is_err());
+}
+
+#[test]
+fn test_remove_dir_all() {
+    let vfs = gen_vfs();
+    vfs.create_dir_all("/outside").unwrap();
+    vfs.create_file("/outside/keep").unwrap();
+    vfs.create_dir_all("/tree/sub/deep").unwrap();
+    for path in ["/tree/f1", "/tree/sub/f2", "/tree/sub/deep/f3"] {
+        vfs.create_file(path)
+            .unwrap()
+            .write_at(0, &vec![0x11u8; 2 * block::SIZE])
+            .unwrap();
+    }
+    // 指向子树之外的目录的符号链接, 只删除链接本身
+    vfs.symlink("/outside", "/tree/sub/link").unwrap();
+    vfs.symlink("f1", "/tree/file_link").unwrap();
+
+    vfs.remove_dir_all("/tree").unwrap();
+    assert!(!vfs.exists("/tree").unwrap());
+    assert!(vfs.exists("/outside/keep").unwrap());
+    let names: Vec<String> = vfs
+        .read_dir("/")
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert!(!names.contains(&"tree".to_string()));
+
+    // path 本身是符号链接时也只删除链接
+    vfs.symlink("/outside", "/outside_link").unwrap();
+    vfs.remove_dir_all("/outside_link").unwrap();
+    assert!(!vfs.exists("/outside_link").unwrap());
+    assert!(vfs.exists("/outside/keep").unwrap());
+
+    assert!(vfs.remove_dir_all("/").is_err());
+    assert!(vfs.remove_dir_all("/missing").is_err());
+}
+
+#[test]
+fn test_read_ahead_indirect_block() {
+    let (vfs, device) = gen_counting_vfs();
+    let blocks = 20;
+    let contents: Vec<u8> = (0..blocks * block::SIZE)
+        .map(|i| (i / block::SIZE) as u8)
+        .collect();
+    vfs.create_file("/scan")
+        .unwrap()
+        .write_at(0, &contents)
+        .unwrap();
+    vfs.flush();
+
+    // 在同一设备上重新挂载, 让缓存是冷的
+    let vfs = VFS::new(Ext2FileSystem::open(device.clone()));
+    let file = vfs.open_file("/scan").unwrap();
+    let mut buf = vec![0u8; block::SIZE];
+    let reads_of = |idx: usize, buf: &mut [u8]| {
+        device.reset();
+        file.read_at(idx * block::SIZE, buf).unwrap();
+        assert!(buf.iter().all(|&byte| byte == idx as u8));
+        device.counts().reads / block::SECTORS_PER_BLOCK
+    };
+
+    for idx in 0..10 {
+        assert_eq!(reads_of(idx, &mut buf), 1);
+    }
+    // 接近第 12 块时, 一级间接块和它指向的前 4 个数据块被提前读入
+    assert_eq!(reads_of(10, &mut buf), 1 + 1 + 4);
+    assert_eq!(reads_of(11, &mut buf), 1);
+    for idx in 12..16 {
+        assert_eq!(reads_of(idx, &mut buf), 0);
+    }
+    assert_eq!(reads_of(16, &mut buf), 1);
+}
+
+#[test]
+fn test_stat_fs() {
+    let vfs = gen_vfs();
+    let before = vfs.stat_fs().unwrap();
+    assert_eq!(before.block_size, block::SIZE as u64);
+    assert!(before.free_blocks <= before.total_blocks);
+    assert!(before.available_blocks <= before.free_blocks);
+    assert!(before.free_inodes <= before.total_inodes);
+
+    let files = 10;
+    vfs.create_dir("/usage").unwrap();
+    for i in 0..files {
+        vfs.create_file(format!("/usage/{}", i))
+            .unwrap()
+            .write_at(0, &vec![0x33u8; 3 * block::SIZE])
+            .unwrap();
+    }
+    let after = vfs.stat_fs().unwrap();
+    assert_eq!(after.total_blocks, before.total_blocks);
+    assert_eq!(after.total_inodes, before.total_inodes);
+    // 每个文件 3 个数据块, 再加上新目录的一个块
+    assert_eq!(after.free_blocks, before.free_blocks - 3 * files - 1);
+    assert_eq!(
+        before.free_blocks - before.available_blocks,
+        after.free_blocks - after.available_blocks
+    );
+    assert_eq!(after.free_inodes, before.free_inodes - files - 1);
+
+    vfs.remove_dir_all("/usage").unwrap();
+    assert_eq!(vfs.stat_fs().unwrap(), before);
+}
+
+#[test]
+fn test_sparsify() {
+    let ext2 = gen_ext2();
+    let path = VfsPath::from("/sparse");
+    let blocks = 5;
+    let mut file = ext2.create_file(path.clone()).unwrap();
+    file.write_at(0, &vec![0x77u8; blocks * block::SIZE])
+        .unwrap();
+    // 中间一块的内容清零, 但块仍然被占用
+    file.write_at(2 * block::SIZE, &vec![0u8; block::SIZE])
+        .unwrap();
+    let sectors = file.metadata().blocks();
+    let free_blocks = ext2.stat_fs().free_blocks;
+    let freed_block = ext2.owned_blocks(path.clone()).unwrap()[2];
+
+    assert_eq!(ext2.sparsify(path.clone()).unwrap(), 1);
+    assert_eq!(ext2.stat_fs().free_blocks, free_blocks + 1);
+    assert_eq!(
+        file.metadata().blocks(),
+        sectors - block::SECTORS_PER_BLOCK as u64
+    );
+    assert!(!ext2
+        .owned_blocks(path.clone())
+        .unwrap()
+        .contains(&freed_block));
+
+    // 空洞读出为 0, 其他块不变, 文件大小不变
+    let mut buf = vec![0xFFu8; blocks * block::SIZE];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), buf.len());
+    assert!(buf[2 * block::SIZE..3 * block::SIZE]
+        .iter()
+        .all(|&x| x == 0));
+    assert!(buf[..2 * block::SIZE].iter().all(|&x| x == 0x77));
+    assert!(buf[3 * block::SIZE..].iter().all(|&x| x == 0x77));
+
+    // 再次执行没有可释放的块, 空洞仍可写入
+    assert_eq!(ext2.sparsify(path.clone()).unwrap(), 0);
+    file.write_at(2 * block::SIZE, b"refill").unwrap();
+    assert_eq!(file.metadata().blocks(), sectors);
+    assert!(ext2.sparsify(VfsPath::from("/")).is_err());
+}
+
+#[test]
+fn test_superblock_recovery_from_backup() {
+    use std::os::unix::fs::FileExt;
+
+    let path = image_copy();
+    let (ext2, device) = gen_ext2_mkfs("16M", &["-g", "1024"]);
+    ext2.verify().unwrap();
+    ext2.create_file(VfsPath::from("/survivor"))
+        .unwrap()
+        .write_at(0, b"still here")
+        .unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 抹掉主 superblock 的 magic, 1 号块组中的备份还在
+    let image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    image.write_all_at(&[0, 0], 1024 + 56).unwrap();
+    // 不带恢复时报错而不是 panic
+    let err = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("invalid superblock"), "{}", err);
+
+    let ext2 = Ext2FileSystem::open_with_recovery(device.clone(), MountOptions::default()).unwrap();
+    let mut buf = [0u8; 10];
+    ext2.open_file(VfsPath::from("/survivor"))
+        .unwrap()
+        .read_at(0, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"still here");
+    // 备份中过期的空闲计数已按块组描述符更正
+    ext2.verify().unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+    let mut magic = [0u8; 2];
+    image.read_exact_at(&mut magic, 1024 + 56).unwrap();
+    assert_eq!(u16::from_le_bytes(magic), 0xEF53);
+
+    // superblock 中的空闲块数与块组描述符不一致时 verify 报错
+    let mut count = [0u8; 4];
+    image.read_exact_at(&mut count, 1024 + 12).unwrap();
+    let tampered = u32::from_le_bytes(count) + 5;
+    image
+        .write_all_at(&tampered.to_le_bytes(), 1024 + 12)
+        .unwrap();
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let err = ext2.verify().unwrap_err();
+    assert!(err.to_string().contains("free counts mismatch"), "{}", err);
+}
+
+#[test]
+fn test_check_reports_unmarked_block() {
+    use std::os::unix::fs::FileExt;
+
+    let path = image_copy();
+    let (ext2, device) = gen_ext2_mkfs("16M", &["-g", "1024"]);
+    ext2.create_dir(VfsPath::from("/dir")).unwrap();
+    ext2.create_file(VfsPath::from("/dir/data"))
+        .unwrap()
+        .write_at(0, &[7u8; 3 * block::SIZE])
+        .unwrap();
+    ext2.link(VfsPath::from("/dir/data"), VfsPath::from("/alias"))
+        .unwrap();
+    let report = ext2.check();
+    assert!(report.is_clean(), "{:?}", report.problems);
+    assert!(report.blocks >= 3);
+
+    let inode_id = ext2.inode_id_of(VfsPath::from("/dir/data")).unwrap();
+    let block_id = ext2.owned_blocks(VfsPath::from("/dir/data")).unwrap()[1] as u64;
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 清除该块在 block bitmap 中的位
+    let image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    let mut word = [0u8; 4];
+    image.read_exact_at(&mut word, 1024 + 32).unwrap();
+    let blocks_per_group = u32::from_le_bytes(word) as u64;
+    let desc = block::SIZE as u64 + block_id / blocks_per_group * 32;
+    image.read_exact_at(&mut word, desc).unwrap();
+    let bitmap = u32::from_le_bytes(word) as u64 * block::SIZE as u64;
+    let bit = block_id % blocks_per_group;
+    let mut byte = [0u8; 1];
+    image.read_exact_at(&mut byte, bitmap + bit / 8).unwrap();
+    byte[0] &= !(1 << (bit % 8));
+    image.write_all_at(&byte, bitmap + bit / 8).unwrap();
+
+    let ext2 = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap();
+    let report = ext2.check();
+    assert_eq!(
+        report.problems,
+        vec![Problem::BlockNotMarked {
+            inode_id,
+            block_id: block_id as usize,
+        }]
+    );
+    // 检查不会修改磁盘
+    assert_eq!(ext2.check(), report);
+    ext2.abandon();
+    image.read_exact_at(&mut byte, bitmap + bit / 8).unwrap();
+    assert_eq!(byte[0] & (1 << (bit % 8)), 0);
+}
+
+#[test]
+fn test_unsupported_feature_named() {
+    use std::os::unix::fs::FileExt;
+
+    let path = image_copy();
+    let (ext2, device) = gen_ext2_mkfs("16M", &[]);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    // 在 feature_incompat (superblock 偏移 0x60) 中加上 extent, 64bit 和一个没有名字的位
+    let image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    let mut word = [0u8; 4];
+    image.read_exact_at(&mut word, 1024 + 0x60).unwrap();
+    let incompat = u32::from_le_bytes(word) | 0x40 | 0x80 | 0x8000_0000;
+    image
+        .write_all_at(&incompat.to_le_bytes(), 1024 + 0x60)
+        .unwrap();
+
+    let err = Ext2FileSystem::open_with(device.clone(), MountOptions::default()).unwrap_err();
+    match err.kind() {
+        VfsErrorKind::UnsupportedFeature(features) => {
+            assert_eq!(features, "extent, 64bit, FEATURE_I31")
+        }
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert!(err.to_string().contains("64bit"), "{}", err);
+}
+
+#[test]
+fn test_read_only_mount_writes_nothing() {
+    let (ext2, device) = gen_counting_ext2();
+    ext2.create_file(VfsPath::from("/kept"))
+        .unwrap()
+        .write_at(0, b"kept")
+        .unwrap();
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+
+    let read_only = MountOptions {
+        read_only: true,
+        ..MountOptions::default()
+    };
+    device.reset();
+    let ext2 = Ext2FileSystem::open_with(device.clone(), read_only.clone()).unwrap();
+    // 读取不会记录 atime
+    let _clock = ClockGuard::set(|| 1_700_000_000);
+    assert!(ext2.is_read_only());
+    let mut buf = [0u8; 4];
+    ext2.open_file(VfsPath::from("/kept"))
+        .unwrap()
+        .read_at(0, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"kept");
+    let err = ext2.create_file(VfsPath::from("/new")).err().unwrap();
+    assert!(err.to_string().contains("read-only"), "{}", err);
+    ext2.flush();
+    fs::block_device::flush();
+    drop(ext2);
+    assert_eq!(device.counts().writes, 0);
+
+    // 只读挂载不能重放日志
+    let replay = MountOptions {
+        replay_journal: true,
+        ..read_only
+    };
+    assert!(Ext2FileSystem::open_with(device.clone(), replay).is_err());
+}
+
+fn gen_fuse(setup: impl FnOnce(&VFS)) -> TestEnv<Ext2Fuse> {
+    let TestEnv { inner, _guard } = gen_vfs();
+    setup(&inner);
+    TestEnv {
+        inner: Ext2Fuse::new(inner),
+        _guard,
+    }
+}
+
+#[test]
+fn test_fuse_lookup_and_getattr() {
+    let mut fuse = gen_fuse(|vfs| {
+        vfs.create_file_with("/fuse_file", b"hello fuse", 0o640)
+            .unwrap();
+        vfs.symlink("fuse_file", "/fuse_link").unwrap();
+        vfs.create_file_with("/fuse_suid", b"", 0o4755).unwrap();
+    });
+
+    let root = fuse.getattr_of(FUSE_ROOT_ID).unwrap();
+    assert_eq!((root.ino, root.kind), (FUSE_ROOT_ID, FileType::Directory));
+
+    let attr = fuse
+        .lookup_attr(FUSE_ROOT_ID, OsStr::new("fuse_file"))
+        .unwrap();
+    assert_eq!(attr.kind, FileType::RegularFile);
+    assert_eq!((attr.size, attr.perm, attr.nlink), (10, 0o640, 1));
+    assert_eq!(attr.blksize, block::SIZE as u32);
+    assert_eq!(fuse.getattr_of(attr.ino).unwrap().size, 10);
+    assert_eq!(fuse.read_of(attr.ino, 6, 100).unwrap(), b"fuse");
+
+    // 符号链接返回其本身的属性, 不跟随
+    let link = fuse
+        .lookup_attr(FUSE_ROOT_ID, OsStr::new("fuse_link"))
+        .unwrap();
+    assert_eq!((link.kind, link.size), (FileType::Symlink, 9));
+    assert_eq!(fuse.getattr_of(link.ino).unwrap().kind, FileType::Symlink);
+
+    // 特殊位也要带上
+    let suid = fuse
+        .lookup_attr(FUSE_ROOT_ID, OsStr::new("fuse_suid"))
+        .unwrap();
+    assert_eq!(suid.perm, 0o4755);
+
+    assert_eq!(
+        fuse.lookup_attr(FUSE_ROOT_ID, OsStr::new("missing"))
+            .unwrap_err(),
+        libc::ENOENT
+    );
+    // 没有 lookup 过的 inode 号不认识
+    assert_eq!(fuse.getattr_of(9999).unwrap_err(), libc::ENOENT);
+}
+
+#[test]
+fn test_fuse_readdir() {
+    let mut fuse = gen_fuse(|vfs| {
+        vfs.create_dir("/fuse_dir").unwrap();
+        vfs.create_file_with("/fuse_dir/inner", b"inner", 0o644)
+            .unwrap();
+    });
+
+    let root = fuse.readdir_of(FUSE_ROOT_ID, 0).unwrap();
+    // 根目录的 "." 和 ".." 都映射到 FUSE 的根
+    let dots: Vec<_> = root
+        .iter()
+        .filter(|(_, _, _, name)| name == "." || name == "..")
+        .map(|(ino, _, _, _)| *ino)
+        .collect();
+    assert_eq!(dots, vec![FUSE_ROOT_ID, FUSE_ROOT_ID]);
+    let (dir_ino, _, kind, _) = root
+        .iter()
+        .find(|(_, _, _, name)| name == "fuse_dir")
+        .unwrap()
+        .clone();
+    assert_eq!(kind, FileType::Directory);
+    // 序号从 1 开始, 从某一项继续时跳过之前的项
+    let offsets: Vec<_> = root.iter().map(|(_, next, _, _)| *next).collect();
+    assert_eq!(offsets, (1..=root.len() as i64).collect::<Vec<_>>());
+    assert_eq!(fuse.readdir_of(FUSE_ROOT_ID, 2).unwrap(), root[2..]);
+
+    // readdir 记下了子目录的路径, 不需要 lookup 就能继续访问
+    let inner = fuse.readdir_of(dir_ino, 0).unwrap();
+    let (inner_ino, _, kind, _) = inner
+        .iter()
+        .find(|(_, _, _, name)| name == "inner")
+        .unwrap()
+        .clone();
+    assert_eq!(kind, FileType::RegularFile);
+    assert_eq!(fuse.read_of(inner_ino, 0, 16).unwrap(), b"inner");
+    assert_eq!(fuse.readdir_of(inner_ino, 0).unwrap_err(), libc::ENOTDIR);
 }